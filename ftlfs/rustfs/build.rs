@@ -1,5 +1,7 @@
 fn main() {
-    println!("cargo:rustc-link-search=native=./lib");
-    println!("cargo:rustc-link-lib=static=ddriver");
+    if std::env::var_os("CARGO_FEATURE_DDRIVER").is_some() {
+        println!("cargo:rustc-link-search=native=./lib");
+        println!("cargo:rustc-link-lib=static=ddriver");
+    }
     println!("cargo:rustc-link-lib=fuse");
 }
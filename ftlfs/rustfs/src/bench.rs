@@ -0,0 +1,229 @@
+//! 预读和写回合并的基准测试，默认被忽略，用tests/Makefile中的`make bench`运行。
+//!
+//! 设备是加了固定延迟的内存磁盘：每次调用延迟CALL_LATENCY，每页再延迟PAGE_LATENCY，
+//! 模拟ddriver每次读写都要加锁、seek再逐个io单元传输的开销。每项测试分别在关闭和打开
+//! 优化时运行同样的负载，打印耗时和设备调用次数
+use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+use crate::buffer::flusher::Flusher;
+use crate::buffer::replacer::PageId;
+use crate::device::{BlockDevice, MemDevice};
+use crate::fs::custom::PAGE_SIZE;
+use crate::fs::def::FEATURE_EXTENTS;
+use crate::fs::layout::{Layout, LayoutOptions};
+use crate::fs::superblock::format;
+use crate::fs::types::{FileType, Inode, InodeId};
+use crate::fs::volume::Volume;
+use std::hint::spin_loop;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const CALL_LATENCY: Duration = Duration::from_micros(20);
+const PAGE_LATENCY: Duration = Duration::from_micros(2);
+const DEVICE_PAGES: usize = 4096;
+const FILE_PAGES: usize = 1024;
+///每次读写的大小，与FUSE默认的最大读写大小相同
+const IO_PAGES: usize = 32;
+
+#[derive(Default)]
+struct Counters {
+    reads: AtomicUsize,
+    writes: AtomicUsize,
+}
+
+impl Counters {
+    fn take(&self) -> (usize, usize) {
+        (
+            self.reads.swap(0, Ordering::Relaxed),
+            self.writes.swap(0, Ordering::Relaxed),
+        )
+    }
+}
+
+struct SlowDevice {
+    inner: MemDevice,
+    counters: Arc<Counters>,
+}
+
+fn delay(pages: usize) {
+    let until = Instant::now() + CALL_LATENCY + PAGE_LATENCY * pages as u32;
+    while Instant::now() < until {
+        spin_loop();
+    }
+}
+
+impl BlockDevice for SlowDevice {
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) {
+        delay(1);
+        self.counters.reads.fetch_add(1, Ordering::Relaxed);
+        self.inner.read_page(page_id, page);
+    }
+
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) {
+        delay(1);
+        self.counters.writes.fetch_add(1, Ordering::Relaxed);
+        self.inner.write_page(page_id, page);
+    }
+
+    fn read_pages(&self, start: PageId, pages: &mut [[u8; PAGE_SIZE]]) {
+        delay(pages.len());
+        self.counters.reads.fetch_add(1, Ordering::Relaxed);
+        self.inner.read_pages(start, pages);
+    }
+
+    fn write_pages(&self, start: PageId, pages: &[[u8; PAGE_SIZE]]) {
+        delay(pages.len());
+        self.counters.writes.fetch_add(1, Ordering::Relaxed);
+        self.inner.write_pages(start, pages);
+    }
+
+    fn page_num(&self) -> usize {
+        self.inner.page_num()
+    }
+
+    fn sync(&self) {
+        self.inner.sync();
+    }
+}
+
+///测量结果：(耗时, 设备读次数, 设备写次数)
+type Sample = (Duration, usize, usize);
+
+///格式化一个新的慢速设备，写入一个FILE_PAGES页的文件并全部写回，返回卷、文件和计数器。
+///flusher被停止，测试中需要写回时手动调用
+fn setup(readahead: usize, write_batch: usize) -> (Volume, Inode, Arc<Counters>) {
+    let counters = Arc::new(Counters::default());
+    let device = SlowDevice {
+        inner: MemDevice::new(DEVICE_PAGES),
+        counters: counters.clone(),
+    };
+    let bpm = ParallelBufferPoolManager::new(4, 32, Box::new(device));
+    bpm.writeback().set_write_batch(write_batch);
+    let layout = Layout::new(DEVICE_PAGES, LayoutOptions::default()).unwrap();
+    let vol = format(Arc::new(bpm), layout, FEATURE_EXTENTS);
+    vol.stop_flusher();
+    vol.readahead.set_max_window(readahead);
+    let mut inode: Inode = unsafe { std::mem::zeroed() };
+    inode.init(&vol, InodeId(1), FileType::REG, 0o644, 0, 0);
+    write_file(&vol, &mut inode);
+    counters.take();
+    (vol, inode, counters)
+}
+
+///按IO_PAGES页一次写满文件，每次写之后写回脏页，返回写回的总耗时
+fn write_file(vol: &Volume, inode: &mut Inode) -> Duration {
+    let chunk: Vec<u8> = (0..IO_PAGES * PAGE_SIZE)
+        .map(|i| (i / PAGE_SIZE) as u8 + 1)
+        .collect();
+    let mut elapsed = Duration::ZERO;
+    for i in 0..FILE_PAGES / IO_PAGES {
+        assert_eq!(inode.write(vol, i * chunk.len(), &chunk), chunk.len());
+        let start = Instant::now();
+        Flusher::new().copy_and_flush(&vol.bpm);
+        elapsed += start.elapsed();
+    }
+    elapsed
+}
+
+fn read_blocks(vol: &Volume, inode: &Inode, blocks: impl Iterator<Item = usize>, pages: usize) {
+    let mut buf = vec![0u8; pages * PAGE_SIZE];
+    for block in blocks {
+        inode.read(vol, block * PAGE_SIZE, &mut buf);
+        assert_eq!(buf[0], (block % IO_PAGES) as u8 + 1);
+    }
+}
+
+fn report(name: &str, off: Sample, on: Sample) {
+    println!(
+        "{name:<12} off {:>8.2?} reads {:>5} writes {:>5} | on {:>8.2?} reads {:>5} writes {:>5} | speedup {:.2}x",
+        off.0,
+        off.1,
+        off.2,
+        on.0,
+        on.1,
+        on.2,
+        off.0.as_secs_f64() / on.0.as_secs_f64()
+    );
+}
+
+fn seq_read(readahead: usize) -> Sample {
+    let (vol, inode, counters) = setup(readahead, 0);
+    let start = Instant::now();
+    read_blocks(&vol, &inode, (0..FILE_PAGES).step_by(IO_PAGES), IO_PAGES);
+    let elapsed = start.elapsed();
+    let (reads, writes) = counters.take();
+    (elapsed, reads, writes)
+}
+
+///顺序读：按IO_PAGES页一次从头读到尾
+#[test]
+#[ignore]
+fn bench_seq_read() {
+    let off = seq_read(0);
+    let on = seq_read(32);
+    report("seq_read", off, on);
+    assert!(on.1 * 4 < off.1);
+}
+
+fn small_seq_read(readahead: usize) -> Sample {
+    let (vol, inode, counters) = setup(readahead, 0);
+    let start = Instant::now();
+    read_blocks(&vol, &inode, 0..FILE_PAGES, 1);
+    let elapsed = start.elapsed();
+    let (reads, writes) = counters.take();
+    (elapsed, reads, writes)
+}
+
+///顺序读：一次读一页
+#[test]
+#[ignore]
+fn bench_small_seq_read() {
+    let off = small_seq_read(0);
+    let on = small_seq_read(32);
+    report("small_read", off, on);
+    assert!(on.1 * 4 < off.1);
+}
+
+fn random_read(readahead: usize) -> Sample {
+    let (vol, inode, counters) = setup(readahead, 0);
+    //固定种子的线性同余序列，两次运行读同样的块
+    let mut x = 12345usize;
+    let blocks = (0..FILE_PAGES).map(|_| {
+        x = x
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (x >> 33) % FILE_PAGES
+    });
+    let start = Instant::now();
+    read_blocks(&vol, &inode, blocks, 1);
+    let elapsed = start.elapsed();
+    let (reads, writes) = counters.take();
+    (elapsed, reads, writes)
+}
+
+///随机读：预读不应该带来额外的设备读
+#[test]
+#[ignore]
+fn bench_random_read() {
+    let off = random_read(0);
+    let on = random_read(32);
+    report("random_read", off, on);
+    assert!(on.1 <= off.1 + off.1 / 10);
+}
+
+fn seq_write(write_batch: usize) -> Sample {
+    let (vol, mut inode, counters) = setup(0, write_batch);
+    let elapsed = write_file(&vol, &mut inode);
+    let (reads, writes) = counters.take();
+    (elapsed, reads, writes)
+}
+
+///顺序覆盖写整个文件，统计写回脏页的耗时
+#[test]
+#[ignore]
+fn bench_seq_write() {
+    let off = seq_write(1);
+    let on = seq_write(0);
+    report("seq_write", off, on);
+    assert!(on.2 * 4 < off.2);
+}
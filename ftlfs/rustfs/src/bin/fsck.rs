@@ -0,0 +1,82 @@
+//! 检查并修复rustfs：
+//!
+//! ```bash
+//! fsck-rustfs [-n | -y] DEVICE
+//! ```
+//!
+//! -n只检查不修改（默认），以只读方式打开设备，也不重放日志；-y重放日志并修复发现的问题。退出码与e2fsck相同：
+//! 0表示没有问题，1表示问题已修复，4表示还有未修复的问题，8表示无法检查
+use rustfs::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+use rustfs::device::{open_device, open_device_read_only};
+use rustfs::fs::fsck::fsck;
+use rustfs::fs::journal;
+use rustfs::fs::superblock::{describe, mount, mount_read_only};
+use std::process::exit;
+use std::sync::Arc;
+
+const USAGE: &str = "usage: fsck-rustfs [-n | -y] DEVICE";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("fsck-rustfs: {msg}");
+    exit(8);
+}
+
+fn main() {
+    env_logger::init();
+    let mut repair = false;
+    let mut device = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-n" => repair = false,
+            "-y" => repair = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ if arg.starts_with('-') || device.is_some() => fail(USAGE),
+            _ => device = Some(arg),
+        }
+    }
+    let Some(spec) = device else { fail(USAGE) };
+    let device = if repair {
+        open_device(&spec)
+    } else {
+        open_device_read_only(&spec)
+    };
+    let device = device.unwrap_or_else(|e| fail(&format!("open {spec} failed: {e}")));
+    let bpm = Arc::new(ParallelBufferPoolManager::new(1, 20, device));
+    let vol = if repair {
+        mount(bpm)
+    } else {
+        mount_read_only(bpm)
+    };
+    let vol = vol.unwrap_or_else(|e| fail(&format!("{spec}: {e}")));
+    if !repair {
+        let pending = journal::pending(&vol).unwrap_or_else(|e| fail(&format!("{spec}: {e}")));
+        if pending > 0 {
+            println!(
+                "{spec}: journal has {pending} transactions not replayed, \
+                 results may be inaccurate"
+            );
+        }
+    }
+    let report = fsck(&vol, repair).unwrap_or_else(|e| fail(&format!("{spec}: {e}")));
+    vol.stop_flusher();
+    println!("{spec}: {}", describe(&vol));
+    print!("{report}");
+    if report.is_clean() {
+        println!("{spec}: clean");
+        exit(0);
+    }
+    if !repair {
+        println!("{spec}: errors found, run with -y to repair");
+        exit(4);
+    }
+    //共享块不会被修复
+    if report.shared_blocks.is_empty() {
+        println!("{spec}: errors repaired");
+        exit(1);
+    }
+    println!("{spec}: errors repaired, shared blocks left unrepaired");
+    exit(4);
+}
@@ -0,0 +1,97 @@
+//! 在块设备上格式化rustfs：
+//!
+//! ```bash
+//! mkfs-rustfs [--inodes N] [--inode-map-pages N] [--data-map-pages N] [--journal-pages N]
+//!             [--data-start PAGE] [--group-blocks N] [--label NAME] [--dir-index] [--extents]
+//!             [--size SIZE] DEVICE
+//! ```
+//!
+//! DEVICE的写法同挂载参数--device。--size用于新建或调整镜像文件的大小，--journal-pages 0表示不记日志
+use rustfs::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+use rustfs::device::{is_memory, open_device, parse_size};
+use rustfs::fs::def::{FEATURE_DIR_INDEX, FEATURE_EXTENTS};
+use rustfs::fs::layout::{Layout, LayoutOptions};
+use rustfs::fs::superblock::{describe, format, set_label};
+use std::process::exit;
+use std::sync::Arc;
+
+const USAGE: &str = "usage: mkfs-rustfs [--inodes N] [--inode-map-pages N] [--data-map-pages N] \
+[--journal-pages N] [--data-start PAGE] [--group-blocks N] [--label NAME] [--dir-index] [--extents] [--size SIZE] DEVICE";
+
+fn fail(msg: &str) -> ! {
+    eprintln!("mkfs-rustfs: {msg}");
+    exit(1);
+}
+
+fn main() {
+    env_logger::init();
+    let mut options = LayoutOptions::default();
+    let mut features = 0;
+    let mut size = None;
+    let mut label = None;
+    let mut device = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| -> usize {
+            let value = args
+                .next()
+                .unwrap_or_else(|| fail(&format!("{name} needs a value")));
+            parse_size(&value).unwrap_or_else(|| fail(&format!("invalid {name} {value}")))
+        };
+        match arg.as_str() {
+            "--inodes" => options.inode_num = Some(value("--inodes")),
+            "--inode-map-pages" => options.inode_map_pages = Some(value("--inode-map-pages")),
+            "--data-map-pages" => options.data_map_pages = Some(value("--data-map-pages")),
+            "--journal-pages" => options.journal_pages = Some(value("--journal-pages")),
+            "--data-start" => options.data_start = Some(value("--data-start")),
+            "--group-blocks" => options.group_blocks = Some(value("--group-blocks")),
+            "--size" => size = Some(value("--size")),
+            "--label" => label = Some(args.next().unwrap_or_else(|| fail(USAGE))),
+            "--dir-index" => features |= FEATURE_DIR_INDEX,
+            "--extents" => features |= FEATURE_EXTENTS,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ if arg.starts_with('-') || device.is_some() => fail(USAGE),
+            _ => device = Some(arg),
+        }
+    }
+    let Some(spec) = device else { fail(USAGE) };
+    if let Some(size) = size {
+        if is_memory(&spec) || spec.starts_with("ddriver:") {
+            fail("--size only applies to image files");
+        }
+        let path = spec.strip_prefix("file:").unwrap_or(&spec);
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_len(size as u64));
+        if let Err(e) = file {
+            fail(&format!("create {path} failed: {e}"));
+        }
+    }
+    let device = open_device(&spec).unwrap_or_else(|e| fail(&format!("open {spec} failed: {e}")));
+    let page_num = device.page_num();
+    let layout = Layout::new(page_num, options).unwrap_or_else(|e| fail(&e));
+    let bpm = ParallelBufferPoolManager::new(1, 20, device);
+    let vol = format(Arc::new(bpm), layout, features);
+    if let Err(e) = set_label(&vol, label.as_deref().unwrap_or("")) {
+        fail(&e);
+    }
+    println!(
+        "{spec}: {page_num} pages, {} inodes, {} journal pages, {} data blocks starting at page {}",
+        layout.inode_num, layout.journal_pages, layout.data_num, layout.data_start
+    );
+    println!(
+        "{spec}: {} groups of {} blocks and {} inodes",
+        layout.data_groups(),
+        layout.group_blocks,
+        layout.group_inodes
+    );
+    println!("{spec}: {}", describe(&vol));
+    //停止flusher时会把所有脏页写回设备
+    vol.stop_flusher();
+}
@@ -1,6 +1,8 @@
+use crate::buffer::flusher::Writeback;
 use crate::buffer::page::{Data, Page};
-use crate::buffer::replacer::{FrameId, LRUReplacer, PageId, Replacer};
-use crate::ddriver::disk::{close_ddriver, init_ddriver, read_page, write_page};
+use crate::buffer::replacer::{AnyReplacer, FrameId, PageId, Replacer};
+use crate::device::BlockDevice;
+use crate::fs::custom::PAGE_SIZE;
 use crate::fs::types::InodeId;
 use crate::utils::defer_guard::{set_flag, DeferGuard};
 use crate::utils::semaphore::Semaphore;
@@ -8,12 +10,12 @@ use libc::free;
 use log::{debug, error, info, trace, warn};
 use parking_lot::Mutex;
 use std::cell::LazyCell;
-use std::collections::{HashMap, LinkedList};
-use std::fmt::{Debug, Formatter};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter, Write};
 use std::ops::{Deref, DerefMut};
 use std::ptr::addr_of_mut;
 use std::slice::IterMut;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
@@ -24,6 +26,43 @@ pub struct BufferPoolManager<R: Replacer<FrameId>> {
     pub instance_index: usize,
     pub inner: Mutex<BPMInner<R>>,
     pub sem: Arc<Semaphore>,
+    pub device: Arc<dyn BlockDevice>,
+    ///所有实例的脏页总数
+    pub dirty_num: Arc<AtomicUsize>,
+    pub writeback: Arc<Writeback>,
+    pub stats: BufferStats,
+}
+
+///缓存池实例的计数器，挂载后只增不减
+#[derive(Debug, Default)]
+pub struct BufferStats {
+    ///要找的页已经在缓存中
+    pub hits: AtomicU64,
+    ///要找的页不在缓存中，需要从设备读入或新建
+    pub misses: AtomicU64,
+    ///为了腾出frame替换掉的页
+    pub evictions: AtomicU64,
+    ///脏页写回设备的次数
+    pub writebacks: AtomicU64,
+    ///所有frame都被pin住或是脏页，fetch_page需要等待信号量的次数
+    pub pin_waits: AtomicU64,
+    ///预读放入缓存的页
+    pub readahead: AtomicU64,
+}
+
+impl BufferStats {
+    pub fn add(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+///某一时刻缓存池实例中frame的使用情况
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Occupancy {
+    ///缓存了页的frame数
+    pub resident: usize,
+    pub pinned: usize,
+    pub dirty: usize,
 }
 
 pub struct BPMInner<R: Replacer<FrameId>> {
@@ -31,14 +70,22 @@ pub struct BPMInner<R: Replacer<FrameId>> {
     pub frames: Vec<Page>,
     pub page_table: HashMap<PageId, FrameId>,
     free_list: Vec<FrameId>,
+    ///从设备读入页的次数，预读用它判断读出的数据是否可能已经过时
+    loads: u64,
 }
 
 impl<R: Replacer<FrameId>> BufferPoolManager<R> {
     ///初始化时，所有frame都在free_list中，replacer为空
-    pub(crate) fn new(pool_size: usize, num_instances: usize, instance_index: usize) -> Self {
-        let mut replacer = R::new(pool_size);
-        let mut frames = Vec::new();
-        frames.reserve(pool_size);
+    pub(crate) fn new(
+        pool_size: usize,
+        num_instances: usize,
+        instance_index: usize,
+        device: Arc<dyn BlockDevice>,
+        dirty_num: Arc<AtomicUsize>,
+        writeback: Arc<Writeback>,
+        replacer: R,
+    ) -> Self {
+        let mut frames = Vec::with_capacity(pool_size);
         for i in 0..pool_size {
             frames.push(Page::default());
         }
@@ -49,6 +96,7 @@ impl<R: Replacer<FrameId>> BufferPoolManager<R> {
             frames,
             page_table,
             free_list,
+            loads: 0,
         };
         BufferPoolManager {
             pool_size,
@@ -56,24 +104,71 @@ impl<R: Replacer<FrameId>> BufferPoolManager<R> {
             instance_index,
             inner: Mutex::new(inner),
             sem: Arc::new(Semaphore::new(pool_size as isize)),
+            device,
+            dirty_num,
+            writeback,
+            stats: BufferStats::default(),
+        }
+    }
+
+    pub fn occupancy(&self) -> Occupancy {
+        let inner = self.inner.lock();
+        let mut occupancy = Occupancy {
+            resident: inner.page_table.len(),
+            ..Default::default()
+        };
+        for page in inner.frames.iter().filter(|page| page.page_id().is_some()) {
+            occupancy.pinned += (page.pin_count() > 0) as usize;
+            occupancy.dirty += page.is_dirty() as usize;
+        }
+        occupancy
+    }
+
+    ///设置frame的脏标记并维护脏页数，调用者持有inner锁
+    pub fn set_dirty(&self, inner: &mut BPMInner<R>, frame_id: FrameId, is_dirty: bool) {
+        let page = &mut inner.frames[frame_id.0];
+        if page.is_dirty() == is_dirty {
+            return;
+        }
+        page.set_is_dirty(is_dirty);
+        if is_dirty {
+            self.dirty_num.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.dirty_num.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    ///脏页数的上限，超过后立即唤醒flusher。未指定时为缓存总页数的一半
+    fn dirty_limit(&self) -> usize {
+        match self.writeback.dirty_limit() {
+            0 => (self.pool_size * self.num_instances / 2).max(1),
+            limit => limit,
         }
     }
 
     pub fn fetch_page(&self, page_id: PageId, is_new: bool) -> Data {
-        self.sem.acquire();
+        if !self.sem.try_acquire() {
+            BufferStats::add(&self.stats.pin_waits);
+            self.sem.acquire();
+        }
         let mut inner = self.inner.lock();
         if let Some(frame_id) = inner.page_table.get(&page_id).cloned() {
+            BufferStats::add(&self.stats.hits);
             let mut page = &mut inner.frames[frame_id.0];
+            //被pin住或者是脏页的frame已经占用了一个信号量，不在replacer中
+            let is_held = page.pin_count() > 0 || page.is_dirty();
             page.increase_pin_count();
             let result = page.data();
-            if page.pin_count() == 1 {
-                inner.replacer.pin(frame_id);
-            } else {
+            if is_held {
                 self.sem.release();
+            } else {
+                inner.replacer.pin(frame_id);
             }
             return result;
         }
 
+        BufferStats::add(&self.stats.misses);
+        inner.loads += 1;
         if let Some(frame_id) = inner.free_list.pop() {
             inner.page_table.insert(page_id, frame_id);
             let page: *mut Page = &mut inner.frames[frame_id.0];
@@ -85,11 +180,13 @@ impl<R: Replacer<FrameId>> BufferPoolManager<R> {
             if is_new {
                 unsafe { data.bytes.fill(0) };
             } else {
-                read_page(page_id, unsafe { &mut data.bytes });
+                self.device.read_page(page_id, unsafe { &mut data.bytes });
             }
             return unsafe { (*page).data() };
         }
-        let frame_id = inner.replacer.victim();
+        //信号量保证没有空闲frame时replacer中至少有一个frame
+        let frame_id = inner.replacer.victim().expect("no frame to evict");
+        BufferStats::add(&self.stats.evictions);
         let page: *mut Page = &mut inner.frames[frame_id.0];
         let victim_page_id = unsafe { (*page).page_id().unwrap() };
         trace!(
@@ -106,10 +203,10 @@ impl<R: Replacer<FrameId>> BufferPoolManager<R> {
         let mut data = unsafe { (*page).data.write() };
         drop(inner);
         if is_new {
-            unsafe { (*data).bytes.fill(0) };
+            unsafe { data.bytes.fill(0) };
         } else {
             trace!("start read page id: {}", page_id.0);
-            read_page(page_id, unsafe { &mut data.bytes });
+            self.device.read_page(page_id, unsafe { &mut data.bytes });
             trace!(
                 "end read page id: {},page data: {:?},page_id = {}",
                 page_id.0,
@@ -120,6 +217,77 @@ impl<R: Replacer<FrameId>> BufferPoolManager<R> {
         result
     }
 
+    ///预读开始前的读入次数，传给install_prefetched
+    fn loads(&self) -> u64 {
+        self.inner.lock().loads
+    }
+
+    fn contains(&self, page_id: PageId) -> bool {
+        self.inner.lock().page_table.contains_key(&page_id)
+    }
+
+    ///把预读出的页放进空闲frame或替换掉一个页，不pin住它，之后与其他未被pin的页一样可以被替换。
+    ///页已经在缓存中、没有可替换的frame，或者读设备之后这个实例又读入过页时放弃，
+    ///后一种情况下那次读入的页可能已经被修改、写回并替换掉，bytes已经过时。返回是否放入
+    fn install_prefetched(&self, page_id: PageId, bytes: &[u8; PAGE_SIZE], loads: u64) -> bool {
+        let mut inner = self.inner.lock();
+        if inner.loads != loads || inner.page_table.contains_key(&page_id) {
+            return false;
+        }
+        //空闲frame和replacer中的frame都不占用信号量，放入后仍然如此，信号量不变
+        let frame_id = match inner.free_list.pop() {
+            Some(frame_id) => frame_id,
+            None => {
+                let Some(frame_id) = inner.replacer.victim() else {
+                    return false;
+                };
+                BufferStats::add(&self.stats.evictions);
+                let victim_page_id = inner.frames[frame_id.0].page_id().unwrap();
+                inner.page_table.remove(&victim_page_id);
+                frame_id
+            }
+        };
+        let page = &mut inner.frames[frame_id.0];
+        page.init_metadata(page_id);
+        page.set_pin_count(0);
+        unsafe { page.data.write().bytes.copy_from_slice(bytes) };
+        inner.page_table.insert(page_id, frame_id);
+        inner.replacer.unpin(frame_id);
+        BufferStats::add(&self.stats.readahead);
+        true
+    }
+
+    ///立即把一个页写回磁盘，页不在缓存中或不是脏页时什么也不做
+    pub fn flush_page(&self, page_id: PageId) {
+        let _flush = self.writeback.lock.lock();
+        let (page, frame_id): (*mut Page, FrameId) = {
+            let mut inner = self.inner.lock();
+            let Some(&frame_id) = inner.page_table.get(&page_id) else {
+                return;
+            };
+            let page = &mut inner.frames[frame_id.0];
+            if !page.is_dirty() {
+                return;
+            }
+            page.increase_pin_count();
+            (page, frame_id)
+        };
+        //与flusher相同，持有读锁时清除脏标记
+        let data = unsafe { (*page).data.read() };
+        let bytes = unsafe { data.bytes };
+        self.set_dirty(&mut self.inner.lock(), frame_id, false);
+        drop(data);
+        self.device.write_page(page_id, &bytes);
+        BufferStats::add(&self.stats.writebacks);
+        let mut inner = self.inner.lock();
+        let page = &mut inner.frames[frame_id.0];
+        page.decrease_pin_count();
+        if page.pin_count() == 0 && !page.is_dirty() {
+            inner.replacer.unpin(frame_id);
+            self.sem.release();
+        }
+    }
+
     pub fn unpin_page(&self, page_id: PageId, is_dirty: bool) {
         trace!("unpin page id: {}", page_id.0);
         let mut inner = self.inner.lock();
@@ -127,18 +295,19 @@ impl<R: Replacer<FrameId>> BufferPoolManager<R> {
         let mut inner = DeferGuard::new(inner, |_| set_flag(0));
         trace!("unpin page id: {},inner lock", page_id.0);
         let frame_id = *inner.page_table.get(&page_id).unwrap();
+        if is_dirty {
+            self.set_dirty(&mut inner, frame_id, true);
+        }
         let page = &mut inner.frames[frame_id.0];
-        if !page.is_dirty() {
-            page.set_is_dirty(is_dirty);
+        page.decrease_pin_count();
+        //脏页在被flusher写回之前不能被替换，写回后由flusher放入replacer
+        if page.pin_count() == 0 && !page.is_dirty() {
+            inner.replacer.unpin(frame_id);
+            self.sem.release();
         }
-        let is_dirty = page.is_dirty();
-
-        if !is_dirty {
-            page.decrease_pin_count();
-            if page.pin_count() == 0 {
-                inner.replacer.unpin(frame_id);
-                self.sem.release();
-            }
+        drop(inner);
+        if is_dirty && self.dirty_num.load(Ordering::Relaxed) > self.dirty_limit() {
+            self.writeback.kick();
         }
     }
 }
@@ -147,25 +316,155 @@ pub struct ParallelBufferPoolManager<R: Replacer<FrameId>> {
     num_instances: usize,
     pool_size: usize,
     pub(crate) instances: Vec<Box<BufferPoolManager<R>>>,
+    device: Arc<dyn BlockDevice>,
+    writeback: Arc<Writeback>,
 }
 
 impl<R: Replacer<FrameId>> ParallelBufferPoolManager<R> {
-    pub fn new(num_instances: usize, pool_size: usize) -> Self {
-        init_ddriver();
-        let mut instances = Vec::new();
-        instances.reserve(num_instances);
+    ///所有实例共享同一个块设备，缓存池析构时设备随之关闭
+    pub fn new(num_instances: usize, pool_size: usize, device: Box<dyn BlockDevice>) -> Self {
+        Self::with_replacer(num_instances, pool_size, device, R::new)
+    }
+
+    ///同new，但每个实例的replacer由new_replacer(pool_size)创建
+    pub fn with_replacer(
+        num_instances: usize,
+        pool_size: usize,
+        device: Box<dyn BlockDevice>,
+        new_replacer: impl Fn(usize) -> R,
+    ) -> Self {
+        let device: Arc<dyn BlockDevice> = Arc::from(device);
+        let dirty_num = Arc::new(AtomicUsize::new(0));
+        let writeback = Arc::new(Writeback::default());
+        let mut instances = Vec::with_capacity(num_instances);
         for i in 0..num_instances {
             instances.push(Box::new(BufferPoolManager::<R>::new(
                 pool_size,
                 num_instances,
                 i,
+                device.clone(),
+                dirty_num.clone(),
+                writeback.clone(),
+                new_replacer(pool_size),
             )));
         }
         Self {
             num_instances,
             pool_size,
             instances,
+            device,
+            writeback,
+        }
+    }
+
+    ///块设备的总页数
+    pub fn page_num(&self) -> usize {
+        self.device.page_num()
+    }
+
+    ///每个实例的frame数
+    pub fn pool_size(&self) -> usize {
+        self.pool_size
+    }
+
+    ///当前的脏页数
+    pub fn dirty_num(&self) -> usize {
+        self.instances[0].dirty_num.load(Ordering::Relaxed)
+    }
+
+    ///绕过缓存直接读写设备，用于日志区
+    pub fn device(&self) -> &dyn BlockDevice {
+        self.device.as_ref()
+    }
+
+    pub fn flush_page(&self, page_id: PageId) {
+        self.page_id_to_instance(page_id).flush_page(page_id)
+    }
+
+    ///各实例共享的写回状态
+    pub fn writeback(&self) -> &Writeback {
+        &self.writeback
+    }
+
+    ///把page_ids中还不在缓存中的页读入缓存但不pin住，连续的页合并成一次设备读。
+    ///最多读入缓存总页数的一半，避免预读把缓存中的页全部挤掉。返回放入缓存的页数
+    pub fn prefetch(&self, page_ids: &[PageId]) -> usize {
+        let mut missing: Vec<usize> = page_ids
+            .iter()
+            .filter(|&&page_id| !self.page_id_to_instance(page_id).contains(page_id))
+            .map(|page_id| page_id.0)
+            .collect();
+        missing.sort_unstable();
+        missing.dedup();
+        missing.truncate((self.pool_size * self.num_instances / 2).max(1));
+        let loads: Vec<u64> = self.instances.iter().map(|bpm| bpm.loads()).collect();
+        let mut installed = 0;
+        for run in missing.chunk_by(|a, b| a + 1 == *b) {
+            let mut pages = vec![[0u8; PAGE_SIZE]; run.len()];
+            self.device.read_pages(PageId(run[0]), &mut pages);
+            for (&page_id, page) in run.iter().zip(pages.iter()) {
+                let bpm = self.page_id_to_instance(PageId(page_id));
+                installed +=
+                    bpm.install_prefetched(PageId(page_id), page, loads[bpm.instance_index])
+                        as usize;
+            }
         }
+        installed
+    }
+
+    ///各实例的统计信息，每行一项，格式为"名字 值"，供/.rustfs/stats读出
+    pub fn stats_report(&self) -> String {
+        let mut report = String::new();
+        let mut totals = [0u64; 6];
+        let mut lines = String::new();
+        for bpm in self.instances.iter() {
+            let stats = &bpm.stats;
+            let counters = [
+                &stats.hits,
+                &stats.misses,
+                &stats.evictions,
+                &stats.writebacks,
+                &stats.pin_waits,
+                &stats.readahead,
+            ]
+            .map(|counter| counter.load(Ordering::Relaxed));
+            for (total, counter) in totals.iter_mut().zip(counters) {
+                *total += counter;
+            }
+            let occupancy = bpm.occupancy();
+            let _ = writeln!(
+                lines,
+                "instance{} frames {} resident {} pinned {} dirty {} hits {} misses {} evictions {} writebacks {} pin_waits {} readahead {}",
+                bpm.instance_index,
+                self.pool_size,
+                occupancy.resident,
+                occupancy.pinned,
+                occupancy.dirty,
+                counters[0],
+                counters[1],
+                counters[2],
+                counters[3],
+                counters[4],
+                counters[5]
+            );
+        }
+        let [hits, misses, evictions, writebacks, pin_waits, readahead] = totals;
+        let hit_ratio = if hits + misses == 0 {
+            0.0
+        } else {
+            hits as f64 / (hits + misses) as f64
+        };
+        let _ = writeln!(report, "instances {}", self.num_instances);
+        let _ = writeln!(report, "frames {}", self.num_instances * self.pool_size);
+        let _ = writeln!(report, "hits {hits}");
+        let _ = writeln!(report, "misses {misses}");
+        let _ = writeln!(report, "hit_ratio {hit_ratio:.4}");
+        let _ = writeln!(report, "evictions {evictions}");
+        let _ = writeln!(report, "writebacks {writebacks}");
+        let _ = writeln!(report, "pin_waits {pin_waits}");
+        let _ = writeln!(report, "readahead {readahead}");
+        let _ = writeln!(report, "dirty {}", self.dirty_num());
+        report + &lines
     }
 
     ///采用直接映射的方式把页分散到不同的buffer pool中
@@ -191,84 +490,122 @@ impl<R: Replacer<FrameId>> ParallelBufferPoolManager<R> {
     }
 }
 
-impl<R: Replacer<FrameId>> Drop for ParallelBufferPoolManager<R> {
-    fn drop(&mut self) {
-        close_ddriver();
+///文件系统使用的缓存池，替换算法在挂载时选择
+pub type Bpm = ParallelBufferPoolManager<AnyReplacer<FrameId>>;
+
+///fetch_page_write等宏访问页的入口：缓存池本身，或者修改页之前要通知日志的卷
+pub trait PageSource {
+    fn bpm(&self) -> &Bpm;
+
+    ///页即将被修改
+    fn note_write(&self, _page_id: usize) {}
+}
+
+impl PageSource for Bpm {
+    fn bpm(&self) -> &Bpm {
+        self
     }
 }
 
-pub static mut BPM: Option<ParallelBufferPoolManager<LRUReplacer<FrameId>>> = None;
+impl<T: PageSource> PageSource for Arc<T> {
+    fn bpm(&self) -> &Bpm {
+        (**self).bpm()
+    }
+
+    fn note_write(&self, page_id: usize) {
+        (**self).note_write(page_id);
+    }
+}
 
-pub struct AutoUnpin {
+///测试用：以全新内存磁盘为设备的缓存池
+#[cfg(test)]
+pub fn mem_bpm(num_instances: usize, pool_size: usize) -> Arc<Bpm> {
+    Arc::new(ParallelBufferPoolManager::new(
+        num_instances,
+        pool_size,
+        Box::new(crate::device::MemDevice::new(1024)),
+    ))
+}
+
+pub struct AutoUnpin<'a> {
+    bpm: &'a Bpm,
     page_id: usize,
     is_dirty: bool,
 }
 
-impl AutoUnpin {
-    pub fn new(page_id: usize, is_dirty: bool) -> Self {
-        AutoUnpin { page_id, is_dirty }
+impl<'a> AutoUnpin<'a> {
+    pub fn new(bpm: &'a Bpm, page_id: usize, is_dirty: bool) -> Self {
+        AutoUnpin {
+            bpm,
+            page_id,
+            is_dirty,
+        }
     }
 }
 
-impl Drop for AutoUnpin {
+impl Drop for AutoUnpin<'_> {
     fn drop(&mut self) {
-        unsafe {
-            info!("unpin page id: {}", self.page_id);
-            BPM.as_mut()
-                .unwrap()
-                .unpin_page(PageId(self.page_id), self.is_dirty);
-        }
+        info!("unpin page id: {}", self.page_id);
+        self.bpm.unpin_page(PageId(self.page_id), self.is_dirty);
     }
 }
 
+///$bpm是实现了PageSource的引用：缓存池，或者修改页之前要通知日志的卷
 #[macro_export]
 macro_rules! fetch_page_write {
     ($var:ident:$page_type:ident,$bpm:ident,$page_id: expr,$auto_unpin:ident) => {
+        $crate::buffer::buffer_pool_manager::PageSource::note_write($bpm, $page_id);
         debug!(
             "fetch_page_write: page_id={},page_type = {},name = {}",
             $page_id,
             stringify!($page_type),
             stringify!($var)
         );
-        let $var = $bpm.fetch_page(PageId($page_id), false);
+        let pool = $crate::buffer::buffer_pool_manager::PageSource::bpm($bpm);
+        let $var = pool.fetch_page(PageId($page_id), false);
         let mut $var = unsafe { (*$var).write() };
         let $var = unsafe { &mut $var.$page_type };
-        let $auto_unpin = AutoUnpin::new($page_id, true);
+        let $auto_unpin = AutoUnpin::new(pool, $page_id, true);
     };
 }
 
 #[macro_export]
 macro_rules! fetch_page_write_lk {
     ($var:ident:$page_type:ident,$bpm:ident,$page_id: expr,$auto_unpin:ident,$lk:ident) => {
+        $crate::buffer::buffer_pool_manager::PageSource::note_write($bpm, $page_id);
         debug!(
             "fetch_page_write: page_id={},page_type = {},name = {}",
             $page_id,
             stringify!($page_type),
             stringify!($var)
         );
-        let $lk = $bpm.fetch_page(PageId($page_id), false);
+        let pool = $crate::buffer::buffer_pool_manager::PageSource::bpm($bpm);
+        let $lk = pool.fetch_page(PageId($page_id), false);
         let mut $lk = unsafe { (*$lk).write() };
         let $var = unsafe { &mut $lk.$page_type };
-        let $auto_unpin = AutoUnpin::new($page_id, true);
+        let $auto_unpin = AutoUnpin::new(pool, $page_id, true);
     };
 }
 
 #[macro_export]
 macro_rules! fetch_page_read {
     ($var:ident:$page_type:ident,$bpm:ident,$page_id: expr,$auto_unpin:ident) => {
-        let $var = $bpm.fetch_page(PageId($page_id), false);
+        let pool = $crate::buffer::buffer_pool_manager::PageSource::bpm($bpm);
+        let $var = pool.fetch_page(PageId($page_id), false);
         let $var = unsafe { (*$var).read() };
         let $var = unsafe { &$var.$page_type };
-        let $auto_unpin = AutoUnpin::new($page_id, false);
+        let $auto_unpin = AutoUnpin::new(pool, $page_id, false);
     };
 }
 
 #[macro_export]
 macro_rules! new_page {
     ($var:ident:$page_type:ident,$bpm:ident,$page_id:expr,$auto_unpin:ident) => {
-        let $var = $bpm.fetch_page(PageId($page_id), true);
+        $crate::buffer::buffer_pool_manager::PageSource::note_write($bpm, $page_id);
+        let pool = $crate::buffer::buffer_pool_manager::PageSource::bpm($bpm);
+        let $var = pool.fetch_page(PageId($page_id), true);
         let mut $var = unsafe { (*$var).write() };
         let $var = unsafe { &mut $var.$page_type };
-        let $auto_unpin = AutoUnpin::new($page_id, true);
+        let $auto_unpin = AutoUnpin::new(pool, $page_id, true);
     };
 }
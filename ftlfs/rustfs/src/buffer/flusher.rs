@@ -1,58 +1,254 @@
-use crate::buffer::buffer_pool_manager::BPM;
+use crate::buffer::buffer_pool_manager::{Bpm, BufferStats, ParallelBufferPoolManager};
 use crate::buffer::page::{Page, PageUnion};
-use crate::buffer::replacer::{PageId, Replacer};
-use crate::ddriver::disk::write_page;
+use crate::buffer::replacer::{FrameId, PageId, Replacer};
 use crate::fs::custom::PAGE_SIZE;
 use crate::utils::defer_guard::{set_flag, DeferGuard, FLAG};
 use crate::utils::semaphore::Semaphore;
 use log::{error, info, trace, warn};
+use parking_lot::{Condvar, Mutex};
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 pub struct Flusher {
-    pages: Vec<([u8; PAGE_SIZE], PageId)>,
+    ///(页的内容, 页号, frame, 所在的缓存池实例)
+    pages: Vec<([u8; PAGE_SIZE], PageId, FrameId, usize)>,
 }
 
-pub static mut FLUSHER: Flusher = Flusher::new();
+///缓存池各实例共享的写回状态
+#[derive(Default)]
+pub struct Writeback {
+    ///同一时刻只允许一路写回。两路并发时，先拷贝的旧数据可能在新数据之后才写到磁盘上
+    pub lock: Mutex<()>,
+    ///flusher被提前唤醒的标记
+    kicked: Mutex<bool>,
+    wakeup: Condvar,
+    ///属于未提交事务的页，不能写回原处，见journal
+    deferred: Mutex<BTreeSet<usize>>,
+    ///脏页数的上限，为0时取缓存总页数的一半
+    dirty_limit: AtomicUsize,
+    ///一次设备写最多合并的页数，为0时取默认值，为1时不合并
+    write_batch: AtomicUsize,
+}
+
+impl Writeback {
+    ///立即唤醒flusher，脏页超过上限时调用
+    pub fn kick(&self) {
+        *self.kicked.lock() = true;
+        self.wakeup.notify_one();
+    }
+
+    ///等待被唤醒或超时，timeout为None时一直等待
+    fn wait(&self, timeout: Option<Duration>) {
+        let mut kicked = self.kicked.lock();
+        if !*kicked {
+            match timeout {
+                Some(timeout) => {
+                    self.wakeup.wait_for(&mut kicked, timeout);
+                }
+                None => self.wakeup.wait(&mut kicked),
+            }
+        }
+        *kicked = false;
+    }
+
+    pub fn dirty_limit(&self) -> usize {
+        self.dirty_limit.load(Ordering::Relaxed)
+    }
+
+    pub fn set_dirty_limit(&self, limit: usize) {
+        self.dirty_limit.store(limit, Ordering::Relaxed);
+    }
+
+    pub fn set_write_batch(&self, batch: usize) {
+        self.write_batch.store(batch, Ordering::Relaxed);
+    }
+
+    ///一次设备写最多合并的页数
+    fn write_batch(&self) -> usize {
+        match self.write_batch.load(Ordering::Relaxed) {
+            0 => 64,
+            batch => batch,
+        }
+    }
+
+    ///推迟页的写回，直到allow
+    pub fn defer(&self, page_id: usize) {
+        self.deferred.lock().insert(page_id);
+    }
+
+    pub fn allow<'a>(&self, page_ids: impl IntoIterator<Item = &'a usize>) {
+        let mut deferred = self.deferred.lock();
+        for page_id in page_ids {
+            deferred.remove(page_id);
+        }
+    }
+
+    pub fn is_deferred(&self, page_id: usize) -> bool {
+        self.deferred.lock().contains(&page_id)
+    }
+
+    ///是否有推迟写回的页
+    pub fn has_deferred(&self) -> bool {
+        !self.deferred.lock().is_empty()
+    }
+}
+
+impl Default for Flusher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Flusher {
     pub const fn new() -> Self {
         Flusher { pages: Vec::new() }
     }
 
-    pub fn copy_and_flush(&mut self) {
-        let p_bpm = unsafe { BPM.as_ref().unwrap() };
-        for bpm in p_bpm.instances.iter() {
-            let mut dirty_pages: Vec<*mut Page> = Vec::new();
+    ///把所有脏页写回磁盘。写回期间flusher持有脏页的一个pin，防止它被替换；
+    ///拷贝数据时持有页的读锁，此时不会有写者修改页，所以可以安全地清除脏标记。
+    ///推迟写回的页（见journal）留在缓存中。所有实例的脏页拷贝完后按页号排序，
+    ///连续的页合并成一次设备写。返回因为正被写者持有而跳过的页数
+    pub fn copy_and_flush<R: Replacer<FrameId>>(
+        &mut self,
+        p_bpm: &ParallelBufferPoolManager<R>,
+    ) -> usize {
+        self.flush(p_bpm, false)
+    }
+
+    ///同copy_and_flush，但等待写者放开页而不是跳过，返回时除推迟写回的页外没有脏页。
+    ///调用者不能持有任何页，持有页写锁的线程也不能在等待调用者，日志的检查点满足这两点
+    pub fn flush_all<R: Replacer<FrameId>>(&mut self, p_bpm: &ParallelBufferPoolManager<R>) {
+        self.flush(p_bpm, true);
+    }
+
+    fn flush<R: Replacer<FrameId>>(
+        &mut self,
+        p_bpm: &ParallelBufferPoolManager<R>,
+        wait: bool,
+    ) -> usize {
+        let writeback = p_bpm.writeback();
+        let _flush = writeback.lock.lock();
+        let mut skipped = 0;
+        for (index, bpm) in p_bpm.instances.iter().enumerate() {
+            let mut dirty_pages: Vec<(*mut Page, FrameId)> = Vec::new();
             let mut inner_lk = bpm.inner.lock();
             let mut inner = &mut *inner_lk;
-            for page in inner.frames.iter_mut() {
+            for (i, page) in inner.frames.iter_mut().enumerate() {
                 if page.is_dirty() {
-                    dirty_pages.push(page);
-                    // page.set_is_dirty(false);
-                    // let page_id = page.page_id().unwrap();
-                    // let data = page.data.read();
-                    // self.pages.push((unsafe { data.bytes.clone() }, page_id));
+                    page.increase_pin_count();
+                    dirty_pages.push((page, FrameId(i)));
                 }
             }
             drop(inner_lk);
-            for page in dirty_pages {
-                let page_id = unsafe { (*page).page_id().unwrap() };
-                let data = unsafe { (*page).data.read() };
-                self.pages.push((unsafe { data.bytes }, page_id));
-                unsafe { (*page).set_is_dirty(false) };
+            for (page, frame_id) in dirty_pages {
+                //不等待时跳过正被写者持有的页，留到下一轮再写回，
+                //避免持有页的操作在等待空闲frame时死锁
+                let data = if wait {
+                    Some(unsafe { (*page).data.read() })
+                } else {
+                    unsafe { (*page).data.try_read() }
+                };
+                let Some(data) = data else {
+                    let mut inner = bpm.inner.lock();
+                    inner.frames[frame_id.0].decrease_pin_count();
+                    skipped += 1;
+                    continue;
+                };
+                let page_id = {
+                    let mut inner = bpm.inner.lock();
+                    let page_id = inner.frames[frame_id.0].page_id().unwrap();
+                    //事务提交前页不能写回原处，持有读锁时检查，事务之后的修改一定要先拿到写锁
+                    if writeback.is_deferred(page_id.0) {
+                        inner.frames[frame_id.0].decrease_pin_count();
+                        continue;
+                    }
+                    bpm.set_dirty(&mut inner, frame_id, false);
+                    page_id
+                };
+                self.pages
+                    .push((unsafe { data.bytes }, page_id, frame_id, index));
+            }
+        }
+        self.pages
+            .sort_unstable_by_key(|(_, page_id, _, _)| page_id.0);
+        let batch = writeback.write_batch();
+        let mut run: Vec<[u8; PAGE_SIZE]> = Vec::with_capacity(batch);
+        for (i, (data, page_id, _, _)) in self.pages.iter().enumerate() {
+            run.push(*data);
+            let next = self.pages.get(i + 1).map(|(_, next, _, _)| next.0);
+            if run.len() == batch || next != Some(page_id.0 + 1) {
+                p_bpm
+                    .device()
+                    .write_pages(PageId(page_id.0 + 1 - run.len()), &run);
+                run.clear();
             }
-            for (data, page_id) in self.pages.iter() {
-                write_page(*page_id, data);
-                let mut inner = &mut *bpm.inner.lock();
-                set_flag(2);
-                let mut inner = DeferGuard::new(inner, |_| set_flag(0));
-                let frame_id = *inner.page_table.get(page_id).unwrap();
-                inner.replacer.unpin(frame_id);
-                inner.frames[frame_id.0].set_pin_count(0);
+        }
+        for (_, _, frame_id, index) in self.pages.iter() {
+            let bpm = &p_bpm.instances[*index];
+            BufferStats::add(&bpm.stats.writebacks);
+            let mut inner = &mut *bpm.inner.lock();
+            set_flag(2);
+            let mut inner = DeferGuard::new(inner, |_| set_flag(0));
+            let page = &mut inner.frames[frame_id.0];
+            page.decrease_pin_count();
+            if page.pin_count() == 0 && !page.is_dirty() {
+                inner.replacer.unpin(*frame_id);
                 bpm.sem.release();
             }
-            self.pages.clear();
+        }
+        self.pages.clear();
+        skipped
+    }
+}
+
+///后台flusher线程，每隔interval毫秒写回一次脏页，为0时只在被唤醒时写回。
+///被丢弃时停止，退出前把剩下的脏页写回
+pub struct FlusherThread {
+    bpm: Arc<Bpm>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FlusherThread {
+    pub fn start(bpm: Arc<Bpm>, interval: u32) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let pool = bpm.clone();
+        let handle = std::thread::spawn(move || {
+            warn!("flusher tid:{}", unsafe { libc::gettid() });
+            let mut flusher = Flusher::new();
+            let mut skipped = 0;
+            while !stopped.load(Ordering::Acquire) {
+                //上一轮有页正被写者持有时尽快重试，唤醒flusher的写者往往还没放开页
+                let timeout = if skipped > 0 {
+                    Some(Duration::from_millis(1))
+                } else if interval == 0 {
+                    None
+                } else {
+                    Some(Duration::from_millis(interval as u64))
+                };
+                pool.writeback().wait(timeout);
+                skipped = flusher.copy_and_flush(&pool);
+            }
+            flusher.copy_and_flush(&pool);
+        });
+        FlusherThread {
+            bpm,
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for FlusherThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        self.bpm.writeback().kick();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
         }
     }
 }
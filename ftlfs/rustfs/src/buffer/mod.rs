@@ -5,11 +5,9 @@ pub mod replacer;
 
 #[cfg(test)]
 mod test {
-    use crate::buffer::buffer_pool_manager::AutoUnpin;
-    use crate::buffer::buffer_pool_manager::{ParallelBufferPoolManager, BPM};
-    use crate::buffer::flusher::FLUSHER;
+    use crate::buffer::buffer_pool_manager::{mem_bpm, AutoUnpin};
+    use crate::buffer::flusher::FlusherThread;
     use crate::buffer::replacer::PageId;
-    use crate::fs::utils::start_flusher;
     use crate::{fetch_page_read, fetch_page_write, new_page};
     use libc::bind;
     use log::{debug, error, info, trace, warn};
@@ -20,13 +18,15 @@ mod test {
     #[test]
     fn test() {
         env_logger::init();
-        unsafe { BPM = Some(ParallelBufferPoolManager::new(5, 2)) };
-        start_flusher();
+        let pool = mem_bpm(5, 2);
+        //新建的页都是脏页，写回之后才能被替换
+        let _flusher = FlusherThread::start(pool.clone(), 10);
         let nthreads = 12;
         let mut handles = Vec::new();
         for i in 0..nthreads {
+            let pool = pool.clone();
             let handle = std::thread::spawn(move || {
-                let bpm = unsafe { BPM.as_ref().unwrap() };
+                let bpm = &*pool;
                 trace!("new page thread {} start", i);
                 new_page!(new_page: bytes, bpm, i, auto_unpin);
                 new_page[0] = i as u8;
@@ -38,8 +38,9 @@ mod test {
         }
         let mut handles = Vec::new();
         for i in 0..nthreads {
+            let pool = pool.clone();
             let handle = std::thread::spawn(move || {
-                let bpm = unsafe { BPM.as_ref().unwrap() };
+                let bpm = &*pool;
                 trace!("fetch page thread {} start", i);
                 fetch_page_read!(page: bytes, bpm, i, auto_unpin);
                 assert_eq!(page[0], i as u8);
@@ -51,8 +52,9 @@ mod test {
         }
         let mut handles = Vec::new();
         for i in 0..nthreads {
+            let pool = pool.clone();
             let handle = std::thread::spawn(move || {
-                let bpm = unsafe { BPM.as_ref().unwrap() };
+                let bpm = &*pool;
                 fetch_page_write!(page: bytes, bpm, i, auto_unpin);
                 page[0] = 6;
             });
@@ -63,8 +65,9 @@ mod test {
         }
         let mut handles = Vec::new();
         for i in 0..nthreads {
+            let pool = pool.clone();
             let handle = std::thread::spawn(move || {
-                let bpm = unsafe { BPM.as_ref().unwrap() };
+                let bpm = &*pool;
                 fetch_page_read!(page: bytes, bpm, i, auto_unpin);
                 assert_eq!(page[0], 6);
             });
@@ -74,4 +77,204 @@ mod test {
             handle.join().unwrap();
         }
     }
+
+    ///每种替换算法下多个线程反复读写远多于缓存容量的页，写入不能丢失，读到的页不能错位
+    #[test]
+    fn test_replacers_stress() {
+        use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+        use crate::buffer::replacer::{AnyReplacer, FrameId, ReplacerKind};
+        use crate::device::MemDevice;
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        const PAGES: usize = 64;
+        for kind in [
+            ReplacerKind::Lru,
+            ReplacerKind::Clock,
+            ReplacerKind::LruK,
+            ReplacerKind::TwoQ,
+        ] {
+            let device = Box::new(MemDevice::new(PAGES));
+            let bpm: ParallelBufferPoolManager<AnyReplacer<FrameId>> =
+                ParallelBufferPoolManager::with_replacer(2, 3, device, |pool_size| {
+                    kind.build(pool_size)
+                });
+            let writes: Vec<AtomicU32> = (0..PAGES).map(|_| AtomicU32::new(0)).collect();
+            std::thread::scope(|s| {
+                for t in 0..8u32 {
+                    let (bpm, writes) = (&bpm, &writes);
+                    s.spawn(move || {
+                        let mut seed = t * 7919 + 1;
+                        for _ in 0..2000 {
+                            seed ^= seed << 13;
+                            seed ^= seed >> 17;
+                            seed ^= seed << 5;
+                            let page_id = PageId(seed as usize % PAGES);
+                            let is_dirty = (seed / PAGES as u32).is_multiple_of(4);
+                            let data = bpm.fetch_page(page_id, false);
+                            if is_dirty {
+                                let mut page = unsafe { (*data).write() };
+                                let bytes = unsafe { &mut page.bytes };
+                                bytes[..8].copy_from_slice(&page_id.0.to_le_bytes());
+                                let count = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+                                bytes[8..12].copy_from_slice(&(count + 1).to_le_bytes());
+                                writes[page_id.0].fetch_add(1, Ordering::Relaxed);
+                            } else {
+                                let page = unsafe { (*data).read() };
+                                let bytes = unsafe { &page.bytes };
+                                let tag = usize::from_le_bytes(bytes[..8].try_into().unwrap());
+                                assert!(tag == 0 || tag == page_id.0, "{kind:?}");
+                            }
+                            bpm.unpin_page(page_id, is_dirty);
+                            if is_dirty {
+                                bpm.flush_page(page_id);
+                            }
+                        }
+                    });
+                }
+            });
+            let mut bytes = [0u8; 4096];
+            for (i, writes) in writes.iter().enumerate() {
+                bpm.device().read_page(PageId(i), &mut bytes);
+                let count = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+                assert_eq!(count, writes.load(Ordering::Relaxed), "{kind:?} page {i}");
+            }
+        }
+    }
+
+    ///脏页在写回之前不会被替换：所有frame都是脏页时读入新页要等待，
+    ///flusher写回后才能替换；正被写者持有的脏页被flusher跳过，留在缓存中
+    #[test]
+    fn test_dirty_eviction() {
+        use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+        use crate::buffer::flusher::Flusher;
+        use crate::buffer::replacer::{AnyReplacer, FrameId};
+        use crate::device::MemDevice;
+        use std::sync::atomic::Ordering;
+
+        let bpm: ParallelBufferPoolManager<AnyReplacer<FrameId>> =
+            ParallelBufferPoolManager::new(1, 2, Box::new(MemDevice::new(16)));
+        let bpm = &bpm;
+        let stats = &bpm.instances[0].stats;
+        let dirty = |page_id: usize| {
+            let data = bpm.fetch_page(PageId(page_id), true);
+            unsafe { (*data).write().bytes[0] = page_id as u8 + 1 };
+            bpm.unpin_page(PageId(page_id), true);
+        };
+        //页0是被pin住并持有写锁的脏页，页1是没有被pin的脏页
+        dirty(0);
+        let data = bpm.fetch_page(PageId(0), false);
+        let lock = unsafe { (*data).write() };
+        dirty(1);
+        let mut bytes = [0u8; 4096];
+        std::thread::scope(|s| {
+            let fetch = s.spawn(|| {
+                bpm.fetch_page(PageId(2), false);
+                bpm.unpin_page(PageId(2), false);
+            });
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(!fetch.is_finished());
+            assert_eq!(stats.evictions.load(Ordering::Relaxed), 0);
+            assert_eq!(Flusher::new().copy_and_flush(bpm), 1);
+            fetch.join().unwrap();
+        });
+        assert_eq!(stats.pin_waits.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.evictions.load(Ordering::Relaxed), 1);
+        //被替换的页1已经写回，页0还没有
+        bpm.device().read_page(PageId(1), &mut bytes);
+        assert_eq!(bytes[0], 2);
+        bpm.device().read_page(PageId(0), &mut bytes);
+        assert_eq!(bytes[0], 0);
+        drop(lock);
+        bpm.unpin_page(PageId(0), false);
+        assert_eq!(Flusher::new().copy_and_flush(bpm), 0);
+        bpm.device().read_page(PageId(0), &mut bytes);
+        assert_eq!(bytes[0], 1);
+        let data = bpm.fetch_page(PageId(1), false);
+        assert_eq!(unsafe { (*data).read().bytes[0] }, 2);
+        bpm.unpin_page(PageId(1), false);
+    }
+
+    #[test]
+    fn test_stats() {
+        use crate::buffer::buffer_pool_manager::{Occupancy, ParallelBufferPoolManager};
+        use crate::buffer::replacer::{AnyReplacer, FrameId};
+        use crate::device::MemDevice;
+        use std::sync::atomic::Ordering;
+
+        let device = Box::new(MemDevice::new(16));
+        let bpm: ParallelBufferPoolManager<AnyReplacer<FrameId>> =
+            ParallelBufferPoolManager::new(1, 2, device);
+        let stats = &bpm.instances[0].stats;
+        for page_id in [0, 1, 0, 2] {
+            bpm.fetch_page(PageId(page_id), false);
+            bpm.unpin_page(PageId(page_id), page_id == 2);
+        }
+        assert_eq!(stats.hits.load(Ordering::Relaxed), 1);
+        assert_eq!(stats.misses.load(Ordering::Relaxed), 3);
+        assert_eq!(stats.evictions.load(Ordering::Relaxed), 1);
+        let occupancy = bpm.instances[0].occupancy();
+        assert_eq!(
+            occupancy,
+            Occupancy {
+                resident: 2,
+                pinned: 0,
+                dirty: 1
+            }
+        );
+        bpm.flush_page(PageId(2));
+        assert_eq!(stats.writebacks.load(Ordering::Relaxed), 1);
+
+        //两个frame都被pin住时第三个页要等待
+        bpm.fetch_page(PageId(3), false);
+        bpm.fetch_page(PageId(4), false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(100));
+                bpm.unpin_page(PageId(3), false);
+            });
+            bpm.fetch_page(PageId(5), false);
+        });
+        assert_eq!(stats.pin_waits.load(Ordering::Relaxed), 1);
+        let report = bpm.stats_report();
+        assert!(report.contains("\nhits 1\nmisses 6\nhit_ratio 0.1429\nevictions 4\n"));
+        assert!(report.contains("instance0 frames 2 resident 2 pinned 2 dirty 0 "));
+    }
+
+    #[test]
+    fn test_prefetch() {
+        use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+        use crate::buffer::replacer::{AnyReplacer, FrameId};
+        use crate::device::MemDevice;
+        use crate::fs::custom::PAGE_SIZE;
+        use std::sync::atomic::Ordering;
+
+        let pages = (0..16).map(|i| [i as u8; PAGE_SIZE]).collect();
+        let bpm: ParallelBufferPoolManager<AnyReplacer<FrameId>> =
+            ParallelBufferPoolManager::new(2, 4, Box::new(MemDevice::from_pages(pages)));
+        //已经在缓存中的脏页不会被设备上的旧数据覆盖
+        let data = bpm.fetch_page(PageId(1), false);
+        unsafe { (*data).write().bytes[0] = 100 };
+        bpm.unpin_page(PageId(1), true);
+        //最多预读缓存总页数的一半
+        let page_ids: Vec<PageId> = (0..8).map(PageId).collect();
+        assert_eq!(bpm.prefetch(&page_ids), 4);
+        let readahead = |i: usize| bpm.instances[i].stats.readahead.load(Ordering::Relaxed);
+        assert_eq!(readahead(0) + readahead(1), 4);
+        for page_id in 0..5 {
+            let data = bpm.fetch_page(PageId(page_id), false);
+            let byte = unsafe { (*data).read().bytes[0] };
+            assert_eq!(byte, if page_id == 1 { 100 } else { page_id as u8 });
+            bpm.unpin_page(PageId(page_id), false);
+        }
+        let hits: u64 = (0..2)
+            .map(|i| bpm.instances[i].stats.hits.load(Ordering::Relaxed))
+            .sum();
+        assert_eq!(hits, 5);
+        //预读的页没有被pin住，可以被替换
+        for page_id in 8..16 {
+            bpm.fetch_page(PageId(page_id), false);
+            bpm.unpin_page(PageId(page_id), false);
+        }
+        assert!(bpm.stats_report().contains("\nreadahead 4\n"));
+    }
 }
@@ -1,6 +1,7 @@
 use crate::buffer::replacer::PageId;
-use crate::ddriver::disk::read_page;
 use crate::fs::custom::PAGE_SIZE;
+use crate::fs::extent::{Extent, ExtentHeader, EXTENT_PER_PAGE};
+use crate::fs::layout::Layout;
 use crate::fs::types::{BitMap, DEntry, Inode};
 use log::debug;
 use parking_lot::RwLock;
@@ -25,6 +26,10 @@ pub union PageUnion {
     pub super_page: SuperPage,
     pub inode_page: InodePage,
     pub dir_page: DirPage,
+    pub index_page: IndexPage,
+    pub dx_page: DxPage,
+    pub xattr_page: XattrPage,
+    pub extent_page: ExtentPage,
 }
 
 impl Default for Page {
@@ -78,9 +83,9 @@ impl Page {
     }
 
     pub fn reset_data(&mut self) {
-        self.data = Box::new(RwLock::new(PageUnion {
+        *self.data = RwLock::new(PageUnion {
             bytes: ([0u8; PAGE_SIZE]),
-        }));
+        });
     }
 
     pub fn init_metadata(&mut self, page_id: PageId) {
@@ -115,14 +120,85 @@ impl DirPage {
     }
 }
 
+//4096 / 4 = 1024，间接索引页，每一项为数据块号，-1表示未分配
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct IndexPage {
+    pub index: [i32; 1024],
+}
+
+//(4096 - 8) / 8 = 511，哈希目录的索引页，entries按哈希值升序排列，
+//第i项指向哈希值不小于entries[i].hash的那部分目录项所在的块
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct DxPage {
+    ///根索引页之下还有几层索引页，只在根索引页中有意义
+    pub depth: u32,
+    pub count: u32,
+    pub entries: [DxEntry; 511],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct DxEntry {
+    pub hash: u32,
+    ///目录内的逻辑块号
+    pub block: u32,
+}
+
+//4096 - 8 = 4088，扩展属性块，entries中依次紧密存放count个属性，格式见fs::xattr
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XattrPage {
+    pub magic: u32,
+    pub count: u32,
+    pub entries: [u8; PAGE_SIZE - 8],
+}
+
+//8 + 340 * 12 = 4088，extent树的节点块，格式见fs::extent
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ExtentPage {
+    pub header: ExtentHeader,
+    pub entries: [Extent; EXTENT_PER_PAGE],
+    blank: [u8; 8],
+}
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct SuperPage {
     magic_num: u32,
     sz_usage: u32,
-    blank: [u8; 4088],
+    version: u32,
+    features: u32,
+    //版本3起保存磁盘布局，见Layout
+    inode_map_start: u32,
+    inode_map_pages: u32,
+    data_map_start: u32,
+    data_map_pages: u32,
+    inode_start: u32,
+    inode_num: u32,
+    data_start: u32,
+    data_num: u32,
+    //版本4起保存以下字段
+    block_size: u32,
+    free_inodes: u32,
+    free_blocks: u32,
+    mount_count: u32,
+    uuid: [u8; 16],
+    label: [u8; LABEL_LEN],
+    //版本5起保存日志区的位置
+    journal_start: u32,
+    journal_pages: u32,
+    //块组大小，旧版本格式化的磁盘为0，挂载时按默认值计算
+    group_blocks: u32,
+    group_inodes: u32,
+    blank: [u8; 3968],
 }
 
+///卷标的最大长度
+pub const LABEL_LEN: usize = 32;
+
 impl SuperPage {
     pub fn magic_num(&self) -> u32 {
         self.magic_num
@@ -132,6 +208,106 @@ impl SuperPage {
         self.magic_num = magic_num;
     }
 
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn set_version(&mut self, version: u32) {
+        self.version = version;
+    }
+
+    pub fn features(&self) -> u32 {
+        self.features
+    }
+
+    pub fn set_features(&mut self, features: u32) {
+        self.features = features;
+    }
+
+    pub fn layout(&self) -> Layout {
+        Layout {
+            inode_map_start: self.inode_map_start as usize,
+            inode_map_pages: self.inode_map_pages as usize,
+            data_map_start: self.data_map_start as usize,
+            data_map_pages: self.data_map_pages as usize,
+            inode_start: self.inode_start as usize,
+            inode_num: self.inode_num as usize,
+            journal_start: self.journal_start as usize,
+            journal_pages: self.journal_pages as usize,
+            data_start: self.data_start as usize,
+            data_num: self.data_num as usize,
+            group_blocks: self.group_blocks as usize,
+            group_inodes: self.group_inodes as usize,
+        }
+    }
+
+    pub fn set_layout(&mut self, layout: &Layout) {
+        self.inode_map_start = layout.inode_map_start as u32;
+        self.inode_map_pages = layout.inode_map_pages as u32;
+        self.data_map_start = layout.data_map_start as u32;
+        self.data_map_pages = layout.data_map_pages as u32;
+        self.inode_start = layout.inode_start as u32;
+        self.inode_num = layout.inode_num as u32;
+        self.journal_start = layout.journal_start as u32;
+        self.journal_pages = layout.journal_pages as u32;
+        self.data_start = layout.data_start as u32;
+        self.data_num = layout.data_num as u32;
+        self.group_blocks = layout.group_blocks as u32;
+        self.group_inodes = layout.group_inodes as u32;
+    }
+
+    pub fn block_size(&self) -> u32 {
+        self.block_size
+    }
+
+    pub fn set_block_size(&mut self, block_size: u32) {
+        self.block_size = block_size;
+    }
+
+    pub fn free_inodes(&self) -> u32 {
+        self.free_inodes
+    }
+
+    pub fn set_free_inodes(&mut self, free_inodes: u32) {
+        self.free_inodes = free_inodes;
+    }
+
+    pub fn free_blocks(&self) -> u32 {
+        self.free_blocks
+    }
+
+    pub fn set_free_blocks(&mut self, free_blocks: u32) {
+        self.free_blocks = free_blocks;
+    }
+
+    pub fn mount_count(&self) -> u32 {
+        self.mount_count
+    }
+
+    pub fn set_mount_count(&mut self, mount_count: u32) {
+        self.mount_count = mount_count;
+    }
+
+    pub fn uuid(&self) -> [u8; 16] {
+        self.uuid
+    }
+
+    pub fn set_uuid(&mut self, uuid: [u8; 16]) {
+        self.uuid = uuid;
+    }
+
+    ///卷标，不足LABEL_LEN字节时以0结尾
+    pub fn label(&self) -> String {
+        let len = self.label.iter().position(|&b| b == 0).unwrap_or(LABEL_LEN);
+        String::from_utf8_lossy(&self.label[..len]).into_owned()
+    }
+
+    ///调用者保证label不超过LABEL_LEN字节
+    pub fn set_label(&mut self, label: &str) {
+        self.label = [0; LABEL_LEN];
+        self.label[..label.len()].copy_from_slice(label.as_bytes());
+    }
+
     pub fn sz_usage(&self) -> u32 {
         self.sz_usage
     }
@@ -144,13 +320,16 @@ impl SuperPage {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::ddriver::disk::write_page;
 
     #[test]
     fn test_struct_size() {
         assert_eq!(std::mem::size_of::<PageUnion>(), PAGE_SIZE);
         assert_eq!(std::mem::size_of::<InodePage>(), PAGE_SIZE);
         assert_eq!(std::mem::size_of::<DirPage>(), PAGE_SIZE);
+        assert_eq!(std::mem::size_of::<IndexPage>(), PAGE_SIZE);
+        assert_eq!(std::mem::size_of::<DxPage>(), PAGE_SIZE);
+        assert_eq!(std::mem::size_of::<XattrPage>(), PAGE_SIZE);
+        assert_eq!(std::mem::size_of::<ExtentPage>(), PAGE_SIZE);
         assert_eq!(std::mem::size_of::<SuperPage>(), PAGE_SIZE);
     }
 }
@@ -1,8 +1,6 @@
-use crate::utils::semaphore::Semaphore;
-use std::collections::linked_list::CursorMut;
-use std::collections::{HashMap, LinkedList};
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::hash::Hash;
-use std::sync::Arc;
+use std::str::FromStr;
 
 #[derive(Clone, Copy, PartialEq, Debug, Hash, Eq)]
 pub struct FrameId(pub(crate) usize);
@@ -10,79 +8,558 @@ pub struct FrameId(pub(crate) usize);
 #[derive(Clone, Copy, PartialEq, Debug, Eq, Hash, Default)]
 pub struct PageId(pub(crate) usize);
 
+///替换算法。replacer中只有可以被替换的资源，缓存池在资源不再被使用时unpin，重新使用时pin
 pub trait Replacer<T> {
     fn new(pool_size: usize) -> Self;
-    fn victim(&mut self) -> T;
 
+    ///选出一个资源并把它移出replacer，replacer为空时返回None
+    fn victim(&mut self) -> Option<T>;
+
+    ///资源重新被使用，在unpin之前不能被替换。资源不在replacer中时什么也不做
     fn pin(&mut self, resource: T);
 
+    ///资源不再被使用，可以被替换，同时记作一次访问
     fn unpin(&mut self, resource: T);
 
+    ///可以被替换的资源数
     fn size(&self) -> usize;
 }
 
+///按插入顺序排列的集合，删除任意元素和取出最早的元素都是O(log n)
+#[derive(Debug)]
+struct OrderedSet<T> {
+    next: u64,
+    order: BTreeMap<u64, T>,
+    index: HashMap<T, u64>,
+}
+
+impl<T: Hash + Eq + Copy> OrderedSet<T> {
+    fn with_capacity(capacity: usize) -> Self {
+        OrderedSet {
+            next: 0,
+            order: BTreeMap::new(),
+            index: HashMap::with_capacity(capacity),
+        }
+    }
+
+    ///放到末尾，已经在集合中时移到末尾
+    fn push_back(&mut self, resource: T) {
+        self.remove(resource);
+        self.order.insert(self.next, resource);
+        self.index.insert(resource, self.next);
+        self.next += 1;
+    }
+
+    fn remove(&mut self, resource: T) -> bool {
+        match self.index.remove(&resource) {
+            Some(seq) => self.order.remove(&seq).is_some(),
+            None => false,
+        }
+    }
+
+    fn pop_front(&mut self) -> Option<T> {
+        let (_, resource) = self.order.pop_first()?;
+        self.index.remove(&resource);
+        Some(resource)
+    }
+
+    fn contains(&self, resource: T) -> bool {
+        self.index.contains_key(&resource)
+    }
+
+    fn len(&self) -> usize {
+        self.order.len()
+    }
+}
+
+///最近最少使用，替换最早unpin的资源
 #[derive(Debug)]
-pub struct LRUReplacer<T: 'static + Hash + Eq + Copy> {
-    container: LinkedList<T>,
-    index: HashMap<T, CursorMut<'static, T>>,
+pub struct LRUReplacer<T> {
+    queue: OrderedSet<T>,
 }
 
 impl<T: Hash + Eq + Copy> Replacer<T> for LRUReplacer<T> {
     fn new(pool_size: usize) -> Self {
         LRUReplacer {
-            container: LinkedList::new(),
+            queue: OrderedSet::with_capacity(pool_size),
+        }
+    }
+
+    fn victim(&mut self) -> Option<T> {
+        self.queue.pop_front()
+    }
+
+    fn pin(&mut self, resource: T) {
+        self.queue.remove(resource);
+    }
+
+    fn unpin(&mut self, resource: T) {
+        self.queue.push_back(resource);
+    }
+
+    fn size(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+#[derive(Debug)]
+struct ClockSlot<T> {
+    resource: T,
+    referenced: bool,
+    evictable: bool,
+}
+
+///时钟算法。资源第一次unpin时放到环上的一个槽中，之后pin和unpin都不移动它，只设置访问位，
+///替换时指针扫过环，跳过被pin住的资源，清除访问位，替换第一个访问位已清除的资源
+#[derive(Debug)]
+pub struct ClockReplacer<T> {
+    slots: Vec<Option<ClockSlot<T>>>,
+    index: HashMap<T, usize>,
+    free_slots: Vec<usize>,
+    hand: usize,
+    size: usize,
+}
+
+impl<T: Hash + Eq + Copy> Replacer<T> for ClockReplacer<T> {
+    fn new(pool_size: usize) -> Self {
+        ClockReplacer {
+            slots: Vec::with_capacity(pool_size),
             index: HashMap::with_capacity(pool_size),
+            free_slots: Vec::new(),
+            hand: 0,
+            size: 0,
         }
     }
-    //pop front
-    fn victim(&mut self) -> T {
-        let resource = self.container.pop_front().unwrap();
-        self.index.remove(&resource);
-        resource
+
+    fn victim(&mut self) -> Option<T> {
+        if self.size == 0 {
+            return None;
+        }
+        //最多扫两圈：第一圈清除访问位，第二圈一定能找到
+        loop {
+            let hand = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            let Some(slot) = self.slots[hand].as_mut() else {
+                continue;
+            };
+            if !slot.evictable {
+                continue;
+            }
+            if slot.referenced {
+                slot.referenced = false;
+                continue;
+            }
+            let resource = slot.resource;
+            self.slots[hand] = None;
+            self.free_slots.push(hand);
+            self.index.remove(&resource);
+            self.size -= 1;
+            return Some(resource);
+        }
     }
 
     fn pin(&mut self, resource: T) {
-        // delete frame_id
-        self.index
-            .remove(&resource)
-            .unwrap()
-            .remove_current()
-            .unwrap();
+        let Some(&i) = self.index.get(&resource) else {
+            return;
+        };
+        let slot = self.slots[i].as_mut().unwrap();
+        if slot.evictable {
+            slot.evictable = false;
+            self.size -= 1;
+        }
     }
 
     fn unpin(&mut self, resource: T) {
-        //push back
-        self.container.push_back(resource);
-        self.index.insert(resource, unsafe {
-            core::mem::transmute(self.container.cursor_back_mut())
+        if let Some(&i) = self.index.get(&resource) {
+            let slot = self.slots[i].as_mut().unwrap();
+            slot.referenced = true;
+            if !slot.evictable {
+                slot.evictable = true;
+                self.size += 1;
+            }
+            return;
+        }
+        let slot = Some(ClockSlot {
+            resource,
+            referenced: true,
+            evictable: true,
         });
+        let i = match self.free_slots.pop() {
+            Some(i) => {
+                self.slots[i] = slot;
+                i
+            }
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(resource, i);
+        self.size += 1;
     }
 
     fn size(&self) -> usize {
-        self.container.len()
+        self.size
+    }
+}
+
+///LRU-K默认的K
+pub const LRU_K: usize = 2;
+
+#[derive(Debug)]
+struct History {
+    ///最近K次访问的时间，最早的在前
+    accesses: VecDeque<u64>,
+    ///可以被替换时在LRUKReplacer::evictable中的键
+    key: Option<(bool, u64)>,
+}
+
+///LRU-K，替换倒数第K次访问最早的资源。访问不足K次的资源优先被替换，它们之间按第一次访问的先后。
+///资源被替换后忘记它的访问记录
+#[derive(Debug)]
+pub struct LRUKReplacer<T> {
+    k: usize,
+    clock: u64,
+    history: HashMap<T, History>,
+    ///键为(是否已有K次访问, 倒数第K次或第一次访问的时间)，最小的先被替换
+    evictable: BTreeMap<(bool, u64), T>,
+}
+
+impl<T: Hash + Eq + Copy> LRUKReplacer<T> {
+    pub fn with_k(pool_size: usize, k: usize) -> Self {
+        assert!(k > 0, "k of LRU-K must be positive");
+        LRUKReplacer {
+            k,
+            clock: 0,
+            history: HashMap::with_capacity(pool_size),
+            evictable: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Hash + Eq + Copy> Replacer<T> for LRUKReplacer<T> {
+    fn new(pool_size: usize) -> Self {
+        Self::with_k(pool_size, LRU_K)
+    }
+
+    fn victim(&mut self) -> Option<T> {
+        let (_, resource) = self.evictable.pop_first()?;
+        self.history.remove(&resource);
+        Some(resource)
+    }
+
+    fn pin(&mut self, resource: T) {
+        if let Some(history) = self.history.get_mut(&resource) {
+            if let Some(key) = history.key.take() {
+                self.evictable.remove(&key);
+            }
+        }
+    }
+
+    fn unpin(&mut self, resource: T) {
+        self.clock += 1;
+        let history = self.history.entry(resource).or_insert(History {
+            accesses: VecDeque::with_capacity(self.k),
+            key: None,
+        });
+        if history.accesses.len() == self.k {
+            history.accesses.pop_front();
+        }
+        history.accesses.push_back(self.clock);
+        if let Some(key) = history.key.take() {
+            self.evictable.remove(&key);
+        }
+        let key = (history.accesses.len() == self.k, history.accesses[0]);
+        history.key = Some(key);
+        self.evictable.insert(key, resource);
+    }
+
+    fn size(&self) -> usize {
+        self.evictable.len()
+    }
+}
+
+///简化的2Q：只访问过一次的资源在FIFO队列A1中，再次访问后移入LRU队列Am。
+///A1超过缓存的1/4时先从A1中替换，一次性的顺序扫描不会把反复访问的页挤出缓存。
+///replacer只看到frame号，frame换页后就忘记它的访问记录，所以没有原算法中记录已替换页的A1out
+#[derive(Debug)]
+pub struct TwoQReplacer<T> {
+    a1: OrderedSet<T>,
+    am: OrderedSet<T>,
+    ///replacer知道的资源，值为是否访问过不止一次
+    hot: HashMap<T, bool>,
+    a1_max: usize,
+}
+
+impl<T: Hash + Eq + Copy> Replacer<T> for TwoQReplacer<T> {
+    fn new(pool_size: usize) -> Self {
+        TwoQReplacer {
+            a1: OrderedSet::with_capacity(pool_size),
+            am: OrderedSet::with_capacity(pool_size),
+            hot: HashMap::with_capacity(pool_size),
+            a1_max: (pool_size / 4).max(1),
+        }
+    }
+
+    fn victim(&mut self) -> Option<T> {
+        let resource = if self.a1.len() > self.a1_max || self.am.len() == 0 {
+            self.a1.pop_front().or_else(|| self.am.pop_front())
+        } else {
+            self.am.pop_front()
+        }?;
+        self.hot.remove(&resource);
+        Some(resource)
+    }
+
+    fn pin(&mut self, resource: T) {
+        if !self.a1.remove(resource) {
+            self.am.remove(resource);
+        }
+    }
+
+    fn unpin(&mut self, resource: T) {
+        match self.hot.get_mut(&resource) {
+            None => {
+                self.hot.insert(resource, false);
+                self.a1.push_back(resource);
+            }
+            Some(hot) => {
+                *hot = true;
+                self.a1.remove(resource);
+                self.am.push_back(resource);
+            }
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.a1.len() + self.am.len()
+    }
+}
+
+///缓存池使用的替换算法，由挂载参数--replacer选择
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ReplacerKind {
+    #[default]
+    Lru,
+    Clock,
+    LruK,
+    TwoQ,
+}
+
+impl FromStr for ReplacerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lru" => Ok(ReplacerKind::Lru),
+            "clock" => Ok(ReplacerKind::Clock),
+            "lru-k" => Ok(ReplacerKind::LruK),
+            "2q" => Ok(ReplacerKind::TwoQ),
+            _ => Err(format!(
+                "unknown replacer {s:?}, expect lru, clock, lru-k or 2q"
+            )),
+        }
+    }
+}
+
+impl ReplacerKind {
+    pub fn build<T: Hash + Eq + Copy>(self, pool_size: usize) -> AnyReplacer<T> {
+        match self {
+            ReplacerKind::Lru => AnyReplacer::Lru(LRUReplacer::new(pool_size)),
+            ReplacerKind::Clock => AnyReplacer::Clock(ClockReplacer::new(pool_size)),
+            ReplacerKind::LruK => AnyReplacer::LruK(LRUKReplacer::new(pool_size)),
+            ReplacerKind::TwoQ => AnyReplacer::TwoQ(TwoQReplacer::new(pool_size)),
+        }
+    }
+}
+
+///运行时选定的替换算法，Replacer::new得到LRU
+#[derive(Debug)]
+pub enum AnyReplacer<T> {
+    Lru(LRUReplacer<T>),
+    Clock(ClockReplacer<T>),
+    LruK(LRUKReplacer<T>),
+    TwoQ(TwoQReplacer<T>),
+}
+
+macro_rules! dispatch {
+    ($self:ident, $r:ident => $e:expr) => {
+        match $self {
+            AnyReplacer::Lru($r) => $e,
+            AnyReplacer::Clock($r) => $e,
+            AnyReplacer::LruK($r) => $e,
+            AnyReplacer::TwoQ($r) => $e,
+        }
+    };
+}
+
+impl<T: Hash + Eq + Copy> Replacer<T> for AnyReplacer<T> {
+    fn new(pool_size: usize) -> Self {
+        ReplacerKind::default().build(pool_size)
+    }
+
+    fn victim(&mut self) -> Option<T> {
+        dispatch!(self, r => r.victim())
+    }
+
+    fn pin(&mut self, resource: T) {
+        dispatch!(self, r => r.pin(resource))
+    }
+
+    fn unpin(&mut self, resource: T) {
+        dispatch!(self, r => r.unpin(resource))
+    }
+
+    fn size(&self) -> usize {
+        dispatch!(self, r => r.size())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use libc::sleep;
+    use std::collections::HashSet;
+
+    ///所有替换算法都要满足的约定
+    fn conformance<R: Replacer<FrameId>>() {
+        let mut replacer = R::new(10);
+        assert_eq!(replacer.size(), 0);
+        assert_eq!(replacer.victim(), None);
+        //pin不在replacer中的资源什么也不做
+        replacer.pin(FrameId(3));
+        assert_eq!(replacer.size(), 0);
+
+        for i in 0..10 {
+            replacer.unpin(FrameId(i));
+        }
+        //重复unpin不会重复计数
+        replacer.unpin(FrameId(2));
+        assert_eq!(replacer.size(), 10);
+        replacer.pin(FrameId(5));
+        replacer.pin(FrameId(5));
+        assert_eq!(replacer.size(), 9);
+        let mut victims = HashSet::new();
+        for _ in 0..4 {
+            assert!(victims.insert(replacer.victim().unwrap()));
+        }
+        assert!(!victims.contains(&FrameId(5)));
+        assert_eq!(replacer.size(), 5);
+        //被pin住的资源unpin后可以再被替换，被替换的资源可以再放回来
+        replacer.unpin(FrameId(5));
+        let back = *victims.iter().next().unwrap();
+        replacer.unpin(back);
+        assert_eq!(replacer.size(), 7);
+        let mut rest = HashSet::new();
+        while let Some(frame_id) = replacer.victim() {
+            assert!(rest.insert(frame_id));
+        }
+        assert_eq!(rest.len(), 7);
+        assert!(rest.contains(&FrameId(5)) && rest.contains(&back));
+        assert_eq!(replacer.size(), 0);
+        assert_eq!(replacer.victim(), None);
+
+        //随机操作，与可替换集合的模型比较
+        let mut replacer = R::new(16);
+        let mut model = HashSet::new();
+        let mut seed = 0x2545f491u32;
+        for _ in 0..5000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let frame_id = FrameId(seed as usize % 16);
+            match seed / 16 % 3 {
+                0 => {
+                    replacer.unpin(frame_id);
+                    model.insert(frame_id);
+                }
+                1 => {
+                    replacer.pin(frame_id);
+                    model.remove(&frame_id);
+                }
+                _ => match replacer.victim() {
+                    Some(victim) => assert!(model.remove(&victim)),
+                    None => assert!(model.is_empty()),
+                },
+            }
+            assert_eq!(replacer.size(), model.len());
+        }
+    }
 
     #[test]
     fn replacer_test() {
+        conformance::<LRUReplacer<FrameId>>();
+        conformance::<ClockReplacer<FrameId>>();
+        conformance::<LRUKReplacer<FrameId>>();
+        conformance::<TwoQReplacer<FrameId>>();
+        conformance::<AnyReplacer<FrameId>>();
+
         let mut replacer = LRUReplacer::new(10);
         for i in 0..10 {
             replacer.unpin(FrameId(i));
         }
         replacer.pin(FrameId(5));
-        assert_eq!(replacer.victim(), FrameId(0));
-        assert_eq!(replacer.victim(), FrameId(1));
-        assert_eq!(replacer.victim(), FrameId(2));
-        assert_eq!(replacer.victim(), FrameId(3));
-        assert_eq!(replacer.victim(), FrameId(4));
-        assert_eq!(replacer.victim(), FrameId(6));
-        assert_eq!(replacer.victim(), FrameId(7));
-        assert_eq!(replacer.victim(), FrameId(8));
-        assert_eq!(replacer.victim(), FrameId(9));
+        for i in [0, 1, 2, 3, 4, 6, 7, 8, 9] {
+            assert_eq!(replacer.victim(), Some(FrameId(i)));
+        }
+        assert_eq!(replacer.victim(), None);
+    }
+
+    #[test]
+    fn test_policies() {
+        //时钟：pin和unpin不改变资源在环上的位置，访问过的资源多得一次机会
+        let mut clock = ClockReplacer::new(4);
+        for i in 0..4 {
+            clock.unpin(FrameId(i));
+        }
+        clock.pin(FrameId(0));
+        clock.unpin(FrameId(0));
+        assert_eq!(clock.victim(), Some(FrameId(0)));
+        clock.unpin(FrameId(2));
+        assert_eq!(clock.victim(), Some(FrameId(1)));
+        assert_eq!(clock.victim(), Some(FrameId(3)));
+        assert_eq!(clock.victim(), Some(FrameId(2)));
+
+        //LRU-K：访问不足K次的先被替换，都有K次时替换倒数第K次访问最早的
+        let mut lru_k = LRUKReplacer::new(4);
+        for i in 0..4 {
+            lru_k.unpin(FrameId(i));
+        }
+        for i in [3, 2, 1] {
+            lru_k.pin(FrameId(i));
+            lru_k.unpin(FrameId(i));
+        }
+        assert_eq!(lru_k.victim(), Some(FrameId(0)));
+        assert_eq!(lru_k.victim(), Some(FrameId(1)));
+        lru_k.unpin(FrameId(4));
+        assert_eq!(lru_k.victim(), Some(FrameId(4)));
+        let mut lru_k = LRUKReplacer::with_k(4, 3);
+        lru_k.unpin(FrameId(0));
+        lru_k.unpin(FrameId(0));
+        lru_k.unpin(FrameId(1));
+        assert_eq!(lru_k.victim(), Some(FrameId(0)));
+
+        //2Q：只访问过一次的页组成的顺序扫描不会挤掉反复访问的页
+        let mut two_q = TwoQReplacer::new(8);
+        for i in 0..2 {
+            two_q.unpin(FrameId(i));
+            two_q.pin(FrameId(i));
+            two_q.unpin(FrameId(i));
+        }
+        for i in 2..8 {
+            two_q.unpin(FrameId(i));
+        }
+        for i in 2..6 {
+            assert_eq!(two_q.victim(), Some(FrameId(i)));
+        }
+        //A1不超过上限后替换Am中最久没有访问的
+        assert_eq!(two_q.victim(), Some(FrameId(0)));
+
+        assert_eq!("2q".parse(), Ok(ReplacerKind::TwoQ));
+        assert_eq!("lru-k".parse(), Ok(ReplacerKind::LruK));
+        assert!("mru".parse::<ReplacerKind>().is_err());
+        assert!(matches!(
+            ReplacerKind::Clock.build::<FrameId>(4),
+            AnyReplacer::Clock(_)
+        ));
     }
 }
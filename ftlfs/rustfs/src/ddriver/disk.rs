@@ -3,9 +3,9 @@
 use crate::buffer::replacer::PageId;
 use crate::ddriver::ioctl::{_IO, _IOR};
 use crate::ddriver::metadata::{disk_size, fd, io_size, set_disk_size, set_fd, set_io_size};
+use crate::device::BlockDevice;
 use crate::fs::custom::PAGE_SIZE;
 use crate::fs::def::PAGE_SIZE_U32;
-use crate::DDRIVER_PATH;
 use libc::{self, c_char, c_int, c_ulong, c_void, off_t, size_t, SEEK_SET};
 use log::{debug, error, warn};
 use parking_lot::Mutex;
@@ -118,9 +118,9 @@ fn check_out_of_range(page_id: PageId) {
     );
 }
 
-pub fn init_ddriver() {
+fn init_ddriver(path: &str) {
     unsafe {
-        let path = CString::new(DDRIVER_PATH).unwrap();
+        let path = CString::new(path).unwrap();
         let fd = ddriver_open(path.as_ptr());
         if fd < 0 {
             panic!("ddriver open failed");
@@ -134,37 +134,104 @@ pub fn init_ddriver() {
 
 static DRIVER_LOCK: Mutex<()> = Mutex::new(());
 
-pub fn read_page(page_id: PageId, page: &mut [u8; PAGE_SIZE]) {
-    let guard = DRIVER_LOCK.lock();
-    check_out_of_range(page_id);
-    let arr_ptr = (&raw mut *page) as *mut [c_char; PAGE_SIZE];
-    let io_sz = io_size();
-    let kio = PAGE_SIZE / io_sz;
+fn close_ddriver() {
     unsafe {
-        seek_blk(page_id.0 * kio);
-        for i in 0..kio {
-            raw_read_blk(&raw mut *(*arr_ptr).get_unchecked_mut(i * io_sz));
-        }
+        let r = ddriver_close(fd());
+        assert_eq!(r, 0, "ddriver_close error");
     }
 }
 
-pub fn write_page(page_id: PageId, page: &[u8; PAGE_SIZE]) {
-    let guard = DRIVER_LOCK.lock();
-    check_out_of_range(page_id);
-    let arr_ptr = page as *const [u8; PAGE_SIZE] as *const [c_char; PAGE_SIZE];
-    let io_sz = io_size();
-    let kio = PAGE_SIZE / io_sz;
-    unsafe {
-        seek_blk(page_id.0 * kio);
-        for i in 0..kio {
-            raw_write_blk((*arr_ptr).get_unchecked(i * io_sz));
+///ddriver模拟磁盘，ddriver库同一时刻只能打开一个设备，析构时关闭
+pub struct DDriver;
+
+impl DDriver {
+    pub fn open(path: &str) -> Self {
+        init_ddriver(path);
+        DDriver
+    }
+}
+
+impl BlockDevice for DDriver {
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) {
+        let guard = DRIVER_LOCK.lock();
+        check_out_of_range(page_id);
+        let arr_ptr = (&raw mut *page) as *mut [c_char; PAGE_SIZE];
+        let io_sz = io_size();
+        let kio = PAGE_SIZE / io_sz;
+        unsafe {
+            seek_blk(page_id.0 * kio);
+            for i in 0..kio {
+                raw_read_blk(&raw mut *(*arr_ptr).get_unchecked_mut(i * io_sz));
+            }
+        }
+    }
+
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) {
+        let guard = DRIVER_LOCK.lock();
+        check_out_of_range(page_id);
+        let arr_ptr = page as *const [u8; PAGE_SIZE] as *const [c_char; PAGE_SIZE];
+        let io_sz = io_size();
+        let kio = PAGE_SIZE / io_sz;
+        unsafe {
+            seek_blk(page_id.0 * kio);
+            for i in 0..kio {
+                raw_write_blk((*arr_ptr).get_unchecked(i * io_sz));
+            }
+        }
+    }
+
+    ///整段只加一次锁、seek一次，之后连续读出所有io单元
+    fn read_pages(&self, start: PageId, pages: &mut [[u8; PAGE_SIZE]]) {
+        let guard = DRIVER_LOCK.lock();
+        if pages.is_empty() {
+            return;
+        }
+        check_out_of_range(PageId(start.0 + pages.len() - 1));
+        let io_sz = io_size();
+        let bytes = pages.as_flattened_mut();
+        unsafe {
+            seek_blk(start.0 * (PAGE_SIZE / io_sz));
+            for offset in (0..bytes.len()).step_by(io_sz) {
+                raw_read_blk(bytes.as_mut_ptr().add(offset) as *mut c_char);
+            }
         }
     }
+
+    fn write_pages(&self, start: PageId, pages: &[[u8; PAGE_SIZE]]) {
+        let guard = DRIVER_LOCK.lock();
+        if pages.is_empty() {
+            return;
+        }
+        check_out_of_range(PageId(start.0 + pages.len() - 1));
+        let io_sz = io_size();
+        let bytes = pages.as_flattened();
+        unsafe {
+            seek_blk(start.0 * (PAGE_SIZE / io_sz));
+            for offset in (0..bytes.len()).step_by(io_sz) {
+                raw_write_blk(bytes.as_ptr().add(offset) as *const c_char);
+            }
+        }
+    }
+
+    fn page_num(&self) -> usize {
+        disk_size() / PAGE_SIZE
+    }
+
+    ///ddriver的fd是宿主机上模拟磁盘文件的描述符，fdatasync返回时之前的写入都已落盘
+    fn sync(&self) {
+        let _guard = DRIVER_LOCK.lock();
+        let r = unsafe { libc::fdatasync(fd_unwrap()) };
+        assert_eq!(
+            r,
+            0,
+            "ddriver sync err: {}",
+            std::io::Error::last_os_error()
+        );
+    }
 }
 
-pub fn close_ddriver() {
-    unsafe {
-        let r = ddriver_close(fd());
-        assert_eq!(r, 0, "ddriver_close error");
+impl Drop for DDriver {
+    fn drop(&mut self) {
+        close_ddriver();
     }
 }
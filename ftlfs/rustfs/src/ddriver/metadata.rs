@@ -1,44 +1,42 @@
 use crate::fs::custom::PAGE_SIZE;
 use crate::fs::def::PAGE_SIZE_U32;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
 
-static mut FD: i32 = 0;
+static FD: AtomicI32 = AtomicI32::new(0);
 
-static mut IO_SIZE: usize = 0;
+static IO_SIZE: AtomicUsize = AtomicUsize::new(0);
 
-static mut DISK_SIZE: usize = 0;
+static DISK_SIZE: AtomicUsize = AtomicUsize::new(0);
 
-pub unsafe fn set_fd(fd: i32) {
-    FD = fd
+pub fn set_fd(fd: i32) {
+    FD.store(fd, Ordering::Relaxed);
 }
 
 pub fn fd() -> i32 {
-    unsafe {
-        assert_ne!(FD, 0);
-        FD
-    }
+    let fd = FD.load(Ordering::Relaxed);
+    assert_ne!(fd, 0);
+    fd
 }
 
-pub unsafe fn set_io_size(io_size: usize) {
+pub fn set_io_size(io_size: usize) {
     assert_ne!(io_size, 0);
     assert_eq!(PAGE_SIZE % io_size, 0);
-    IO_SIZE = io_size;
+    IO_SIZE.store(io_size, Ordering::Relaxed);
 }
 
 pub fn io_size() -> usize {
-    unsafe {
-        assert_ne!(IO_SIZE, 0);
-        IO_SIZE
-    }
+    let io_size = IO_SIZE.load(Ordering::Relaxed);
+    assert_ne!(io_size, 0);
+    io_size
 }
 
-pub unsafe fn set_disk_size(disk_size: usize) {
+pub fn set_disk_size(disk_size: usize) {
     assert_ne!(disk_size, 0);
-    DISK_SIZE = disk_size;
+    DISK_SIZE.store(disk_size, Ordering::Relaxed);
 }
 
 pub fn disk_size() -> usize {
-    unsafe {
-        assert_ne!(DISK_SIZE, 0);
-        DISK_SIZE
-    }
+    let disk_size = DISK_SIZE.load(Ordering::Relaxed);
+    assert_ne!(disk_size, 0);
+    disk_size
 }
@@ -5,21 +5,22 @@ mod metadata;
 #[cfg(test)]
 mod test {
     use crate::buffer::replacer::PageId;
-    use crate::ddriver::disk::{close_ddriver, init_ddriver, read_page, write_page};
+    use crate::ddriver::disk::DDriver;
+    use crate::device::BlockDevice;
+    use crate::fs::custom::DDRIVER_PATH;
     use libc::munlock;
 
     #[test]
     fn test() {
-        init_ddriver();
+        let device = DDriver::open(DDRIVER_PATH);
         let mut buf = [0u8; 4096];
         for i in 0..6 {
             buf[0] = i as u8;
-            write_page(PageId(i), &mut buf);
+            device.write_page(PageId(i), &buf);
         }
         for i in 0..6 {
-            read_page(PageId(i), &mut buf);
+            device.read_page(PageId(i), &mut buf);
             println!("{}", buf[0]);
         }
-        close_ddriver();
     }
 }
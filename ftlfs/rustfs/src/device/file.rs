@@ -0,0 +1,77 @@
+use crate::buffer::replacer::PageId;
+use crate::device::{check_out_of_range, check_range, BlockDevice};
+use crate::fs::custom::PAGE_SIZE;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+
+///磁盘镜像文件，用pread/pwrite按页读写，文件大小决定设备大小
+pub struct FileDevice {
+    file: File,
+    page_num: usize,
+}
+
+impl FileDevice {
+    pub fn open(path: &str) -> io::Result<Self> {
+        Self::open_with(path, OpenOptions::new().read(true).write(true))
+    }
+
+    ///以只读方式打开，写入时panic
+    pub fn open_read_only(path: &str) -> io::Result<Self> {
+        Self::open_with(path, OpenOptions::new().read(true))
+    }
+
+    fn open_with(path: &str, options: &OpenOptions) -> io::Result<Self> {
+        let file = options.open(path)?;
+        let page_num = file.metadata()?.len() as usize / PAGE_SIZE;
+        if page_num == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("image {path} is smaller than a page"),
+            ));
+        }
+        Ok(FileDevice { file, page_num })
+    }
+}
+
+impl BlockDevice for FileDevice {
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) {
+        check_out_of_range(self, page_id);
+        self.file
+            .read_exact_at(page, (page_id.0 * PAGE_SIZE) as u64)
+            .unwrap_or_else(|e| panic!("read page {} err: {e}", page_id.0));
+    }
+
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) {
+        check_out_of_range(self, page_id);
+        self.file
+            .write_all_at(page, (page_id.0 * PAGE_SIZE) as u64)
+            .unwrap_or_else(|e| panic!("write page {} err: {e}", page_id.0));
+    }
+
+    ///连续的页用一次pread读出
+    fn read_pages(&self, start: PageId, pages: &mut [[u8; PAGE_SIZE]]) {
+        check_range(self, start, pages.len());
+        self.file
+            .read_exact_at(pages.as_flattened_mut(), (start.0 * PAGE_SIZE) as u64)
+            .unwrap_or_else(|e| panic!("read pages from {} err: {e}", start.0));
+    }
+
+    ///连续的页用一次pwrite写入
+    fn write_pages(&self, start: PageId, pages: &[[u8; PAGE_SIZE]]) {
+        check_range(self, start, pages.len());
+        self.file
+            .write_all_at(pages.as_flattened(), (start.0 * PAGE_SIZE) as u64)
+            .unwrap_or_else(|e| panic!("write pages from {} err: {e}", start.0));
+    }
+
+    fn page_num(&self) -> usize {
+        self.page_num
+    }
+
+    fn sync(&self) {
+        self.file
+            .sync_data()
+            .unwrap_or_else(|e| panic!("sync image err: {e}"));
+    }
+}
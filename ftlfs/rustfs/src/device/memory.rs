@@ -0,0 +1,57 @@
+use crate::buffer::replacer::PageId;
+use crate::device::{check_out_of_range, check_range, BlockDevice};
+use crate::fs::custom::PAGE_SIZE;
+use parking_lot::Mutex;
+
+///内存中的磁盘，创建时全部清零，主要用于测试
+pub struct MemDevice {
+    pages: Mutex<Vec<[u8; PAGE_SIZE]>>,
+    page_num: usize,
+}
+
+impl MemDevice {
+    pub fn new(page_num: usize) -> Self {
+        MemDevice {
+            pages: Mutex::new(vec![[0; PAGE_SIZE]; page_num]),
+            page_num,
+        }
+    }
+
+    ///用已有的页内容创建，测试中用来模拟崩溃后留在磁盘上的数据
+    pub fn from_pages(pages: Vec<[u8; PAGE_SIZE]>) -> Self {
+        let page_num = pages.len();
+        MemDevice {
+            pages: Mutex::new(pages),
+            page_num,
+        }
+    }
+}
+
+impl BlockDevice for MemDevice {
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) {
+        check_out_of_range(self, page_id);
+        page.copy_from_slice(&self.pages.lock()[page_id.0]);
+    }
+
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) {
+        check_out_of_range(self, page_id);
+        self.pages.lock()[page_id.0].copy_from_slice(page);
+    }
+
+    fn read_pages(&self, start: PageId, pages: &mut [[u8; PAGE_SIZE]]) {
+        check_range(self, start, pages.len());
+        pages.copy_from_slice(&self.pages.lock()[start.0..start.0 + pages.len()]);
+    }
+
+    fn write_pages(&self, start: PageId, pages: &[[u8; PAGE_SIZE]]) {
+        check_range(self, start, pages.len());
+        self.pages.lock()[start.0..start.0 + pages.len()].copy_from_slice(pages);
+    }
+
+    fn page_num(&self) -> usize {
+        self.page_num
+    }
+
+    ///写入在返回前已经完成，没有需要等待的
+    fn sync(&self) {}
+}
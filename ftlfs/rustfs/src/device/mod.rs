@@ -0,0 +1,190 @@
+//! 块设备层。缓存层只通过BlockDevice按页读写磁盘，具体的存储由挂载参数--device选择：
+//!
+//! - `ddriver:PATH`：课程提供的ddriver模拟磁盘（需要启用ddriver特性）
+//! - `mem`或`mem:SIZE`：内存中的磁盘，SIZE可带K/M/G后缀，默认4M，挂载时格式化，卸载后数据丢失
+//! - `file:PATH`或直接写`PATH`：普通的磁盘镜像文件
+pub mod file;
+pub mod memory;
+pub mod readonly;
+
+use crate::buffer::replacer::PageId;
+use crate::fs::custom::PAGE_SIZE;
+use std::io;
+
+pub use file::FileDevice;
+pub use memory::MemDevice;
+pub use readonly::ReadOnlyDevice;
+
+pub trait BlockDevice: Send + Sync {
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]);
+
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]);
+
+    ///从start开始连续读出pages.len()页。默认逐页读，设备可以合并成一次读
+    fn read_pages(&self, start: PageId, pages: &mut [[u8; PAGE_SIZE]]) {
+        for (i, page) in pages.iter_mut().enumerate() {
+            self.read_page(PageId(start.0 + i), page);
+        }
+    }
+
+    ///从start开始连续写入pages.len()页。默认逐页写，设备可以合并成一次写
+    fn write_pages(&self, start: PageId, pages: &[[u8; PAGE_SIZE]]) {
+        for (i, page) in pages.iter().enumerate() {
+            self.write_page(PageId(start.0 + i), page);
+        }
+    }
+
+    ///设备的总页数
+    fn page_num(&self) -> usize;
+
+    ///写屏障：返回时之前的所有写入都已落到持久存储上，日志依靠它保证写入顺序。
+    ///每个设备都要实现，不能留空
+    fn sync(&self);
+}
+
+fn check_out_of_range(device: &dyn BlockDevice, page_id: PageId) {
+    assert!(
+        page_id.0 < device.page_num(),
+        "out of device range,page id = {}",
+        page_id.0
+    );
+}
+
+///检查从start开始的count页都在设备范围内
+fn check_range(device: &dyn BlockDevice, start: PageId, count: usize) {
+    if count > 0 {
+        check_out_of_range(device, PageId(start.0 + count - 1));
+    }
+}
+
+///展开路径开头的~
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => home + rest,
+        _ => path.to_string(),
+    }
+}
+
+///解析带K/M/G后缀的大小
+pub fn parse_size(size: &str) -> Option<usize> {
+    let (num, unit) = match size.as_bytes().last()? {
+        b'K' | b'k' => (&size[..size.len() - 1], 1 << 10),
+        b'M' | b'm' => (&size[..size.len() - 1], 1 << 20),
+        b'G' | b'g' => (&size[..size.len() - 1], 1 << 30),
+        _ => (size, 1),
+    };
+    num.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+///是否是内存中的磁盘
+pub fn is_memory(spec: &str) -> bool {
+    spec == "mem" || spec.starts_with("mem:")
+}
+
+///按--device参数打开块设备
+pub fn open_device(spec: &str) -> io::Result<Box<dyn BlockDevice>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    if is_memory(spec) {
+        let size = match spec.strip_prefix("mem:") {
+            Some(size) => {
+                parse_size(size).ok_or_else(|| invalid(format!("invalid device size {size}")))?
+            }
+            None => 4 << 20,
+        };
+        if size < PAGE_SIZE {
+            return Err(invalid(format!(
+                "device size {size} is smaller than a page"
+            )));
+        }
+        return Ok(Box::new(MemDevice::new(size / PAGE_SIZE)));
+    }
+    if let Some(path) = spec.strip_prefix("ddriver:") {
+        #[cfg(feature = "ddriver")]
+        return Ok(Box::new(crate::ddriver::disk::DDriver::open(&expand_home(
+            path,
+        ))));
+        #[cfg(not(feature = "ddriver"))]
+        return Err(invalid(format!(
+            "ddriver device {path} is not supported, rebuild with the ddriver feature"
+        )));
+    }
+    let path = spec.strip_prefix("file:").unwrap_or(spec);
+    Ok(Box::new(FileDevice::open(&expand_home(path))?))
+}
+
+///只读地打开块设备，镜像文件以只读方式打开，写入任何设备都会panic
+pub fn open_device_read_only(spec: &str) -> io::Result<Box<dyn BlockDevice>> {
+    let device: Box<dyn BlockDevice> = if is_memory(spec) || spec.starts_with("ddriver:") {
+        open_device(spec)?
+    } else {
+        let path = spec.strip_prefix("file:").unwrap_or(spec);
+        Box::new(FileDevice::open_read_only(&expand_home(path))?)
+    };
+    Ok(Box::new(ReadOnlyDevice(device)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check_device(device: &dyn BlockDevice) {
+        let mut buf = [0u8; PAGE_SIZE];
+        for i in 0..6 {
+            buf.fill(i as u8);
+            device.write_page(PageId(i), &buf);
+        }
+        for i in (0..6).rev() {
+            device.read_page(PageId(i), &mut buf);
+            assert!(buf.iter().all(|&b| b == i as u8));
+        }
+        //多页读写与逐页读写的结果一致
+        let mut pages = vec![[0u8; PAGE_SIZE]; 3];
+        device.read_pages(PageId(2), &mut pages);
+        assert!((0..3).all(|i| pages[i].iter().all(|&b| b == i as u8 + 2)));
+        for (i, page) in pages.iter_mut().enumerate() {
+            page.fill(10 + i as u8);
+        }
+        device.write_pages(PageId(1), &pages);
+        device.read_page(PageId(3), &mut buf);
+        assert!(buf.iter().all(|&b| b == 12));
+        device.read_page(PageId(4), &mut buf);
+        assert!(buf.iter().all(|&b| b == 4));
+    }
+
+    #[test]
+    fn test_mem_device() {
+        let device = open_device("mem:64K").unwrap();
+        assert_eq!(device.page_num(), 16);
+        check_device(device.as_ref());
+        assert_eq!(open_device("mem").unwrap().page_num(), 1024);
+        assert!(open_device("mem:1x").is_err());
+        assert!(open_device("mem:100").is_err());
+        assert!(is_memory("mem") && is_memory("mem:16M"));
+        assert!(!is_memory("memory.img"));
+    }
+
+    #[test]
+    fn test_file_device() {
+        let path = std::env::temp_dir().join(format!("rustfs-test-{}.img", std::process::id()));
+        std::fs::write(&path, vec![0u8; 8 * PAGE_SIZE]).unwrap();
+        let spec = format!("file:{}", path.display());
+        {
+            let device = open_device(&spec).unwrap();
+            assert_eq!(device.page_num(), 8);
+            check_device(device.as_ref());
+        }
+        //重新打开后数据仍在
+        let device = open_device(path.to_str().unwrap()).unwrap();
+        let mut buf = [0u8; PAGE_SIZE];
+        device.read_page(PageId(5), &mut buf);
+        assert_eq!(buf[0], 5);
+        drop(device);
+        let device = open_device_read_only(&spec).unwrap();
+        device.read_page(PageId(3), &mut buf);
+        assert_eq!(buf[0], 12);
+        let write = || device.write_page(PageId(3), &[0; PAGE_SIZE]);
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(write)).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(open_device(&spec).is_err());
+    }
+}
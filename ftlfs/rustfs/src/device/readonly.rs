@@ -0,0 +1,32 @@
+use crate::buffer::replacer::PageId;
+use crate::device::BlockDevice;
+use crate::fs::custom::PAGE_SIZE;
+
+///只读的设备，fsck -n使用。任何写入都是bug，直接panic而不是改动被检查的磁盘
+pub struct ReadOnlyDevice(pub Box<dyn BlockDevice>);
+
+impl BlockDevice for ReadOnlyDevice {
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) {
+        self.0.read_page(page_id, page);
+    }
+
+    fn write_page(&self, page_id: PageId, _: &[u8; PAGE_SIZE]) {
+        panic!("write page {} to a read-only device", page_id.0);
+    }
+
+    fn read_pages(&self, start: PageId, pages: &mut [[u8; PAGE_SIZE]]) {
+        self.0.read_pages(start, pages);
+    }
+
+    fn write_pages(&self, start: PageId, _: &[[u8; PAGE_SIZE]]) {
+        panic!("write pages from {} to a read-only device", start.0);
+    }
+
+    fn page_num(&self) -> usize {
+        self.0.page_num()
+    }
+
+    fn sync(&self) {
+        self.0.sync();
+    }
+}
@@ -0,0 +1,274 @@
+//! 块组分配器，组织方式类似ext的块组：
+//!
+//! - inode位图和数据位图按Layout中的组大小划分成块组，第i个inode组与第i个数据组对应
+//! - 每组的空闲数在格式化和挂载时从位图中数出，保存在内存中，随分配和释放更新。
+//!   分配时直接跳过没有空闲位的组，不必逐页读出位图
+//! - 普通文件的inode放在父目录所在的组，目录的inode分散到空闲inode不少于平均值、
+//!   对应数据组空闲块最多的组；文件的第一个数据块从其inode对应的数据组开始找
+//! - 没有目标位置的分配（索引块、extent节点、扩展属性块等）从上次分配的位置之后接着找（next-fit）
+//!
+//! 空闲数只是分配时的提示，磁盘上的位图才是准确的，崩溃后不需要修复
+use crate::buffer::buffer_pool_manager::AutoUnpin;
+use crate::buffer::replacer::PageId;
+use crate::fetch_page_read;
+use crate::fs::layout::BITS_PER_PAGE;
+use crate::fs::types::{bitmap_alloc_range, FileType, InodeId};
+use crate::fs::volume::Volume;
+use parking_lot::{Mutex, MutexGuard};
+
+///一个位图的块组
+#[derive(Default)]
+struct Groups {
+    ///位图的起始页
+    start: usize,
+    ///位图中的有效位数
+    limit: usize,
+    ///每组的位数
+    size: usize,
+    ///每组的空闲位数
+    free: Vec<u32>,
+    ///上次分配的位置之后，没有目标位置的分配从这里开始找
+    cursor: usize,
+}
+
+///卷的inode位图和数据位图的块组
+#[derive(Default)]
+pub struct Allocator {
+    inodes: Mutex<Groups>,
+    data: Mutex<Groups>,
+}
+
+impl Groups {
+    ///从位图中数出每组的空闲位数，组大小是8的倍数，按字节统计即可
+    fn load(vol: &Volume, start: usize, limit: usize, size: usize) -> Self {
+        let mut free: Vec<u32> = (0..limit.div_ceil(size))
+            .map(|group| (limit - group * size).min(size) as u32)
+            .collect();
+        for i in 0..limit.div_ceil(BITS_PER_PAGE) {
+            fetch_page_read!(bitmap_page: bitmap, vol, start + i, au);
+            for (j, byte) in bitmap_page.data.iter().enumerate() {
+                let bit = i * BITS_PER_PAGE + j * 8;
+                if bit >= limit {
+                    break;
+                }
+                let mask = (0xffu16 >> (8 - (limit - bit).min(8))) as u8;
+                free[bit / size] -= (byte & mask).count_ones();
+            }
+        }
+        Groups {
+            start,
+            limit,
+            size,
+            free,
+            cursor: 0,
+        }
+    }
+
+    fn group_range(&self, group: usize) -> (usize, usize) {
+        (group * self.size, ((group + 1) * self.size).min(self.limit))
+    }
+
+    ///先在goal所在组中从goal往后找，再从组的开头找到goal，之后依次在其余有空闲位的组中找
+    fn alloc(&mut self, vol: &Volume, goal: Option<usize>) -> Option<u32> {
+        let goal = goal
+            .filter(|&goal| goal < self.limit)
+            .unwrap_or(self.cursor);
+        let groups = self.free.len();
+        let first = goal / self.size;
+        for k in 0..groups {
+            let group = (first + k) % groups;
+            if self.free[group] == 0 {
+                continue;
+            }
+            let (begin, end) = self.group_range(group);
+            let found = if k == 0 {
+                bitmap_alloc_range(vol, self.start, goal, end)
+                    .or_else(|| bitmap_alloc_range(vol, self.start, begin, goal))
+            } else {
+                bitmap_alloc_range(vol, self.start, begin, end)
+            };
+            let Some(n) = found else {
+                //整组都找过了，空闲数已经过时
+                self.free[group] = 0;
+                continue;
+            };
+            self.free[group] = self.free[group].saturating_sub(1);
+            self.cursor = (n as usize + 1) % self.limit;
+            return Some(n);
+        }
+        None
+    }
+}
+
+///锁住start页开始的位图的块组，块组还不是当前布局的时先从位图中数出来
+fn lock_groups(vol: &Volume, start: usize) -> Option<MutexGuard<'_, Groups>> {
+    let layout = &vol.layout;
+    let (groups, limit, size) = if start == layout.inode_map_start {
+        (&vol.alloc.inodes, layout.inode_num, layout.group_inodes)
+    } else if start == layout.data_map_start {
+        (&vol.alloc.data, layout.data_num, layout.group_blocks)
+    } else {
+        return None;
+    };
+    let mut groups = groups.lock();
+    if (groups.start, groups.limit, groups.size) != (start, limit, size) {
+        *groups = Groups::load(vol, start, limit, size);
+    }
+    Some(groups)
+}
+
+///从位图中重新数出各组的空闲数，格式化和挂载（重放日志之后）时调用
+pub fn load_groups(vol: &Volume) {
+    let layout = &vol.layout;
+    *vol.alloc.inodes.lock() = Groups::load(
+        vol,
+        layout.inode_map_start,
+        layout.inode_num,
+        layout.group_inodes,
+    );
+    *vol.alloc.data.lock() = Groups::load(
+        vol,
+        layout.data_map_start,
+        layout.data_num,
+        layout.group_blocks,
+    );
+}
+
+///位图中的第n位被bitmap_set改成了allocated，调用时不能持有该位图页
+pub(crate) fn note_bit(vol: &Volume, start: usize, n: usize, allocated: bool) {
+    let Some(mut groups) = lock_groups(vol, start) else {
+        return;
+    };
+    if n >= groups.limit {
+        return;
+    }
+    let group = n / groups.size;
+    let free = &mut groups.free[group];
+    *free = if allocated {
+        free.saturating_sub(1)
+    } else {
+        *free + 1
+    };
+}
+
+///在从start页开始的位图中分配一位，优先goal，goal为None时从next-fit游标开始找
+pub(crate) fn alloc_bit(vol: &Volume, start: usize, goal: Option<usize>) -> Option<u32> {
+    lock_groups(vol, start)?.alloc(vol, goal)
+}
+
+///为parent目录下新建的file_type类型的文件选择inode组并分配inode
+pub(crate) fn alloc_inode_bit(vol: &Volume, parent: InodeId, file_type: FileType) -> Option<u32> {
+    let mut inodes = lock_groups(vol, vol.layout.inode_map_start)?;
+    let parent_group = (parent.0 as usize / inodes.size).min(inodes.free.len() - 1);
+    let group = if file_type == FileType::DIR {
+        dir_group(vol, &inodes, parent_group)
+    } else {
+        parent_group
+    };
+    let goal = group * inodes.size;
+    inodes.alloc(vol, Some(goal))
+}
+
+///为新目录选择inode组：从父目录的下一组开始，在空闲inode不少于平均值的组中
+///选对应数据组空闲块最多的，相同时选靠前的，使目录分散到各组
+fn dir_group(vol: &Volume, inodes: &Groups, parent_group: usize) -> usize {
+    let groups = inodes.free.len();
+    let average = inodes.free.iter().map(|&free| free as usize).sum::<usize>() / groups;
+    let data = vol.alloc.data.lock();
+    (1..=groups)
+        .rev()
+        .map(|k| (parent_group + k) % groups)
+        .filter(|&group| inodes.free[group] as usize >= average.max(1))
+        .max_by_key(|&group| data.free.get(group).copied().unwrap_or(0))
+        .unwrap_or(parent_group)
+}
+
+///inode对应的数据组的第一个块，文件的第一个数据块从这里开始找
+pub(crate) fn home_block(vol: &Volume, inode_id: InodeId) -> i32 {
+    let layout = &vol.layout;
+    let inode_group = inode_id.0 as usize / layout.group_inodes;
+    let group = inode_group * layout.data_groups() / layout.inode_groups().max(1);
+    (group * layout.group_blocks) as i32
+}
+
+///(inode组, 数据组)各组的空闲数，供fsck和测试使用
+pub fn group_free_counts(vol: &Volume) -> (Vec<u32>, Vec<u32>) {
+    let inodes = lock_groups(vol, vol.layout.inode_map_start)
+        .unwrap()
+        .free
+        .clone();
+    let blocks = lock_groups(vol, vol.layout.data_map_start)
+        .unwrap()
+        .free
+        .clone();
+    (inodes, blocks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::mem_bpm;
+    use crate::fs::layout::{Layout, LayoutOptions};
+    use crate::fs::superblock::{format, mount};
+    use crate::fs::types::{
+        alloc_block, alloc_block_near, alloc_inode_near, bitmap_set, free_block, free_inode,
+    };
+
+    #[test]
+    fn test_groups() {
+        //数据区与日志区为32页时的默认布局相同
+        let options = LayoutOptions {
+            group_blocks: Some(128),
+            journal_pages: Some(0),
+            data_start: Some(43),
+            ..Default::default()
+        };
+        let layout = Layout::new(1024, options).unwrap();
+        let vol = &format(mem_bpm(1, 20), layout, 0);
+        let (inodes, blocks) = group_free_counts(vol);
+        assert_eq!(inodes.len(), 8);
+        assert_eq!(inodes[0], 31);
+        assert_eq!(blocks, [128, 128, 128, 128, 128, 128, 128, 85]);
+
+        //普通文件放在父目录的组，目录分散到其他组
+        let file = alloc_inode_near(vol, InodeId(0), FileType::REG).unwrap();
+        assert_eq!(file.0, 1);
+        let dir = alloc_inode_near(vol, InodeId(0), FileType::DIR).unwrap();
+        assert_eq!(dir.0, 32);
+        let sub = alloc_inode_near(vol, dir, FileType::REG).unwrap();
+        assert_eq!(sub.0, 33);
+        //数据组1的空闲块少了，下一个目录跳过它
+        for _ in 0..10 {
+            alloc_block_near(vol, Some(128), false).unwrap();
+        }
+        let dir2 = alloc_inode_near(vol, dir, FileType::DIR).unwrap();
+        assert_eq!(dir2.0 as usize / layout.group_inodes, 2);
+
+        //文件的第一个数据块在inode对应的数据组中
+        let mut inode = sub.load(vol);
+        inode.init(vol, sub, FileType::REG, 0o644, 0, 0);
+        assert_eq!(home_block(vol, sub), 128);
+        let page_id = inode.block_page_id_or_alloc(vol, 0).unwrap();
+        assert_eq!(page_id, layout.data_page_id(138));
+
+        //没有目标位置时从上次分配的位置之后接着找
+        let block = alloc_block(vol, false).unwrap();
+        assert_eq!(block, 139);
+        free_block(vol, 128);
+        assert_eq!(alloc_block(vol, false).unwrap(), 140);
+
+        //写满一组后分配跳到下一组，空闲数与位图一致
+        for _ in 0..116 {
+            alloc_block_near(vol, Some(130), false).unwrap();
+        }
+        assert_eq!(group_free_counts(vol).1[1], 0);
+        assert_eq!(alloc_block_near(vol, Some(130), false).unwrap(), 256);
+        free_inode(vol, file);
+        bitmap_set(vol, layout.data_map_start, 300, true);
+        let counts = group_free_counts(vol);
+        let vol = &mount(vol.bpm.clone()).unwrap();
+        assert_eq!(group_free_counts(vol), counts);
+        assert_eq!(counts.0[0], 31);
+        assert_eq!(counts.1[2], 126);
+    }
+}
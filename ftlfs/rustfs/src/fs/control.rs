@@ -0,0 +1,118 @@
+//! 挂载点下的只读虚拟目录/.rustfs，其中的文件不占用inode，也不出现在根目录的列表中。
+//! 文件内容在open时生成一次快照，之后的读取都来自这份快照，
+//! 和/proc一样按路径stat时大小为0，并用direct_io打开，读取不受文件大小的限制：
+//!
+//! - /.rustfs/stats：缓存池的统计信息，见ParallelBufferPoolManager::stats_report
+use crate::fs::utils::now;
+use crate::fs::volume::Volume;
+use libc::{getgid, getuid};
+
+pub const CONTROL_DIR: &str = "/.rustfs";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Entry {
+    Dir,
+    Stats,
+}
+
+const FILES: [(&str, Entry); 1] = [("stats", Entry::Stats)];
+
+///path是否在/.rustfs之下（包括它本身），这些路径不能被创建、删除或修改
+pub fn is_control(path: &str) -> bool {
+    path.strip_prefix(CONTROL_DIR)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+pub fn lookup(path: &str) -> Option<Entry> {
+    if path == CONTROL_DIR {
+        return Some(Entry::Dir);
+    }
+    let name = path.strip_prefix(CONTROL_DIR)?.strip_prefix('/')?;
+    FILES
+        .iter()
+        .find(|(file, _)| *file == name)
+        .map(|&(_, entry)| entry)
+}
+
+///目录中的文件名和对应的文件
+pub fn list() -> Vec<(&'static str, Entry)> {
+    FILES.to_vec()
+}
+
+///文件的当前内容，在open时调用，目录返回None
+pub fn content(vol: &Volume, entry: Entry) -> Option<String> {
+    match entry {
+        Entry::Dir => None,
+        Entry::Stats => Some(vol.bpm.stats_report()),
+    }
+}
+
+///文件大小为0，打开的文件由调用者换成快照的长度。属主为挂载文件系统的用户
+pub fn fill_stat(entry: Entry, stat: &mut libc::stat) {
+    let now = now();
+    stat.st_ino = match entry {
+        Entry::Dir => libc::ino_t::MAX - 1,
+        Entry::Stats => libc::ino_t::MAX - 2,
+    };
+    (stat.st_mode, stat.st_nlink) = match entry {
+        Entry::Dir => (libc::S_IFDIR | 0o555, 2),
+        Entry::Stats => (libc::S_IFREG | 0o444, 1),
+    };
+    stat.st_size = 0;
+    stat.st_uid = unsafe { getuid() };
+    stat.st_gid = unsafe { getgid() };
+    stat.st_atime = now.tv_sec;
+    stat.st_atime_nsec = now.tv_nsec;
+    stat.st_mtime = now.tv_sec;
+    stat.st_mtime_nsec = now.tv_nsec;
+    stat.st_ctime = now.tv_sec;
+    stat.st_ctime_nsec = now.tv_nsec;
+}
+
+///从offset开始读出快照的内容，返回读出的字节数
+pub fn read(snapshot: &[u8], offset: usize, buf: &mut [u8]) -> usize {
+    let Some(rest) = snapshot.get(offset..) else {
+        return 0;
+    };
+    let len = rest.len().min(buf.len());
+    buf[..len].copy_from_slice(&rest[..len]);
+    len
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::mem_bpm;
+    use crate::fs::layout::{Layout, LayoutOptions};
+    use crate::fs::superblock::format;
+
+    #[test]
+    fn test_control() {
+        let layout = Layout::new(1024, LayoutOptions::default()).unwrap();
+        let vol = &format(mem_bpm(2, 8), layout, 0);
+        assert!(is_control("/.rustfs"));
+        assert!(is_control("/.rustfs/x"));
+        assert!(!is_control("/.rustfsx"));
+        assert!(!is_control("/a/.rustfs"));
+        assert_eq!(lookup("/.rustfs"), Some(Entry::Dir));
+        assert_eq!(lookup("/.rustfs/stats"), Some(Entry::Stats));
+        assert_eq!(lookup("/.rustfs/x"), None);
+        assert_eq!(list(), [("stats", Entry::Stats)]);
+
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        fill_stat(Entry::Dir, &mut stat);
+        assert_eq!(stat.st_mode, libc::S_IFDIR | 0o555);
+        fill_stat(Entry::Stats, &mut stat);
+        assert_eq!(stat.st_mode, libc::S_IFREG | 0o444);
+        assert_eq!(stat.st_size, 0);
+
+        let text = content(vol, Entry::Stats).unwrap();
+        assert!(text.starts_with("instances 2\nframes 16\n"));
+        assert!(text.contains("\ninstance1 frames 8 "));
+        assert_eq!(content(vol, Entry::Dir), None);
+        let mut buf = vec![0u8; 10];
+        assert_eq!(read(text.as_bytes(), 0, &mut buf), 10);
+        assert_eq!(&buf, b"instances ");
+        assert_eq!(read(text.as_bytes(), 1 << 20, &mut buf), 0);
+    }
+}
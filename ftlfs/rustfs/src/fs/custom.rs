@@ -10,5 +10,11 @@ pub const DATA_MAP_PAGE_ID: usize = 2;
 pub const INODE_START_PAGE_ID: usize = 3;
 pub const DATA_START_PAGE_ID: usize = 256;
 pub const INODE_SIZE: usize = 128;
+///inode表能容纳的inode数
+pub const INODE_NUM: usize = (DATA_START_PAGE_ID - INODE_START_PAGE_ID) * (PAGE_SIZE / INODE_SIZE);
 pub const MAX_FILE_NAME: usize = 128;
 pub const DIR_ENTRY_PER_PAGE: usize = PAGE_SIZE / 256;
+pub const DIRECT_INDEX_NUM: usize = 12;
+pub const INDEX_PER_PAGE: usize = PAGE_SIZE / 4;
+pub const MAX_FILE_BLOCK_NUM: usize =
+    DIRECT_INDEX_NUM + INDEX_PER_PAGE + INDEX_PER_PAGE * INDEX_PER_PAGE;
@@ -1,208 +1,982 @@
+//! 目录缓存：缓存查找过的路径分量，磁盘上的目录才是准确的。
+//!
+//! - 每个目录节点有自己的锁，保护它已经查找过的名字。查找时同一时刻只持有一个目录的锁，
+//!   修改目录时从检查名字到写完磁盘一直持有该目录的锁，跨目录的rename另外持有rename_lock
+//! - 磁盘上不存在的名字缓存为负目录项，再次查找时不必读目录
+//! - 目录项总数（包括负目录项）超过上限时，从最久没用过的开始替换掉整棵子树。
+//!   search返回的节点在使用期间连同它的祖先都不会被替换，保证同一个目录在缓存中只有一个节点
 use crate::buffer::buffer_pool_manager::AutoUnpin;
-use crate::buffer::buffer_pool_manager::BPM;
 use crate::buffer::replacer::PageId;
-use crate::buffer::replacer::{LRUReplacer, Replacer};
-use crate::fs::custom::INODE_MAP_PAGE_ID;
 use crate::fs::def::SUCCESS;
-use crate::fs::types::{FileType, InodeId};
+use crate::fs::handle;
+use crate::fs::perm::{self, caller, init_owner, Cred};
+use crate::fs::types::{alloc_inode_near, check_name, free_inode, FileType, InodeId};
+use crate::fs::utils::now;
+use crate::fs::volume::Volume;
 use crate::{fetch_page_read, fetch_page_write, fetch_page_write_lk};
 use libc::c_int;
 use log::{debug, error, trace};
+use parking_lot::Mutex;
 use std::collections::HashMap;
-use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+
+///挂载时目录缓存的目录项上限
+pub const DCACHE_ENTRIES: usize = 4096;
 
 pub struct DEntry {
-    pub children: HashMap<String, Box<DEntry>>,
-    pub father: Option<NonNull<DEntry>>,
     pub file_type: FileType,
     pub inode_id: InodeId,
-    pub name: String,
+    ///父目录，根目录和被替换出缓存的子树的根没有父目录
+    parent: Mutex<Weak<DEntry>>,
+    ///目录中已经查找过的名字
+    children: Mutex<HashMap<String, Child>>,
+    ///所属缓存的目录项数，节点释放时减去它的子项
+    entries: Arc<AtomicUsize>,
+}
+
+struct Child {
+    ///None是负目录项，表示目录中没有这个名字
+    entry: Option<Arc<DEntry>>,
+    last_used: u64,
 }
 
 impl DEntry {
-    pub fn new(
-        children: HashMap<String, Box<DEntry>>,
-        father: Option<NonNull<DEntry>>,
-        file_type: FileType,
-        inode_id: InodeId,
-        name: String,
-    ) -> Self {
-        Self {
-            children,
-            father,
-            file_type,
-            inode_id,
-            name,
-        }
+    ///缓存中的父目录，已被删除或被替换出缓存时为None
+    pub fn parent(&self) -> Option<Arc<DEntry>> {
+        self.parent.lock().upgrade()
     }
 }
 
-unsafe impl Send for DCache {}
-
-unsafe impl Sync for DCache {}
+impl Drop for DEntry {
+    fn drop(&mut self) {
+        self.entries
+            .fetch_sub(self.children.get_mut().len(), Ordering::Relaxed);
+    }
+}
 
 pub struct DCache {
-    root: DEntry,
-    max_inode_num: usize,
-    inode_num: usize,
-    replacer: LRUReplacer<*const DEntry>,
+    vol: Arc<Volume>,
+    root: Arc<DEntry>,
+    max_entries: usize,
+    entries: Arc<AtomicUsize>,
+    ///访问计数，作为目录项最近一次被使用的时间
+    clock: AtomicU64,
+    ///跨目录的rename互斥，检查目录是否被移到自己的子树中时父目录不会变化
+    rename_lock: Mutex<()>,
+    evicting: Mutex<()>,
 }
 
 impl DCache {
-    pub fn new(max_inode_num: usize) -> Self {
-        let root = DEntry::new(
-            HashMap::new(),
-            None,
-            FileType::DIR,
-            InodeId(0),
-            String::from("/"),
-        );
-        let replacer = LRUReplacer::new(max_inode_num);
+    pub fn new(vol: Arc<Volume>, max_entries: usize) -> Self {
+        let entries = Arc::new(AtomicUsize::new(0));
+        let root = Arc::new(DEntry {
+            file_type: FileType::DIR,
+            inode_id: InodeId(0),
+            parent: Mutex::new(Weak::new()),
+            children: Mutex::new(HashMap::new()),
+            entries: entries.clone(),
+        });
         Self {
+            vol,
             root,
-            max_inode_num,
-            inode_num: 0,
-            replacer,
+            max_entries,
+            entries,
+            clock: AtomicU64::new(0),
+            rename_lock: Mutex::new(()),
+            evicting: Mutex::new(()),
+        }
+    }
+
+    pub fn root(&self) -> Arc<DEntry> {
+        self.root.clone()
+    }
+
+    ///缓存的目录项数，包括负目录项
+    pub fn entries(&self) -> usize {
+        self.entries.load(Ordering::Relaxed)
+    }
+
+    fn new_entry(
+        &self,
+        parent: &Arc<DEntry>,
+        inode_id: InodeId,
+        file_type: FileType,
+    ) -> Arc<DEntry> {
+        Arc::new(DEntry {
+            file_type,
+            inode_id,
+            parent: Mutex::new(Arc::downgrade(parent)),
+            children: Mutex::new(HashMap::new()),
+            entries: self.entries.clone(),
+        })
+    }
+
+    ///在持有锁的目录中记下name对应的目录项，entry为None时记为负目录项
+    fn set_child(
+        &self,
+        children: &mut HashMap<String, Child>,
+        name: &str,
+        entry: Option<Arc<DEntry>>,
+    ) {
+        let child = Child {
+            entry,
+            last_used: self.clock.fetch_add(1, Ordering::Relaxed),
+        };
+        if children.insert(name.to_string(), child).is_none() {
+            self.entries.fetch_add(1, Ordering::Relaxed);
         }
     }
 
     /// 从根目录开始寻找指定路径的目录项
-    pub fn search(&mut self, path: &str) -> Option<NonNull<DEntry>> {
+    pub fn search(&self, path: &str) -> Option<Arc<DEntry>> {
         trace!("searching path: {}", path);
-        let mut cur = NonNull::from(&mut self.root);
-        let mut path_iter = path.split('/').peekable();
-        while let Some(p) = path_iter.next() {
-            if p.is_empty() {
-                continue;
-            }
-            if let Some(child) = unsafe { cur.as_mut().children.get_mut(p) } {
-                if child.file_type != FileType::DIR && path_iter.peek().is_some() {
-                    return None;
-                }
-                cur = NonNull::from(child.as_mut());
-            } else if let Some(new_node) = unsafe { self.try_load_dir_entry(cur, p) } {
-                cur = new_node;
-            } else {
+        let mut cur = self.root.clone();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            //过长的名字不可能存在，也不缓存为负目录项
+            if cur.file_type != FileType::DIR || check_name(name) != SUCCESS {
                 return None;
             }
+            cur = self.lookup(&cur, name)?;
         }
         Some(cur)
     }
 
-    unsafe fn try_load_dir_entry(
-        &mut self,
-        dir: NonNull<DEntry>,
-        target: &str,
-    ) -> Option<NonNull<DEntry>> {
-        let inode_id = dir.as_ref().inode_id;
-        let bpm = BPM.as_ref().unwrap();
-        let (page_id, offset) = inode_id.seek();
-        fetch_page_read!(inode_page: inode_page, bpm, page_id, auto_unpin_inode_page);
-        let inode = &inode_page.inodes[offset];
-        let (inode_id, file_type) = inode.search_dir_by_name(target)?;
-        let load_dir_entry = Box::new(DEntry::new(
-            HashMap::new(),
-            Some(dir),
-            file_type,
-            inode_id,
-            target.to_string(),
-        ));
-        (*dir.as_ptr())
-            .children
-            .insert(target.to_string(), load_dir_entry);
-        let ptr = (*dir.as_ptr()).children.get_mut(target).unwrap().as_mut();
-        Some(NonNull::from(ptr))
-    }
-    /// # Safety
-    /// 解引用了裸指针
+    ///按cred的权限查找路径，路径中的每个目录都需要搜索权限
+    pub fn resolve(&self, path: &str, cred: &Cred) -> Result<Arc<DEntry>, c_int> {
+        let vol = &*self.vol;
+        let mut cur = self.root.clone();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if cur.file_type != FileType::DIR {
+                return Err(-libc::ENOTDIR);
+            }
+            let ret = check_name(name);
+            if ret != SUCCESS {
+                return Err(ret);
+            }
+            perm::check(&cur.inode_id.load(vol), cred, libc::X_OK)?;
+            cur = self.lookup(&cur, name).ok_or(-libc::ENOENT)?;
+        }
+        Ok(cur)
+    }
+
+    ///在目录中查找name，不在缓存中时从磁盘读出并缓存结果，不存在时缓存为负目录项
+    pub fn lookup(&self, dir: &Arc<DEntry>, name: &str) -> Option<Arc<DEntry>> {
+        let vol = &*self.vol;
+        let mut children = dir.children.lock();
+        if let Some(child) = children.get_mut(name) {
+            child.last_used = self.clock.fetch_add(1, Ordering::Relaxed);
+            return child.entry.clone();
+        }
+        let entry = dir
+            .inode_id
+            .load(vol)
+            .search_dir_by_name(vol, name)
+            .map(|(inode_id, file_type)| self.new_entry(dir, inode_id, file_type));
+        self.set_child(&mut children, name, entry.clone());
+        drop(children);
+        self.shrink();
+        entry
+    }
+
     //在一个目录下插入一个目录项，先判断是否有重复
-    pub unsafe fn insert(
-        &mut self,
-        dir: NonNull<DEntry>,
+    pub fn insert(&self, dir: &Arc<DEntry>, name: &str, file_type: FileType, mode: u32) -> c_int {
+        match self.create(dir, name, file_type, mode) {
+            Ok(_) => SUCCESS,
+            Err(ret) => ret,
+        }
+    }
+
+    ///insert的实现，成功时返回新建的inode号，fuse的create用它直接打开新文件
+    pub fn create(
+        &self,
+        dir: &Arc<DEntry>,
         name: &str,
         file_type: FileType,
-    ) -> c_int {
-        if (*dir.as_ptr()).children.get_mut(name).is_some() {
-            return libc::EEXIST;
+        mode: u32,
+    ) -> Result<InodeId, c_int> {
+        let vol = &*self.vol;
+        let ret = check_name(name);
+        if ret != SUCCESS {
+            return Err(ret);
+        }
+        let mut children = dir.children.lock();
+        if children
+            .get(name)
+            .is_some_and(|child| child.entry.is_some())
+        {
+            return Err(-libc::EEXIST);
         }
-        let inode_id = dir.as_ref().inode_id;
-        let bpm = BPM.as_ref().unwrap();
-        let (page_id, offset) = inode_id.seek();
+        let inode_id = dir.inode_id;
+        let (page_id, offset) = inode_id.seek(vol);
         fetch_page_write_lk!(
             inode_page: inode_page,
-            bpm,
+            vol,
             page_id,
             auto_unpin_inode_page,
             lk_i
         );
         let inode = &mut inode_page.inodes[offset];
-        if inode.search_dir_by_name(name).is_some() {
-            return libc::EEXIST;
+        if inode.search_dir_by_name(vol, name).is_some() {
+            return Err(-libc::EEXIST);
         }
         //目录项写入磁盘
-        fetch_page_write_lk!(inode_bit_map: bitmap, bpm, INODE_MAP_PAGE_ID, au, lk);
-        let inode_id = inode_bit_map.alloc().unwrap();
-        drop(lk);
-        inode.add_dir_entry(name, file_type, InodeId(inode_id));
-        //inode写入磁盘
-        let (new_page_id, offset) = InodeId(inode_id).seek();
-        if new_page_id != page_id {
-            fetch_page_write!(inode_page: inode_page, bpm, new_page_id, au);
-            let inode = &mut inode_page.inodes[offset];
-            inode.init_dir(InodeId(inode_id));
-        } else {
-            let inode = &mut inode_page.inodes[offset];
-            inode.init_dir(InodeId(inode_id));
+        let Some(InodeId(inode_id)) = alloc_inode_near(vol, inode_id, file_type) else {
+            return Err(-libc::ENOSPC);
+        };
+        let ret = inode.add_dir_entry(vol, name, file_type, InodeId(inode_id));
+        if ret != SUCCESS {
+            free_inode(vol, InodeId(inode_id));
+            return Err(ret);
+        }
+        let now = now();
+        inode.set_mtime(now);
+        inode.set_ctime(now);
+        if file_type == FileType::DIR {
+            inode.nlink += 1;
         }
+        //setgid目录中新建的inode继承目录的组
+        let (uid, gid, mode) = init_owner(inode, &caller(), file_type == FileType::DIR, mode);
+        //先放开目录inode所在的页再写新inode，不同时持有两个inode页的锁。
+        //目录的锁还没有放开，其他线程在此之前看不到新的目录项
         drop(lk_i);
+        drop(auto_unpin_inode_page);
+        //inode写入磁盘
+        let (new_page_id, offset) = InodeId(inode_id).seek(vol);
+        {
+            fetch_page_write!(inode_page: inode_page, vol, new_page_id, au);
+            inode_page.inodes[offset].init(vol, InodeId(inode_id), file_type, mode, uid, gid);
+        }
         //目录项写入内存
-        (*dir.as_ptr()).children.insert(
-            name.to_string(),
-            Box::new(DEntry::new(
-                HashMap::new(),
-                Some(dir),
-                file_type,
-                InodeId(inode_id),
-                name.to_string(),
-            )),
-        );
+        let entry = self.new_entry(dir, InodeId(inode_id), file_type);
+        self.set_child(&mut children, name, Some(entry));
+        drop(children);
+        self.shrink();
         debug!("insert dir entry success");
+        Ok(InodeId(inode_id))
+    }
+
+    //从目录中删除一个目录项，is_dir为true时对应rmdir，否则对应unlink
+    pub fn remove(&self, dir: &Arc<DEntry>, name: &str, is_dir: bool) -> c_int {
+        let vol = &*self.vol;
+        if dir.file_type != FileType::DIR {
+            return -libc::ENOTDIR;
+        }
+        let ret = check_name(name);
+        if ret != SUCCESS {
+            return ret;
+        }
+        let mut children = dir.children.lock();
+        let dir_inode_id = dir.inode_id;
+        let Some((inode_id, file_type)) = dir_inode_id.load(vol).search_dir_by_name(vol, name)
+        else {
+            return -libc::ENOENT;
+        };
+        if is_dir && file_type != FileType::DIR {
+            return -libc::ENOTDIR;
+        }
+        if !is_dir && file_type == FileType::DIR {
+            return -libc::EISDIR;
+        }
+        //持有要删除的目录的锁，检查它为空之后不会有人在其中创建文件
+        let victim = children.get(name).and_then(|child| child.entry.clone());
+        let victim_children = victim
+            .as_ref()
+            .filter(|_| is_dir)
+            .map(|victim| victim.children.lock());
+        if is_dir && !inode_id.load(vol).is_empty_dir(vol) {
+            return -libc::ENOTEMPTY;
+        }
+        let (page_id, offset) = dir_inode_id.seek(vol);
+        {
+            fetch_page_write!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+            let dir_inode = &mut inode_page.inodes[offset];
+            dir_inode.remove_dir_entry(vol, name);
+            let now = now();
+            dir_inode.set_mtime(now);
+            dir_inode.set_ctime(now);
+            if is_dir {
+                dir_inode.nlink -= 1;
+            }
+        }
+        self.drop_link(inode_id);
+        drop(victim_children);
+        self.set_child(&mut children, name, None);
+        drop(children);
+        self.shrink();
+        debug!("remove dir entry success");
+        SUCCESS
+    }
+
+    //把old_dir下的old_name移动为new_dir下的new_name，new_name已存在时原地替换它
+    pub fn rename(
+        &self,
+        old_dir: &Arc<DEntry>,
+        old_name: &str,
+        new_dir: &Arc<DEntry>,
+        new_name: &str,
+    ) -> c_int {
+        let vol = &*self.vol;
+        if old_dir.file_type != FileType::DIR || new_dir.file_type != FileType::DIR {
+            return -libc::ENOTDIR;
+        }
+        for name in [old_name, new_name] {
+            let ret = check_name(name);
+            if ret != SUCCESS {
+                return ret;
+            }
+        }
+        let same_dir = Arc::ptr_eq(old_dir, new_dir);
+        let _rename = (!same_dir).then(|| self.rename_lock.lock());
+        //多个目录总是先锁祖先再锁后代，无关的两个目录之间的顺序由rename_lock保证
+        let (mut old_children, mut new_guard) = if same_dir {
+            (old_dir.children.lock(), None)
+        } else if Self::is_ancestor(new_dir, old_dir) {
+            let new_children = new_dir.children.lock();
+            (old_dir.children.lock(), Some(new_children))
+        } else {
+            let old_children = old_dir.children.lock();
+            (old_children, Some(new_dir.children.lock()))
+        };
+        let old_dir_id = old_dir.inode_id;
+        let new_dir_id = new_dir.inode_id;
+        let Some((inode_id, file_type)) = old_dir_id.load(vol).search_dir_by_name(vol, old_name)
+        else {
+            return -libc::ENOENT;
+        };
+        let is_dir = file_type == FileType::DIR;
+        //不能把目录移动到它自己的子树中
+        if is_dir {
+            let mut cur = Some(new_dir.clone());
+            while let Some(node) = cur {
+                if node.inode_id == inode_id {
+                    return -libc::EINVAL;
+                }
+                cur = node.parent.lock().upgrade();
+            }
+        }
+        let target = new_dir_id.load(vol).search_dir_by_name(vol, new_name);
+        if let Some((target_id, target_type)) = target {
+            if target_id == inode_id {
+                return SUCCESS;
+            }
+            match (is_dir, target_type == FileType::DIR) {
+                (true, false) => return -libc::ENOTDIR,
+                (false, true) => return -libc::EISDIR,
+                _ => {}
+            }
+        }
+        //与remove相同，被替换的目录检查为空时持有它的锁。它是old_dir或old_dir的祖先时一定不为空，
+        //这时不能再锁它
+        let victim = new_guard
+            .as_ref()
+            .unwrap_or(&old_children)
+            .get(new_name)
+            .and_then(|child| child.entry.clone())
+            .filter(|victim| {
+                victim.file_type == FileType::DIR
+                    && !Arc::ptr_eq(victim, old_dir)
+                    && !Self::is_ancestor(victim, old_dir)
+            });
+        let victim_children = victim.as_ref().map(|victim| victim.children.lock());
+        if let Some((target_id, FileType::DIR)) = target {
+            if !target_id.load(vol).is_empty_dir(vol) {
+                return -libc::ENOTEMPTY;
+            }
+        }
+        let now = now();
+        let cross_dir = is_dir && old_dir_id != new_dir_id;
+        //先让新名字指向源inode，已存在的目标在这一步被原地替换
+        {
+            let (page_id, offset) = new_dir_id.seek(vol);
+            fetch_page_write!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+            let dir_inode = &mut inode_page.inodes[offset];
+            if target.is_some() {
+                dir_inode.replace_dir_entry(vol, new_name, file_type, inode_id);
+            } else {
+                let ret = dir_inode.add_dir_entry(vol, new_name, file_type, inode_id);
+                if ret != SUCCESS {
+                    return ret;
+                }
+            }
+            if cross_dir {
+                dir_inode.nlink += 1;
+            }
+            if matches!(target, Some((_, FileType::DIR))) {
+                dir_inode.nlink -= 1;
+            }
+            dir_inode.set_mtime(now);
+            dir_inode.set_ctime(now);
+        }
+        {
+            let (page_id, offset) = old_dir_id.seek(vol);
+            fetch_page_write!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+            let dir_inode = &mut inode_page.inodes[offset];
+            dir_inode.remove_dir_entry(vol, old_name);
+            if cross_dir {
+                dir_inode.nlink -= 1;
+            }
+            dir_inode.set_mtime(now);
+            dir_inode.set_ctime(now);
+        }
+        {
+            let (page_id, offset) = inode_id.seek(vol);
+            fetch_page_write!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+            inode_page.inodes[offset].set_ctime(now);
+        }
+        if let Some((target_id, _)) = target {
+            self.drop_link(target_id);
+        }
+        drop(victim_children);
+        //同步内存中的目录树，移动的目录连同已缓存的子树一起挂到新目录下
+        let node = old_children
+            .get_mut(old_name)
+            .and_then(|child| child.entry.take());
+        self.set_child(&mut old_children, old_name, None);
+        let node = match node {
+            Some(node) => {
+                *node.parent.lock() = Arc::downgrade(new_dir);
+                node
+            }
+            None => self.new_entry(new_dir, inode_id, file_type),
+        };
+        let new_children = match new_guard.as_mut() {
+            Some(children) => children,
+            None => &mut old_children,
+        };
+        self.set_child(new_children, new_name, Some(node));
+        drop(new_guard);
+        drop(old_children);
+        self.shrink();
+        debug!("rename dir entry success");
         SUCCESS
     }
 
-    pub fn all_dir_entry_name(&self, dir: NonNull<DEntry>) -> Vec<String> {
-        let inode_id = unsafe { dir.as_ref().inode_id };
-        let bpm = unsafe { BPM.as_ref().unwrap() };
-        let (page_id, offset) = inode_id.seek();
-        fetch_page_read!(inode_page: inode_page, bpm, page_id, au);
+    ///ancestor是否是entry的祖先，调用者持有rename_lock
+    fn is_ancestor(ancestor: &Arc<DEntry>, entry: &Arc<DEntry>) -> bool {
+        let mut cur = entry.parent.lock().upgrade();
+        while let Some(node) = cur {
+            if Arc::ptr_eq(&node, ancestor) {
+                return true;
+            }
+            cur = node.parent.lock().upgrade();
+        }
+        false
+    }
+
+    //在dir下创建指向target的符号链接name
+    pub fn symlink(&self, dir: &Arc<DEntry>, name: &str, target: &str) -> c_int {
+        let vol = &*self.vol;
+        if target.len() >= libc::PATH_MAX as usize {
+            return -libc::ENAMETOOLONG;
+        }
+        let inode_id = match self.create(dir, name, FileType::SYMLINK, 0o777) {
+            Ok(inode_id) => inode_id,
+            Err(ret) => return ret,
+        };
+        let written = {
+            let (page_id, offset) = inode_id.seek(vol);
+            fetch_page_write!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+            inode_page.inodes[offset].set_symlink(vol, target.as_bytes())
+        };
+        //长目标写不下时撤销创建
+        if !written {
+            self.remove(dir, name, false);
+            return -libc::ENOSPC;
+        }
+        SUCCESS
+    }
+
+    //为inode_id在new_dir下增加一个名为new_name的硬链接，目录不能有硬链接
+    pub fn link(
+        &self,
+        inode_id: InodeId,
+        file_type: FileType,
+        new_dir: &Arc<DEntry>,
+        new_name: &str,
+    ) -> c_int {
+        let vol = &*self.vol;
+        if file_type == FileType::DIR {
+            return -libc::EPERM;
+        }
+        if new_dir.file_type != FileType::DIR {
+            return -libc::ENOTDIR;
+        }
+        let ret = check_name(new_name);
+        if ret != SUCCESS {
+            return ret;
+        }
+        let mut children = new_dir.children.lock();
+        let new_dir_id = new_dir.inode_id;
+        if children
+            .get(new_name)
+            .is_some_and(|child| child.entry.is_some())
+            || new_dir_id.load(vol).search_dir_by_name(vol, new_name).is_some()
+        {
+            return -libc::EEXIST;
+        }
+        let now = now();
+        {
+            let (page_id, offset) = new_dir_id.seek(vol);
+            fetch_page_write!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+            let dir_inode = &mut inode_page.inodes[offset];
+            let ret = dir_inode.add_dir_entry(vol, new_name, file_type, inode_id);
+            if ret != SUCCESS {
+                return ret;
+            }
+            dir_inode.set_mtime(now);
+            dir_inode.set_ctime(now);
+        }
+        {
+            let (page_id, offset) = inode_id.seek(vol);
+            fetch_page_write!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+            let inode = &mut inode_page.inodes[offset];
+            inode.nlink += 1;
+            inode.set_ctime(now);
+        }
+        let entry = self.new_entry(new_dir, inode_id, file_type);
+        self.set_child(&mut children, new_name, Some(entry));
+        drop(children);
+        self.shrink();
+        debug!("link dir entry success");
+        SUCCESS
+    }
+
+    //指向inode的目录项被删除后减少其链接数，链接数归零时交给handles，
+    //由调用者改完目录之后用handle::free_unlinked释放inode和它占用的块
+    fn drop_link(&self, inode_id: InodeId) {
+        let vol = &*self.vol;
+        let (page_id, offset) = inode_id.seek(vol);
+        fetch_page_write!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+        let inode = &mut inode_page.inodes[offset];
+        inode.nlink = if inode.is_dir() {
+            0
+        } else {
+            inode.nlink.saturating_sub(1)
+        };
+        inode.set_ctime(now());
+        if inode.nlink == 0 {
+            vol.handles.defer_free(inode_id);
+        }
+    }
+
+    pub fn all_dir_entry_name(&self, dir: &DEntry) -> Vec<String> {
+        let vol = &*self.vol;
+        let inode_id = dir.inode_id;
+        let (page_id, offset) = inode_id.seek(vol);
+        fetch_page_read!(inode_page: inode_page, vol, page_id, au);
         let inode = &inode_page.inodes[offset];
-        inode.all_dir_entry_name()
+        inode.all_dir_entry_name(vol)
+    }
+
+    ///目录项超过上限时，从最久没用过的开始替换掉没有被使用的子树，直到降到上限的3/4。
+    ///只用try_lock，正被其他线程持有的目录这一轮跳过
+    fn shrink(&self) {
+        if self.entries() <= self.max_entries {
+            return;
+        }
+        let Some(_evicting) = self.evicting.try_lock() else {
+            return;
+        };
+        let mut candidates = Vec::new();
+        Self::collect(&self.root, &mut candidates);
+        candidates.sort_unstable_by_key(|(last_used, _, _)| *last_used);
+        let target = self.max_entries * 3 / 4;
+        for (_, dir, name) in candidates {
+            if self.entries() <= target {
+                break;
+            }
+            let Some(dir) = dir.upgrade() else {
+                continue;
+            };
+            let Some(mut children) = dir.children.try_lock() else {
+                continue;
+            };
+            let unused = match children.get(&name) {
+                Some(child) => child.entry.as_ref().is_none_or(Self::unused),
+                None => false,
+            };
+            if unused {
+                children.remove(&name);
+                self.entries.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        debug!("dcache shrinked to {} entries", self.entries());
+    }
+
+    ///收集所有目录项的(最近使用时间, 所在目录, 名字)
+    fn collect(dir: &Arc<DEntry>, candidates: &mut Vec<(u64, Weak<DEntry>, String)>) {
+        let subdirs: Vec<Arc<DEntry>> = {
+            let Some(children) = dir.children.try_lock() else {
+                return;
+            };
+            for (name, child) in children.iter() {
+                candidates.push((child.last_used, Arc::downgrade(dir), name.clone()));
+            }
+            children
+                .values()
+                .filter_map(|child| child.entry.clone())
+                .filter(|entry| entry.file_type == FileType::DIR)
+                .collect()
+        };
+        for subdir in subdirs.iter() {
+            Self::collect(subdir, candidates);
+        }
+    }
+
+    ///调用者持有entry所在目录的锁。entry和它的子树中的节点都只被父目录引用时才可以替换，
+    ///此时其他线程只能经过父目录找到它们
+    fn unused(entry: &Arc<DEntry>) -> bool {
+        Arc::strong_count(entry) == 1
+            && entry.children.try_lock().is_some_and(|children| {
+                children
+                    .values()
+                    .all(|child| child.entry.as_ref().is_none_or(Self::unused))
+            })
     }
 
     pub fn print(&self) {
-        Self::print_helper(&self.root);
+        Self::print_helper("/", &self.root);
     }
 
-    fn print_helper(dir: &DEntry) {
-        debug!("{} {}", dir.name, dir.inode_id.0);
-        for (_, child) in dir.children.iter() {
-            Self::print_helper(child);
+    fn print_helper(name: &str, dir: &DEntry) {
+        debug!("{} {}", name, dir.inode_id.0);
+        for (name, child) in dir.children.lock().iter() {
+            if let Some(entry) = child.entry.as_ref() {
+                Self::print_helper(name, entry);
+            }
         }
     }
 }
 
-pub static mut D_CACHE: Option<DCache> = None;
-
 #[cfg(test)]
 mod test {
-    use crate::buffer::buffer_pool_manager::{ParallelBufferPoolManager, BPM};
-    use crate::fs::dcache::{DCache, D_CACHE};
-    use crate::fs::utils::start_flusher;
+    use crate::buffer::buffer_pool_manager::{mem_bpm, AutoUnpin};
+    use crate::buffer::replacer::PageId;
+    use crate::fs::dcache::DCache;
+    use crate::fs::def::SUCCESS;
+    use crate::fs::handle::free_unlinked;
+    use crate::fs::layout::{Layout, LayoutOptions};
+    use crate::fs::types::{DEntry, FileType, InodeId};
+    use crate::fs::volume::Volume;
+    use crate::{fetch_page_read, new_page};
+    use log::debug;
+    use std::sync::Arc;
+
+    //在测试磁盘上格式化出只有根目录的文件系统，inode数要够test_large_dir使用
+    fn format(num_instances: usize, pool_size: usize) -> Arc<Volume> {
+        let options = LayoutOptions {
+            inode_num: Some(1024),
+            ..Default::default()
+        };
+        let layout = Layout::new(1024, options).unwrap();
+        Arc::new(crate::fs::superblock::format(mem_bpm(num_instances, pool_size), layout, 0))
+    }
+
+    fn used_blocks(vol: &Volume) -> usize {
+        fetch_page_read!(data_map_page: bitmap, vol, vol.layout.data_map_start, au);
+        data_map_page.data.iter().map(|b| b.count_ones() as usize).sum()
+    }
 
     #[test]
     fn test() {
-        unsafe { BPM = Some(ParallelBufferPoolManager::new(4, 10)) };
-        start_flusher();
-        unsafe { D_CACHE = Some(DCache::new(100)) };
+        let vol = format(4, 10);
+        let dir_tree = DCache::new(vol, 100);
+        assert!(dir_tree.root().parent().is_none());
+    }
+
+    #[test]
+    fn test_remove_and_rename() {
+        let vol = &format(1, 20);
+        let dir_tree = DCache::new(vol.clone(), 100);
+        {
+            let root = dir_tree.search("/").unwrap();
+            assert_eq!(dir_tree.insert(&root, "a", FileType::DIR, 0o755), SUCCESS);
+            let a = dir_tree.search("/a").unwrap();
+            assert_eq!(dir_tree.insert(&a, "f", FileType::REG, 0o644), SUCCESS);
+            let f = dir_tree.search("/a/f").unwrap().inode_id;
+            let blocks = used_blocks(vol);
+            {
+                let (page_id, offset) = f.seek(vol);
+                crate::fetch_page_write!(inode_page: inode_page, vol, page_id, au);
+                assert_eq!(inode_page.inodes[offset].write(vol, 0, &[1u8; 8192]), 8192);
+            }
+            assert_eq!(used_blocks(vol), blocks + 2);
+
+            assert_eq!(dir_tree.remove(&root, "a", true), -libc::ENOTEMPTY);
+            assert_eq!(dir_tree.remove(&root, "a", false), -libc::EISDIR);
+            assert_eq!(dir_tree.rename(&root, "a", &a, "b"), -libc::EINVAL);
+
+            //跨目录移动文件
+            assert_eq!(dir_tree.rename(&a, "f", &root, "g"), SUCCESS);
+            assert!(dir_tree.search("/a/f").is_none());
+            assert_eq!(dir_tree.search("/g").unwrap().inode_id, f);
+            assert_eq!(InodeId(0).load(vol).search_dir_by_name(vol, "g"), Some((f, FileType::REG)));
+
+            //替换已存在的目标，被替换的文件的块被回收
+            assert_eq!(dir_tree.insert(&root, "h", FileType::REG, 0o644), SUCCESS);
+            let h = dir_tree.search("/h").unwrap().inode_id;
+            assert_eq!(dir_tree.rename(&root, "h", &root, "g"), SUCCESS);
+            free_unlinked(vol);
+            assert_eq!(used_blocks(vol), blocks);
+            assert_eq!(dir_tree.search("/g").unwrap().inode_id, h);
+            assert!(dir_tree.search("/h").is_none());
+
+            assert_eq!(dir_tree.remove(&root, "g", true), -libc::ENOTDIR);
+            assert_eq!(dir_tree.remove(&root, "g", false), SUCCESS);
+            assert!(dir_tree.search("/g").is_none());
+            assert_eq!(InodeId(0).load(vol).nlink, 3);
+            assert_eq!(dir_tree.remove(&root, "a", true), SUCCESS);
+            assert!(dir_tree.search("/a").is_none());
+            assert_eq!(InodeId(0).load(vol).nlink, 2);
+            assert!(InodeId(0).load(vol).is_empty_dir(vol));
+        }
+    }
+
+    #[test]
+    fn test_large_dir() {
+        let vol = &format(1, 20);
+        let dir_tree = DCache::new(vol.clone(), 100);
+        {
+            let root = dir_tree.search("/").unwrap();
+            assert_eq!(dir_tree.insert(&root, "d", FileType::DIR, 0o755), SUCCESS);
+            let d = dir_tree.search("/d").unwrap();
+            let d_id = d.inode_id;
+            for i in 0..300 {
+                assert_eq!(
+                    dir_tree.insert(&d, &format!("f{i}"), FileType::REG, 0o644),
+                    SUCCESS
+                );
+            }
+            let inode = d_id.load(vol);
+            assert_ne!(inode.indirect_index, -1);
+            assert_eq!(inode.all_dir_entry_name(vol).len(), 300);
+            assert!(inode.search_dir_by_name(vol, "f299").is_some());
+            let size = inode.size;
+            for i in 0..300 {
+                if i % 2 == 0 {
+                    assert_eq!(dir_tree.remove(&d, &format!("f{i}"), false), SUCCESS);
+                }
+            }
+            //删除后空出的目录项被复用，目录不再增长
+            for i in 0..150 {
+                assert_eq!(
+                    dir_tree.insert(&d, &format!("g{i}"), FileType::REG, 0o644),
+                    SUCCESS
+                );
+            }
+            let inode = d_id.load(vol);
+            assert_eq!(inode.size, size);
+            assert_eq!(inode.all_dir_entry_name(vol).len(), 300);
+            assert!(inode.search_dir_by_name(vol, "f0").is_none());
+            assert!(inode.search_dir_by_name(vol, "g149").is_some());
+        }
+    }
+
+    #[test]
+    fn test_links() {
+        let vol = &format(1, 20);
+        let dir_tree = DCache::new(vol.clone(), 100);
+        {
+            let root = dir_tree.search("/").unwrap();
+            assert_eq!(dir_tree.insert(&root, "d", FileType::DIR, 0o755), SUCCESS);
+            let d = dir_tree.search("/d").unwrap();
+            assert_eq!(dir_tree.insert(&root, "f", FileType::REG, 0o644), SUCCESS);
+            assert_eq!(dir_tree.insert(&root, "f", FileType::REG, 0o644), -libc::EEXIST);
+            let f = dir_tree.search("/f").unwrap().inode_id;
+            {
+                let (page_id, offset) = f.seek(vol);
+                crate::fetch_page_write!(inode_page: inode_page, vol, page_id, au);
+                assert_eq!(inode_page.inodes[offset].write(vol, 0, &[1u8; 100]), 100);
+            }
+
+            //硬链接共享同一个inode，删除最后一个名字时才释放
+            assert_eq!(dir_tree.link(f, FileType::REG, &d, "g"), SUCCESS);
+            let blocks = used_blocks(vol);
+            assert_eq!(dir_tree.link(f, FileType::REG, &d, "g"), -libc::EEXIST);
+            let d_id = d.inode_id;
+            assert_eq!(dir_tree.link(d_id, FileType::DIR, &root, "e"), -libc::EPERM);
+            assert_eq!(dir_tree.search("/d/g").unwrap().inode_id, f);
+            assert_eq!(f.load(vol).nlink, 2);
+            assert_eq!(dir_tree.remove(&root, "f", false), SUCCESS);
+            assert_eq!(f.load(vol).nlink, 1);
+            assert_eq!(used_blocks(vol), blocks);
+            let mut buf = [0u8; 100];
+            assert_eq!(f.load(vol).read(vol, 0, &mut buf), 100);
+            assert_eq!(buf, [1u8; 100]);
+
+            //短目标存放在inode中，长目标占用一个数据块
+            assert_eq!(dir_tree.symlink(&root, "s", "d/g"), SUCCESS);
+            let s = dir_tree.search("/s").unwrap().inode_id;
+            let inode = s.load(vol);
+            assert_eq!(inode.file_type, FileType::SYMLINK);
+            assert_eq!(inode.st_mode(), libc::S_IFLNK | 0o777);
+            assert_eq!(inode.symlink_target(vol), b"d/g");
+            assert_eq!(used_blocks(vol), blocks);
+            let long = "x/".repeat(100);
+            assert_eq!(dir_tree.symlink(&d, "l", &long), SUCCESS);
+            let l = dir_tree.search("/d/l").unwrap().inode_id;
+            assert_eq!(l.load(vol).symlink_target(vol), long.as_bytes());
+            assert_eq!(l.load(vol).size, long.len() as u64);
+            assert_eq!(used_blocks(vol), blocks + 1);
+            assert_eq!(
+                dir_tree.symlink(&d, "m", &"x".repeat(libc::PATH_MAX as usize)),
+                -libc::ENAMETOOLONG
+            );
+            //符号链接本身也可以有硬链接
+            assert_eq!(dir_tree.link(s, FileType::SYMLINK, &d, "t"), SUCCESS);
+            let report = crate::fs::fsck::fsck(vol, false).unwrap();
+            assert!(report.is_clean(), "{report}");
+
+            assert_eq!(dir_tree.remove(&d, "l", false), SUCCESS);
+            assert_eq!(dir_tree.remove(&root, "s", false), SUCCESS);
+            assert_eq!(dir_tree.remove(&d, "t", false), SUCCESS);
+            assert_eq!(dir_tree.remove(&d, "g", false), SUCCESS);
+            free_unlinked(vol);
+            assert_eq!(used_blocks(vol), blocks - 1);
+            let report = crate::fs::fsck::fsck(vol, false).unwrap();
+            assert!(report.is_clean(), "{report}");
+        }
+    }
+
+    #[test]
+    fn test_negative_and_shrink() {
+        let vol = &format(1, 20);
+        let dir_tree = DCache::new(vol.clone(), 16);
+        let root = dir_tree.search("/").unwrap();
+        //不存在的名字缓存为负目录项，创建后变为正目录项
+        assert!(dir_tree.search("/x").is_none());
+        assert_eq!(dir_tree.entries(), 1);
+        assert!(dir_tree.search("/x/y").is_none());
+        assert_eq!(dir_tree.entries(), 1);
+        assert_eq!(dir_tree.insert(&root, "x", FileType::DIR, 0o755), SUCCESS);
+        let x = dir_tree.search("/x").unwrap();
+        assert_eq!(dir_tree.entries(), 1);
+        assert_eq!(dir_tree.insert(&x, "sub", FileType::DIR, 0o755), SUCCESS);
+        let sub = dir_tree.search("/x/sub").unwrap();
+        assert_eq!(dir_tree.insert(&sub, "f", FileType::REG, 0o644), SUCCESS);
+        assert_eq!(dir_tree.remove(&sub, "f", false), SUCCESS);
+        assert!(dir_tree.search("/x/sub/f").is_none());
+        assert_eq!(dir_tree.entries(), 3);
+        drop(x);
+
+        //超过上限后替换最久没有用过的目录项，被持有的节点和它的祖先不会被替换
+        for i in 0..40 {
+            assert_eq!(
+                dir_tree.insert(&root, &format!("f{i}"), FileType::REG, 0o644),
+                SUCCESS
+            );
+            assert!(dir_tree.entries() <= 16);
+        }
+        assert!(Arc::ptr_eq(&dir_tree.search("/x/sub").unwrap(), &sub));
+        let weak = Arc::downgrade(&sub);
+        drop(sub);
+        for i in 0..40 {
+            assert!(dir_tree.search(&format!("/f{i}")).is_some());
+            assert!(dir_tree.entries() <= 16);
+        }
+        assert!(weak.upgrade().is_none());
+        //被替换的目录项重新从磁盘读入
+        assert!(dir_tree.search("/x/sub").is_some());
+        assert!(dir_tree.search("/x/sub/f").is_none());
+    }
+
+    ///多个线程在各自的目录和共享的目录中并发创建、改名和删除，缓存很小，频繁替换
+    #[test]
+    fn test_concurrent() {
+        let vol = &format(4, 16);
+        let dir_tree = &DCache::new(vol.clone(), 32);
+        let root = dir_tree.search("/").unwrap();
+        assert_eq!(
+            dir_tree.insert(&root, "shared", FileType::DIR, 0o755),
+            SUCCESS
+        );
+        std::thread::scope(|s| {
+            for t in 0..4 {
+                s.spawn(move || {
+                    let root = dir_tree.search("/").unwrap();
+                    let name = format!("t{t}");
+                    assert_eq!(dir_tree.insert(&root, &name, FileType::DIR, 0o755), SUCCESS);
+                    for i in 0..40 {
+                        let dir = dir_tree.search(&format!("/{name}")).unwrap();
+                        let shared = dir_tree.search("/shared").unwrap();
+                        let file = format!("f{i}");
+                        assert_eq!(dir_tree.insert(&dir, &file, FileType::REG, 0o644), SUCCESS);
+                        //共享目录中的同名文件只有一个线程能创建
+                        let ret = dir_tree.insert(&shared, &format!("s{i}"), FileType::REG, 0o644);
+                        assert!(ret == SUCCESS || ret == -libc::EEXIST);
+                        if i % 2 == 0 {
+                            let moved = format!("m{t}_{i}");
+                            assert_eq!(dir_tree.rename(&dir, &file, &shared, &moved), SUCCESS);
+                            if i % 4 == 0 {
+                                assert_eq!(dir_tree.remove(&shared, &moved, false), SUCCESS);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        let shared = dir_tree.search("/shared").unwrap().inode_id;
+        assert_eq!(shared.load(vol).all_dir_entry_name(vol).len(), 80);
+        for t in 0..4 {
+            let dir = dir_tree.search(&format!("/t{t}")).unwrap().inode_id;
+            assert_eq!(dir.load(vol).all_dir_entry_name(vol).len(), 20);
+            assert!(dir_tree.search(&format!("/t{t}/f1")).is_some());
+            assert!(dir_tree.search(&format!("/t{t}/f2")).is_none());
+            assert!(dir_tree.search(&format!("/shared/m{t}_2")).is_some());
+            assert!(dir_tree.search(&format!("/shared/m{t}_4")).is_none());
+        }
+        free_unlinked(vol);
+        let report = crate::fs::fsck::fsck(vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+    }
+
+    #[test]
+    fn test_names() {
+        let vol = &format(1, 20);
+        let dir_tree = DCache::new(vol.clone(), 100);
+        let root = dir_tree.search("/").unwrap();
+        //名字按UTF-8字节存放，长度按字节计算
+        let utf8 = "文件名-é😀";
+        let longest = "名".repeat(42) + "ab";
+        assert_eq!(longest.len(), 128);
+        assert_eq!(dir_tree.insert(&root, utf8, FileType::DIR, 0o755), SUCCESS);
+        assert_eq!(
+            dir_tree.insert(&root, &longest, FileType::REG, 0o644),
+            SUCCESS
+        );
+        let too_long = longest.clone() + "c";
+        assert_eq!(
+            dir_tree.insert(&root, &too_long, FileType::REG, 0o644),
+            -libc::ENAMETOOLONG
+        );
+        assert!(dir_tree.search(&format!("/{too_long}")).is_none());
+        assert_eq!(dir_tree.entries(), 2);
+        let dir = dir_tree.search(&format!("/{utf8}")).unwrap();
+        let file = dir_tree.search(&format!("/{longest}")).unwrap().inode_id;
+        assert_eq!(
+            dir_tree.link(file, FileType::REG, &dir, &too_long),
+            -libc::ENAMETOOLONG
+        );
+        assert_eq!(
+            dir_tree.rename(&root, &longest, &dir, &too_long),
+            -libc::ENAMETOOLONG
+        );
+        assert_eq!(
+            dir_tree.remove(&root, &too_long, false),
+            -libc::ENAMETOOLONG
+        );
+        assert_eq!(dir_tree.rename(&root, &longest, &dir, "ü"), SUCCESS);
+        let mut names = InodeId(0).load(vol).all_dir_entry_name(vol);
+        names.extend(dir.inode_id.load(vol).all_dir_entry_name(vol));
+        assert_eq!(names, [utf8, "ü"]);
+
+        //旧版本写入的目录项没有记录名字长度，名字以0结尾
+        let mut entry: DEntry = unsafe { std::mem::zeroed() };
+        entry.init(&longest, FileType::REG, file);
+        assert_eq!(entry.name_bytes(), longest.as_bytes());
+        entry.init("abc", FileType::REG, file);
+        entry.name_len = 0;
+        assert_eq!(entry.name(), "abc");
     }
 }
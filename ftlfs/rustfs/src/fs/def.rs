@@ -7,4 +7,21 @@ pub const PAGE_SIZE_U32: u32 = PAGE_SIZE as u32;
 
 pub const MAGIC_NUM: u32 = 0x52415455;
 
+///磁盘格式版本号，inode格式变化时递增。版本2在inode中加入了大小、权限、属主和时间戳，
+///版本3在超级块中保存磁盘布局，版本4又加入了块大小、空闲计数、UUID、卷标和挂载次数，
+///版本5加入了日志区，版本6在inode中加入了扩展属性块号，版本7起可以用extent树映射数据块
+pub const FS_VERSION: u32 = 7;
+
+///仍能挂载的最低版本，版本2的磁盘使用固定布局
+pub const FS_MIN_VERSION: u32 = 2;
+
+///超级块中的特性位：新建的目录使用哈希索引，格式化时选定
+pub const FEATURE_DIR_INDEX: u32 = 1;
+
+///超级块中的特性位：inode用extent树代替间接索引映射数据块，格式化时选定
+pub const FEATURE_EXTENTS: u32 = 2;
+
+///本版本支持的所有特性位，超级块中有其他特性位时拒绝挂载
+pub const FEATURE_ALL: u32 = FEATURE_DIR_INDEX | FEATURE_EXTENTS;
+
 pub const SUCCESS: c_int = 0;
@@ -0,0 +1,495 @@
+//! 仿照ext4的extent树，格式化时选定后代替直接索引和间接索引映射文件的数据块。
+//!
+//! 一个extent把一段连续的逻辑块映射到一段连续的数据块。inode中原来存放块号的56字节是树根，
+//! 由8字节的节点头和4个extent组成；放不下时树根的内容移到一个新的节点块中，树根变为索引节点。
+//! 节点块中有340项，叶子节点的项是extent，索引节点的项记录子节点覆盖的最小逻辑块号和子节点的块号，
+//! 第0项覆盖所有更小的逻辑块号。节点满时对半分裂，分裂出的新节点插入父节点中，树根满时树增高一层。
+//! 新块紧接在前一个逻辑块之后分配时直接延长前一个extent，所以顺序写入的文件只需要很少的extent。
+use crate::buffer::buffer_pool_manager::AutoUnpin;
+use crate::buffer::replacer::PageId;
+use crate::fs::types::{alloc_block, free_block, Inode};
+use crate::fs::volume::Volume;
+use crate::{fetch_page_read, fetch_page_write};
+use log::{debug, warn};
+
+pub const EXTENT_MAGIC: u16 = 0xf30a;
+
+///树根中的项数
+pub const EXTENT_ROOT_NUM: usize = 4;
+
+///(4096 - 8) / 12 = 340，节点块中的项数
+pub const EXTENT_PER_PAGE: usize = 340;
+
+///extent树最多映射的逻辑块数
+pub const EXTENT_MAX_FILE_BLOCK_NUM: usize = u32::MAX as usize;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ExtentHeader {
+    pub magic: u16,
+    pub count: u16,
+    pub max: u16,
+    ///节点之下还有几层，叶子节点为0
+    pub depth: u16,
+}
+
+///叶子节点中是从逻辑块block开始的len个块，依次映射到从start开始的数据块；
+///索引节点中start是子节点的块号，len不使用
+#[repr(C)]
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Extent {
+    pub block: u32,
+    pub len: u32,
+    pub start: i32,
+}
+
+///inode中的树根，与direct_index、indirect_index和double_indirect_index占用同样的56字节
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ExtentRoot {
+    pub header: ExtentHeader,
+    pub entries: [Extent; EXTENT_ROOT_NUM],
+}
+
+///extent树中的一个节点：inode中的树根或某个节点块
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Node {
+    Root,
+    Block(i32),
+}
+
+///从根到叶子路径上的一个节点，pos是新项在entries中的插入位置
+struct PathNode {
+    node: Node,
+    depth: u16,
+    entries: Vec<Extent>,
+    pos: usize,
+}
+
+impl Inode {
+    ///数据块是否由extent树映射，内联的符号链接不使用extent树
+    pub fn uses_extents(&self, vol: &Volume) -> bool {
+        vol.extents && !self.has_inline_data()
+    }
+
+    fn extent_root(&self) -> &ExtentRoot {
+        let offset = std::mem::offset_of!(Inode, direct_index);
+        unsafe { &*((self as *const Inode as *const u8).add(offset) as *const _) }
+    }
+
+    fn extent_root_mut(&mut self) -> &mut ExtentRoot {
+        let offset = std::mem::offset_of!(Inode, direct_index);
+        unsafe { &mut *((self as *mut Inode as *mut u8).add(offset) as *mut _) }
+    }
+
+    ///初始化为空的extent树，inode初始化时调用
+    pub(crate) fn init_extents(&mut self) {
+        store_root(self, 0, &[]);
+    }
+
+    ///读出一个节点的(depth, 项)，节点块损坏时返回None
+    pub(crate) fn load_extent_node(&self, vol: &Volume, node: Node) -> Option<(u16, Vec<Extent>)> {
+        let (header, entries) = match node {
+            Node::Root => {
+                let root = self.extent_root();
+                (root.header, root.entries.to_vec())
+            }
+            Node::Block(block) => {
+                fetch_page_read!(extent_page: extent_page, vol, vol.layout.data_page_id(block), au);
+                (extent_page.header, extent_page.entries.to_vec())
+            }
+        };
+        if header.magic != EXTENT_MAGIC || header.count > header.max {
+            warn!("bad extent node {:?} of inode {}", node, self.inode_id.0);
+            return None;
+        }
+        Some((header.depth, entries[..header.count as usize].to_vec()))
+    }
+
+    pub(crate) fn store_extent_node(
+        &mut self,
+        vol: &Volume,
+        node: Node,
+        depth: u16,
+        entries: &[Extent],
+    ) {
+        match node {
+            Node::Root => store_root(self, depth, entries),
+            Node::Block(block) => store_block(vol, block, depth, entries),
+        }
+    }
+
+    ///逻辑块映射到的数据块号，未映射时返回None
+    pub(crate) fn extent_lookup(&self, vol: &Volume, block_id: usize) -> Option<i32> {
+        let block_id = u32::try_from(block_id).ok()?;
+        let mut node = Node::Root;
+        loop {
+            let (depth, entries) = self.load_extent_node(vol, node)?;
+            let pos = entries.partition_point(|e| e.block <= block_id);
+            if depth > 0 {
+                node = Node::Block(entries.get(pos.max(1) - 1)?.start);
+                continue;
+            }
+            let extent = entries.get(pos.checked_sub(1)?)?;
+            return (block_id - extent.block < extent.len)
+                .then(|| extent.start + (block_id - extent.block) as i32);
+        }
+    }
+
+    ///从树根向下找到block_id所在的叶子
+    fn extent_path(&self, vol: &Volume, block_id: u32) -> Option<Vec<PathNode>> {
+        let mut path = Vec::new();
+        let mut node = Node::Root;
+        loop {
+            let (depth, entries) = self.load_extent_node(vol, node)?;
+            let pos = entries.partition_point(|e| e.block <= block_id);
+            if depth == 0 {
+                path.push(PathNode {
+                    node,
+                    depth,
+                    entries,
+                    pos,
+                });
+                return Some(path);
+            }
+            let i = pos.max(1) - 1;
+            let child = Node::Block(entries[i].start);
+            path.push(PathNode {
+                node,
+                depth,
+                entries,
+                pos: i + 1,
+            });
+            node = child;
+        }
+    }
+
+    ///把未映射的逻辑块block_id映射到数据块start。与前一个extent相接时延长它，
+    ///否则插入新的extent，节点满时逐层分裂。无法分配节点块时返回false，树保持不变
+    pub(crate) fn extent_insert(&mut self, vol: &Volume, block_id: usize, start: i32) -> bool {
+        let Ok(block_id) = u32::try_from(block_id) else {
+            return false;
+        };
+        let Some(mut path) = self.extent_path(vol, block_id) else {
+            return false;
+        };
+        let leaf = path.last_mut().unwrap();
+        if let Some(prev) = leaf.pos.checked_sub(1).map(|i| &mut leaf.entries[i]) {
+            if prev.block + prev.len == block_id && prev.start + prev.len as i32 == start {
+                prev.len += 1;
+                let (node, depth) = (leaf.node, leaf.depth);
+                let entries = std::mem::take(&mut leaf.entries);
+                self.store_extent_node(vol, node, depth, &entries);
+                return true;
+            }
+        }
+        //先分配好分裂需要的所有块，从叶子向上每个满的节点需要一块，树根满时用于增高
+        let full = path
+            .iter()
+            .rev()
+            .take_while(|n| n.entries.len() == max_entries(n.node))
+            .count();
+        let mut spare = Vec::with_capacity(full);
+        for _ in 0..full {
+            let Some(block) = alloc_block(vol, false) else {
+                spare.into_iter().for_each(|block| free_block(vol, block));
+                return false;
+            };
+            spare.push(block);
+        }
+        let mut entry = Extent {
+            block: block_id,
+            len: 1,
+            start,
+        };
+        for level in (0..path.len()).rev() {
+            let PathNode {
+                node,
+                depth,
+                ref mut entries,
+                pos,
+            } = path[level];
+            entries.insert(pos, entry);
+            if entries.len() <= max_entries(node) {
+                self.store_extent_node(vol, node, depth, entries);
+                break;
+            }
+            let sibling = spare.pop().unwrap();
+            if node == Node::Root {
+                store_block(vol, sibling, depth, entries);
+                let child = Extent {
+                    block: entries[0].block,
+                    len: 0,
+                    start: sibling,
+                };
+                store_root(self, depth + 1, &[child]);
+                debug!(
+                    "extent tree of inode {} grows to depth {}",
+                    self.inode_id.0,
+                    depth + 1
+                );
+                break;
+            }
+            let right = entries.split_off(entries.len() / 2);
+            let Node::Block(block) = node else {
+                unreachable!()
+            };
+            store_block(vol, block, depth, entries);
+            store_block(vol, sibling, depth, &right);
+            entry = Extent {
+                block: right[0].block,
+                len: 0,
+                start: sibling,
+            };
+        }
+        true
+    }
+
+    ///释放逻辑块号不小于keep的所有数据块，变空的节点块也一并释放
+    pub(crate) fn extent_truncate(&mut self, vol: &Volume, keep: usize) {
+        let keep = keep.min(EXTENT_MAX_FILE_BLOCK_NUM) as u32;
+        if truncate_node(vol, self, Node::Root, keep) {
+            //树根变空后回到只有一个空叶子的状态
+            store_root(self, 0, &[]);
+        }
+        //树根只剩一个子节点且放得下它的项时，把子节点的项移回树根，树降低一层
+        while let Some((depth, entries)) = self.load_extent_node(vol, Node::Root) {
+            if depth == 0 || entries.len() != 1 {
+                break;
+            }
+            let child = entries[0].start;
+            let Some((child_depth, child_entries)) = self.load_extent_node(vol, Node::Block(child))
+            else {
+                break;
+            };
+            if child_entries.len() > EXTENT_ROOT_NUM {
+                break;
+            }
+            store_root(self, child_depth, &child_entries);
+            free_block(vol, child);
+        }
+    }
+
+    ///小于block_id的最后一个已映射的逻辑块和它的数据块号
+    pub(crate) fn extent_prev(&self, vol: &Volume, block_id: usize) -> Option<(usize, i32)> {
+        let block_id = u32::try_from(block_id).unwrap_or(u32::MAX);
+        prev_in_node(vol, self, Node::Root, block_id)
+    }
+
+    ///extent树中的所有数据块和节点块
+    pub(crate) fn extent_blocks(&self, vol: &Volume) -> Vec<i32> {
+        let mut blocks = Vec::new();
+        collect_blocks(vol, self, Node::Root, &mut blocks);
+        blocks
+    }
+}
+
+fn max_entries(node: Node) -> usize {
+    match node {
+        Node::Root => EXTENT_ROOT_NUM,
+        Node::Block(_) => EXTENT_PER_PAGE,
+    }
+}
+
+fn header(depth: u16, count: usize, max: usize) -> ExtentHeader {
+    ExtentHeader {
+        magic: EXTENT_MAGIC,
+        count: count as u16,
+        max: max as u16,
+        depth,
+    }
+}
+
+fn store_root(inode: &mut Inode, depth: u16, entries: &[Extent]) {
+    let root = inode.extent_root_mut();
+    root.header = header(depth, entries.len(), EXTENT_ROOT_NUM);
+    root.entries = [Extent::default(); EXTENT_ROOT_NUM];
+    root.entries[..entries.len()].copy_from_slice(entries);
+}
+
+fn store_block(vol: &Volume, block: i32, depth: u16, entries: &[Extent]) {
+    fetch_page_write!(extent_page: extent_page, vol, vol.layout.data_page_id(block), au);
+    extent_page.header = header(depth, entries.len(), EXTENT_PER_PAGE);
+    extent_page.entries = [Extent::default(); EXTENT_PER_PAGE];
+    extent_page.entries[..entries.len()].copy_from_slice(entries);
+}
+
+///截断node之下的子树，返回node是否已经没有任何项
+fn truncate_node(vol: &Volume, inode: &mut Inode, node: Node, keep: u32) -> bool {
+    let Some((depth, entries)) = inode.load_extent_node(vol, node) else {
+        return false;
+    };
+    let mut kept = Vec::with_capacity(entries.len());
+    for (i, mut entry) in entries.iter().copied().enumerate() {
+        if depth == 0 {
+            let end = entry.block + entry.len;
+            if end <= keep {
+                kept.push(entry);
+                continue;
+            }
+            let from = keep.saturating_sub(entry.block);
+            for j in from..entry.len {
+                free_block(vol, entry.start + j as i32);
+            }
+            entry.len = from;
+            if entry.len > 0 {
+                kept.push(entry);
+            }
+            continue;
+        }
+        //下一个子节点的下界不超过keep时，这个子节点中的块都要保留
+        if entries.get(i + 1).is_some_and(|next| next.block <= keep) {
+            kept.push(entry);
+            continue;
+        }
+        if truncate_node(vol, inode, Node::Block(entry.start), keep) {
+            free_block(vol, entry.start);
+        } else {
+            kept.push(entry);
+        }
+    }
+    if kept.len() != entries.len() || depth == 0 {
+        inode.store_extent_node(vol, node, depth, &kept);
+    }
+    kept.is_empty()
+}
+
+fn prev_in_node(vol: &Volume, inode: &Inode, node: Node, block_id: u32) -> Option<(usize, i32)> {
+    let (depth, entries) = inode.load_extent_node(vol, node)?;
+    let pos = entries.partition_point(|e| e.block < block_id);
+    if depth == 0 {
+        let extent = entries.get(pos.checked_sub(1)?)?;
+        let last = (extent.block + extent.len).min(block_id) - 1;
+        return Some((last as usize, extent.start + (last - extent.block) as i32));
+    }
+    //比第一个子节点的下界还小的块也插入在第一个子节点中
+    entries[..pos.max(1)]
+        .iter()
+        .rev()
+        .find_map(|entry| prev_in_node(vol, inode, Node::Block(entry.start), block_id))
+}
+
+fn collect_blocks(vol: &Volume, inode: &Inode, node: Node, blocks: &mut Vec<i32>) {
+    let Some((depth, entries)) = inode.load_extent_node(vol, node) else {
+        return;
+    };
+    for entry in entries {
+        if depth == 0 {
+            blocks.extend((0..entry.len).map(|j| entry.start + j as i32));
+        } else {
+            blocks.push(entry.start);
+            collect_blocks(vol, inode, Node::Block(entry.start), blocks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::mem_bpm;
+    use crate::fs::custom::PAGE_SIZE;
+    use crate::fs::dcache::DCache;
+    use crate::fs::def::{FEATURE_EXTENTS, SUCCESS};
+    use crate::fs::fsck::fsck;
+    use crate::fs::layout::{Layout, LayoutOptions};
+    use crate::fs::superblock::{format, free_counts};
+    use crate::fs::types::{FileType, InodeId};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_extents() {
+        let layout = Layout::new(1024, LayoutOptions::default()).unwrap();
+        let vol = &Arc::new(format(mem_bpm(1, 20), layout, FEATURE_EXTENTS));
+        let (_, free_blocks) = free_counts(vol);
+        let mut inode: Inode = unsafe { std::mem::zeroed() };
+        inode.init(vol, InodeId(1), FileType::REG, 0o644, 0, 0);
+        assert!(inode.uses_extents(vol));
+
+        //顺序写入的块连续分配，合并成一个extent
+        let data: Vec<u8> = (0..100 * PAGE_SIZE).map(|i| (i % 253) as u8).collect();
+        assert_eq!(inode.write(vol, 0, &data), data.len());
+        let (depth, entries) = inode.load_extent_node(vol, Node::Root).unwrap();
+        assert_eq!((depth, entries.len()), (0, 1));
+        assert_eq!(entries[0].len, 100);
+        let mut buf = vec![0u8; data.len()];
+        assert_eq!(inode.read(vol, 0, &mut buf), data.len());
+        assert_eq!(buf, data);
+
+        //隔一块写一块，每个块都是单独的extent，树根放不下后增高并分裂叶子
+        for i in 0..400 {
+            assert_eq!(
+                inode.write(vol, (200 + 2 * i) * PAGE_SIZE, &[(i % 251) as u8 + 1]),
+                1
+            );
+        }
+        let (depth, entries) = inode.load_extent_node(vol, Node::Root).unwrap();
+        assert_eq!(depth, 1);
+        assert!(entries.len() > 1);
+        for i in 0..400 {
+            let mut byte = [0u8];
+            assert_eq!(inode.read(vol, (200 + 2 * i) * PAGE_SIZE, &mut byte), 1);
+            assert_eq!(byte[0], (i % 251) as u8 + 1);
+            assert!(inode.block_page_id(vol, 201 + 2 * i).is_none());
+        }
+        let first = inode.extent_lookup(vol, 0).unwrap();
+        assert_eq!(inode.extent_lookup(vol, 99), Some(first + 99));
+        let blocks = inode.extent_blocks(vol);
+        assert_eq!(blocks.len(), 100 + 400 + entries.len());
+        assert_eq!(inode.page_ids(vol).len(), blocks.len());
+
+        //截断到中间，后半部分的数据块和变空的叶子被释放
+        inode.truncate(vol, (200 + 2 * 100) as u64 * PAGE_SIZE as u64);
+        assert!(inode.block_page_id(vol, 400).is_none());
+        assert!(inode.block_page_id(vol, 398).is_some());
+        assert!(inode.extent_blocks(vol).len() < blocks.len());
+        assert_eq!(
+            free_counts(vol).1 as usize,
+            free_blocks as usize - inode.extent_blocks(vol).len()
+        );
+        inode.truncate(vol, 50 * PAGE_SIZE as u64);
+        assert_eq!(inode.load_extent_node(vol, Node::Root).unwrap().0, 0);
+        assert_eq!(inode.extent_blocks(vol).len(), 50);
+        inode.free_blocks(vol);
+        assert!(inode.extent_blocks(vol).is_empty());
+        assert_eq!(free_counts(vol).1, free_blocks);
+
+        //fsck遍历extent树，越界的extent在修复时被删除
+        let dir_tree = DCache::new(vol.clone(), 100);
+        let f = {
+            let root = dir_tree.search("/").unwrap();
+            assert_eq!(dir_tree.insert(&root, "f", FileType::REG, 0o644), SUCCESS);
+            dir_tree.search("/f").unwrap().inode_id
+        };
+        let mut inode = f.load(vol);
+        for i in 0..10 {
+            assert_eq!(
+                inode.write(vol, 3 * i * PAGE_SIZE, &data[..PAGE_SIZE]),
+                PAGE_SIZE
+            );
+        }
+        assert_eq!(inode.load_extent_node(vol, Node::Root).unwrap().0, 1);
+        store(vol, &inode);
+        let report = fsck(vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+        let (_, mut entries) = inode.load_extent_node(vol, Node::Root).unwrap();
+        let leaf = Node::Block(entries[0].start);
+        let (_, mut extents) = inode.load_extent_node(vol, leaf).unwrap();
+        let lost = extents[1].start;
+        extents[1].start = layout.data_num as i32;
+        inode.store_extent_node(vol, leaf, 0, &extents);
+        let report = fsck(vol, false).unwrap();
+        assert_eq!(report.bad_blocks, [(f, layout.data_num as i32)]);
+        assert_eq!(report.leaked_blocks, [lost as u32]);
+        fsck(vol, true).unwrap();
+        let report = fsck(vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+        assert!(f.load(vol).block_page_id(vol, 3).is_none());
+        assert!(f.load(vol).block_page_id(vol, 6).is_some());
+    }
+
+    fn store(vol: &Volume, inode: &Inode) {
+        let (page_id, offset) = inode.inode_id.seek(vol);
+        fetch_page_write!(inode_page: inode_page, vol, page_id, au);
+        inode_page.inodes[offset] = *inode;
+    }
+}
@@ -0,0 +1,928 @@
+//! 与挂载方式无关的文件系统操作。
+//!
+//! Filesystem仿照fuse的低层接口，用inode号而不是路径指定文件，调用者的身份作为参数传入，
+//! 出错时返回负的errno。RustFs是它的实现，持有挂载的卷（缓存池、磁盘布局、日志、打开的句柄表、
+//! 块组分配器和预读状态，见fs::volume）、目录缓存、lookup的引用计数和挂载选项，
+//! 同一个进程中可以同时挂载多个RustFs。interface.rs中的fuse回调只负责把路径解析为inode、
+//! 转换参数和返回值。
+//!
+//! 与低层接口一样，lookup以及创建文件的操作让返回的inode的查找次数加一，直到forget。
+//! 只有被查找过的目录可以作为parent，因为目录缓存需要从根目录开始的节点
+use crate::buffer::buffer_pool_manager::AutoUnpin;
+use crate::buffer::buffer_pool_manager::Bpm;
+use crate::buffer::replacer::PageId;
+use crate::fs::dcache::{DCache, DEntry, DCACHE_ENTRIES};
+use crate::fs::def::SUCCESS;
+use crate::fs::handle;
+use crate::fs::journal::{self, begin};
+use crate::fs::perm::{self, as_caller, Cred};
+use crate::fs::readahead::DEFAULT_WINDOW;
+use crate::fs::superblock::{self, describe, fill_statvfs, is_formatted, record_mount};
+use crate::fs::types::{max_file_size, FileType, Inode, InodeId};
+use crate::fs::utils::{now, sync_inode};
+use crate::fs::volume::{Volume, FLUSH_INTERVAL};
+use crate::fs::xattr;
+use crate::{fetch_page_read, fetch_page_write};
+use libc::{c_int, R_OK, W_OK, X_OK};
+use log::{debug, info};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub type FsResult<T> = Result<T, c_int>;
+
+///文件属性，同stat
+pub type FileAttr = libc::stat;
+
+///根目录的inode号
+pub const ROOT: InodeId = InodeId(0);
+
+///readdir中"."和".."占用的偏移。槽位s上的目录项的偏移是s + 2，交给add的是下一项的偏移
+const DOT_ENTRIES: i64 = 2;
+
+///readdir交给调用者的目录项：名字、inode号、类型和下一项的偏移，返回false时停止
+pub type AddEntry<'a> = &'a mut dyn FnMut(&[u8], InodeId, FileType, i64) -> bool;
+
+pub enum TimeOrNow {
+    Now,
+    Time(libc::timespec),
+}
+
+///setattr要修改的属性，None表示不修改
+#[derive(Default)]
+pub struct SetAttr {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub size: Option<u64>,
+    pub atime: Option<TimeOrNow>,
+    pub mtime: Option<TimeOrNow>,
+    ///通过打开的句柄修改（ftruncate），截断时不再检查写权限
+    pub fh: Option<u64>,
+}
+
+pub trait Filesystem {
+    ///在目录parent中查找name
+    fn lookup(&self, cred: &Cred, parent: InodeId, name: &str) -> FsResult<FileAttr>;
+    ///ino的查找次数减少nlookup
+    fn forget(&self, ino: InodeId, nlookup: u64);
+    fn getattr(&self, cred: &Cred, ino: InodeId) -> FsResult<FileAttr>;
+    fn setattr(&self, cred: &Cred, ino: InodeId, attr: SetAttr) -> FsResult<FileAttr>;
+    fn readlink(&self, cred: &Cred, ino: InodeId) -> FsResult<Vec<u8>>;
+    fn mknod(&self, cred: &Cred, parent: InodeId, name: &str, mode: u32) -> FsResult<FileAttr>;
+    fn mkdir(&self, cred: &Cred, parent: InodeId, name: &str, mode: u32) -> FsResult<FileAttr>;
+    fn unlink(&self, cred: &Cred, parent: InodeId, name: &str) -> FsResult<()>;
+    fn rmdir(&self, cred: &Cred, parent: InodeId, name: &str) -> FsResult<()>;
+    fn symlink(&self, cred: &Cred, parent: InodeId, name: &str, target: &str)
+        -> FsResult<FileAttr>;
+    fn rename(
+        &self,
+        cred: &Cred,
+        parent: InodeId,
+        name: &str,
+        new_parent: InodeId,
+        new_name: &str,
+    ) -> FsResult<()>;
+    fn link(
+        &self,
+        cred: &Cred,
+        ino: InodeId,
+        new_parent: InodeId,
+        new_name: &str,
+    ) -> FsResult<FileAttr>;
+    ///打开文件，返回句柄
+    fn open(&self, cred: &Cred, ino: InodeId, flags: c_int) -> FsResult<u64>;
+    fn read(
+        &self,
+        cred: &Cred,
+        ino: InodeId,
+        fh: u64,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> FsResult<usize>;
+    fn write(
+        &self,
+        cred: &Cred,
+        ino: InodeId,
+        fh: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> FsResult<usize>;
+    ///每次close时调用
+    fn flush(&self, cred: &Cred, ino: InodeId, fh: u64) -> FsResult<()>;
+    fn release(&self, cred: &Cred, ino: InodeId, fh: u64) -> FsResult<()>;
+    fn fsync(&self, cred: &Cred, ino: InodeId, fh: u64, datasync: bool) -> FsResult<()>;
+    fn opendir(&self, cred: &Cred, ino: InodeId, flags: c_int) -> FsResult<u64>;
+    ///从offset开始把目录项交给add
+    fn readdir(
+        &self,
+        cred: &Cred,
+        ino: InodeId,
+        fh: u64,
+        offset: i64,
+        add: AddEntry<'_>,
+    ) -> FsResult<()>;
+    fn releasedir(&self, cred: &Cred, ino: InodeId, fh: u64) -> FsResult<()>;
+    fn fsyncdir(&self, cred: &Cred, ino: InodeId, fh: u64, datasync: bool) -> FsResult<()>;
+    fn statfs(&self, cred: &Cred, ino: InodeId) -> FsResult<libc::statvfs>;
+    fn setxattr(
+        &self,
+        cred: &Cred,
+        ino: InodeId,
+        name: &str,
+        value: &[u8],
+        flags: c_int,
+    ) -> FsResult<()>;
+    fn getxattr(&self, cred: &Cred, ino: InodeId, name: &str) -> FsResult<Vec<u8>>;
+    ///以0分隔的名字列表
+    fn listxattr(&self, cred: &Cred, ino: InodeId) -> FsResult<Vec<u8>>;
+    fn removexattr(&self, cred: &Cred, ino: InodeId, name: &str) -> FsResult<()>;
+    fn access(&self, cred: &Cred, ino: InodeId, mask: c_int) -> FsResult<()>;
+    ///创建并打开文件
+    fn create(
+        &self,
+        cred: &Cred,
+        parent: InodeId,
+        name: &str,
+        mode: u32,
+        flags: c_int,
+    ) -> FsResult<(FileAttr, u64)>;
+    ///卸载
+    fn destroy(&self);
+}
+
+pub struct FsOptions {
+    ///目录缓存的目录项上限
+    pub dcache_entries: usize,
+    ///关闭文件时是否同fsync一样把文件写到磁盘上
+    pub sync_on_close: bool,
+    ///顺序读时最多预读的页数，为0时关闭预读
+    pub readahead: usize,
+    ///flusher写回脏页的间隔（毫秒），为0时只在脏页超过上限时写回
+    pub flush_interval: u32,
+}
+
+impl Default for FsOptions {
+    fn default() -> Self {
+        FsOptions {
+            dcache_entries: DCACHE_ENTRIES,
+            sync_on_close: false,
+            readahead: DEFAULT_WINDOW,
+            flush_interval: FLUSH_INTERVAL,
+        }
+    }
+}
+
+pub struct RustFs {
+    pub vol: Arc<Volume>,
+    dcache: DCache,
+    ///被查找过、还没有forget的inode的目录项和查找次数
+    pinned: Mutex<HashMap<u32, (Arc<DEntry>, u64)>>,
+    sync_on_close: bool,
+}
+
+///lookup_path找到的inode，被丢弃时forget
+pub struct PathEntry<'a> {
+    fs: &'a RustFs,
+    pub ino: InodeId,
+}
+
+impl PathEntry<'_> {
+    ///不再自动forget，由调用者用完之后forget
+    pub fn keep(self) -> InodeId {
+        let ino = self.ino;
+        std::mem::forget(self);
+        ino
+    }
+}
+
+impl Drop for PathEntry<'_> {
+    fn drop(&mut self) {
+        self.fs.forget(self.ino, 1);
+    }
+}
+
+///dcache中修改目录的操作成功时返回SUCCESS，失败时返回负的errno
+fn to_result(ret: c_int) -> FsResult<()> {
+    if ret == SUCCESS {
+        Ok(())
+    } else {
+        Err(ret)
+    }
+}
+
+fn stat(vol: &Volume, ino: InodeId) -> FileAttr {
+    let (page_id, offset) = ino.seek(vol);
+    fetch_page_read!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+    let mut stat: FileAttr = unsafe { std::mem::zeroed() };
+    inode_page.inodes[offset].fill_stat(&mut stat);
+    stat
+}
+
+///在inode所在页的写锁下修改inode
+fn update<T>(vol: &Volume, ino: InodeId, f: impl FnOnce(&mut Inode) -> T) -> T {
+    let (page_id, offset) = ino.seek(vol);
+    fetch_page_write!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+    f(&mut inode_page.inodes[offset])
+}
+
+///记录一个打开的句柄。查找之后文件可能已经被删除，此时放弃打开
+fn open_inode(vol: &Volume, ino: InodeId) -> FsResult<u64> {
+    let fh = vol.handles.open(ino);
+    if ino.load(vol).nlink == 0 {
+        release_inode(vol, ino);
+        return Err(-libc::ENOENT);
+    }
+    Ok(fh)
+}
+
+///关闭一个句柄，最后一个句柄关闭时释放已经被删除的文件
+fn release_inode(vol: &Volume, ino: InodeId) {
+    if vol.handles.release(ino) {
+        let _tx = begin(vol);
+        handle::free(vol, ino);
+    }
+}
+
+impl RustFs {
+    ///在已经挂载的卷上创建文件系统
+    pub fn new(vol: Arc<Volume>, options: FsOptions) -> Self {
+        RustFs {
+            dcache: DCache::new(vol.clone(), options.dcache_entries),
+            vol,
+            pinned: Mutex::new(HashMap::new()),
+            sync_on_close: options.sync_on_close,
+        }
+    }
+
+    ///在缓存池上挂载。不会格式化设备，磁盘上没有文件系统时返回错误，需要先用mkfs-rustfs格式化
+    pub fn mount(bpm: Bpm, options: FsOptions) -> Result<Self, String> {
+        if !is_formatted(&bpm) {
+            return Err("no rustfs file system on the device, run mkfs-rustfs first".to_string());
+        }
+        let vol = superblock::mount(Arc::new(bpm))?;
+        journal::start(&vol)?;
+        record_mount(&vol);
+        vol.readahead.set_max_window(options.readahead);
+        vol.start_flusher(options.flush_interval);
+        info!("mount: {}", describe(&vol));
+        Ok(Self::new(Arc::new(vol), options))
+    }
+
+    ///按cred的权限查找路径，返回的inode在PathEntry被丢弃之前可以作为parent
+    pub fn lookup_path(&self, cred: &Cred, path: &str) -> FsResult<PathEntry<'_>> {
+        let entry = self.dcache.resolve(path, cred)?;
+        let ino = entry.inode_id;
+        self.pin(entry);
+        Ok(PathEntry { fs: self, ino })
+    }
+
+    fn pin(&self, entry: Arc<DEntry>) {
+        let mut pinned = self.pinned.lock();
+        let slot = pinned
+            .entry(entry.inode_id.0)
+            .or_insert_with(|| (entry.clone(), 0));
+        //inode号被重新使用时换成新的目录项
+        slot.0 = entry;
+        slot.1 += 1;
+    }
+
+    ///被查找过的目录
+    fn dir(&self, ino: InodeId) -> FsResult<Arc<DEntry>> {
+        let vol = &*self.vol;
+        let dir = if ino == ROOT {
+            self.dcache.root()
+        } else {
+            let pinned = self.pinned.lock();
+            pinned.get(&ino.0).ok_or(-libc::ESTALE)?.0.clone()
+        };
+        if dir.file_type != FileType::DIR {
+            return Err(-libc::ENOTDIR);
+        }
+        //已经被删除的目录中不能再创建文件
+        if ino != ROOT && ino.load(vol).nlink == 0 {
+            return Err(-libc::ENOENT);
+        }
+        Ok(dir)
+    }
+
+    ///要在其中新建或删除目录项的目录，调用者需要它的写和搜索权限
+    fn writable_dir(&self, cred: &Cred, ino: InodeId) -> FsResult<Arc<DEntry>> {
+        let vol = &*self.vol;
+        let dir = self.dir(ino)?;
+        perm::check(&ino.load(vol), cred, W_OK | X_OK)?;
+        Ok(dir)
+    }
+
+    ///调用者能否删除或改名dir中的name
+    fn may_delete(&self, dir: &DEntry, name: &str, cred: &Cred) -> FsResult<()> {
+        let vol = &*self.vol;
+        let dir_inode = dir.inode_id.load(vol);
+        let (victim, _) = dir_inode
+            .search_dir_by_name(vol, name)
+            .ok_or(-libc::ENOENT)?;
+        perm::may_delete(&dir_inode, &victim.load(vol), cred)
+    }
+
+    ///刚在dir中创建的name，同lookup一样增加查找次数
+    fn entry(&self, dir: &Arc<DEntry>, name: &str) -> FsResult<FileAttr> {
+        let vol = &*self.vol;
+        let entry = self.dcache.lookup(dir, name).ok_or(-libc::ENOENT)?;
+        let ino = entry.inode_id;
+        self.pin(entry);
+        Ok(stat(vol, ino))
+    }
+
+    fn make(
+        &self,
+        cred: &Cred,
+        parent: InodeId,
+        name: &str,
+        file_type: FileType,
+        mode: u32,
+    ) -> FsResult<FileAttr> {
+        let vol = &*self.vol;
+        let _tx = begin(vol);
+        let dir = self.writable_dir(cred, parent)?;
+        to_result(as_caller(cred, || {
+            self.dcache.insert(&dir, name, file_type, mode)
+        }))?;
+        self.entry(&dir, name)
+    }
+
+    fn remove(&self, cred: &Cred, parent: InodeId, name: &str, is_dir: bool) -> FsResult<()> {
+        let vol = &*self.vol;
+        let _tx = begin(vol);
+        let dir = self.writable_dir(cred, parent)?;
+        self.may_delete(&dir, name, cred)?;
+        let result = to_result(self.dcache.remove(&dir, name, is_dir));
+        handle::free_unlinked(vol);
+        result
+    }
+}
+
+impl Filesystem for RustFs {
+    fn lookup(&self, cred: &Cred, parent: InodeId, name: &str) -> FsResult<FileAttr> {
+        let vol = &*self.vol;
+        let dir = self.dir(parent)?;
+        to_result(crate::fs::types::check_name(name))?;
+        perm::check(&parent.load(vol), cred, X_OK)?;
+        let entry = self.dcache.lookup(&dir, name).ok_or(-libc::ENOENT)?;
+        let ino = entry.inode_id;
+        self.pin(entry);
+        Ok(stat(vol, ino))
+    }
+
+    fn forget(&self, ino: InodeId, nlookup: u64) {
+        let mut pinned = self.pinned.lock();
+        if let Some(slot) = pinned.get_mut(&ino.0) {
+            slot.1 = slot.1.saturating_sub(nlookup);
+            if slot.1 == 0 {
+                pinned.remove(&ino.0);
+            }
+        }
+    }
+
+    fn getattr(&self, _cred: &Cred, ino: InodeId) -> FsResult<FileAttr> {
+        let vol = &*self.vol;
+        Ok(stat(vol, ino))
+    }
+
+    ///先检查所有要修改的属性，任何一项不允许时什么都不改；
+    ///全部允许后依次修改属主、权限、大小和时间，修改了任何属性时更新ctime。
+    ///缩短时一步释放不完的块先分多个事务释放，期间不放开日志
+    fn setattr(&self, cred: &Cred, ino: InodeId, attr: SetAttr) -> FsResult<FileAttr> {
+        let vol = &*self.vol;
+        let _tx = begin(vol);
+        let uid = attr.uid.unwrap_or(u32::MAX);
+        let gid = attr.gid.unwrap_or(u32::MAX);
+        update(vol, ino, |inode| {
+            if attr.uid.is_some() || attr.gid.is_some() {
+                perm::may_chown(inode, cred, uid, gid)?;
+            }
+            if attr.mode.is_some() {
+                perm::may_chmod(inode, cred)?;
+            }
+            if let Some(size) = attr.size {
+                if inode.is_dir() {
+                    return Err(-libc::EISDIR);
+                }
+                if attr.fh.is_none() {
+                    perm::check(inode, cred, W_OK)?;
+                }
+                if size as usize > max_file_size(vol) {
+                    return Err(-libc::EFBIG);
+                }
+            }
+            if attr.atime.is_some() || attr.mtime.is_some() {
+                //设置为指定的时间需要是属主，设置为当前时间有写权限即可
+                let explicit = [&attr.atime, &attr.mtime]
+                    .iter()
+                    .any(|time| matches!(time, Some(TimeOrNow::Time(_))));
+                perm::may_set_times(inode, cred, explicit)?;
+            }
+            Ok(())
+        })?;
+        if let Some(size) = attr.size {
+            while !update(vol, ino, |inode| inode.truncate(vol, size)) {
+                journal::restart(vol);
+            }
+        }
+        update(vol, ino, |inode| {
+            let now = now();
+            if attr.uid.is_some() || attr.gid.is_some() {
+                perm::chown(inode, cred, uid, gid)?;
+            }
+            if let Some(mode) = attr.mode {
+                perm::chmod(inode, cred, mode)?;
+            }
+            if let Some(size) = attr.size {
+                inode.truncate(vol, size);
+                perm::write_kill_suid(inode, cred);
+            }
+            if attr.atime.is_some() || attr.mtime.is_some() {
+                match attr.atime {
+                    Some(TimeOrNow::Now) => inode.set_atime(now),
+                    Some(TimeOrNow::Time(time)) => inode.set_atime(time),
+                    None => {}
+                }
+                match attr.mtime {
+                    Some(TimeOrNow::Now) => inode.set_mtime(now),
+                    Some(TimeOrNow::Time(time)) => inode.set_mtime(time),
+                    None => {}
+                }
+            }
+            let changed = attr.uid.is_some()
+                || attr.gid.is_some()
+                || attr.mode.is_some()
+                || attr.size.is_some()
+                || attr.atime.is_some()
+                || attr.mtime.is_some();
+            if changed {
+                inode.set_ctime(now);
+            }
+            let mut stat: FileAttr = unsafe { std::mem::zeroed() };
+            inode.fill_stat(&mut stat);
+            Ok(stat)
+        })
+    }
+
+    fn readlink(&self, _cred: &Cred, ino: InodeId) -> FsResult<Vec<u8>> {
+        let vol = &*self.vol;
+        let inode = ino.load(vol);
+        if inode.file_type != FileType::SYMLINK {
+            return Err(-libc::EINVAL);
+        }
+        Ok(inode.symlink_target(vol))
+    }
+
+    fn mknod(&self, cred: &Cred, parent: InodeId, name: &str, mode: u32) -> FsResult<FileAttr> {
+        self.make(cred, parent, name, FileType::REG, mode)
+    }
+
+    fn mkdir(&self, cred: &Cred, parent: InodeId, name: &str, mode: u32) -> FsResult<FileAttr> {
+        self.make(cred, parent, name, FileType::DIR, mode)
+    }
+
+    fn unlink(&self, cred: &Cred, parent: InodeId, name: &str) -> FsResult<()> {
+        self.remove(cred, parent, name, false)
+    }
+
+    fn rmdir(&self, cred: &Cred, parent: InodeId, name: &str) -> FsResult<()> {
+        self.remove(cred, parent, name, true)
+    }
+
+    fn symlink(
+        &self,
+        cred: &Cred,
+        parent: InodeId,
+        name: &str,
+        target: &str,
+    ) -> FsResult<FileAttr> {
+        let vol = &*self.vol;
+        let _tx = begin(vol);
+        let dir = self.writable_dir(cred, parent)?;
+        to_result(as_caller(cred, || self.dcache.symlink(&dir, name, target)))?;
+        self.entry(&dir, name)
+    }
+
+    fn rename(
+        &self,
+        cred: &Cred,
+        parent: InodeId,
+        name: &str,
+        new_parent: InodeId,
+        new_name: &str,
+    ) -> FsResult<()> {
+        let vol = &*self.vol;
+        let _tx = begin(vol);
+        let old_dir = self.writable_dir(cred, parent)?;
+        let new_dir = self.writable_dir(cred, new_parent)?;
+        self.may_delete(&old_dir, name, cred)?;
+        //被替换的目标同样受粘着位限制
+        let new_dir_inode = new_parent.load(vol);
+        if let Some((target, _)) = new_dir_inode.search_dir_by_name(vol, new_name) {
+            perm::may_delete(&new_dir_inode, &target.load(vol), cred)?;
+        }
+        //移到其他目录的子目录的..随之改变，需要子目录的写权限
+        if !Arc::ptr_eq(&old_dir, &new_dir) {
+            let (moved, file_type) = parent.load(vol).search_dir_by_name(vol, name).unwrap();
+            if file_type == FileType::DIR {
+                perm::check(&moved.load(vol), cred, W_OK)?;
+            }
+        }
+        let result = to_result(self.dcache.rename(&old_dir, name, &new_dir, new_name));
+        //被替换的目标的最后一个名字
+        handle::free_unlinked(vol);
+        result
+    }
+
+    fn link(
+        &self,
+        cred: &Cred,
+        ino: InodeId,
+        new_parent: InodeId,
+        new_name: &str,
+    ) -> FsResult<FileAttr> {
+        let vol = &*self.vol;
+        let _tx = begin(vol);
+        let dir = self.writable_dir(cred, new_parent)?;
+        let file_type = ino.load(vol).file_type;
+        to_result(self.dcache.link(ino, file_type, &dir, new_name))?;
+        self.entry(&dir, new_name)
+    }
+
+    fn open(&self, cred: &Cred, ino: InodeId, flags: c_int) -> FsResult<u64> {
+        let vol = &*self.vol;
+        let inode = ino.load(vol);
+        if inode.is_dir() && flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(-libc::EISDIR);
+        }
+        perm::check(&inode, cred, perm::open_mask(flags))?;
+        open_inode(vol, ino)
+    }
+
+    fn read(
+        &self,
+        _cred: &Cred,
+        ino: InodeId,
+        _fh: u64,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> FsResult<usize> {
+        let vol = &*self.vol;
+        let (page_id, index) = ino.seek(vol);
+        let (read, atime_outdated) = {
+            fetch_page_read!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+            let inode = &inode_page.inodes[index];
+            if inode.is_dir() {
+                return Err(-libc::EISDIR);
+            }
+            (
+                inode.read(vol, offset as usize, buf),
+                inode.atime_outdated(),
+            )
+        };
+        if atime_outdated {
+            let _tx = begin(vol);
+            update(vol, ino, |inode| inode.set_atime(now()));
+        }
+        Ok(read)
+    }
+
+    fn write(
+        &self,
+        cred: &Cred,
+        ino: InodeId,
+        _fh: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> FsResult<usize> {
+        let vol = &*self.vol;
+        let _tx = begin(vol);
+        if offset as usize + data.len() > max_file_size(vol) {
+            return Err(-libc::EFBIG);
+        }
+        //一个事务写不下时提交已经写入的部分，在新的事务中接着写
+        let mut written = 0;
+        loop {
+            let len = update(vol, ino, |inode| {
+                if inode.is_dir() {
+                    return Err(-libc::EISDIR);
+                }
+                let len = inode.write(vol, offset as usize + written, &data[written..]);
+                if len == 0 && written == 0 && !data.is_empty() {
+                    return Err(-libc::ENOSPC);
+                }
+                perm::write_kill_suid(inode, cred);
+                Ok(len)
+            })?;
+            written += len;
+            if len == 0 || written == data.len() {
+                return Ok(written);
+            }
+            journal::restart(vol);
+        }
+    }
+
+    ///挂载时指定了sync_on_close才写回文件
+    fn flush(&self, cred: &Cred, ino: InodeId, fh: u64) -> FsResult<()> {
+        if !self.sync_on_close {
+            return Ok(());
+        }
+        self.fsync(cred, ino, fh, false)
+    }
+
+    fn release(&self, _cred: &Cred, ino: InodeId, _fh: u64) -> FsResult<()> {
+        let vol = &*self.vol;
+        release_inode(vol, ino);
+        Ok(())
+    }
+
+    ///把整个inode写到磁盘上，datasync时也同步元数据
+    fn fsync(&self, _cred: &Cred, ino: InodeId, _fh: u64, _datasync: bool) -> FsResult<()> {
+        let vol = &*self.vol;
+        sync_inode(vol, ino);
+        Ok(())
+    }
+
+    fn opendir(&self, cred: &Cred, ino: InodeId, _flags: c_int) -> FsResult<u64> {
+        let vol = &*self.vol;
+        let inode = ino.load(vol);
+        if !inode.is_dir() {
+            return Err(-libc::ENOTDIR);
+        }
+        perm::check(&inode, cred, R_OK)?;
+        open_inode(vol, ino)
+    }
+
+    fn readdir(
+        &self,
+        _cred: &Cred,
+        ino: InodeId,
+        _fh: u64,
+        offset: i64,
+        add: AddEntry<'_>,
+    ) -> FsResult<()> {
+        let vol = &*self.vol;
+        let dir = ino.load(vol);
+        if !dir.is_dir() {
+            return Err(-libc::ENOTDIR);
+        }
+        //".."只用来告诉内核inode号，目录没有被查找过时用它自己的
+        let parent = self
+            .dir(ino)
+            .ok()
+            .and_then(|dir| dir.parent())
+            .map_or(ino, |parent| parent.inode_id);
+        let dots = [(".", ino), ("..", parent)];
+        for (i, (name, dot)) in dots.iter().enumerate().skip(offset as usize) {
+            if !add(name.as_bytes(), *dot, FileType::DIR, i as i64 + 1) {
+                return Ok(());
+            }
+        }
+        let pos = (offset - DOT_ENTRIES).max(0) as u64;
+        dir.read_dir_from(vol, pos, |slot, entry| {
+            let next = slot as i64 + DOT_ENTRIES + 1;
+            add(entry.name_bytes(), entry.inode_id, entry.file_type, next)
+        });
+        Ok(())
+    }
+
+    fn releasedir(&self, cred: &Cred, ino: InodeId, fh: u64) -> FsResult<()> {
+        self.release(cred, ino, fh)
+    }
+
+    fn fsyncdir(&self, cred: &Cred, ino: InodeId, fh: u64, datasync: bool) -> FsResult<()> {
+        self.fsync(cred, ino, fh, datasync)
+    }
+
+    fn statfs(&self, _cred: &Cred, _ino: InodeId) -> FsResult<libc::statvfs> {
+        let vol = &*self.vol;
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        fill_statvfs(vol, &mut stat);
+        Ok(stat)
+    }
+
+    fn setxattr(
+        &self,
+        cred: &Cred,
+        ino: InodeId,
+        name: &str,
+        value: &[u8],
+        flags: c_int,
+    ) -> FsResult<()> {
+        let vol = &*self.vol;
+        let _tx = begin(vol);
+        update(vol, ino, |inode| {
+            perm::check_xattr(inode, cred, name, true)?;
+            to_result(xattr::set(vol, inode, name.as_bytes(), value, flags))?;
+            inode.set_ctime(now());
+            Ok(())
+        })
+    }
+
+    fn getxattr(&self, cred: &Cred, ino: InodeId, name: &str) -> FsResult<Vec<u8>> {
+        let vol = &*self.vol;
+        let inode = ino.load(vol);
+        perm::check_xattr(&inode, cred, name, false)?;
+        xattr::get(vol, &inode, name.as_bytes()).ok_or(-libc::ENODATA)
+    }
+
+    fn listxattr(&self, cred: &Cred, ino: InodeId) -> FsResult<Vec<u8>> {
+        let vol = &*self.vol;
+        Ok(perm::filter_xattr_names(
+            xattr::list(vol, &ino.load(vol)),
+            cred,
+        ))
+    }
+
+    fn removexattr(&self, cred: &Cred, ino: InodeId, name: &str) -> FsResult<()> {
+        let vol = &*self.vol;
+        let _tx = begin(vol);
+        update(vol, ino, |inode| {
+            perm::check_xattr(inode, cred, name, true)?;
+            to_result(xattr::remove(vol, inode, name.as_bytes()))?;
+            inode.set_ctime(now());
+            Ok(())
+        })
+    }
+
+    fn access(&self, cred: &Cred, ino: InodeId, mask: c_int) -> FsResult<()> {
+        let vol = &*self.vol;
+        if mask == libc::F_OK {
+            return Ok(());
+        }
+        perm::check(&ino.load(vol), cred, mask)
+    }
+
+    fn create(
+        &self,
+        cred: &Cred,
+        parent: InodeId,
+        name: &str,
+        mode: u32,
+        _flags: c_int,
+    ) -> FsResult<(FileAttr, u64)> {
+        let vol = &*self.vol;
+        let dir = self.writable_dir(cred, parent)?;
+        let ino = {
+            let _tx = begin(vol);
+            as_caller(cred, || self.dcache.create(&dir, name, FileType::REG, mode))?
+        };
+        let fh = open_inode(vol, ino)?;
+        Ok((self.entry(&dir, name)?, fh))
+    }
+
+    ///释放还没有关闭的孤儿inode，清空日志，写回所有脏页后停止flusher
+    fn destroy(&self) {
+        let vol = &*self.vol;
+        for ino in vol.handles.take_orphans() {
+            let _tx = begin(vol);
+            handle::free(vol, ino);
+        }
+        journal::stop(vol);
+        vol.stop_flusher();
+        debug!(
+            "unmounted, {} inodes still looked up",
+            self.pinned.lock().len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+    use crate::device::MemDevice;
+    use crate::fs::fsck::fsck;
+    use crate::harness::mkfs;
+
+    fn root() -> Cred {
+        Cred::new(0, 0, Vec::new())
+    }
+
+    fn ino(stat: &FileAttr) -> InodeId {
+        InodeId(stat.st_ino as u32)
+    }
+
+    fn names(fs: &RustFs, dir: InodeId) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut add = |name: &[u8], _: InodeId, _: FileType, _: i64| {
+            names.push(String::from_utf8(name.to_vec()).unwrap());
+            true
+        };
+        fs.readdir(&root(), dir, 0, 0, &mut add).unwrap();
+        names
+    }
+
+    #[test]
+    fn test_filesystem() {
+        let bpm = ParallelBufferPoolManager::new(1, 128, Box::new(MemDevice::new(1024)));
+        //没有格式化的设备不能挂载
+        let err = RustFs::mount(bpm, FsOptions::default()).err().unwrap();
+        assert!(err.contains("mkfs-rustfs"));
+        let bpm = ParallelBufferPoolManager::new(1, 128, mkfs(1024, 0));
+        let fs = RustFs::mount(bpm, FsOptions::default()).unwrap();
+        let vol = &*fs.vol;
+        let cred = root();
+
+        let d = ino(&fs.mkdir(&cred, ROOT, "d", 0o755).unwrap());
+        assert_eq!(
+            fs.mkdir(&cred, ROOT, "d", 0o755).unwrap_err(),
+            -libc::EEXIST
+        );
+        let (attr, fh) = fs.create(&cred, d, "f", 0o644, libc::O_RDWR).unwrap();
+        let f = ino(&attr);
+        assert_eq!(attr.st_mode, libc::S_IFREG | 0o644);
+        assert_eq!(
+            fs.create(&cred, d, "f", 0o644, libc::O_RDWR).unwrap_err(),
+            -libc::EEXIST
+        );
+        assert_eq!(fs.write(&cred, f, fh, 0, b"hello").unwrap(), 5);
+        let mut buf = [0u8; 16];
+        assert_eq!(fs.read(&cred, f, fh, 1, &mut buf).unwrap(), 4);
+        assert_eq!(&buf[..4], b"ello");
+        fs.release(&cred, f, fh).unwrap();
+        assert_eq!(fs.lookup(&cred, d, "f").unwrap().st_size, 5);
+        assert_eq!(fs.lookup(&cred, d, "g").unwrap_err(), -libc::ENOENT);
+        assert_eq!(fs.lookup(&cred, f, "g").unwrap_err(), -libc::ENOTDIR);
+        assert_eq!(names(&fs, d), [".", "..", "f"]);
+
+        let attr = SetAttr {
+            size: Some(2),
+            mode: Some(0o600),
+            ..Default::default()
+        };
+        let attr = fs.setattr(&cred, f, attr).unwrap();
+        assert_eq!((attr.st_size, attr.st_mode & 0o777), (2, 0o600));
+
+        //普通用户不能在root的目录中创建文件，也不能修改root的文件
+        let alice = Cred::new(1000, 1000, Vec::new());
+        assert_eq!(fs.mknod(&alice, d, "a", 0o644).unwrap_err(), -libc::EACCES);
+        let chmod = SetAttr {
+            mode: Some(0o777),
+            ..Default::default()
+        };
+        assert_eq!(fs.setattr(&alice, f, chmod).unwrap_err(), -libc::EPERM);
+        let open = fs.open(&alice, f, libc::O_RDONLY);
+        assert_eq!(open.unwrap_err(), -libc::EACCES);
+        //新建的inode属于参数中的调用者
+        fs.setattr(
+            &cred,
+            d,
+            SetAttr {
+                mode: Some(0o777),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let a = fs.mknod(&alice, d, "a", 0o644).unwrap();
+        assert_eq!((a.st_uid, a.st_gid), (1000, 1000));
+        //任何一项检查失败时其他属性也不修改
+        let a = ino(&a);
+        let attrs = [(Some(0), None), (None, Some(max_file_size(vol) as u64 + 1))];
+        for (uid, size) in attrs {
+            let attr = SetAttr {
+                uid,
+                mode: Some(0o600),
+                size,
+                ..Default::default()
+            };
+            assert!(fs.setattr(&alice, a, attr).is_err());
+            let stat = fs.getattr(&alice, a).unwrap();
+            assert_eq!((stat.st_uid, stat.st_mode & 0o777), (1000, 0o644));
+        }
+        let attr = SetAttr {
+            mode: Some(0o700),
+            size: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(fs.setattr(&cred, d, attr).unwrap_err(), -libc::EISDIR);
+        assert_eq!(fs.getattr(&cred, d).unwrap().st_mode & 0o777, 0o777);
+
+        fs.rename(&cred, d, "f", ROOT, "g").unwrap();
+        assert_eq!(names(&fs, ROOT), [".", "..", "d", "g"]);
+        let link = fs.link(&cred, f, d, "h").unwrap();
+        assert_eq!(link.st_nlink, 2);
+        fs.symlink(&cred, ROOT, "s", "d/h").unwrap();
+        let s = ino(&fs.lookup(&cred, ROOT, "s").unwrap());
+        assert_eq!(fs.readlink(&cred, s).unwrap(), b"d/h");
+        fs.unlink(&cred, ROOT, "g").unwrap();
+        fs.unlink(&cred, d, "h").unwrap();
+        fs.unlink(&cred, d, "a").unwrap();
+        fs.unlink(&cred, ROOT, "s").unwrap();
+        assert_eq!(fs.unlink(&cred, ROOT, "d").unwrap_err(), -libc::EISDIR);
+        fs.rmdir(&cred, ROOT, "d").unwrap();
+        //被删除的目录只能用来查找，没有被查找过的inode不能作为parent
+        assert_eq!(fs.mknod(&cred, d, "x", 0o644).unwrap_err(), -libc::ENOENT);
+        fs.forget(d, 100);
+        assert_eq!(fs.mknod(&cred, d, "x", 0o644).unwrap_err(), -libc::ESTALE);
+        assert_eq!(names(&fs, ROOT), [".", ".."]);
+        let report = fsck(vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+
+        fs.destroy();
+        assert_eq!(fs.vol.bpm.dirty_num(), 0);
+    }
+}
@@ -0,0 +1,509 @@
+use crate::buffer::buffer_pool_manager::AutoUnpin;
+use crate::buffer::replacer::PageId;
+use crate::fs::extent::Node;
+use crate::fs::layout::BITS_PER_PAGE;
+use crate::fs::superblock::{free_counts, set_free_counts};
+use crate::fs::types::{bitmap_count, bitmap_set, FileType, Inode, InodeId};
+use crate::fs::volume::Volume;
+use crate::fs::xattr;
+use crate::{fetch_page_read, fetch_page_write};
+use log::debug;
+use std::fmt;
+
+///fsck发现的问题。修复模式下除共享块外都已被修复
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    ///指向无效inode或重复指向目录的目录项：(所在目录, 名字)
+    pub dangling_entries: Vec<(InodeId, String)>,
+    ///越界的块号，修复时从inode或索引块中删除
+    pub bad_blocks: Vec<(InodeId, i32)>,
+    ///已被其他inode使用的块，只报告不修复
+    pub shared_blocks: Vec<(InodeId, i32)>,
+    ///链接数错误的inode：(inode, 记录的链接数, 实际的链接数)
+    pub bad_nlinks: Vec<(InodeId, u32, u32)>,
+    ///位图中已分配但无法从根目录到达的inode
+    pub orphan_inodes: Vec<u32>,
+    ///能从根目录到达但位图中未分配的inode
+    pub unmarked_inodes: Vec<u32>,
+    ///位图中已分配但没有被任何inode使用的块
+    pub leaked_blocks: Vec<u32>,
+    ///被inode使用但位图中未分配的块
+    pub unmarked_blocks: Vec<u32>,
+    ///超级块中错误的空闲inode数：(记录的, 实际的)
+    pub bad_free_inodes: Option<(u32, u32)>,
+    ///超级块中错误的空闲块数：(记录的, 实际的)
+    pub bad_free_blocks: Option<(u32, u32)>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.dangling_entries.is_empty()
+            && self.bad_blocks.is_empty()
+            && self.shared_blocks.is_empty()
+            && self.bad_nlinks.is_empty()
+            && self.orphan_inodes.is_empty()
+            && self.unmarked_inodes.is_empty()
+            && self.leaked_blocks.is_empty()
+            && self.unmarked_blocks.is_empty()
+            && self.bad_free_inodes.is_none()
+            && self.bad_free_blocks.is_none()
+    }
+}
+
+impl fmt::Display for FsckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (dir, name) in &self.dangling_entries {
+            writeln!(f, "dangling entry {:?} in directory inode {}", name, dir.0)?;
+        }
+        for (inode, block) in &self.bad_blocks {
+            writeln!(f, "inode {} references invalid block {}", inode.0, block)?;
+        }
+        for (inode, block) in &self.shared_blocks {
+            writeln!(
+                f,
+                "inode {} shares block {} with another inode",
+                inode.0, block
+            )?;
+        }
+        for (inode, recorded, actual) in &self.bad_nlinks {
+            writeln!(
+                f,
+                "inode {} has nlink {}, should be {}",
+                inode.0, recorded, actual
+            )?;
+        }
+        for inode in &self.orphan_inodes {
+            writeln!(f, "orphan inode {}", inode)?;
+        }
+        for inode in &self.unmarked_inodes {
+            writeln!(
+                f,
+                "inode {} in use but not marked in the inode bitmap",
+                inode
+            )?;
+        }
+        for block in &self.leaked_blocks {
+            writeln!(f, "leaked block {}", block)?;
+        }
+        for block in &self.unmarked_blocks {
+            writeln!(
+                f,
+                "block {} in use but not marked in the data bitmap",
+                block
+            )?;
+        }
+        if let Some((recorded, actual)) = self.bad_free_inodes {
+            writeln!(f, "free inode count is {recorded}, should be {actual}")?;
+        }
+        if let Some((recorded, actual)) = self.bad_free_blocks {
+            writeln!(f, "free block count is {recorded}, should be {actual}")?;
+        }
+        Ok(())
+    }
+}
+
+///检查已挂载（见superblock::mount）的文件系统：从根目录遍历所有目录项，
+///收集每个inode使用的块，再与两个位图和inode的链接数对照。repair为true时就地修复
+pub fn fsck(vol: &Volume, repair: bool) -> Result<FsckReport, String> {
+    let l = vol.layout;
+    let mut report = FsckReport::default();
+    let root = InodeId(0).load(vol);
+    if root.inode_id != InodeId(0) || root.file_type != FileType::DIR {
+        return Err("root inode is not a directory".to_string());
+    }
+    let mut reachable = vec![false; l.inode_num];
+    let mut links = vec![0u32; l.inode_num];
+    let mut used_blocks = vec![false; l.data_num];
+    let mut dirs = Vec::new();
+    reachable[0] = true;
+    //根目录的"."和".."都指向自己
+    links[0] = 2;
+    if check_blocks(vol, InodeId(0), repair, &mut used_blocks, &mut report) {
+        dirs.push(InodeId(0));
+    }
+    while let Some(dir_id) = dirs.pop() {
+        let mut dir = dir_id.load(vol);
+        for (name, inode_id, file_type) in dir.dir_entries(vol) {
+            let i = inode_id.0 as usize;
+            //目录只能有一个父目录，第二次遇到时也当作无效目录项
+            if !valid_target(vol, inode_id, file_type) || file_type == FileType::DIR && reachable[i]
+            {
+                debug!("dangling entry {} in {:?}", name, dir_id);
+                if repair {
+                    dir.remove_dir_entry(vol, &name);
+                }
+                report.dangling_entries.push((dir_id, name));
+                continue;
+            }
+            if file_type == FileType::DIR {
+                //子目录的".."指向父目录
+                links[dir_id.0 as usize] += 1;
+                links[i] += 2;
+            } else {
+                links[i] += 1;
+            }
+            if !reachable[i] {
+                reachable[i] = true;
+                if check_blocks(vol, inode_id, repair, &mut used_blocks, &mut report)
+                    && file_type == FileType::DIR
+                {
+                    dirs.push(inode_id);
+                }
+            }
+        }
+    }
+    for i in (0..l.inode_num).filter(|&i| reachable[i]) {
+        let mut inode = InodeId(i as u32).load(vol);
+        if inode.nlink != links[i] {
+            report
+                .bad_nlinks
+                .push((inode.inode_id, inode.nlink, links[i]));
+            if repair {
+                inode.nlink = links[i];
+                store_inode(vol, &inode);
+            }
+        }
+    }
+    (report.orphan_inodes, report.unmarked_inodes) =
+        check_bitmap(vol, l.inode_map_start, &reachable, repair);
+    (report.leaked_blocks, report.unmarked_blocks) =
+        check_bitmap(vol, l.data_map_start, &used_blocks, repair);
+    //修复模式下位图已被修正，按修正后的位图计算空闲数
+    let free_inodes = (l.inode_num - bitmap_count(vol, l.inode_map_start, l.inode_num)) as u32;
+    let free_blocks = (l.data_num - bitmap_count(vol, l.data_map_start, l.data_num)) as u32;
+    let (recorded_inodes, recorded_blocks) = free_counts(vol);
+    if recorded_inodes != free_inodes {
+        report.bad_free_inodes = Some((recorded_inodes, free_inodes));
+    }
+    if recorded_blocks != free_blocks {
+        report.bad_free_blocks = Some((recorded_blocks, free_blocks));
+    }
+    if repair {
+        set_free_counts(vol, free_inodes, free_blocks);
+    }
+    Ok(report)
+}
+
+///inode_id是否在范围内并且是一个类型为file_type的在用inode
+fn valid_target(vol: &Volume, inode_id: InodeId, file_type: FileType) -> bool {
+    if inode_id.0 as usize >= vol.layout.inode_num {
+        return false;
+    }
+    let inode = inode_id.load(vol);
+    inode.inode_id == inode_id && inode.file_type == file_type && inode.nlink > 0
+}
+
+fn store_inode(vol: &Volume, inode: &Inode) {
+    let (page_id, offset) = inode.inode_id.seek(vol);
+    fetch_page_write!(inode_page: inode_page, vol, page_id, au);
+    inode_page.inodes[offset] = *inode;
+}
+
+///把inode使用的块记入used_blocks，返回inode的块号是否都有效（修复后也视为有效）
+fn check_blocks(
+    vol: &Volume,
+    inode_id: InodeId,
+    repair: bool,
+    used_blocks: &mut [bool],
+    report: &mut FsckReport,
+) -> bool {
+    let mut inode = inode_id.load(vol);
+    let bad = report.bad_blocks.len();
+    let mut blocks = Vec::new();
+    let mut xattr_block = inode.xattr_block();
+    if check_slot(vol, inode_id, &mut xattr_block, repair, report) {
+        blocks.push(xattr_block);
+        for (name, value_block) in xattr::value_blocks(vol, xattr_block) {
+            let mut slot = value_block;
+            if check_slot(vol, inode_id, &mut slot, repair, report) {
+                collect_index(vol, inode_id, value_block, 1, repair, report, &mut blocks);
+            } else if repair {
+                xattr::forget(vol, xattr_block, &name);
+            }
+        }
+    }
+    inode.set_xattr_block(xattr_block);
+    if inode.uses_extents(vol) {
+        check_extents(
+            vol,
+            inode_id,
+            &mut inode,
+            Node::Root,
+            repair,
+            report,
+            &mut blocks,
+        );
+    } else if !inode.has_inline_data() {
+        for slot in inode.direct_index.iter_mut() {
+            if check_slot(vol, inode_id, slot, repair, report) {
+                blocks.push(*slot);
+            }
+        }
+        if check_slot(vol, inode_id, &mut inode.indirect_index, repair, report) {
+            collect_index(
+                vol,
+                inode_id,
+                inode.indirect_index,
+                1,
+                repair,
+                report,
+                &mut blocks,
+            );
+        }
+        if check_slot(
+            vol,
+            inode_id,
+            &mut inode.double_indirect_index,
+            repair,
+            report,
+        ) {
+            collect_index(
+                vol,
+                inode_id,
+                inode.double_indirect_index,
+                2,
+                repair,
+                report,
+                &mut blocks,
+            );
+        }
+    }
+    for block in blocks {
+        if used_blocks[block as usize] {
+            report.shared_blocks.push((inode_id, block));
+        }
+        used_blocks[block as usize] = true;
+    }
+    if report.bad_blocks.len() == bad {
+        return true;
+    }
+    if repair {
+        store_inode(vol, &inode);
+    }
+    repair
+}
+
+///检查一个块号，越界时记入bad_blocks并在修复模式下改为-1，返回该项是否指向可用的块
+fn check_slot(
+    vol: &Volume,
+    inode_id: InodeId,
+    slot: &mut i32,
+    repair: bool,
+    report: &mut FsckReport,
+) -> bool {
+    if *slot == -1 {
+        return false;
+    }
+    if *slot < 0 || *slot as usize >= vol.layout.data_num {
+        report.bad_blocks.push((inode_id, *slot));
+        if repair {
+            *slot = -1;
+        }
+        return false;
+    }
+    true
+}
+
+///收集索引块本身及其下depth层的所有块
+fn collect_index(
+    vol: &Volume,
+    inode_id: InodeId,
+    index_block: i32,
+    depth: usize,
+    repair: bool,
+    report: &mut FsckReport,
+    blocks: &mut Vec<i32>,
+) {
+    blocks.push(index_block);
+    let page_id = vol.layout.data_page_id(index_block);
+    let mut index = {
+        fetch_page_read!(index_page: index_page, vol, page_id, au);
+        index_page.index
+    };
+    let bad = report.bad_blocks.len();
+    for slot in index.iter_mut() {
+        if check_slot(vol, inode_id, slot, repair, report) {
+            if depth == 1 {
+                blocks.push(*slot);
+            } else {
+                collect_index(vol, inode_id, *slot, depth - 1, repair, report, blocks);
+            }
+        }
+    }
+    if repair && report.bad_blocks.len() != bad {
+        fetch_page_write!(index_page: index_page, vol, page_id, au);
+        index_page.index = index;
+    }
+}
+
+///收集extent树中node之下的所有块。越界的extent或子节点记入bad_blocks，修复时从节点中删除，
+///损坏的子节点块也当作越界处理
+fn check_extents(
+    vol: &Volume,
+    inode_id: InodeId,
+    inode: &mut Inode,
+    node: Node,
+    repair: bool,
+    report: &mut FsckReport,
+    blocks: &mut Vec<i32>,
+) {
+    let Some((depth, mut entries)) = inode.load_extent_node(vol, node) else {
+        return;
+    };
+    let bad = report.bad_blocks.len();
+    let data_num = vol.layout.data_num as i64;
+    entries.retain(|entry| {
+        let len = if depth == 0 { entry.len as i64 } else { 1 };
+        let valid = entry.start >= 0
+            && entry.start as i64 + len <= data_num
+            && (depth == 0
+                || inode
+                    .load_extent_node(vol, Node::Block(entry.start))
+                    .is_some());
+        if !valid {
+            report.bad_blocks.push((inode_id, entry.start));
+            return !repair;
+        }
+        if depth == 0 {
+            blocks.extend((0..entry.len).map(|j| entry.start + j as i32));
+        } else {
+            blocks.push(entry.start);
+            check_extents(
+                vol,
+                inode_id,
+                inode,
+                Node::Block(entry.start),
+                repair,
+                report,
+                blocks,
+            );
+        }
+        true
+    });
+    if repair && report.bad_blocks.len() != bad {
+        inode.store_extent_node(vol, node, depth, &entries);
+    }
+}
+
+///对照从start页开始的位图与实际使用情况，返回(已分配但未使用, 已使用但未分配)
+fn check_bitmap(vol: &Volume, start: usize, used: &[bool], repair: bool) -> (Vec<u32>, Vec<u32>) {
+    let mut extra = Vec::new();
+    let mut missing = Vec::new();
+    for (i, used) in used.chunks(BITS_PER_PAGE).enumerate() {
+        fetch_page_read!(bitmap_page: bitmap, vol, start + i, au);
+        for (bit, &used) in used.iter().enumerate() {
+            let n = (i * BITS_PER_PAGE + bit) as u32;
+            match (bitmap_page.test(bit as u32), used) {
+                (true, false) => extra.push(n),
+                (false, true) => missing.push(n),
+                _ => {}
+            }
+        }
+    }
+    if repair {
+        for &n in &extra {
+            bitmap_set(vol, start, n, false);
+        }
+        for &n in &missing {
+            bitmap_set(vol, start, n, true);
+        }
+    }
+    (extra, missing)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::mem_bpm;
+    use crate::fs::dcache::DCache;
+    use crate::fs::def::SUCCESS;
+    use crate::fs::layout::{Layout, LayoutOptions};
+    use crate::fs::superblock::format;
+    use crate::fs::types::bitmap_test;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_fsck() {
+        let layout = Layout::new(1024, LayoutOptions::default()).unwrap();
+        let vol = &Arc::new(format(mem_bpm(1, 20), layout, 0));
+        let dir_tree = DCache::new(vol.clone(), 100);
+        let f = {
+            let root = dir_tree.search("/").unwrap();
+            assert_eq!(dir_tree.insert(&root, "d", FileType::DIR, 0o755), SUCCESS);
+            let d = dir_tree.search("/d").unwrap();
+            assert_eq!(dir_tree.insert(&d, "f", FileType::REG, 0o644), SUCCESS);
+            dir_tree.search("/d/f").unwrap().inode_id
+        };
+        let mut inode = f.load(vol);
+        let data = vec![7u8; 20 * 4096];
+        assert_eq!(inode.write(vol, 0, &data), data.len());
+        store_inode(vol, &inode);
+        let report = fsck(vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+
+        //制造各种不一致
+        let l = vol.layout;
+        bitmap_set(vol, l.data_map_start, 500, true);
+        bitmap_set(vol, l.inode_map_start, 100, true);
+        bitmap_set(vol, l.data_map_start, inode.direct_index[3] as u32, false);
+        let mut root = InodeId(0).load(vol);
+        assert_eq!(
+            root.add_dir_entry(vol, "ghost", FileType::REG, InodeId(200)),
+            SUCCESS
+        );
+        assert_eq!(
+            root.add_dir_entry(vol, "loop", FileType::DIR, InodeId(0)),
+            SUCCESS
+        );
+        store_inode(vol, &root);
+        let mut inode = f.load(vol);
+        let old_block5 = inode.direct_index[5];
+        inode.nlink = 5;
+        inode.direct_index[5] = 5000;
+        store_inode(vol, &inode);
+
+        let report = fsck(vol, false).unwrap();
+        let mut dangling: Vec<_> = report.dangling_entries.iter().map(|e| &e.1[..]).collect();
+        dangling.sort();
+        assert_eq!(dangling, ["ghost", "loop"]);
+        assert_eq!(report.bad_blocks, [(f, 5000)]);
+        assert_eq!(report.bad_nlinks, [(f, 5, 1)]);
+        assert_eq!(report.orphan_inodes, [100]);
+        assert!(report.unmarked_inodes.is_empty());
+        //direct_index[5]越界后原来的块不再被使用
+        assert_eq!(report.leaked_blocks, [old_block5 as u32, 500]);
+        assert_eq!(report.unmarked_blocks, [inode.direct_index[3] as u32]);
+        assert!(report.shared_blocks.is_empty());
+        //直接改位图不会更新超级块中的计数，数据位图一置一清正好抵消
+        let (free_inodes, free_blocks) = free_counts(vol);
+        assert_eq!(report.bad_free_inodes, Some((free_inodes, free_inodes - 1)));
+        assert_eq!(report.bad_free_blocks, None);
+        assert!(!report.is_clean());
+
+        let report = fsck(vol, true).unwrap();
+        assert!(!report.is_clean());
+        let report = fsck(vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+        assert!(!bitmap_test(vol, l.data_map_start, 500));
+        assert!(!bitmap_test(vol, l.data_map_start, old_block5 as u32));
+        assert!(!bitmap_test(vol, l.inode_map_start, 100));
+        assert!(bitmap_test(
+            vol,
+            l.data_map_start,
+            inode.direct_index[3] as u32
+        ));
+        let inode = f.load(vol);
+        assert_eq!(inode.nlink, 1);
+        assert_eq!(inode.direct_index[5], -1);
+        let mut buf = vec![0u8; 4096];
+        assert_eq!(inode.read(vol, 3 * 4096, &mut buf), 4096);
+        assert!(buf.iter().all(|b| *b == 7));
+        assert!(InodeId(0)
+            .load(vol)
+            .search_dir_by_name(vol, "ghost")
+            .is_none());
+        assert!(InodeId(0).load(vol).search_dir_by_name(vol, "d").is_some());
+        //泄漏的两个块回到空闲计数中
+        assert_eq!(free_counts(vol), (free_inodes, free_blocks + 1));
+    }
+}
@@ -0,0 +1,229 @@
+//! 打开的文件和目录。open、create和opendir把inode号放在fuse_file_info.fh中，
+//! 之后的read、write、fgetattr、ftruncate和fsync直接使用它，不再查找路径。
+//!
+//! 每个inode记录被打开的次数。最后一个名字被删除时如果inode还被打开，只把它记为孤儿，
+//! 数据块和inode留到最后一次release时释放，在此之前仍然可以通过已经打开的句柄读写；
+//! 没有被打开的inode在删除操作改完目录之后释放。大文件分多个事务释放，
+//! 崩溃时留下的孤儿inode没有目录项指向它，由fsck回收。
+//!
+//! /.rustfs下的文件没有inode，打开时生成一份内容快照，fh是快照的编号，
+//! 之后的fgetattr和read都使用这份快照，release时丢弃
+use crate::buffer::buffer_pool_manager::AutoUnpin;
+use crate::buffer::replacer::PageId;
+use crate::fetch_page_write;
+use crate::fs::journal;
+use crate::fs::types::{free_inode, InodeId};
+use crate::fs::volume::Volume;
+use log::debug;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+///打开/.rustfs目录时使用的fh，它没有inode
+pub const CONTROL_FH: u64 = u64::MAX;
+///快照的fh从这里开始编号，都大于任何inode号
+const SNAPSHOT_FH: u64 = 1 << 32;
+
+#[derive(Default)]
+struct Open {
+    ///打开的句柄数
+    count: usize,
+    ///最后一个名字已被删除
+    orphan: bool,
+}
+
+///卷上打开的句柄
+pub struct Handles {
+    open: Mutex<BTreeMap<u32, Open>>,
+    ///最后一个名字已被删除、没有被打开、等待free_unlinked释放的inode
+    unlinked: Mutex<Vec<InodeId>>,
+    snapshots: Mutex<BTreeMap<u64, Arc<[u8]>>>,
+    next_snapshot: AtomicU64,
+}
+
+impl Default for Handles {
+    fn default() -> Self {
+        Handles {
+            open: Mutex::new(BTreeMap::new()),
+            unlinked: Mutex::new(Vec::new()),
+            snapshots: Mutex::new(BTreeMap::new()),
+            next_snapshot: AtomicU64::new(SNAPSHOT_FH),
+        }
+    }
+}
+
+///fh对应的inode，/.rustfs下的文件返回None
+pub fn inode(fh: u64) -> Option<InodeId> {
+    (fh < SNAPSHOT_FH).then_some(InodeId(fh as u32))
+}
+
+impl Handles {
+    ///打开inode，返回放在fh中的值
+    pub fn open(&self, inode_id: InodeId) -> u64 {
+        self.open.lock().entry(inode_id.0).or_default().count += 1;
+        inode_id.0 as u64
+    }
+
+    ///保存/.rustfs下文件打开时的内容，返回放在fh中的值
+    pub fn open_snapshot(&self, content: String) -> u64 {
+        let fh = self.next_snapshot.fetch_add(1, Ordering::Relaxed);
+        self.snapshots
+            .lock()
+            .insert(fh, content.into_bytes().into());
+        fh
+    }
+
+    ///fh对应的快照，/.rustfs目录和普通文件返回None
+    pub fn snapshot(&self, fh: u64) -> Option<Arc<[u8]>> {
+        self.snapshots.lock().get(&fh).cloned()
+    }
+
+    pub fn release_snapshot(&self, fh: u64) {
+        self.snapshots.lock().remove(&fh);
+    }
+
+    ///关闭一个句柄，inode已是孤儿并且这是最后一个句柄时返回true，由调用者释放inode
+    #[must_use]
+    pub fn release(&self, inode_id: InodeId) -> bool {
+        let mut open = self.open.lock();
+        let Some(entry) = open.get_mut(&inode_id.0) else {
+            return false;
+        };
+        entry.count -= 1;
+        if entry.count > 0 {
+            return false;
+        }
+        open.remove(&inode_id.0).unwrap().orphan
+    }
+
+    ///inode的最后一个名字被删除时调用，此时还持有目录和inode的页，不能释放。
+    ///inode仍被打开时记为孤儿，由最后一次release释放；否则由删除操作随后调用free_unlinked释放
+    pub fn defer_free(&self, inode_id: InodeId) {
+        match self.open.lock().get_mut(&inode_id.0) {
+            Some(entry) => entry.orphan = true,
+            None => self.unlinked.lock().push(inode_id),
+        }
+    }
+
+    pub fn is_open(&self, inode_id: InodeId) -> bool {
+        self.open.lock().contains_key(&inode_id.0)
+    }
+
+    ///卸载时丢弃所有句柄，返回其中的孤儿inode
+    pub fn take_orphans(&self) -> Vec<InodeId> {
+        std::mem::take(&mut *self.open.lock())
+            .into_iter()
+            .filter(|(_, entry)| entry.orphan)
+            .map(|(inode_id, _)| InodeId(inode_id))
+            .collect()
+    }
+}
+
+///释放孤儿inode的数据块和inode本身。在事务中调用时每释放一步提交一次，
+///调用时不能持有任何页
+pub fn free(vol: &Volume, inode_id: InodeId) {
+    debug!("free orphan inode {}", inode_id.0);
+    while !free_step(vol, inode_id) {
+        journal::restart(vol);
+    }
+}
+
+///释放inode的一步，全部释放完时返回true
+fn free_step(vol: &Volume, inode_id: InodeId) -> bool {
+    let (page_id, offset) = inode_id.seek(vol);
+    fetch_page_write!(inode_page: inode_page, vol, page_id, auto_unpin_inode_page);
+    let inode = &mut inode_page.inodes[offset];
+    if inode.nlink != 0 {
+        return true;
+    }
+    if !inode.free_blocks(vol) {
+        return false;
+    }
+    free_inode(vol, inode_id);
+    true
+}
+
+///释放删除操作留下的没有被打开的inode，在删除操作的事务中改完目录之后调用
+pub fn free_unlinked(vol: &Volume) {
+    let unlinked = std::mem::take(&mut *vol.handles.unlinked.lock());
+    for inode_id in unlinked {
+        free(vol, inode_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::mem_bpm;
+    use crate::fs::dcache::DCache;
+    use crate::fs::def::SUCCESS;
+    use crate::fs::fsck::fsck;
+    use crate::fs::layout::{Layout, LayoutOptions};
+    use crate::fs::superblock::{format, free_counts};
+    use crate::fs::types::FileType;
+
+    #[test]
+    fn test_orphan() {
+        let layout = Layout::new(1024, LayoutOptions::default()).unwrap();
+        let vol = Arc::new(format(mem_bpm(1, 20), layout, 0));
+        let dir_tree = DCache::new(vol.clone(), 100);
+        let vol = &*vol;
+        let handles = &vol.handles;
+        let root = dir_tree.search("/").unwrap();
+        let f = dir_tree.create(&root, "f", FileType::REG, 0o644).unwrap();
+        let counts = free_counts(vol);
+        {
+            let (page_id, offset) = f.seek(vol);
+            fetch_page_write!(inode_page: inode_page, vol, page_id, au);
+            assert_eq!(inode_page.inodes[offset].write(vol, 0, &[1u8; 5000]), 5000);
+        }
+
+        //没有被删除的文件关闭时不释放
+        let fh = handles.open(f);
+        assert_eq!(inode(fh), Some(f));
+        assert_eq!(inode(CONTROL_FH), None);
+        let snap = handles.open_snapshot("abc".to_string());
+        assert_eq!(inode(snap), None);
+        assert_eq!(handles.snapshot(snap).as_deref(), Some(&b"abc"[..]));
+        assert_eq!(handles.snapshot(CONTROL_FH), None);
+        handles.release_snapshot(snap);
+        assert_eq!(handles.snapshot(snap), None);
+        assert!(!handles.release(f));
+        assert!(!handles.is_open(f));
+
+        //删除后仍然可以通过句柄读取，最后一个句柄关闭时才释放
+        let fh = handles.open(f);
+        handles.open(f);
+        assert_eq!(dir_tree.remove(&root, "f", false), SUCCESS);
+        assert!(dir_tree.search("/f").is_none());
+        let orphan = f.load(vol);
+        assert_eq!(orphan.nlink, 0);
+        let mut buf = [0u8; 5000];
+        assert_eq!(orphan.read(vol, 0, &mut buf), 5000);
+        assert_eq!(buf, [1u8; 5000]);
+        assert_ne!(free_counts(vol), counts);
+        //崩溃时留下的孤儿inode由fsck发现
+        let report = fsck(vol, false).unwrap();
+        assert_eq!(report.orphan_inodes, [f.0]);
+        assert!(!handles.release(inode(fh).unwrap()));
+        assert!(handles.release(f));
+        free(vol, f);
+        assert_eq!(free_counts(vol), (counts.0 + 1, counts.1));
+        let report = fsck(vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+
+        //卸载时取出还没有关闭的孤儿
+        let g = dir_tree.create(&root, "g", FileType::REG, 0o644).unwrap();
+        dir_tree.create(&root, "h", FileType::REG, 0o644).unwrap();
+        let h = dir_tree.search("/h").unwrap().inode_id;
+        handles.open(g);
+        handles.open(h);
+        assert_eq!(dir_tree.remove(&root, "g", false), SUCCESS);
+        assert_eq!(handles.take_orphans(), [g]);
+        assert!(!handles.is_open(h));
+        free(vol, g);
+        let report = fsck(vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+    }
+}
@@ -0,0 +1,365 @@
+//! 仿照ext4 htree的哈希目录索引。
+//!
+//! 启用索引的目录中，逻辑块0是根索引页，其余块是索引页或叶子。叶子就是普通的目录页，
+//! 索引页按名字哈希值把目录项分到各个叶子中，查找一个名字只需读出从根到叶子路径上的页。
+//! 叶子满时按哈希值对半分裂，同一哈希值的目录项尽量留在同一个叶子中；
+//! 实在无法分开时，新叶子的下界哈希值带上最低位的延续标记，查找时会继续读它。
+use crate::buffer::buffer_pool_manager::AutoUnpin;
+use crate::buffer::page::{DxEntry, DxPage};
+use crate::buffer::replacer::PageId;
+use crate::fs::custom::{DIR_ENTRY_PER_PAGE, PAGE_SIZE};
+use crate::fs::def::SUCCESS;
+use crate::fs::types::{DEntry, FileType, Inode, InodeId};
+use crate::fs::volume::Volume;
+use crate::{fetch_page_read, fetch_page_write};
+use libc::c_int;
+use log::debug;
+
+pub const DX_ENTRY_PER_PAGE: usize = 511;
+
+///名字的哈希值（FNV-1a），最低位留作延续标记，总是为0
+pub fn dx_hash(name: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for &b in name {
+        hash ^= b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash & !1
+}
+
+impl Inode {
+    pub fn is_indexed_dir(&self, vol: &Volume) -> bool {
+        self.file_type == FileType::DIR && vol.dir_index
+    }
+
+    fn dir_block_page_id(&self, vol: &Volume, block: u32) -> usize {
+        self.block_page_id(vol, block as usize)
+            .expect("dir index points to a hole")
+    }
+
+    fn read_dx(&self, vol: &Volume, block: u32) -> DxPage {
+        fetch_page_read!(dx_page: dx_page, vol, self.dir_block_page_id(vol, block), au);
+        *dx_page
+    }
+
+    ///从根索引页按hash向下查找，返回路径上每个索引页的(逻辑块号, 选中项的下标)以及找到的叶子
+    fn dx_path(&self, vol: &Volume, hash: u32) -> (Vec<(u32, usize)>, u32) {
+        let depth = self.read_dx(vol, 0).depth;
+        let mut path = Vec::new();
+        let mut block = 0;
+        for _ in 0..=depth {
+            let dx = self.read_dx(vol, block);
+            let entries = &dx.entries[..dx.count as usize];
+            let i = entries.partition_point(|e| e.hash <= hash).max(1) - 1;
+            path.push((block, i));
+            block = entries[i].block;
+        }
+        (path, block)
+    }
+
+    ///把path移到按哈希值排序的下一个叶子，返回该叶子和它的下界哈希值，没有下一个叶子时返回None
+    fn dx_next_leaf(&self, vol: &Volume, path: &mut [(u32, usize)]) -> Option<(u32, u32)> {
+        let mut level = path.len();
+        loop {
+            if level == 0 {
+                return None;
+            }
+            level -= 1;
+            let (block, i) = path[level];
+            if i + 1 < self.read_dx(vol, block).count as usize {
+                path[level].1 = i + 1;
+                break;
+            }
+        }
+        let mut entry = self.read_dx(vol, path[level].0).entries[path[level].1];
+        let hash = entry.hash;
+        for step in path.iter_mut().skip(level + 1) {
+            *step = (entry.block, 0);
+            entry = self.read_dx(vol, entry.block).entries[0];
+        }
+        Some((entry.block, hash))
+    }
+
+    ///可能含有name的叶子页：name的哈希值所在的叶子，以及紧随其后带延续标记的叶子
+    pub(crate) fn dx_leaf_page_ids(&self, vol: &Volume, name: &str) -> Vec<usize> {
+        if self.size == 0 {
+            return Vec::new();
+        }
+        let hash = dx_hash(name.as_bytes());
+        let (mut path, leaf) = self.dx_path(vol, hash);
+        let mut result = vec![self.dir_block_page_id(vol, leaf)];
+        while let Some((leaf, bound)) = self.dx_next_leaf(vol, &mut path) {
+            if bound != hash | 1 {
+                break;
+            }
+            result.push(self.dir_block_page_id(vol, leaf));
+        }
+        result
+    }
+
+    ///按哈希值顺序列出所有叶子页
+    pub(crate) fn dx_all_leaf_page_ids(&self, vol: &Volume) -> Vec<usize> {
+        self.dx_leaf_blocks(vol)
+            .into_iter()
+            .map(|block| self.dir_block_page_id(vol, block))
+            .collect()
+    }
+
+    ///按哈希值顺序列出所有叶子的逻辑块号
+    pub(crate) fn dx_leaf_blocks(&self, vol: &Volume) -> Vec<u32> {
+        let mut result = Vec::new();
+        if self.size != 0 {
+            self.dx_collect_leaves(vol, 0, self.read_dx(vol, 0).depth, &mut result);
+        }
+        result
+    }
+
+    fn dx_collect_leaves(&self, vol: &Volume, block: u32, depth: u32, result: &mut Vec<u32>) {
+        let dx = self.read_dx(vol, block);
+        for entry in &dx.entries[..dx.count as usize] {
+            if depth == 0 {
+                result.push(entry.block);
+            } else {
+                self.dx_collect_leaves(vol, entry.block, depth - 1, result);
+            }
+        }
+    }
+
+    ///在目录末尾追加一个清零的块，返回它的逻辑块号
+    fn dir_append_block(&mut self, vol: &Volume) -> Option<u32> {
+        let block = self.size as usize / PAGE_SIZE;
+        self.block_page_id_or_alloc(vol, block)?;
+        self.size += PAGE_SIZE as u64;
+        Some(block as u32)
+    }
+
+    ///向索引目录插入目录项，叶子已满时分裂叶子，必要时逐层分裂索引页
+    pub(crate) fn dx_add_entry(
+        &mut self,
+        vol: &Volume,
+        name: &str,
+        file_type: FileType,
+        inode_id: InodeId,
+    ) -> c_int {
+        if self.size == 0 {
+            //空目录：块0为根索引页，块1为第一个叶子
+            let (Some(root), Some(leaf)) = (self.dir_append_block(vol), self.dir_append_block(vol))
+            else {
+                return -libc::ENOSPC;
+            };
+            fetch_page_write!(dx_page: dx_page, vol, self.dir_block_page_id(vol, root), au);
+            dx_page.depth = 0;
+            dx_page.count = 1;
+            dx_page.entries[0] = DxEntry { hash: 0, block: leaf };
+        }
+        let (mut path, leaf) = self.dx_path(vol, dx_hash(name.as_bytes()));
+        let leaf_page_id = self.dir_block_page_id(vol, leaf);
+        {
+            fetch_page_write!(dir_page: dir_page, vol, leaf_page_id, au);
+            if let Some(entry) = dir_page.dir_entries.iter_mut().find(|e| !e.is_valid) {
+                entry.init(name, file_type, inode_id);
+                return SUCCESS;
+            }
+        }
+        //叶子已满。先分配好分裂需要的所有块，避免分裂到一半时空间不足
+        let full_levels = path
+            .iter()
+            .rev()
+            .take_while(|(block, _)| self.read_dx(vol, *block).count as usize == DX_ENTRY_PER_PAGE)
+            .count();
+        let mut need = 1 + full_levels;
+        if full_levels == path.len() {
+            need += 1;
+        }
+        let mut spare = Vec::with_capacity(need);
+        for _ in 0..need {
+            let Some(block) = self.dir_append_block(vol) else {
+                return -libc::ENOSPC;
+            };
+            spare.push(block);
+        }
+        let new_leaf = spare.pop().unwrap();
+        let split_hash = self.dx_split_leaf(vol, leaf_page_id, new_leaf, name, file_type, inode_id);
+        debug!("split dir leaf {} at hash {:#x}", leaf, split_hash);
+        let level = path.len() - 1;
+        self.dx_insert(vol, &mut path, level, split_hash, new_leaf, &mut spare);
+        SUCCESS
+    }
+
+    ///把满的叶子和新目录项按哈希值分到原叶子和new_leaf中，返回new_leaf的下界哈希值
+    fn dx_split_leaf(
+        &self,
+        vol: &Volume,
+        leaf_page_id: usize,
+        new_leaf: u32,
+        name: &str,
+        file_type: FileType,
+        inode_id: InodeId,
+    ) -> u32 {
+        fetch_page_write!(dir_page: dir_page, vol, leaf_page_id, au);
+        let mut entries: Vec<DEntry> = dir_page.dir_entries.to_vec();
+        let mut new_entry = entries[0];
+        new_entry.init(name, file_type, inode_id);
+        entries.push(new_entry);
+        entries.sort_by_key(|e| dx_hash(e.name_bytes()));
+        let hashes: Vec<u32> = entries.iter().map(|e| dx_hash(e.name_bytes())).collect();
+        //在中点附近找哈希值变化的位置，使同一哈希值的目录项留在同一个叶子中
+        let mid = entries.len() / 2;
+        let (split, split_hash) = match (1..entries.len())
+            .filter(|&i| hashes[i] != hashes[i - 1])
+            .min_by_key(|&i| i.abs_diff(mid))
+        {
+            Some(i) => (i, hashes[i]),
+            None => (mid, hashes[mid] | 1),
+        };
+        for (j, entry) in dir_page.dir_entries.iter_mut().enumerate() {
+            if j < split {
+                *entry = entries[j];
+            } else {
+                entry.is_valid = false;
+            }
+        }
+        fetch_page_write!(new_page: dir_page, vol, self.dir_block_page_id(vol, new_leaf), au_new);
+        for (j, entry) in entries[split..].iter().enumerate() {
+            new_page.dir_entries[j] = *entry;
+        }
+        split_hash
+    }
+
+    ///把(hash, block)插入path第level层索引页的选中项之后。索引页已满时分裂它，
+    ///根索引页已满时把它的内容移到新的索引页中，树增高一层。spare是预先分配好的块
+    fn dx_insert(
+        &mut self,
+        vol: &Volume,
+        path: &mut Vec<(u32, usize)>,
+        level: usize,
+        hash: u32,
+        block: u32,
+        spare: &mut Vec<u32>,
+    ) {
+        let (node, i) = path[level];
+        let mut dx = self.read_dx(vol, node);
+        if (dx.count as usize) < DX_ENTRY_PER_PAGE {
+            insert_entry(&mut dx, i + 1, DxEntry { hash, block });
+            fetch_page_write!(dx_page: dx_page, vol, self.dir_block_page_id(vol, node), au);
+            *dx_page = dx;
+            return;
+        }
+        let sibling = spare.pop().unwrap();
+        if level == 0 {
+            {
+                fetch_page_write!(child: dx_page, vol, self.dir_block_page_id(vol, sibling), au);
+                *child = dx;
+                child.depth = 0;
+            }
+            {
+                fetch_page_write!(root: dx_page, vol, self.dir_block_page_id(vol, node), au);
+                root.depth += 1;
+                root.count = 1;
+                root.entries[0] = DxEntry {
+                    hash: 0,
+                    block: sibling,
+                };
+            }
+            path[0].1 = 0;
+            path.insert(1, (sibling, i));
+            return self.dx_insert(vol, path, 1, hash, block, spare);
+        }
+        let count = dx.count as usize;
+        let mid = count / 2;
+        let mut right = dx;
+        right.depth = 0;
+        right.count = (count - mid) as u32;
+        right.entries[..count - mid].copy_from_slice(&dx.entries[mid..count]);
+        dx.count = mid as u32;
+        let split_hash = right.entries[0].hash;
+        if i < mid {
+            insert_entry(&mut dx, i + 1, DxEntry { hash, block });
+        } else {
+            insert_entry(&mut right, i + 1 - mid, DxEntry { hash, block });
+        }
+        {
+            fetch_page_write!(dx_page: dx_page, vol, self.dir_block_page_id(vol, node), au);
+            *dx_page = dx;
+        }
+        {
+            fetch_page_write!(dx_page: dx_page, vol, self.dir_block_page_id(vol, sibling), au);
+            *dx_page = right;
+        }
+        self.dx_insert(vol, path, level - 1, split_hash, sibling, spare);
+    }
+}
+
+fn insert_entry(dx: &mut DxPage, pos: usize, entry: DxEntry) {
+    let count = dx.count as usize;
+    dx.entries.copy_within(pos..count, pos + 1);
+    dx.entries[pos] = entry;
+    dx.count += 1;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::mem_bpm;
+    use crate::fs::def::FEATURE_DIR_INDEX;
+    use crate::fs::layout::{Layout, LayoutOptions};
+    use crate::fs::superblock::format;
+
+    #[test]
+    fn test_dir_index() {
+        let vol = &format(
+            mem_bpm(1, 20),
+            Layout::new(1024, LayoutOptions::default()).unwrap(),
+            FEATURE_DIR_INDEX,
+        );
+        let mut dir: Inode = unsafe { std::mem::zeroed() };
+        dir.init(vol, InodeId(0), FileType::DIR, 0o755, 0, 0);
+        assert!(dir.is_empty_dir(vol));
+        //c40998和c702947的哈希值相同
+        assert_eq!(dx_hash(b"c40998"), dx_hash(b"c702947"));
+        let mut names: Vec<String> = (0..1000).map(|i| format!("file{i}")).collect();
+        names.push("c40998".to_string());
+        names.push("c702947".to_string());
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(dir.add_dir_entry(vol, name, FileType::REG, InodeId(i as u32 + 1)), SUCCESS);
+        }
+        assert!(dir.dx_all_leaf_page_ids(vol).len() > 1);
+        for (i, name) in names.iter().enumerate() {
+            assert_eq!(dir.dx_leaf_page_ids(vol, name).len(), 1);
+            assert_eq!(
+                dir.search_dir_by_name(vol, name),
+                Some((InodeId(i as u32 + 1), FileType::REG))
+            );
+        }
+        assert!(dir.search_dir_by_name(vol, "file1000").is_none());
+        assert_eq!(dir.all_dir_entry_name(vol).len(), names.len());
+        //按槽位顺序读出，叶子按逻辑块号而不是哈希值排列
+        let mut slots = Vec::new();
+        dir.read_dir_from(vol, 0, |slot, entry| {
+            slots.push(slot);
+            entry.is_valid
+        });
+        assert_eq!(slots.len(), names.len());
+        assert!(slots.windows(2).all(|w| w[0] < w[1]));
+
+        for name in names.iter().step_by(2) {
+            assert!(dir.remove_dir_entry(vol, name).is_some());
+        }
+        let size = dir.size;
+        for name in names.iter().step_by(2) {
+            assert!(dir.search_dir_by_name(vol, name).is_none());
+            assert_eq!(dir.add_dir_entry(vol, name, FileType::DIR, InodeId(0)), SUCCESS);
+        }
+        //删除后空出的目录项被复用，不需要再分裂叶子
+        assert_eq!(dir.size, size);
+        assert_eq!(
+            dir.search_dir_by_name(vol, "file0"),
+            Some((InodeId(0), FileType::DIR))
+        );
+        for name in names.iter() {
+            assert!(dir.remove_dir_entry(vol, name).is_some());
+        }
+        assert!(dir.is_empty_dir(vol));
+        dir.free_blocks(vol);
+    }
+}
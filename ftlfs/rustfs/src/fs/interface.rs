@@ -1,32 +1,56 @@
-use crate::buffer::buffer_pool_manager::AutoUnpin;
-use crate::buffer::buffer_pool_manager::{BufferPoolManager, ParallelBufferPoolManager, BPM};
-use crate::buffer::page::{Page, SuperPage};
-use crate::buffer::replacer::PageId;
-use crate::fs::custom::{
-    DATA_MAP_PAGE_ID, DATA_START_PAGE_ID, DDRIVER_PATH, INODE_MAP_PAGE_ID, PAGE_SIZE,
+use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+use crate::buffer::replacer::ReplacerKind;
+use crate::device::{is_memory, open_device};
+use crate::fs::control;
+use crate::fs::dcache::DCACHE_ENTRIES;
+use crate::fs::def::SUCCESS;
+use crate::fs::filesystem::{
+    FileAttr, Filesystem, FsOptions, FsResult, PathEntry, RustFs, SetAttr, TimeOrNow, ROOT,
 };
-use crate::fs::dcache::{DCache, D_CACHE};
-use crate::fs::def::{MAGIC_NUM, PAGE_SIZE_U32, SUCCESS};
-use crate::fs::types::{DEntry, FileType, Inode, InodeId};
-use crate::fs::utils::{split_path, start_flusher};
-use crate::{fetch_page_write, fuse, new_page};
-use libc::{
-    self, blkcnt_t, blksize_t, c_char, c_int, c_uint, c_ulong, c_void, getgid, getuid, off_t,
-    size_t, time, unshare, S_IFDIR, S_IFREG,
-};
-use log::{debug, error, info, trace, warn};
-use std::alloc::alloc;
-use std::ptr::null_mut;
-use std::sync::{Mutex, RwLock};
-use std::{ffi::CString, mem, ptr::null};
+use crate::fs::handle::{self, CONTROL_FH};
+use crate::fs::perm::{caller, Cred};
+use crate::fs::superblock::format_default;
+use crate::fs::types::{FileType, InodeId};
+use crate::fs::utils::{check_path, split_path};
+use crate::fuse;
+use libc::{self, c_char, c_int, c_void, off_t, size_t, S_IFDIR};
+use log::{error, trace};
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::{ffi::CString, mem};
+
+///挂载时缓存池的frame数，要放得下一个事务修改的所有元数据页，见journal::MIN_POOL_SIZE
+const POOL_SIZE: usize = 128;
 
-/// fuse function interface
+// fuse function interface
 
 macro_rules! cstr_convert_or_return {
     ($cstr: expr, $name: expr) => {
         match cstr_check($cstr, $name) {
             Some(s) => s,
-            None => return libc::ENOENT,
+            None => return -libc::EINVAL,
+        }
+    };
+}
+
+///路径必须是UTF-8，每个分量不超过MAX_FILE_NAME字节
+macro_rules! path_convert_or_return {
+    ($cstr: expr, $name: expr) => {{
+        let path = cstr_convert_or_return!($cstr, $name);
+        let ret = check_path(path);
+        if ret != SUCCESS {
+            return ret;
+        }
+        path
+    }};
+}
+
+///Err时直接返回其中的错误码
+macro_rules! ok_or_return {
+    ($result: expr) => {
+        match $result {
+            Ok(value) => value,
+            Err(ret) => return ret,
         }
     };
 }
@@ -37,56 +61,146 @@ fn cstr_check(cstr: *const c_char, err_output: &str) -> Option<&'static str> {
         Err(_) => {
             println!("{err_output} utf8 err.\n");
             unsafe {
-                libc::printf("try: <%s>\n\0".as_ptr().cast(), cstr);
+                libc::printf(c"try: <%s>\n".as_ptr(), cstr);
             };
             None
         }
     }
 }
 
+static FS: RwLock<Option<Arc<RustFs>>> = RwLock::new(None);
+
+///挂载时换上新的文件系统
+pub fn set_fs(fs: RustFs) {
+    *FS.write() = Some(Arc::new(fs));
+}
+
+///当前挂载的文件系统
+pub fn fs() -> Arc<RustFs> {
+    FS.read().clone().expect("filesystem is not mounted")
+}
+
+///测试中通过fuse回调访问的文件系统。回调都使用同一个FS，持有TestMount的测试依次执行，
+///drop时卸下文件系统，不经过回调的测试不受影响
+#[cfg(test)]
+pub struct TestMount(parking_lot::MutexGuard<'static, ()>);
+
+#[cfg(test)]
+impl TestMount {
+    pub fn new(fs: RustFs) -> Self {
+        static LOCK: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+        let guard = LOCK.lock();
+        set_fs(fs);
+        TestMount(guard)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestMount {
+    fn drop(&mut self) {
+        FS.write().take();
+    }
+}
+
+///要在其中新建或删除目录项的父目录和名字
+fn parent<'a, 'b>(
+    fs: &'a RustFs,
+    path: &'b str,
+    cred: &Cred,
+) -> Result<(PathEntry<'a>, &'b str), c_int> {
+    let (parent_path, name) = split_path(path);
+    Ok((fs.lookup_path(cred, parent_path)?, name))
+}
+
+fn to_errno(result: FsResult<()>) -> c_int {
+    match result {
+        Ok(()) => SUCCESS,
+        Err(ret) => ret,
+    }
+}
+
+///路径接口不需要新建的inode的查找次数
+fn created(fs: &RustFs, result: FsResult<FileAttr>) -> c_int {
+    let stat = ok_or_return!(result);
+    fs.forget(InodeId(stat.st_ino as u32), 1);
+    SUCCESS
+}
+
+///按getxattr和listxattr的约定把data复制到buf中：size为0时只返回所需长度，buf太小时返回ERANGE
+fn copy_xattr_out(data: &[u8], buf: *mut c_char, size: size_t) -> c_int {
+    if size == 0 {
+        return data.len() as c_int;
+    }
+    if size < data.len() {
+        return -libc::ERANGE;
+    }
+    let buf = unsafe { std::slice::from_raw_parts_mut(buf as *mut u8, data.len()) };
+    buf.copy_from_slice(data);
+    data.len() as c_int
+}
+
 pub extern "C" fn rustfs_init(_: *mut fuse::fuse_conn_info) -> c_int {
     env_logger::init();
-    unsafe { BPM = Some(ParallelBufferPoolManager::new(1, 20)) };
-    start_flusher();
-    unsafe { D_CACHE = Some(DCache::new(100)) };
-    let dir_tree = unsafe { D_CACHE.as_mut().unwrap() };
-    dir_tree.print();
-    unsafe {
-        let bpm = BPM.as_ref().unwrap();
-        fetch_page_write!(super_page: super_page, bpm, 0, auto_unpin_super_page);
-        if super_page.magic_num() == MAGIC_NUM {
-            trace!("find existing file system");
-        } else {
-            new_page!(
-                inode_map_page: bitmap,
-                bpm,
-                INODE_MAP_PAGE_ID,
-                auto_unpin_inode_map_page
-            );
-            inode_map_page.set(0);
-            let root_dir_inode_id = InodeId(0);
-            let (page_id, index) = root_dir_inode_id.seek();
-            new_page!(
-                root_dir_inode_page: inode_page,
-                bpm,
-                page_id,
-                auto_unpin_root_dir_inode_page
-            );
-            let root_dir_inode = &mut root_dir_inode_page.inodes[index];
-            root_dir_inode.init_dir(root_dir_inode_id);
-            new_page!(
-                data_map_page: bitmap,
-                bpm,
-                DATA_MAP_PAGE_ID,
-                auto_unpin_data_map_page
-            );
-            super_page.set_magic_num(MAGIC_NUM);
+    let spec = unsafe { std::ffi::CStr::from_ptr(crate::NEWFS_OPTIONS.device) }.to_string_lossy();
+    let device = match open_device(&spec) {
+        Ok(device) => device,
+        Err(e) => {
+            error!("open device {} failed: {}", spec, e);
+            std::process::exit(-1);
+        }
+    };
+    let replacer = unsafe { crate::NEWFS_OPTIONS.replacer };
+    let kind = if replacer.is_null() {
+        ReplacerKind::default()
+    } else {
+        let name = unsafe { std::ffi::CStr::from_ptr(replacer) }.to_string_lossy();
+        match name.parse::<ReplacerKind>() {
+            Ok(kind) => kind,
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(-1);
+            }
+        }
+    };
+    let bpm = ParallelBufferPoolManager::with_replacer(1, POOL_SIZE, device, |pool_size| {
+        kind.build(pool_size)
+    });
+    //内存磁盘每次挂载时都是空的
+    let bpm = if is_memory(&spec) {
+        match format_default(bpm) {
+            Ok(bpm) => bpm,
+            Err(e) => {
+                error!("format {} failed: {}", spec, e);
+                std::process::exit(-1);
+            }
+        }
+    } else {
+        bpm
+    };
+    let options = unsafe { &*std::ptr::addr_of!(crate::NEWFS_OPTIONS) };
+    bpm.writeback().set_dirty_limit(options.dirty_limit as usize);
+    bpm.writeback().set_write_batch(options.write_batch as usize);
+    let options = FsOptions {
+        dcache_entries: DCACHE_ENTRIES,
+        sync_on_close: options.sync_on_close != 0,
+        readahead: options.readahead as usize,
+        flush_interval: options.flush_interval,
+    };
+    match RustFs::mount(bpm, options) {
+        Ok(fs) => set_fs(fs),
+        Err(e) => {
+            error!("mount {} failed: {}", spec, e);
+            std::process::exit(-1);
         }
     }
     SUCCESS
 }
 
-pub extern "C" fn rustfs_destory(_: *mut c_void) {}
+pub extern "C" fn rustfs_destory(_: *mut c_void) {
+    if let Some(fs) = FS.write().take() {
+        fs.destroy();
+    }
+}
 /// # Safety
 /// 解引用了裸指针
 pub unsafe extern "C" fn rustfs_getattr(
@@ -94,127 +208,756 @@ pub unsafe extern "C" fn rustfs_getattr(
     rustfs_stat: *mut libc::stat,
 ) -> c_int {
     trace!("----------------------------get_attr----------------------------");
-    let path = cstr_convert_or_return!(path, "rustfs_getattr");
+    let path = path_convert_or_return!(path, "rustfs_getattr");
     let stat = unsafe { &mut *rustfs_stat };
-    let mut dir_tree = unsafe { D_CACHE.as_mut().unwrap() };
-    let inode = unsafe { dir_tree.search(path) };
-    let Some(inode) = inode else { return -libc::ENOENT; };
-    if unsafe { (*inode.as_ptr()).file_type } == FileType::DIR {
-        stat.st_mode = libc::S_IFDIR;
-    } else if unsafe { (*inode.as_ptr()).file_type } == FileType::REG {
-        stat.st_mode = libc::S_IFREG;
+    if let Some(entry) = control::lookup(path) {
+        control::fill_stat(entry, stat);
+        return SUCCESS;
     }
+    let fs = fs();
+    let cred = caller();
+    let file = ok_or_return!(fs.lookup_path(&cred, path));
+    *stat = ok_or_return!(fs.getattr(&cred, file.ino));
     SUCCESS
 }
 
-pub extern "C" fn rustfs_readdir(
+///fstat打开的文件，文件可能已经被删除
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_fgetattr(
     path: *const c_char,
+    rustfs_stat: *mut libc::stat,
+    fi: *mut fuse::fuse_file_info,
+) -> c_int {
+    trace!("----------------------------fgetattr----------------------------");
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fi = unsafe { &*fi };
+    let Some(inode_id) = handle::inode(fi.fh) else {
+        let ret = unsafe { rustfs_getattr(path, rustfs_stat) };
+        if let Some(snapshot) = fs().vol.handles.snapshot(fi.fh) {
+            unsafe { (*rustfs_stat).st_size = snapshot.len() as off_t };
+        }
+        return ret;
+    };
+    *unsafe { &mut *rustfs_stat } = ok_or_return!(fs().getattr(&caller(), inode_id));
+    SUCCESS
+}
+
+///打开文件，把inode号放在fh中。/.rustfs下的文件只能以只读方式打开，fh是内容快照的编号
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_open(path: *const c_char, fi: *mut fuse::fuse_file_info) -> c_int {
+    trace!("------------------------open------------------------");
+    let path = path_convert_or_return!(path, "rustfs_open");
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fi = unsafe { &mut *fi };
+    if let Some(entry) = control::lookup(path) {
+        if fi.flags & libc::O_ACCMODE != libc::O_RDONLY {
+            return -libc::EACCES;
+        }
+        let fs = fs();
+        fi.fh = match control::content(&fs.vol, entry) {
+            Some(content) => fs.vol.handles.open_snapshot(content),
+            None => CONTROL_FH,
+        };
+        fi.set_direct_io();
+        return SUCCESS;
+    }
+    let fs = fs();
+    let cred = caller();
+    let file = ok_or_return!(fs.lookup_path(&cred, path));
+    fi.fh = ok_or_return!(fs.open(&cred, file.ino, fi.flags));
+    SUCCESS
+}
+
+///创建并打开文件
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_create(
+    path: *const c_char,
+    mode: libc::mode_t,
+    fi: *mut fuse::fuse_file_info,
+) -> c_int {
+    trace!("------------------------create------------------------");
+    let path = path_convert_or_return!(path, "rustfs_create");
+    if control::is_control(path) {
+        return -libc::EPERM;
+    }
+    let fs = fs();
+    let cred = caller();
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fi = unsafe { &mut *fi };
+    let (dir, name) = ok_or_return!(parent(&fs, path, &cred));
+    let (stat, fh) = ok_or_return!(fs.create(&cred, dir.ino, name, mode, fi.flags));
+    fi.fh = fh;
+    created(&fs, Ok(stat))
+}
+
+///关闭文件，最后一个句柄关闭时释放已经被删除的文件
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_release(_path: *const c_char, fi: *mut fuse::fuse_file_info) -> c_int {
+    trace!("------------------------release------------------------");
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fh = unsafe { (*fi).fh };
+    let Some(inode_id) = handle::inode(fh) else {
+        fs().vol.handles.release_snapshot(fh);
+        return SUCCESS;
+    };
+    to_errno(fs().release(&caller(), inode_id, fh))
+}
+
+///打开的目录在releasedir之前一直保留查找次数，readdir由此找到".."
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_opendir(path: *const c_char, fi: *mut fuse::fuse_file_info) -> c_int {
+    trace!("------------------------opendir------------------------");
+    let path = path_convert_or_return!(path, "rustfs_opendir");
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fi = unsafe { &mut *fi };
+    match control::lookup(path) {
+        Some(control::Entry::Dir) => {
+            fi.fh = CONTROL_FH;
+            return SUCCESS;
+        }
+        Some(_) => return -libc::ENOTDIR,
+        None => {}
+    }
+    let fs = fs();
+    let cred = caller();
+    let dir = ok_or_return!(fs.lookup_path(&cred, path));
+    fi.fh = ok_or_return!(fs.opendir(&cred, dir.ino, fi.flags));
+    dir.keep();
+    SUCCESS
+}
+
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_releasedir(_path: *const c_char, fi: *mut fuse::fuse_file_info) -> c_int {
+    trace!("------------------------releasedir------------------------");
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fh = unsafe { (*fi).fh };
+    let Some(inode_id) = handle::inode(fh) else { return SUCCESS; };
+    let fs = fs();
+    fs.forget(inode_id, 1);
+    to_errno(fs.releasedir(&caller(), inode_id, fh))
+}
+
+///用filler填入一个目录项，只带inode号和文件类型，缓冲区已满时返回false
+fn fill_dir(
+    buf: *mut c_void,
+    filler: fuse::fuse_fill_dir_t,
+    name: &[u8],
+    stat: &libc::stat,
+    next_off: off_t,
+) -> bool {
+    let name = CString::new(name).unwrap();
+    filler(buf, name.as_ptr(), stat, next_off) == 0
+}
+
+fn dir_stat(ino: libc::ino_t, mode: libc::mode_t) -> libc::stat {
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    stat.st_ino = ino;
+    stat.st_mode = mode;
+    stat
+}
+
+///一次填入尽可能多的目录项，offset是上次填入的最后一项交给filler的偏移
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_readdir(
+    _path: *const c_char,
     buf: *mut c_void,
     filler: fuse::fuse_fill_dir_t,
     offset: off_t,
-    _fi: *mut fuse::fuse_file_info,
+    fi: *mut fuse::fuse_file_info,
 ) -> c_int {
     trace!("------------------------readdir------------------------");
-    let path = cstr_convert_or_return!(path, "rustfs_readdir");
-    let dir_tree = unsafe { D_CACHE.as_mut().unwrap() };
-    let dir = unsafe { dir_tree.search(path) };
-    let Some(dir) = dir else { return -libc::ENOENT; };
-    let names = unsafe { dir_tree.all_dir_entry_name(dir) };
-    if offset < names.len() as off_t {
-        let c_name = CString::new(names[offset as usize].clone()).unwrap();
-        filler(buf, c_name.as_ptr(), null(), offset + 1);
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fh = unsafe { (*fi).fh };
+    let Some(inode_id) = handle::inode(fh) else {
+        return readdir_control(buf, filler, offset);
+    };
+    let mut add = |name: &[u8], ino: InodeId, file_type: FileType, next_off: off_t| {
+        let stat = dir_stat(ino.0 as libc::ino_t, file_type.st_mode());
+        fill_dir(buf, filler, name, &stat, next_off)
+    };
+    to_errno(fs().readdir(&caller(), inode_id, fh, offset, &mut add))
+}
+
+///列出/.rustfs，偏移就是目录项的序号
+fn readdir_control(buf: *mut c_void, filler: fuse::fuse_fill_dir_t, offset: off_t) -> c_int {
+    let mut entries = vec![(".", dir_stat(0, S_IFDIR)), ("..", dir_stat(0, S_IFDIR))];
+    control::fill_stat(control::Entry::Dir, &mut entries[0].1);
+    for (name, entry) in control::list() {
+        let mut stat = dir_stat(0, 0);
+        control::fill_stat(entry, &mut stat);
+        entries.push((name, stat));
+    }
+    for (i, (name, stat)) in entries.iter().enumerate().skip(offset as usize) {
+        if !fill_dir(buf, filler, name.as_bytes(), stat, i as off_t + 1) {
+            break;
+        }
     }
     SUCCESS
 }
 
-pub extern "C" fn rustfs_mkdir(path: *const c_char, _mode: libc::mode_t) -> c_int {
+pub extern "C" fn rustfs_mkdir(path: *const c_char, mode: libc::mode_t) -> c_int {
     trace!("------------------------mkdir------------------------");
-    unsafe {
-        let path = cstr_convert_or_return!(path, "rustfs_mkdir");
-        let dir_tree = unsafe { D_CACHE.as_mut().unwrap() };
-        let (parent_path, name) = split_path(path);
-        let dir = unsafe { dir_tree.search(parent_path) };
-        let Some(dir) = dir else { return -libc::ENOENT; };
-        dir_tree.insert(dir, name, FileType::DIR)
+    let path = path_convert_or_return!(path, "rustfs_mkdir");
+    if control::is_control(path) {
+        return -libc::EPERM;
     }
+    let fs = fs();
+    let cred = caller();
+    let (dir, name) = ok_or_return!(parent(&fs, path, &cred));
+    created(&fs, fs.mkdir(&cred, dir.ino, name, mode))
 }
 
 pub extern "C" fn rustfs_mknod(
     path: *const c_char,
-    _mode: libc::mode_t,
+    mode: libc::mode_t,
     _dev: libc::dev_t,
 ) -> c_int {
     trace!("------------------------mknod------------------------");
-    let path = cstr_convert_or_return!(path, "rustfs_mknod");
-    let dir_tree = unsafe { D_CACHE.as_mut().unwrap() };
-    let (parent_path, name) = split_path(path);
-    let dir = unsafe { dir_tree.search(parent_path) };
-    let Some(dir) = dir else { return -libc::ENOENT; };
-    unsafe { dir_tree.insert(dir, name, FileType::REG) }
+    let path = path_convert_or_return!(path, "rustfs_mknod");
+    if control::is_control(path) {
+        return -libc::EPERM;
+    }
+    let fs = fs();
+    let cred = caller();
+    let (dir, name) = ok_or_return!(parent(&fs, path, &cred));
+    created(&fs, fs.mknod(&cred, dir.ino, name, mode))
 }
 
-pub extern "C" fn rustfs_write(
-    path: *const c_char,
-    _src: *const c_char,
-    _size: size_t,
-    _off: off_t,
-    _info: *mut fuse::fuse_file_info,
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_write(
+    _path: *const c_char,
+    src: *const c_char,
+    size: size_t,
+    off: off_t,
+    info: *mut fuse::fuse_file_info,
 ) -> c_int {
     trace!("------------------------write------------------------");
-    let _path = cstr_convert_or_return!(path, "rustfs_write");
-    todo!()
+    if off < 0 {
+        return -libc::EINVAL;
+    }
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fh = unsafe { (*info).fh };
+    let Some(inode_id) = handle::inode(fh) else { return -libc::EPERM; };
+    let src = unsafe { std::slice::from_raw_parts(src as *const u8, size) };
+    match fs().write(&caller(), inode_id, fh, off as u64, src) {
+        Ok(written) => written as c_int,
+        Err(ret) => ret,
+    }
 }
 
-pub extern "C" fn rustfs_read(
-    path: *const c_char,
-    _src: *mut c_char,
-    _size: size_t,
-    _off: off_t,
-    _info: *mut fuse::fuse_file_info,
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_read(
+    _path: *const c_char,
+    dst: *mut c_char,
+    size: size_t,
+    off: off_t,
+    info: *mut fuse::fuse_file_info,
 ) -> c_int {
     trace!("------------------------read------------------------");
-    let _path = cstr_convert_or_return!(path, "rustfs_read");
-    todo!()
+    if off < 0 {
+        return -libc::EINVAL;
+    }
+    let dst = unsafe { std::slice::from_raw_parts_mut(dst as *mut u8, size) };
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fh = unsafe { (*info).fh };
+    let Some(inode_id) = handle::inode(fh) else {
+        //只有/.rustfs下的文件没有inode，从open时的快照读出
+        return match fs().vol.handles.snapshot(fh) {
+            Some(snapshot) => control::read(&snapshot, off as usize, dst) as c_int,
+            None => -libc::EISDIR,
+        };
+    };
+    match fs().read(&caller(), inode_id, fh, off as u64, dst) {
+        Ok(read) => read as c_int,
+        Err(ret) => ret,
+    }
 }
 
-pub extern "C" fn rustfs_access(path: *const c_char, _typ: c_int) -> c_int {
+pub extern "C" fn rustfs_access(path: *const c_char, typ: c_int) -> c_int {
     trace!("------------------------access------------------------");
-    let path = cstr_convert_or_return!(path, "rustfs_access");
-    let dir_tree = unsafe { D_CACHE.as_mut().unwrap() };
-    if unsafe { dir_tree.search(path).is_some() } {
-        SUCCESS
-    } else {
-        -libc::ENOENT
+    let path = path_convert_or_return!(path, "rustfs_access");
+    if control::lookup(path).is_some() {
+        return if typ & libc::W_OK != 0 {
+            -libc::EACCES
+        } else {
+            SUCCESS
+        };
     }
+    let fs = fs();
+    let cred = caller();
+    let file = ok_or_return!(fs.lookup_path(&cred, path));
+    to_errno(fs.access(&cred, file.ino, typ))
 }
 
 pub extern "C" fn rustfs_unlink(path: *const c_char) -> c_int {
     trace!("------------------------unlink------------------------");
-    let _path = cstr_convert_or_return!(path, "rustfs_access");
-    todo!()
+    let path = path_convert_or_return!(path, "rustfs_unlink");
+    if control::is_control(path) {
+        return -libc::EPERM;
+    }
+    let fs = fs();
+    let cred = caller();
+    let (dir, name) = ok_or_return!(parent(&fs, path, &cred));
+    to_errno(fs.unlink(&cred, dir.ino, name))
 }
 
 pub extern "C" fn rustfs_rmdir(path: *const c_char) -> c_int {
     trace!("------------------------rmdir------------------------");
-    let _path = cstr_convert_or_return!(path, "rustfs_rmdir");
-    todo!()
+    let path = path_convert_or_return!(path, "rustfs_rmdir");
+    if control::is_control(path) {
+        return -libc::EPERM;
+    }
+    if path == "/" {
+        return -libc::EBUSY;
+    }
+    let fs = fs();
+    let cred = caller();
+    let (dir, name) = ok_or_return!(parent(&fs, path, &cred));
+    to_errno(fs.rmdir(&cred, dir.ino, name))
 }
 
 pub extern "C" fn rustfs_rename(old_name: *const c_char, new_name: *const c_char) -> c_int {
     trace!("------------------------rename------------------------");
-    let _old_name = cstr_convert_or_return!(old_name, "rustfs_rename");
-    let _new_name = cstr_convert_or_return!(new_name, "rustfs_rename");
-    todo!()
+    let old_name = path_convert_or_return!(old_name, "rustfs_rename");
+    let new_name = path_convert_or_return!(new_name, "rustfs_rename");
+    if control::is_control(old_name) || control::is_control(new_name) {
+        return -libc::EPERM;
+    }
+    let fs = fs();
+    let cred = caller();
+    let (old_dir, old_name) = ok_or_return!(parent(&fs, old_name, &cred));
+    let (new_dir, new_name) = ok_or_return!(parent(&fs, new_name, &cred));
+    to_errno(fs.rename(&cred, old_dir.ino, old_name, new_dir.ino, new_name))
 }
 
-pub extern "C" fn rustfs_utimens(path: *const c_char, tv: *const [libc::timespec; 2]) -> c_int {
-    trace!("------------------------utimens------------------------");
-    let _path = cstr_convert_or_return!(path, "rustfs_utimens");
+///from是链接的目标，to是新建的符号链接的路径
+pub extern "C" fn rustfs_symlink(from: *const c_char, to: *const c_char) -> c_int {
+    trace!("------------------------symlink------------------------");
+    let target = cstr_convert_or_return!(from, "rustfs_symlink");
+    let path = path_convert_or_return!(to, "rustfs_symlink");
+    if control::is_control(path) {
+        return -libc::EPERM;
+    }
+    let fs = fs();
+    let cred = caller();
+    let (dir, name) = ok_or_return!(parent(&fs, path, &cred));
+    created(&fs, fs.symlink(&cred, dir.ino, name, target))
+}
+
+///把链接目标写入buf，超出size时截断，结果总是以0结尾
+pub extern "C" fn rustfs_readlink(path: *const c_char, buf: *mut c_char, size: size_t) -> c_int {
+    trace!("------------------------readlink------------------------");
+    let path = path_convert_or_return!(path, "rustfs_readlink");
+    let fs = fs();
+    let cred = caller();
+    let file = ok_or_return!(fs.lookup_path(&cred, path));
+    let target = ok_or_return!(fs.readlink(&cred, file.ino));
+    if size == 0 {
+        return SUCCESS;
+    }
+    let len = target.len().min(size - 1);
+    let buf = unsafe { std::slice::from_raw_parts_mut(buf as *mut u8, size) };
+    buf[..len].copy_from_slice(&target[..len]);
+    buf[len] = 0;
     SUCCESS
 }
 
-pub extern "C" fn rustfs_truncate(path: *const c_char, _offset: libc::off_t) -> c_int {
+pub extern "C" fn rustfs_link(old_path: *const c_char, new_path: *const c_char) -> c_int {
+    trace!("------------------------link------------------------");
+    let old_path = path_convert_or_return!(old_path, "rustfs_link");
+    let new_path = path_convert_or_return!(new_path, "rustfs_link");
+    if control::is_control(old_path) || control::is_control(new_path) {
+        return -libc::EPERM;
+    }
+    let fs = fs();
+    let cred = caller();
+    let file = ok_or_return!(fs.lookup_path(&cred, old_path));
+    let (dir, name) = ok_or_return!(parent(&fs, new_path, &cred));
+    created(&fs, fs.link(&cred, file.ino, dir.ino, name))
+}
+
+///按路径修改属性，/.rustfs下的文件不能修改
+fn setattr(path: &str, attr: SetAttr) -> c_int {
+    if control::is_control(path) {
+        return -libc::EPERM;
+    }
+    let fs = fs();
+    let cred = caller();
+    let file = ok_or_return!(fs.lookup_path(&cred, path));
+    to_errno(fs.setattr(&cred, file.ino, attr).map(|_| ()))
+}
+
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_utimens(
+    path: *const c_char,
+    tv: *const [libc::timespec; 2],
+) -> c_int {
+    trace!("------------------------utimens------------------------");
+    let path = path_convert_or_return!(path, "rustfs_utimens");
+    //tv为空表示把两个时间都设置为当前时间
+    let [atime, mtime] = if tv.is_null() {
+        [libc::timespec { tv_sec: 0, tv_nsec: libc::UTIME_NOW }; 2]
+    } else {
+        unsafe { *tv }
+    };
+    let time = |time: libc::timespec| match time.tv_nsec {
+        libc::UTIME_OMIT => None,
+        libc::UTIME_NOW => Some(TimeOrNow::Now),
+        _ => Some(TimeOrNow::Time(time)),
+    };
+    let attr = SetAttr {
+        atime: time(atime),
+        mtime: time(mtime),
+        ..Default::default()
+    };
+    setattr(path, attr)
+}
+
+pub extern "C" fn rustfs_chmod(path: *const c_char, mode: libc::mode_t) -> c_int {
+    trace!("------------------------chmod------------------------");
+    let path = path_convert_or_return!(path, "rustfs_chmod");
+    let attr = SetAttr {
+        mode: Some(mode),
+        ..Default::default()
+    };
+    setattr(path, attr)
+}
+
+///uid或gid为-1时不修改
+pub extern "C" fn rustfs_chown(path: *const c_char, uid: libc::uid_t, gid: libc::gid_t) -> c_int {
+    trace!("------------------------chown------------------------");
+    let path = path_convert_or_return!(path, "rustfs_chown");
+    let attr = SetAttr {
+        uid: (uid != u32::MAX).then_some(uid),
+        gid: (gid != u32::MAX).then_some(gid),
+        ..Default::default()
+    };
+    setattr(path, attr)
+}
+
+pub extern "C" fn rustfs_truncate(path: *const c_char, offset: libc::off_t) -> c_int {
     trace!("------------------------truncate------------------------");
-    let _path = cstr_convert_or_return!(path, "rustfs_truncate");
-    todo!()
+    let path = path_convert_or_return!(path, "rustfs_truncate");
+    if offset < 0 {
+        return -libc::EINVAL;
+    }
+    let attr = SetAttr {
+        size: Some(offset as u64),
+        ..Default::default()
+    };
+    setattr(path, attr)
+}
+
+///通过打开的句柄截断，不再检查写权限
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_ftruncate(
+    _path: *const c_char,
+    offset: libc::off_t,
+    info: *mut fuse::fuse_file_info,
+) -> c_int {
+    trace!("------------------------ftruncate------------------------");
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fh = unsafe { (*info).fh };
+    let Some(inode_id) = handle::inode(fh) else { return -libc::EPERM; };
+    if offset < 0 {
+        return -libc::EINVAL;
+    }
+    let attr = SetAttr {
+        size: Some(offset as u64),
+        fh: Some(fh),
+        ..Default::default()
+    };
+    to_errno(fs().setattr(&caller(), inode_id, attr).map(|_| ()))
+}
+
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_statfs(_path: *const c_char, stat: *mut libc::statvfs) -> c_int {
+    trace!("------------------------statfs------------------------");
+    *unsafe { &mut *stat } = ok_or_return!(fs().statfs(&caller(), ROOT));
+    SUCCESS
+}
+
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_fsync(
+    _path: *const c_char,
+    datasync: c_int,
+    info: *mut fuse::fuse_file_info,
+) -> c_int {
+    trace!("------------------------fsync------------------------");
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fh = unsafe { (*info).fh };
+    let Some(inode_id) = handle::inode(fh) else { return SUCCESS; };
+    to_errno(fs().fsync(&caller(), inode_id, fh, datasync != 0))
+}
+
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_fsyncdir(
+    _path: *const c_char,
+    datasync: c_int,
+    info: *mut fuse::fuse_file_info,
+) -> c_int {
+    trace!("------------------------fsyncdir------------------------");
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fh = unsafe { (*info).fh };
+    let Some(inode_id) = handle::inode(fh) else { return SUCCESS; };
+    to_errno(fs().fsyncdir(&caller(), inode_id, fh, datasync != 0))
+}
+
+///每次close都会调用
+/// # Safety
+/// 解引用了裸指针
+pub unsafe extern "C" fn rustfs_flush(_path: *const c_char, info: *mut fuse::fuse_file_info) -> c_int {
+    trace!("------------------------flush------------------------");
+    // SAFETY: fuse为每个请求传入自己的fuse_file_info，它在回调返回前一直有效
+    let fh = unsafe { (*info).fh };
+    let Some(inode_id) = handle::inode(fh) else { return SUCCESS; };
+    to_errno(fs().flush(&caller(), inode_id, fh))
+}
+
+pub extern "C" fn rustfs_setxattr(
+    path: *const c_char,
+    name: *const c_char,
+    value: *const c_char,
+    size: size_t,
+    flags: c_int,
+) -> c_int {
+    trace!("------------------------setxattr------------------------");
+    let path = path_convert_or_return!(path, "rustfs_setxattr");
+    if control::is_control(path) {
+        return -libc::EPERM;
+    }
+    let name = cstr_convert_or_return!(name, "rustfs_setxattr");
+    //长度为0的值可能传入空指针
+    let value = if size == 0 {
+        &[][..]
+    } else {
+        unsafe { std::slice::from_raw_parts(value as *const u8, size) }
+    };
+    let fs = fs();
+    let cred = caller();
+    let file = ok_or_return!(fs.lookup_path(&cred, path));
+    to_errno(fs.setxattr(&cred, file.ino, name, value, flags))
+}
+
+pub extern "C" fn rustfs_getxattr(
+    path: *const c_char,
+    name: *const c_char,
+    buf: *mut c_char,
+    size: size_t,
+) -> c_int {
+    trace!("------------------------getxattr------------------------");
+    let path = path_convert_or_return!(path, "rustfs_getxattr");
+    let name = cstr_convert_or_return!(name, "rustfs_getxattr");
+    let fs = fs();
+    let cred = caller();
+    let file = ok_or_return!(fs.lookup_path(&cred, path));
+    let value = ok_or_return!(fs.getxattr(&cred, file.ino, name));
+    copy_xattr_out(&value, buf, size)
+}
+
+pub extern "C" fn rustfs_listxattr(path: *const c_char, buf: *mut c_char, size: size_t) -> c_int {
+    trace!("------------------------listxattr------------------------");
+    let path = path_convert_or_return!(path, "rustfs_listxattr");
+    let fs = fs();
+    let cred = caller();
+    let file = ok_or_return!(fs.lookup_path(&cred, path));
+    let names = ok_or_return!(fs.listxattr(&cred, file.ino));
+    copy_xattr_out(&names, buf, size)
+}
+
+pub extern "C" fn rustfs_removexattr(path: *const c_char, name: *const c_char) -> c_int {
+    trace!("------------------------removexattr------------------------");
+    let path = path_convert_or_return!(path, "rustfs_removexattr");
+    if control::is_control(path) {
+        return -libc::EPERM;
+    }
+    let name = cstr_convert_or_return!(name, "rustfs_removexattr");
+    let fs = fs();
+    let cred = caller();
+    let file = ok_or_return!(fs.lookup_path(&cred, path));
+    to_errno(fs.removexattr(&cred, file.ino, name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::harness::mkfs;
+    use libc::S_IFREG;
+    use std::ffi::CStr;
+
+    ///模拟内核的readdir缓冲区，最多放下cap个目录项
+    struct Listing {
+        cap: usize,
+        entries: Vec<(String, libc::ino_t, libc::mode_t, off_t)>,
+    }
+
+    extern "C" fn filler(
+        buf: *mut c_void,
+        name: *const c_char,
+        stat: *const libc::stat,
+        off: off_t,
+    ) -> c_int {
+        let listing = unsafe { &mut *(buf as *mut Listing) };
+        if listing.entries.len() == listing.cap {
+            return 1;
+        }
+        let name = unsafe { CStr::from_ptr(name) }
+            .to_str()
+            .unwrap()
+            .to_string();
+        let stat = unsafe { &*stat };
+        listing
+            .entries
+            .push((name, stat.st_ino, stat.st_mode & libc::S_IFMT, off));
+        0
+    }
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    ///从offset开始读一次目录
+    fn readdir(
+        path: &str,
+        offset: off_t,
+        cap: usize,
+    ) -> Vec<(String, libc::ino_t, libc::mode_t, off_t)> {
+        let mut fi: fuse::fuse_file_info = unsafe { mem::zeroed() };
+        let mut listing = Listing {
+            cap,
+            entries: Vec::new(),
+        };
+        let buf = &mut listing as *mut Listing as *mut c_void;
+        unsafe {
+            assert_eq!(rustfs_opendir(c(path).as_ptr(), &mut fi), SUCCESS);
+            assert_eq!(
+                rustfs_readdir(c(path).as_ptr(), buf, filler, offset, &mut fi),
+                SUCCESS
+            );
+            assert_eq!(rustfs_releasedir(c(path).as_ptr(), &mut fi), SUCCESS);
+        }
+        listing.entries
+    }
+
+    fn ino(path: &str) -> libc::ino_t {
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        assert_eq!(
+            unsafe { rustfs_getattr(c(path).as_ptr(), &mut stat) },
+            SUCCESS
+        );
+        stat.st_ino
+    }
+
+    #[test]
+    fn test_readdir() {
+        let bpm = ParallelBufferPoolManager::new(1, 128, mkfs(1024, 0));
+        let _mount = TestMount::new(RustFs::mount(bpm, FsOptions::default()).unwrap());
+        assert_eq!(rustfs_mkdir(c("/d").as_ptr(), 0o755), SUCCESS);
+        for i in 0..40 {
+            assert_eq!(
+                rustfs_mknod(c(&format!("/d/f{i}")).as_ptr(), 0o644, 0),
+                SUCCESS
+            );
+        }
+        assert_eq!(
+            rustfs_symlink(c("f0").as_ptr(), c("/d/link").as_ptr()),
+            SUCCESS
+        );
+
+        //一次填满整个目录，带上"."、".."和文件类型
+        let all = readdir("/d", 0, usize::MAX);
+        assert_eq!(all.len(), 43);
+        let d = ino("/d");
+        assert_eq!(all[0], (".".to_string(), d, S_IFDIR, 1));
+        assert_eq!(all[1], ("..".to_string(), 0, S_IFDIR, 2));
+        let f0 = ino("/d/f0");
+        assert_eq!(all[2], ("f0".to_string(), f0, S_IFREG, 3));
+        assert_eq!(all[42].0, "link");
+        assert_eq!(all[42].2, libc::S_IFLNK);
+
+        //缓冲区满时从上次的偏移继续，中途删除和加入目录项不影响其他目录项
+        let mut names = Vec::new();
+        let mut offset = 0;
+        loop {
+            let part = readdir("/d", offset, 7);
+            let Some(last) = part.last() else { break };
+            offset = last.3;
+            names.extend(part.into_iter().map(|entry| entry.0));
+            if names.len() == 14 {
+                assert_eq!(rustfs_unlink(c("/d/f3").as_ptr()), SUCCESS);
+                assert_eq!(rustfs_unlink(c("/d/f30").as_ptr()), SUCCESS);
+                assert_eq!(rustfs_mknod(c("/d/new").as_ptr(), 0o644, 0), SUCCESS);
+            }
+        }
+        let mut expected: Vec<String> = all.into_iter().map(|entry| entry.0).collect();
+        expected.retain(|name| name != "f30");
+        assert_eq!(names, expected);
+        assert!(readdir("/d", offset, usize::MAX).is_empty());
+
+        //只读出新加入的目录项，它复用了f3的槽位
+        let all = readdir("/d", 0, usize::MAX);
+        assert_eq!(all[5].0, "new");
+        assert_eq!(all.len(), 42);
+
+        let control = readdir("/.rustfs", 0, usize::MAX);
+        let control: Vec<&str> = control.iter().map(|entry| entry.0.as_str()).collect();
+        assert_eq!(control, [".", "..", "stats"]);
+        assert_eq!(readdir("/.rustfs", 2, usize::MAX).len(), 1);
+    }
+
+    #[test]
+    fn test_control_snapshot() {
+        let bpm = ParallelBufferPoolManager::new(1, 128, mkfs(1024, 0));
+        let _mount = TestMount::new(RustFs::mount(bpm, FsOptions::default()).unwrap());
+        let path = c("/.rustfs/stats");
+        let mut fi: fuse::fuse_file_info = unsafe { mem::zeroed() };
+        fi.flags = libc::O_WRONLY;
+        assert_eq!(unsafe { rustfs_open(path.as_ptr(), &mut fi) }, -libc::EACCES);
+        fi.flags = libc::O_RDONLY;
+        assert_eq!(unsafe { rustfs_open(path.as_ptr(), &mut fi) }, SUCCESS);
+        assert_eq!(fi.bitfield & 1, 1);
+
+        //按路径stat时大小为0，打开的文件报告快照的长度
+        let mut stat: libc::stat = unsafe { mem::zeroed() };
+        assert_eq!(unsafe { rustfs_getattr(path.as_ptr(), &mut stat) }, SUCCESS);
+        assert_eq!(stat.st_size, 0);
+        assert_eq!(
+            unsafe { rustfs_fgetattr(path.as_ptr(), &mut stat, &mut fi) },
+            SUCCESS
+        );
+        let size = stat.st_size as usize;
+        assert!(size > 0);
+
+        //打开之后的访问不改变读出的内容
+        let mut first = vec![0u8; size + 10];
+        let read = |buf: &mut [u8], off: off_t, fi: &mut fuse::fuse_file_info| {
+            let dst = buf.as_mut_ptr() as *mut c_char;
+            unsafe { rustfs_read(path.as_ptr(), dst, buf.len(), off, fi) }
+        };
+        assert_eq!(read(&mut first[..7], 0, &mut fi), 7);
+        for i in 0..10 {
+            assert_eq!(rustfs_mkdir(c(&format!("/d{i}")).as_ptr(), 0o755), SUCCESS);
+        }
+        assert_eq!(read(&mut first[7..], 7, &mut fi), (size - 7) as c_int);
+        assert_eq!(read(&mut first, size as off_t, &mut fi), 0);
+        let mut again = vec![0u8; size];
+        assert_eq!(read(&mut again, 0, &mut fi), size as c_int);
+        assert_eq!(&first[..size], &again[..]);
+        assert_eq!(unsafe { rustfs_release(path.as_ptr(), &mut fi) }, SUCCESS);
+        assert!(fs().vol.handles.snapshot(fi.fh).is_none());
+    }
 }
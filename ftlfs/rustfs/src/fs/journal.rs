@@ -0,0 +1,755 @@
+//! 写前日志，采用ext3的ordered模式：
+//!
+//! - 一次fuse操作是一个事务，事务中修改的元数据页（超级块、位图、inode表、目录和索引块）
+//!   在提交前不会写回原处，提交时先把文件数据页写回磁盘，再把元数据页的完整内容写入日志区，
+//!   最后写提交块。提交后元数据页由flusher照常写回原处
+//! - 日志区的第一页是日志超级块，记录日志中第一个事务的序号；之后依次存放事务，
+//!   每个事务由描述块、元数据页的副本和提交块组成，提交块中有整个事务的校验和
+//! - 日志写到一半或事务过多时做检查点：把所有脏页写回原处，再更新日志超级块清空日志
+//! - 挂载时重放所有完整的事务。曾作为元数据记入日志、之后又被用作文件数据的页会在描述块中撤销，
+//!   重放时跳过该页更早的副本，避免覆盖新数据
+//!
+//! 每个卷同一时刻只有一个事务，事务在begin时记下所在的线程，提交后其他线程才能开始事务，
+//! 所以修改文件系统的操作是串行的，只有不修改元数据的操作（查找、读文件、读目录）可以并行
+//!
+//! 一个事务最多修改TX_PAGES个元数据页，begin时预留出这么多日志空间。写大文件、截断和释放
+//! 大文件等可能修改更多页的操作拆成多个事务，中间用restart提交，每个事务结束时文件系统都是一致的
+use crate::buffer::buffer_pool_manager::AutoUnpin;
+use crate::buffer::flusher::Flusher;
+use crate::buffer::replacer::PageId;
+use crate::fs::custom::PAGE_SIZE;
+use crate::fs::volume::Volume;
+use crate::{fetch_page_read, new_page};
+use log::{debug, info, warn};
+use parking_lot::{Condvar, Mutex};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, ThreadId};
+
+pub const JOURNAL_MAGIC: u32 = 0x4a52_4653;
+pub const BLOCK_SUPER: u32 = 1;
+pub const BLOCK_DESCRIPTOR: u32 = 2;
+pub const BLOCK_COMMIT: u32 = 3;
+///描述块能记录的页号数
+pub const JOURNAL_IDS: usize = (PAGE_SIZE - 32) / 4;
+///一个事务最多修改的元数据页数
+pub const TX_PAGES: usize = 64;
+///日志区最少的页数：日志超级块，以及一个最大的事务和它的描述块、提交块
+pub const MIN_JOURNAL_PAGES: usize = TX_PAGES + 3;
+///记日志时缓存池每个实例最少的frame数。未提交的元数据页不能写回，一直占用缓存，
+///另外为持有这些页的操作留出几个frame（inode页、索引页和正在写的数据页）
+pub const MIN_POOL_SIZE: usize = TX_PAGES + 8;
+
+///日志块。描述块的ids中先是count个元数据页号，再是revoke_count个撤销的页号；
+///提交块的checksum是描述块和所有副本的校验和
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct JournalBlock {
+    magic: u32,
+    block_type: u32,
+    seq: u64,
+    count: u32,
+    revoke_count: u32,
+    checksum: u64,
+    ids: [u32; JOURNAL_IDS],
+}
+
+impl JournalBlock {
+    fn new(block_type: u32, seq: u64) -> Self {
+        JournalBlock {
+            magic: JOURNAL_MAGIC,
+            block_type,
+            seq,
+            count: 0,
+            revoke_count: 0,
+            checksum: 0,
+            ids: [0; JOURNAL_IDS],
+        }
+    }
+
+    fn is(&self, block_type: u32, seq: u64) -> bool {
+        self.magic == JOURNAL_MAGIC && self.block_type == block_type && self.seq == seq
+    }
+
+    fn bytes(&self) -> [u8; PAGE_SIZE] {
+        unsafe { std::mem::transmute(*self) }
+    }
+
+    fn from_bytes(bytes: &[u8; PAGE_SIZE]) -> Self {
+        unsafe { std::mem::transmute(*bytes) }
+    }
+}
+
+///FNV-1a校验和
+fn checksum(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+const CHECKSUM_INIT: u64 = 0xcbf2_9ce4_8422_2325;
+
+///卷的日志
+#[derive(Default)]
+pub struct Journal {
+    state: Mutex<State>,
+    ///事务提交后通知等待开始事务的线程
+    idle: Condvar,
+    ///挂载后才开始记日志，格式化和fsck等工具不记日志
+    active: AtomicBool,
+}
+
+#[derive(Default)]
+struct State {
+    ///下一个事务的序号
+    next_seq: u64,
+    ///下一个事务在日志中的位置，相对于日志超级块之后的第一页
+    head: usize,
+    ///上次检查点之后记入日志的元数据页
+    logged: BTreeSet<usize>,
+    ///正在进行的事务所在的线程
+    owner: Option<ThreadId>,
+    ///正在进行的事务修改的元数据页和文件数据页
+    meta: BTreeSet<usize>,
+    data: BTreeSet<usize>,
+}
+
+impl State {
+    ///当前线程是否在事务中
+    fn in_tx(&self) -> bool {
+        self.owner == Some(thread::current().id())
+    }
+}
+
+///事务，离开作用域时提交。嵌套的begin返回的事务不做任何事
+pub struct Transaction<'a> {
+    vol: Option<&'a Volume>,
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if let Some(vol) = self.vol {
+            commit(vol);
+        }
+    }
+}
+
+///日志区中存放事务的页数
+fn capacity(vol: &Volume) -> usize {
+    vol.layout.journal_pages.saturating_sub(1)
+}
+
+///为下一个事务预留日志空间：日志剩下的页放不下一个最大的事务，
+///或者撤销记录可能放不进描述块时先做检查点
+fn reserve(vol: &Volume, state: &mut State) {
+    if state.head + TX_PAGES + 2 > capacity(vol) || state.logged.len() + TX_PAGES > JOURNAL_IDS {
+        checkpoint(vol, state);
+    }
+}
+
+///开始一个事务，在fuse操作开头调用
+pub fn begin(vol: &Volume) -> Transaction<'_> {
+    let journal = &vol.journal;
+    if !journal.active.load(Ordering::Acquire) {
+        return Transaction { vol: None };
+    }
+    let mut state = journal.state.lock();
+    if state.in_tx() {
+        return Transaction { vol: None };
+    }
+    while state.owner.is_some() {
+        journal.idle.wait(&mut state);
+    }
+    //等待期间日志可能已经停止
+    if !journal.active.load(Ordering::Acquire) {
+        return Transaction { vol: None };
+    }
+    reserve(vol, &mut state);
+    state.owner = Some(thread::current().id());
+    state.meta.clear();
+    state.data.clear();
+    Transaction { vol: Some(vol) }
+}
+
+///提交当前线程正在进行的事务，并立即开始下一个，期间不放开日志，拆开的操作中间不会插入其他修改。
+///调用时不能持有任何页，提交时要读出事务修改的元数据页。当前线程不在事务中时什么也不做
+pub fn restart(vol: &Volume) {
+    let mut state = vol.journal.state.lock();
+    if !state.in_tx() {
+        return;
+    }
+    write_transaction(vol, &mut state);
+    reserve(vol, &mut state);
+}
+
+///当前事务还能修改的元数据页数，不在事务中时没有限制
+pub fn room(vol: &Volume) -> usize {
+    let state = vol.journal.state.lock();
+    if state.in_tx() {
+        TX_PAGES - state.meta.len()
+    } else {
+        usize::MAX
+    }
+}
+
+///页即将被修改，由fetch_page_write等宏调用。当前线程不在事务中时什么也不做
+pub fn note_write(vol: &Volume, page_id: usize) {
+    let mut state = vol.journal.state.lock();
+    if !state.in_tx() {
+        return;
+    }
+    if state.data.contains(&page_id) || state.meta.contains(&page_id) {
+        return;
+    }
+    //操作按TX_PAGES拆分事务，超出说明某个操作少算了要修改的页
+    assert!(
+        state.meta.len() < TX_PAGES,
+        "transaction modifies more than {TX_PAGES} metadata pages"
+    );
+    vol.bpm.writeback().defer(page_id);
+    state.meta.insert(page_id);
+}
+
+///页是文件数据，提交前直接写回原处而不记日志，须在修改数据页之前调用
+pub fn mark_data(vol: &Volume, page_id: usize) {
+    let mut state = vol.journal.state.lock();
+    if !state.in_tx() {
+        return;
+    }
+    state.data.insert(page_id);
+    //新分配的块在初始化时被当作了元数据
+    if state.meta.remove(&page_id) {
+        vol.bpm.writeback().allow([&page_id]);
+    }
+}
+
+fn commit(vol: &Volume) {
+    let journal = &vol.journal;
+    let mut state = journal.state.lock();
+    write_transaction(vol, &mut state);
+    state.owner = None;
+    journal.idle.notify_all();
+}
+
+///把当前事务写入日志
+fn write_transaction(vol: &Volume, state: &mut State) {
+    let bpm = &*vol.bpm;
+    let meta = std::mem::take(&mut state.meta);
+    let data = std::mem::take(&mut state.data);
+    //ordered模式：数据先于引用它的元数据落盘
+    for &page_id in data.iter() {
+        bpm.flush_page(PageId(page_id));
+    }
+    let revoked: Vec<usize> = data
+        .iter()
+        .filter(|page_id| state.logged.contains(page_id))
+        .copied()
+        .collect();
+    if meta.is_empty() && revoked.is_empty() {
+        return;
+    }
+    let device = bpm.device();
+    let log_start = vol.layout.journal_start + 1;
+    let seq = state.next_seq;
+    let mut descriptor = JournalBlock::new(BLOCK_DESCRIPTOR, seq);
+    descriptor.count = meta.len() as u32;
+    descriptor.revoke_count = revoked.len() as u32;
+    for (id, &page_id) in descriptor
+        .ids
+        .iter_mut()
+        .zip(meta.iter().chain(revoked.iter()))
+    {
+        *id = page_id as u32;
+    }
+    let descriptor = descriptor.bytes();
+    let mut hash = checksum(CHECKSUM_INIT, &descriptor);
+    device.write_page(PageId(log_start + state.head), &descriptor);
+    for (i, &page_id) in meta.iter().enumerate() {
+        let image = {
+            fetch_page_read!(page: bytes, bpm, page_id, au);
+            *page
+        };
+        hash = checksum(hash, &image);
+        device.write_page(PageId(log_start + state.head + 1 + i), &image);
+    }
+    device.sync();
+    let mut commit = JournalBlock::new(BLOCK_COMMIT, seq);
+    commit.checksum = hash;
+    device.write_page(
+        PageId(log_start + state.head + 1 + meta.len()),
+        &commit.bytes(),
+    );
+    device.sync();
+    debug!("commit transaction {seq}: {meta:?}, revoke {revoked:?}");
+    state.head += meta.len() + 2;
+    state.next_seq += 1;
+    state.logged.retain(|page_id| !revoked.contains(page_id));
+    state.logged.extend(meta.iter());
+    bpm.writeback().allow(&meta);
+}
+
+///把所有脏页写回原处后清空日志。只在事务之间调用，此时没有推迟写回的页，
+///调用者不持有任何页，其他线程不修改元数据，等待页的写者不会死锁
+fn checkpoint(vol: &Volume, state: &mut State) {
+    let bpm = &*vol.bpm;
+    Flusher::new().flush_all(bpm);
+    bpm.device().sync();
+    write_super(vol, state.next_seq);
+    state.head = 0;
+    state.logged.clear();
+}
+
+fn write_super(vol: &Volume, seq: u64) {
+    let device = vol.bpm.device();
+    device.write_page(
+        PageId(vol.layout.journal_start),
+        &JournalBlock::new(BLOCK_SUPER, seq).bytes(),
+    );
+    device.sync();
+}
+
+///格式化时初始化空的日志。起始序号随机选取，格式化前留在日志区的旧事务不会被重放
+pub fn format_journal(vol: &Volume) {
+    vol.journal.active.store(false, Ordering::Release);
+    if vol.layout.journal_pages == 0 {
+        return;
+    }
+    let seq = RandomState::new().build_hasher().finish() >> 16;
+    let mut state = vol.journal.state.lock();
+    state.next_seq = seq;
+    state.head = 0;
+    state.logged.clear();
+    write_super(vol, seq);
+}
+
+///日志中完整的事务：(序号, 修改的页号和内容)
+type Transactions = Vec<(u64, Vec<(usize, [u8; PAGE_SIZE])>)>;
+
+///读出日志中所有完整的事务，返回这些事务、每页最后一次被撤销的事务和下一个序号，不修改设备
+fn scan(vol: &Volume) -> Result<(Transactions, BTreeMap<usize, u64>, u64), String> {
+    let layout = vol.layout;
+    let device = vol.bpm.device();
+    let mut bytes = [0u8; PAGE_SIZE];
+    device.read_page(PageId(layout.journal_start), &mut bytes);
+    let block = JournalBlock::from_bytes(&bytes);
+    if block.magic != JOURNAL_MAGIC || block.block_type != BLOCK_SUPER {
+        return Err("journal superblock is corrupted".to_string());
+    }
+    let capacity = capacity(vol);
+    let log_start = layout.journal_start + 1;
+    let mut seq = block.seq;
+    let mut head = 0;
+    let mut transactions = Vec::new();
+    //页号 -> 撤销该页的最后一个事务
+    let mut revoked = BTreeMap::new();
+    loop {
+        if head + 2 > capacity {
+            break;
+        }
+        device.read_page(PageId(log_start + head), &mut bytes);
+        let descriptor = JournalBlock::from_bytes(&bytes);
+        let (count, revoke_count) = (descriptor.count as usize, descriptor.revoke_count as usize);
+        if !descriptor.is(BLOCK_DESCRIPTOR, seq)
+            || count + revoke_count > JOURNAL_IDS
+            || head + count + 2 > capacity
+        {
+            break;
+        }
+        let mut hash = checksum(CHECKSUM_INIT, &bytes);
+        let mut images = Vec::with_capacity(count);
+        for i in 0..count {
+            device.read_page(PageId(log_start + head + 1 + i), &mut bytes);
+            hash = checksum(hash, &bytes);
+            images.push((descriptor.ids[i] as usize, bytes));
+        }
+        device.read_page(PageId(log_start + head + 1 + count), &mut bytes);
+        let commit = JournalBlock::from_bytes(&bytes);
+        if !commit.is(BLOCK_COMMIT, seq) || commit.checksum != hash {
+            warn!("drop incomplete transaction {seq}");
+            break;
+        }
+        for &page_id in &descriptor.ids[count..count + revoke_count] {
+            revoked.insert(page_id as usize, seq);
+        }
+        transactions.push((seq, images));
+        head += count + 2;
+        seq += 1;
+    }
+    Ok((transactions, revoked, seq))
+}
+
+///日志中还没有重放的完整事务数，只读地检查时用来提示检查结果可能不准确
+pub fn pending(vol: &Volume) -> Result<usize, String> {
+    if vol.layout.journal_pages == 0 {
+        return Ok(0);
+    }
+    Ok(scan(vol)?.0.len())
+}
+
+///挂载时重放日志中所有完整的事务，之后由start开始记日志
+pub fn recover(vol: &Volume) -> Result<(), String> {
+    let journal = &vol.journal;
+    journal.active.store(false, Ordering::Release);
+    let layout = vol.layout;
+    if layout.journal_pages == 0 {
+        return Ok(());
+    }
+    let bpm = &*vol.bpm;
+    let (transactions, revoked, seq) = scan(vol)?;
+    let journal_area = layout.journal_start..layout.journal_start + layout.journal_pages;
+    for (seq, images) in transactions.iter() {
+        for (page_id, image) in images {
+            if *page_id >= bpm.page_num() || journal_area.contains(page_id) {
+                return Err(format!("journal transaction {seq} has bad page {page_id}"));
+            }
+            if revoked.get(page_id).is_some_and(|revoked| revoked > seq) {
+                continue;
+            }
+            //重放不记日志，直接写缓存池
+            new_page!(page: bytes, bpm, *page_id, au);
+            page.copy_from_slice(image);
+        }
+    }
+    if !transactions.is_empty() {
+        info!("replayed {} journal transactions", transactions.len());
+    }
+    let mut state = journal.state.lock();
+    state.next_seq = seq;
+    checkpoint(vol, &mut state);
+    Ok(())
+}
+
+///重放之后开始记日志。日志区放不下一个最大的事务，或者缓存池放不下它修改的页时返回错误，
+///不会退化为不记日志的写回。没有日志区的卷什么也不做
+pub fn start(vol: &Volume) -> Result<(), String> {
+    let journal_pages = vol.layout.journal_pages;
+    if journal_pages == 0 {
+        return Ok(());
+    }
+    if journal_pages < MIN_JOURNAL_PAGES {
+        return Err(format!(
+            "journal has {journal_pages} pages, at least {MIN_JOURNAL_PAGES} are needed; \
+             run fsck-rustfs and reformat with a larger --journal-pages"
+        ));
+    }
+    let pool_size = vol.bpm.pool_size();
+    if pool_size < MIN_POOL_SIZE {
+        return Err(format!(
+            "buffer pool has {pool_size} frames per instance, journaling needs {MIN_POOL_SIZE}"
+        ));
+    }
+    vol.journal.active.store(true, Ordering::Release);
+    Ok(())
+}
+
+///记日志时等待其他线程正在进行的事务提交，此后所有已完成的修改都已落盘，返回false表示没有在记日志
+pub fn sync(vol: &Volume) -> bool {
+    let journal = &vol.journal;
+    if !journal.active.load(Ordering::Acquire) {
+        return false;
+    }
+    //提交时已经sync过设备
+    let mut state = journal.state.lock();
+    while state.owner.is_some() && !state.in_tx() {
+        journal.idle.wait(&mut state);
+    }
+    journal.active.load(Ordering::Acquire)
+}
+
+///卸载前调用：写回所有脏页，清空日志并停止记日志
+pub fn stop(vol: &Volume) {
+    let journal = &vol.journal;
+    let mut state = journal.state.lock();
+    while state.owner.is_some() {
+        journal.idle.wait(&mut state);
+    }
+    if journal.active.swap(false, Ordering::AcqRel) {
+        checkpoint(vol, &mut state);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::{mem_bpm, ParallelBufferPoolManager};
+    use crate::device::{MemDevice, ReadOnlyDevice};
+    use crate::fs::def::SUCCESS;
+    use crate::fs::filesystem::{FsOptions, RustFs};
+    use crate::fs::fsck::fsck;
+    use crate::fs::interface::{
+        fs, rustfs_mkdir, rustfs_mknod, rustfs_open, rustfs_release, rustfs_rmdir, rustfs_truncate,
+        rustfs_unlink, rustfs_write, set_fs, TestMount,
+    };
+    use crate::fs::layout::{Layout, LayoutOptions};
+    use crate::fs::perm::Cred;
+    use crate::fs::superblock::{format, free_counts, mount_read_only};
+    use crate::fs::types::InodeId;
+    use crate::harness;
+    use std::ffi::CString;
+    use std::sync::Arc;
+
+    fn c(path: &str) -> CString {
+        CString::new(path).unwrap()
+    }
+
+    fn lookup(path: &str) -> Option<InodeId> {
+        let root = Cred::new(0, 0, Vec::new());
+        fs().lookup_path(&root, path).ok().map(|entry| entry.ino)
+    }
+
+    fn write(path: &str, data: &[u8]) {
+        let mut fi: crate::fuse::fuse_file_info = unsafe { std::mem::zeroed() };
+        fi.flags = libc::O_WRONLY;
+        unsafe {
+            assert_eq!(rustfs_open(c(path).as_ptr(), &mut fi), SUCCESS);
+            let written = rustfs_write(
+                c(path).as_ptr(),
+                data.as_ptr().cast(),
+                data.len(),
+                0,
+                &mut fi,
+            );
+            assert_eq!(written as usize, data.len());
+            rustfs_release(c(path).as_ptr(), &mut fi);
+        }
+    }
+
+    ///崩溃时磁盘上的内容
+    fn snapshot() -> Vec<[u8; PAGE_SIZE]> {
+        let fs = fs();
+        let device = fs.vol.bpm.device();
+        let mut pages = vec![[0; PAGE_SIZE]; device.page_num()];
+        for (i, page) in pages.iter_mut().enumerate() {
+            device.read_page(PageId(i), page);
+        }
+        pages
+    }
+
+    ///丢弃缓存中的所有内容，从pages重新挂载
+    fn remount(pages: Vec<[u8; PAGE_SIZE]>) {
+        let device = Box::new(MemDevice::from_pages(pages));
+        let bpm = ParallelBufferPoolManager::new(1, 128, device);
+        set_fs(RustFs::mount(bpm, FsOptions::default()).unwrap());
+    }
+
+    ///格式化并挂载，然后停止flusher，此后只有提交和检查点会写磁盘
+    fn setup(journal_pages: usize) -> TestMount {
+        setup_disk(1024, journal_pages)
+    }
+
+    fn setup_disk(page_num: usize, journal_pages: usize) -> TestMount {
+        let options = LayoutOptions {
+            journal_pages: Some(journal_pages),
+            ..Default::default()
+        };
+        let device = Box::new(MemDevice::new(page_num));
+        let bpm = Arc::new(ParallelBufferPoolManager::new(1, 128, device));
+        let vol = format(bpm, Layout::new(page_num, options).unwrap(), 0);
+        //格式化写入的页由flusher退出前写回
+        vol.stop_flusher();
+        let bpm = ParallelBufferPoolManager::new(1, 128, harness::snapshot(&vol.bpm));
+        let mount = TestMount::new(RustFs::mount(bpm, FsOptions::default()).unwrap());
+        fs().vol.stop_flusher();
+        mount
+    }
+
+    #[test]
+    fn test_replay() {
+        //日志足够大，测试中途不会做检查点
+        let _mount = setup(128);
+        let journal_start = fs().vol.layout.journal_start;
+        assert_eq!(rustfs_mkdir(c("/x").as_ptr(), 0o755), SUCCESS);
+        assert_eq!(rustfs_mknod(c("/x/y").as_ptr(), 0o644, 0), SUCCESS);
+        let dir_block = lookup("/x").unwrap().load(&fs().vol).direct_index[0];
+        assert_ne!(dir_block, -1);
+        assert_eq!(rustfs_unlink(c("/x/y").as_ptr()), SUCCESS);
+        assert_eq!(rustfs_rmdir(c("/x").as_ptr()), SUCCESS);
+        //x的目录块作为元数据记入了日志，释放后被文件数据重新使用，重放时不能覆盖数据
+        assert_eq!(rustfs_mknod(c("/f").as_ptr(), 0o644, 0), SUCCESS);
+        let dir_page = fs().vol.layout.data_page_id(dir_block);
+        assert!(fs().vol.journal.state.lock().logged.contains(&dir_page));
+        let data: Vec<u8> = (0..3 * PAGE_SIZE).map(|i| (i % 251) as u8).collect();
+        write("/f", &data);
+        let f = lookup("/f").unwrap();
+        assert!(f.load(&fs().vol).direct_index[..3].contains(&dir_block));
+        assert!(!fs().vol.journal.state.lock().logged.contains(&dir_page));
+        let head = fs().vol.journal.state.lock().head;
+        let pages = snapshot();
+        //元数据还没有写回原处
+        assert_ne!(pages[0], {
+            let fs = fs();
+            let bpm = &*fs.vol.bpm;
+            fetch_page_read!(super_page: bytes, bpm, 0, au);
+            *super_page
+        });
+
+        remount(pages.clone());
+        assert_eq!(lookup("/f"), Some(f));
+        assert_eq!(lookup("/x"), None);
+        let mut buf = vec![0; data.len()];
+        assert_eq!(f.load(&fs().vol).read(&fs().vol, 0, &mut buf), data.len());
+        assert_eq!(buf, data);
+        let report = fsck(&fs().vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+        //重放后做了检查点，日志已清空
+        assert_eq!(fs().vol.journal.state.lock().head, 0);
+
+        //最后一个事务（写文件）的提交块没有写完，整个事务被丢弃
+        let mut torn = pages;
+        torn[journal_start + head].fill(0);
+        remount(torn);
+        assert_eq!(lookup("/f"), Some(f));
+        assert_eq!(f.load(&fs().vol).size, 0);
+        let report = fsck(&fs().vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+        stop(&fs().vol);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let _mount = setup(128);
+        assert_eq!(rustfs_mkdir(c("/x").as_ptr(), 0o755), SUCCESS);
+        assert_eq!(rustfs_mknod(c("/x/y").as_ptr(), 0o644, 0), SUCCESS);
+        let pages = snapshot();
+        //只读挂载不重放日志，检查的是元数据写回原处之前的状态，整个过程不写设备
+        let device = ReadOnlyDevice(Box::new(MemDevice::from_pages(pages.clone())));
+        let bpm = ParallelBufferPoolManager::new(1, 128, Box::new(device));
+        let vol = mount_read_only(Arc::new(bpm)).unwrap();
+        assert!(pending(&vol).unwrap() >= 2);
+        let report = fsck(&vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+        vol.stop_flusher();
+        //正常挂载时重放并做检查点，日志中不再有事务
+        remount(pages);
+        assert!(lookup("/x/y").is_some());
+        assert_eq!(pending(&fs().vol), Ok(0));
+        stop(&fs().vol);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let _mount = setup(96);
+        let capacity = capacity(&fs().vol);
+        //日志剩下的空间放不下一个最大的事务时，开始新事务前做检查点，日志不会写满
+        for i in 0..capacity {
+            let path = format!("/f{i}");
+            assert_eq!(rustfs_mknod(c(&path).as_ptr(), 0o644, 0), SUCCESS);
+            assert!(fs().vol.journal.state.lock().head <= capacity);
+            assert!(!fs().vol.bpm.writeback().has_deferred());
+        }
+        remount(snapshot());
+        for i in 0..capacity {
+            assert!(lookup(&format!("/f{i}")).is_some());
+        }
+        let report = fsck(&fs().vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+
+        //重新格式化后，日志区中留下的旧事务不会被重放
+        fs().vol.stop_flusher();
+        assert_eq!(rustfs_mknod(c("/g").as_ptr(), 0o644, 0), SUCCESS);
+        assert!(fs().vol.journal.state.lock().head > 0);
+        let vol = format(fs().vol.bpm.clone(), fs().vol.layout, 0);
+        //格式化写入的页由flusher退出前写回
+        vol.stop_flusher();
+        remount(snapshot());
+        assert_eq!(lookup("/g"), None);
+        assert_eq!(lookup("/f0"), None);
+        stop(&fs().vol);
+    }
+
+    #[test]
+    fn test_split() {
+        //最小的日志，文件超过一步能释放的块数
+        let _mount = setup_disk(4096, MIN_JOURNAL_PAGES);
+        //写入的数据远多于缓存，脏页要靠flusher写回
+        fs().vol.start_flusher(0);
+        let capacity = capacity(&fs().vol);
+        assert_eq!(rustfs_mkdir(c("/d").as_ptr(), 0o755), SUCCESS);
+        assert_eq!(rustfs_mknod(c("/d/f").as_ptr(), 0o644, 0), SUCCESS);
+        let counts = free_counts(&fs().vol);
+        let data: Vec<u8> = (0..2500 * PAGE_SIZE).map(|i| (i % 253) as u8).collect();
+        write("/d/f", &data);
+        assert!(fs().vol.journal.state.lock().head <= capacity);
+        remount(snapshot());
+        let f = lookup("/d/f").unwrap();
+        let mut buf = vec![0; data.len()];
+        assert_eq!(f.load(&fs().vol).read(&fs().vol, 0, &mut buf), data.len());
+        assert_eq!(buf, data);
+        let report = fsck(&fs().vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+
+        //截断分多个事务，每个事务都放得进日志
+        let seq = fs().vol.journal.state.lock().next_seq;
+        let size = 10 * PAGE_SIZE + 100;
+        assert_eq!(rustfs_truncate(c("/d/f").as_ptr(), size as i64), SUCCESS);
+        assert!(fs().vol.journal.state.lock().next_seq >= seq + 3);
+        assert!(fs().vol.journal.state.lock().head <= capacity);
+        remount(snapshot());
+        let f = lookup("/d/f").unwrap();
+        assert_eq!(f.load(&fs().vol).size as usize, size);
+        let mut buf = vec![0; size];
+        assert_eq!(f.load(&fs().vol).read(&fs().vol, 0, &mut buf), size);
+        assert_eq!(buf, data[..size]);
+        let report = fsck(&fs().vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+
+        //删除大文件同样分多个事务释放
+        write("/d/f", &data);
+        let seq = fs().vol.journal.state.lock().next_seq;
+        assert_eq!(rustfs_unlink(c("/d/f").as_ptr()), SUCCESS);
+        assert!(fs().vol.journal.state.lock().next_seq >= seq + 3);
+        remount(snapshot());
+        assert_eq!(lookup("/d/f"), None);
+        //f的inode和所有块都已释放
+        assert_eq!(free_counts(&fs().vol), (counts.0 + 1, counts.1));
+        let report = fsck(&fs().vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+        stop(&fs().vol);
+    }
+
+    #[test]
+    fn test_room() {
+        let _mount = setup(128);
+        assert_eq!(rustfs_mknod(c("/f").as_ptr(), 0o644, 0), SUCCESS);
+        let f = lookup("/f").unwrap();
+        let vol = &fs().vol;
+        assert_eq!(room(vol), usize::MAX);
+        let _tx = begin(vol);
+        assert_eq!(room(vol), TX_PAGES);
+        //事务快满时不再写入，提交后在新的事务中接着写
+        let page_num = vol.bpm.page_num();
+        for page_id in page_num - (TX_PAGES - 10)..page_num {
+            note_write(vol, page_id);
+        }
+        assert_eq!(room(vol), 10);
+        let mut inode = f.load(vol);
+        assert_eq!(inode.write(vol, 0, &[1; 3 * PAGE_SIZE]), 0);
+        restart(vol);
+        assert_eq!(room(vol), TX_PAGES);
+        assert_eq!(inode.write(vol, 0, &[1; 3 * PAGE_SIZE]), 3 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_too_small() {
+        //旧版本格式化的日志放不下一个最大的事务，挂载失败而不是不记日志
+        let mut layout = Layout::new(1024, LayoutOptions::default()).unwrap();
+        layout.journal_pages = 32;
+        let vol = format(mem_bpm(1, 128), layout, 0);
+        vol.stop_flusher();
+        let bpm = ParallelBufferPoolManager::new(1, 128, harness::snapshot(&vol.bpm));
+        assert!(RustFs::mount(bpm, FsOptions::default()).is_err());
+        //fsck等工具仍然可以重放和检查
+        let bpm = Arc::new(ParallelBufferPoolManager::new(
+            1,
+            20,
+            harness::snapshot(&vol.bpm),
+        ));
+        assert!(crate::fs::superblock::mount(bpm).is_ok());
+
+        //缓存池放不下一个最大的事务
+        let bpm = ParallelBufferPoolManager::new(1, MIN_POOL_SIZE - 1, harness::mkfs(1024, 0));
+        assert!(RustFs::mount(bpm, FsOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+use crate::fs::custom::{
+    DATA_MAP_PAGE_ID, DATA_START_PAGE_ID, INODE_MAP_PAGE_ID, INODE_NUM, INODE_SIZE,
+    INODE_START_PAGE_ID, PAGE_SIZE,
+};
+use crate::fs::journal::MIN_JOURNAL_PAGES;
+
+///一个位图页能管理的位数
+pub const BITS_PER_PAGE: usize = PAGE_SIZE * 8;
+
+pub const INODE_PER_PAGE: usize = PAGE_SIZE / INODE_SIZE;
+
+///磁盘布局，格式化时确定并写入超级块，挂载时从超级块读出。
+///依次为超级块、inode位图、数据位图、inode表、日志区和数据区，页号都是绝对页号
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layout {
+    pub inode_map_start: usize,
+    pub inode_map_pages: usize,
+    pub data_map_start: usize,
+    pub data_map_pages: usize,
+    pub inode_start: usize,
+    pub inode_num: usize,
+    ///日志区，页数为0时不记日志
+    pub journal_start: usize,
+    pub journal_pages: usize,
+    pub data_start: usize,
+    ///数据块数
+    pub data_num: usize,
+    ///每个块组的数据块数和inode数，都是8的倍数，见fs::alloc
+    pub group_blocks: usize,
+    pub group_inodes: usize,
+}
+
+///格式化时可以指定的参数，未指定的项按磁盘大小自动计算
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LayoutOptions {
+    pub inode_num: Option<usize>,
+    pub inode_map_pages: Option<usize>,
+    pub data_map_pages: Option<usize>,
+    pub journal_pages: Option<usize>,
+    pub data_start: Option<usize>,
+    pub group_blocks: Option<usize>,
+}
+
+impl Layout {
+    ///版本2及以前的固定布局
+    pub const fn legacy(page_num: usize) -> Self {
+        let data_num = page_num.saturating_sub(DATA_START_PAGE_ID);
+        Layout {
+            inode_map_start: INODE_MAP_PAGE_ID,
+            inode_map_pages: 1,
+            data_map_start: DATA_MAP_PAGE_ID,
+            data_map_pages: 1,
+            inode_start: INODE_START_PAGE_ID,
+            inode_num: INODE_NUM,
+            journal_start: DATA_START_PAGE_ID,
+            journal_pages: 0,
+            data_start: DATA_START_PAGE_ID,
+            data_num: if data_num < BITS_PER_PAGE {
+                data_num
+            } else {
+                BITS_PER_PAGE
+            },
+            group_blocks: BITS_PER_PAGE,
+            group_inodes: BITS_PER_PAGE,
+        }
+    }
+
+    ///按page_num页大小的磁盘计算布局。默认每4页（16K）配一个inode，
+    ///日志区取磁盘的1/32，介于128页和1024页之间，一个数据位图页管理一个块组
+    pub fn new(page_num: usize, options: LayoutOptions) -> Result<Self, String> {
+        let inode_num = options
+            .inode_num
+            .unwrap_or((page_num / 4).max(INODE_PER_PAGE));
+        if inode_num == 0 {
+            return Err("inode count must be positive".to_string());
+        }
+        let inode_map_pages = options
+            .inode_map_pages
+            .unwrap_or(inode_num.div_ceil(BITS_PER_PAGE));
+        //数据块数不会超过总页数，按总页数估计数据位图的大小
+        let data_map_pages = options
+            .data_map_pages
+            .unwrap_or(page_num.div_ceil(BITS_PER_PAGE));
+        let inode_map_start = 1;
+        let data_map_start = inode_map_start + inode_map_pages;
+        let inode_start = data_map_start + data_map_pages;
+        let inode_end = inode_start + inode_num.div_ceil(INODE_PER_PAGE);
+        let journal_pages = options
+            .journal_pages
+            .unwrap_or((page_num / 32).clamp(128, 1024));
+        if journal_pages != 0 && journal_pages < MIN_JOURNAL_PAGES {
+            return Err(format!("journal needs at least {MIN_JOURNAL_PAGES} pages"));
+        }
+        let journal_start = inode_end;
+        let journal_end = journal_start + journal_pages;
+        let data_start = options.data_start.unwrap_or(journal_end);
+        if inode_map_pages * BITS_PER_PAGE < inode_num {
+            return Err(format!(
+                "{inode_map_pages} inode bitmap pages cannot hold {inode_num} inodes"
+            ));
+        }
+        if data_start < journal_end {
+            return Err(format!(
+                "data start {data_start} overlaps the inode table and journal ending at page {journal_end}"
+            ));
+        }
+        if data_start >= page_num {
+            return Err(format!(
+                "no room for data: data start {data_start}, device has {page_num} pages"
+            ));
+        }
+        let data_num = page_num - data_start;
+        if data_map_pages * BITS_PER_PAGE < data_num {
+            return Err(format!(
+                "{data_map_pages} data bitmap pages cannot hold {data_num} blocks"
+            ));
+        }
+        let group_blocks = options.group_blocks.unwrap_or(BITS_PER_PAGE);
+        if group_blocks == 0 || !group_blocks.is_multiple_of(8) {
+            return Err(format!(
+                "blocks per group {group_blocks} is not a positive multiple of 8"
+            ));
+        }
+        let mut layout = Layout {
+            inode_map_start,
+            inode_map_pages,
+            data_map_start,
+            data_map_pages,
+            inode_start,
+            inode_num,
+            journal_start,
+            journal_pages,
+            data_start,
+            data_num,
+            group_blocks,
+            group_inodes: 0,
+        };
+        layout.group_inodes = layout.default_group_inodes();
+        Ok(layout)
+    }
+
+    ///把inode平均分到各个块组，使inode组与数据组一一对应
+    pub fn default_group_inodes(&self) -> usize {
+        let groups = self.data_num.div_ceil(self.group_blocks).max(1);
+        self.inode_num.div_ceil(groups).next_multiple_of(8)
+    }
+
+    ///数据块组数
+    pub fn data_groups(&self) -> usize {
+        self.data_num.div_ceil(self.group_blocks)
+    }
+
+    ///inode组数
+    pub fn inode_groups(&self) -> usize {
+        self.inode_num.div_ceil(self.group_inodes)
+    }
+
+    ///检查从超级块读出的布局是否与page_num页大小的磁盘相符，各区域不能重叠或越界
+    pub fn check(&self, page_num: usize) -> Result<(), String> {
+        let inode_end = self.inode_start + self.inode_num.div_ceil(INODE_PER_PAGE);
+        if self.inode_map_start == 0
+            || self.data_map_start < self.inode_map_start + self.inode_map_pages
+            || self.inode_start < self.data_map_start + self.data_map_pages
+            || self.journal_start < inode_end
+            || self.journal_pages == 1
+            || self.data_start < self.journal_start + self.journal_pages
+        {
+            return Err(format!("overlapping regions in {self:?}"));
+        }
+        if self.inode_map_pages * BITS_PER_PAGE < self.inode_num
+            || self.data_map_pages * BITS_PER_PAGE < self.data_num
+        {
+            return Err(format!("bitmap too small in {self:?}"));
+        }
+        if self.data_start + self.data_num > page_num {
+            return Err(format!("{self:?} exceeds the device of {page_num} pages"));
+        }
+        if [self.group_blocks, self.group_inodes]
+            .iter()
+            .any(|&size| size == 0 || !size.is_multiple_of(8))
+        {
+            return Err(format!("invalid group size in {self:?}"));
+        }
+        Ok(())
+    }
+
+    ///inode所在的页和页内下标
+    pub fn inode_pos(&self, inode_id: u32) -> (usize, usize) {
+        let inode_id = inode_id as usize;
+        (
+            self.inode_start + inode_id / INODE_PER_PAGE,
+            inode_id % INODE_PER_PAGE,
+        )
+    }
+
+    ///数据块所在的页
+    pub fn data_page_id(&self, block: i32) -> usize {
+        self.data_start + block as usize
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        //默认参数的4M磁盘
+        let layout = Layout::new(1024, LayoutOptions::default()).unwrap();
+        assert_eq!(layout.inode_map_start, 1);
+        assert_eq!(layout.data_map_start, 2);
+        assert_eq!(layout.inode_start, 3);
+        assert_eq!(layout.inode_num, 256);
+        assert_eq!((layout.journal_start, layout.journal_pages), (11, 128));
+        assert_eq!(layout.data_start, 139);
+        assert_eq!(layout.data_num, 885);
+        assert_eq!((layout.data_groups(), layout.inode_groups()), (1, 1));
+
+        let options = LayoutOptions {
+            inode_num: Some(100_000),
+            data_map_pages: Some(3),
+            ..Default::default()
+        };
+        let layout = Layout::new(1 << 16, options).unwrap();
+        assert_eq!(layout.inode_map_pages, 4);
+        assert_eq!(layout.data_map_start, 5);
+        assert_eq!(layout.inode_start, 8);
+        assert_eq!(layout.journal_pages, 1024);
+        assert_eq!(layout.data_start, 8 + 3125 + 1024);
+        assert_eq!(layout.inode_pos(33), (9, 1));
+        //inode按数据块组平均分配
+        assert_eq!(layout.data_groups(), 2);
+        assert_eq!(layout.group_inodes, 50_000);
+        assert_eq!(layout.inode_groups(), 2);
+
+        let options = LayoutOptions {
+            group_blocks: Some(128),
+            ..Default::default()
+        };
+        let layout = Layout::new(1024, options).unwrap();
+        assert_eq!(layout.data_groups(), 7);
+        assert_eq!(layout.group_inodes, 40);
+        assert_eq!(layout.inode_groups(), 7);
+        let options = LayoutOptions {
+            group_blocks: Some(100),
+            ..Default::default()
+        };
+        assert!(Layout::new(1024, options).is_err());
+
+        //不要日志
+        let options = LayoutOptions {
+            journal_pages: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(Layout::new(1024, options).unwrap().data_start, 11);
+        let options = LayoutOptions {
+            journal_pages: Some(1),
+            ..Default::default()
+        };
+        assert!(Layout::new(1024, options).is_err());
+        //日志区放不下一个最大的事务
+        let options = LayoutOptions {
+            journal_pages: Some(MIN_JOURNAL_PAGES - 1),
+            ..Default::default()
+        };
+        assert!(Layout::new(1024, options).is_err());
+
+        let options = LayoutOptions {
+            data_start: Some(4),
+            ..Default::default()
+        };
+        assert!(Layout::new(1024, options).is_err());
+        let options = LayoutOptions {
+            inode_map_pages: Some(1),
+            inode_num: Some(BITS_PER_PAGE + 1),
+            ..Default::default()
+        };
+        assert!(Layout::new(1 << 20, options).is_err());
+        assert!(Layout::new(3, LayoutOptions::default()).is_err());
+
+        let mut layout = Layout::new(1024, LayoutOptions::default()).unwrap();
+        assert!(layout.check(1024).is_ok());
+        assert!(layout.check(1000).is_err());
+        assert!(Layout::legacy(1024).check(1024).is_ok());
+        layout.group_inodes = 0;
+        assert!(layout.check(1024).is_err());
+        layout.group_inodes = 8;
+        layout.inode_start = 2;
+        assert!(layout.check(1024).is_err());
+    }
+}
@@ -1,6 +1,20 @@
+pub mod alloc;
+pub mod control;
 pub mod custom;
 pub mod dcache;
 pub mod def;
+pub mod extent;
+pub mod filesystem;
+pub mod fsck;
+pub mod handle;
+pub mod htree;
 pub mod interface;
+pub mod journal;
+pub mod layout;
+pub mod perm;
+pub mod readahead;
+pub mod superblock;
 pub mod types;
 pub mod utils;
+pub mod volume;
+pub mod xattr;
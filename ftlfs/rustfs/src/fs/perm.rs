@@ -0,0 +1,529 @@
+//! POSIX权限检查。调用者的身份来自fuse_get_context，附加组在第一次用到时通过fuse_getgroups读出，
+//! as_caller可以在当前线程中临时指定调用者，Filesystem用它把参数中的身份交给目录缓存。
+//!
+//! - 路径中的每个目录都需要搜索（x）权限，见DCache::resolve
+//! - 在目录中创建、删除和改名需要目录的写和搜索权限；设置了粘着位的目录中，
+//!   只有目录项指向的文件的属主、目录的属主和root能删除或改名
+//! - 设置了setgid的目录中新建的文件属于目录的组，新建的子目录继承setgid
+//! - root不受rwx位的限制，但执行普通文件要求至少有一个x位
+use crate::fs::types::Inode;
+use crate::fuse;
+use libc::{c_int, R_OK, S_ISGID, S_ISUID, S_ISVTX, S_IXGRP, W_OK, X_OK};
+use std::cell::{OnceCell, RefCell};
+
+#[derive(Clone, Debug)]
+pub struct Cred {
+    pub uid: u32,
+    pub gid: u32,
+    ///附加组，从fuse请求中得到的身份第一次用到时才读出
+    groups: OnceCell<Vec<u32>>,
+}
+
+thread_local! {
+    static OVERRIDE: RefCell<Option<Cred>> = const { RefCell::new(None) };
+}
+
+impl Cred {
+    pub fn new(uid: u32, gid: u32, groups: Vec<u32>) -> Self {
+        Cred {
+            uid,
+            gid,
+            groups: OnceCell::from(groups),
+        }
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.get_or_init(fuse_groups).contains(&gid)
+    }
+}
+
+///发起当前fuse请求的进程的附加组，不在fuse请求中或读取失败时为空
+fn fuse_groups() -> Vec<u32> {
+    let mut groups = vec![0; 32];
+    loop {
+        let n = unsafe { fuse::fuse_getgroups(groups.len() as c_int, groups.as_mut_ptr()) };
+        if n < 0 {
+            return Vec::new();
+        }
+        if n as usize <= groups.len() {
+            groups.truncate(n as usize);
+            return groups;
+        }
+        groups.resize(n as usize, 0);
+    }
+}
+
+///发起当前fuse请求的进程的身份，不在fuse请求中时为本进程
+pub fn caller() -> Cred {
+    if let Some(cred) = OVERRIDE.with(|cred| cred.borrow().clone()) {
+        return cred;
+    }
+    let context = unsafe { fuse::fuse_get_context() };
+    if context.is_null() {
+        unsafe { Cred::new(libc::getuid(), libc::getgid(), Vec::new()) }
+    } else {
+        let (uid, gid) = unsafe { ((*context).uid, (*context).gid) };
+        Cred {
+            uid,
+            gid,
+            groups: OnceCell::new(),
+        }
+    }
+}
+
+///以cred的身份执行f，f中的caller()返回cred
+pub fn as_caller<T>(cred: &Cred, f: impl FnOnce() -> T) -> T {
+    let old = OVERRIDE.with(|old| old.replace(Some(cred.clone())));
+    let result = f();
+    OVERRIDE.with(|cur| *cur.borrow_mut() = old);
+    result
+}
+
+///检查cred对inode是否有mask（R_OK、W_OK、X_OK的组合）要求的权限，没有时返回-EACCES
+pub fn check(inode: &Inode, cred: &Cred, mask: c_int) -> Result<(), c_int> {
+    let mask = (mask & (R_OK | W_OK | X_OK)) as u32;
+    if cred.is_root() {
+        if mask & X_OK as u32 != 0 && !inode.is_dir() && inode.mode & 0o111 == 0 {
+            return Err(-libc::EACCES);
+        }
+        return Ok(());
+    }
+    let bits = if cred.uid == inode.uid {
+        inode.mode >> 6
+    } else if cred.in_group(inode.gid) {
+        inode.mode >> 3
+    } else {
+        inode.mode
+    };
+    if bits & mask == mask {
+        Ok(())
+    } else {
+        Err(-libc::EACCES)
+    }
+}
+
+///open的flags需要的权限，O_TRUNC需要写权限
+pub fn open_mask(flags: c_int) -> c_int {
+    let mask = match flags & libc::O_ACCMODE {
+        libc::O_RDONLY => R_OK,
+        libc::O_WRONLY => W_OK,
+        _ => R_OK | W_OK,
+    };
+    if flags & libc::O_TRUNC != 0 {
+        mask | W_OK
+    } else {
+        mask
+    }
+}
+
+pub fn is_owner(inode: &Inode, cred: &Cred) -> bool {
+    cred.is_root() || cred.uid == inode.uid
+}
+
+///能否从目录dir中删除（或改名）指向victim的目录项
+pub fn may_delete(dir: &Inode, victim: &Inode, cred: &Cred) -> Result<(), c_int> {
+    check(dir, cred, W_OK | X_OK)?;
+    if dir.mode & S_ISVTX != 0 && !is_owner(dir, cred) && !is_owner(victim, cred) {
+        return Err(-libc::EPERM);
+    }
+    Ok(())
+}
+
+///在目录dir中新建的inode的属主、组和权限位
+pub fn init_owner(dir: &Inode, cred: &Cred, is_dir: bool, mode: u32) -> (u32, u32, u32) {
+    let mut mode = mode & 0o7777;
+    let gid = if dir.mode & S_ISGID != 0 {
+        if is_dir {
+            mode |= S_ISGID;
+        }
+        dir.gid
+    } else {
+        cred.gid
+    };
+    //不属于文件所在组的用户不能创建setgid文件
+    if !is_dir && mode & S_ISGID != 0 && !cred.is_root() && !cred.in_group(gid) {
+        mode &= !S_ISGID;
+    }
+    (cred.uid, gid, mode)
+}
+
+///chmod：只有属主和root能修改
+pub fn may_chmod(inode: &Inode, cred: &Cred) -> Result<(), c_int> {
+    if !is_owner(inode, cred) {
+        return Err(-libc::EPERM);
+    }
+    Ok(())
+}
+
+///chmod：检查权限后修改，不属于文件所在组的普通用户设置的setgid位被清掉
+pub fn chmod(inode: &mut Inode, cred: &Cred, mode: u32) -> Result<(), c_int> {
+    may_chmod(inode, cred)?;
+    let mut mode = mode & 0o7777;
+    if !cred.is_root() && !cred.in_group(inode.gid) {
+        mode &= !S_ISGID;
+    }
+    inode.mode = mode;
+    Ok(())
+}
+
+///chown：只有root能修改属主；属主可以把组改为自己所在的组。uid或gid为-1表示不修改
+pub fn may_chown(inode: &Inode, cred: &Cred, uid: u32, gid: u32) -> Result<(), c_int> {
+    if cred.is_root() {
+        return Ok(());
+    }
+    if uid != u32::MAX && uid != inode.uid {
+        return Err(-libc::EPERM);
+    }
+    if gid != u32::MAX && gid != inode.gid && !(is_owner(inode, cred) && cred.in_group(gid)) {
+        return Err(-libc::EPERM);
+    }
+    Ok(())
+}
+
+///chown：检查权限后修改。修改普通文件的属主或组时清掉setuid位，组可执行时也清掉setgid位
+pub fn chown(inode: &mut Inode, cred: &Cred, uid: u32, gid: u32) -> Result<(), c_int> {
+    may_chown(inode, cred, uid, gid)?;
+    //-1表示不修改对应的id
+    let uid = (uid != u32::MAX).then_some(uid);
+    let gid = (gid != u32::MAX).then_some(gid);
+    if uid.is_none() && gid.is_none() {
+        return Ok(());
+    }
+    if !inode.is_dir() {
+        kill_suid(inode);
+    }
+    inode.uid = uid.unwrap_or(inode.uid);
+    inode.gid = gid.unwrap_or(inode.gid);
+    Ok(())
+}
+
+///清掉setuid位，组可执行时也清掉setgid位（没有组执行位的setgid表示强制锁，保留）
+pub fn kill_suid(inode: &mut Inode) {
+    inode.mode &= !S_ISUID;
+    if inode.mode & S_IXGRP != 0 {
+        inode.mode &= !S_ISGID;
+    }
+}
+
+///普通用户写文件或改变文件大小时清掉setuid和setgid位
+pub fn write_kill_suid(inode: &mut Inode, cred: &Cred) {
+    if !cred.is_root() {
+        kill_suid(inode);
+    }
+}
+
+///utimens：设置为指定的时间只有属主和root可以，设置为当前时间有写权限即可
+pub fn may_set_times(inode: &Inode, cred: &Cred, explicit: bool) -> Result<(), c_int> {
+    if is_owner(inode, cred) {
+        return Ok(());
+    }
+    if explicit {
+        return Err(-libc::EPERM);
+    }
+    check(inode, cred, W_OK)
+}
+
+///扩展属性的权限：user.*同文件的读写权限，只能设置在普通文件和目录上；
+///trusted.*只有root能访问，security.*只有root能修改
+pub fn check_xattr(inode: &Inode, cred: &Cred, name: &str, write: bool) -> Result<(), c_int> {
+    if name.starts_with("user.") {
+        if !inode.is_dir() && !inode.is_reg() {
+            return Err(if write { -libc::EPERM } else { -libc::ENODATA });
+        }
+        return check(inode, cred, if write { W_OK } else { R_OK });
+    }
+    if cred.is_root() {
+        return Ok(());
+    }
+    if name.starts_with("trusted.") {
+        return Err(if write { -libc::EPERM } else { -libc::ENODATA });
+    }
+    if write && name.starts_with("security.") {
+        return Err(-libc::EPERM);
+    }
+    Ok(())
+}
+
+///listxattr中只列出cred能读的名字
+pub fn filter_xattr_names(names: Vec<u8>, cred: &Cred) -> Vec<u8> {
+    if cred.is_root() {
+        return names;
+    }
+    names
+        .split_inclusive(|&b| b == 0)
+        .filter(|name| !name.starts_with(b"trusted."))
+        .flatten()
+        .copied()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+    use crate::fs::def::SUCCESS;
+    use crate::fs::filesystem::{FsOptions, RustFs};
+    use crate::fs::interface::*;
+    use crate::fs::types::FileType;
+    use crate::fuse::fuse_file_info;
+    use crate::harness::mkfs;
+    use libc::{S_IRWXU, X_OK};
+    use std::ffi::CString;
+
+    fn inode(file_type: FileType, mode: u32, uid: u32, gid: u32) -> Inode {
+        let mut inode: Inode = unsafe { std::mem::zeroed() };
+        inode.file_type = file_type;
+        inode.mode = mode;
+        inode.uid = uid;
+        inode.gid = gid;
+        inode
+    }
+
+    #[test]
+    fn test_rules() {
+        let root = Cred::new(0, 0, Vec::new());
+        let alice = Cred::new(1000, 1000, vec![2000]);
+        let bob = Cred::new(1001, 1001, Vec::new());
+
+        //属主、组和其他人分别使用对应的权限位
+        let file = inode(FileType::REG, 0o640, 1000, 2000);
+        assert_eq!(check(&file, &alice, R_OK | W_OK), Ok(()));
+        assert_eq!(check(&file, &alice, X_OK), Err(-libc::EACCES));
+        assert_eq!(
+            check(&file, &Cred::new(1002, 2000, Vec::new()), R_OK),
+            Ok(())
+        );
+        assert_eq!(
+            check(&file, &Cred::new(1002, 2000, Vec::new()), W_OK),
+            Err(-libc::EACCES)
+        );
+        assert_eq!(check(&file, &bob, R_OK), Err(-libc::EACCES));
+        //root不受限制，但不能执行没有x位的文件
+        assert_eq!(check(&file, &root, R_OK | W_OK), Ok(()));
+        assert_eq!(check(&file, &root, X_OK), Err(-libc::EACCES));
+        assert_eq!(
+            check(&inode(FileType::DIR, 0, 1000, 1000), &root, X_OK),
+            Ok(())
+        );
+
+        assert_eq!(open_mask(libc::O_RDONLY), R_OK);
+        assert_eq!(open_mask(libc::O_RDONLY | libc::O_TRUNC), R_OK | W_OK);
+        assert_eq!(open_mask(libc::O_RDWR), R_OK | W_OK);
+
+        //粘着位目录中只能删除自己的文件
+        let tmp = inode(FileType::DIR, 0o1777, 0, 0);
+        assert_eq!(may_delete(&tmp, &file, &alice), Ok(()));
+        assert_eq!(may_delete(&tmp, &file, &bob), Err(-libc::EPERM));
+        assert_eq!(may_delete(&tmp, &file, &root), Ok(()));
+        assert_eq!(
+            may_delete(&inode(FileType::DIR, 0o777, 0, 0), &file, &bob),
+            Ok(())
+        );
+        assert_eq!(
+            may_delete(&inode(FileType::DIR, 0o755, 0, 0), &file, &bob),
+            Err(-libc::EACCES)
+        );
+
+        //setgid目录中新建的文件属于目录的组，子目录继承setgid
+        let shared = inode(FileType::DIR, 0o2775, 0, 3000);
+        assert_eq!(init_owner(&shared, &bob, true, 0o755), (1001, 3000, 0o2755));
+        assert_eq!(
+            init_owner(&shared, &bob, false, 0o2755),
+            (1001, 3000, 0o755)
+        );
+        assert_eq!(init_owner(&tmp, &bob, false, 0o2755), (1001, 1001, 0o2755));
+
+        //chmod和chown
+        let mut file = inode(FileType::REG, 0o6755, 1000, 1000);
+        assert_eq!(chmod(&mut file, &bob, 0o777), Err(-libc::EPERM));
+        assert_eq!(chmod(&mut file, &alice, 0o2755), Ok(()));
+        assert_eq!(file.mode, 0o2755);
+        file.gid = 3000;
+        assert_eq!(chmod(&mut file, &alice, 0o2755), Ok(()));
+        assert_eq!(file.mode, 0o755);
+        assert_eq!(chown(&mut file, &alice, 1001, u32::MAX), Err(-libc::EPERM));
+        assert_eq!(chown(&mut file, &bob, u32::MAX, 1001), Err(-libc::EPERM));
+        assert_eq!(chown(&mut file, &alice, u32::MAX, 1001), Err(-libc::EPERM));
+        file.mode = 0o6755;
+        assert_eq!(chown(&mut file, &alice, 1000, 2000), Ok(()));
+        assert_eq!((file.uid, file.gid, file.mode), (1000, 2000, 0o755));
+        assert_eq!(chown(&mut file, &root, 1001, u32::MAX), Ok(()));
+        assert_eq!((file.uid, file.gid), (1001, 2000));
+
+        //没有组执行位的setgid不清除
+        let mut file = inode(FileType::REG, 0o6744, 1000, 1000);
+        write_kill_suid(&mut file, &root);
+        assert_eq!(file.mode, 0o6744);
+        write_kill_suid(&mut file, &alice);
+        assert_eq!(file.mode, 0o2744);
+
+        let file = inode(FileType::REG, 0o666, 1000, 1000);
+        assert_eq!(may_set_times(&file, &bob, false), Ok(()));
+        assert_eq!(may_set_times(&file, &bob, true), Err(-libc::EPERM));
+        assert_eq!(may_set_times(&file, &alice, true), Ok(()));
+
+        //扩展属性
+        assert_eq!(check_xattr(&file, &bob, "user.a", false), Ok(()));
+        assert_eq!(check_xattr(&file, &bob, "user.a", true), Ok(()));
+        let file = inode(FileType::REG, 0o644, 1000, 1000);
+        assert_eq!(check_xattr(&file, &bob, "user.a", true), Err(-libc::EACCES));
+        assert_eq!(
+            check_xattr(&file, &bob, "trusted.a", false),
+            Err(-libc::ENODATA)
+        );
+        assert_eq!(check_xattr(&file, &root, "trusted.a", true), Ok(()));
+        assert_eq!(check_xattr(&file, &alice, "security.a", false), Ok(()));
+        assert_eq!(
+            check_xattr(&file, &alice, "security.a", true),
+            Err(-libc::EPERM)
+        );
+        let link = inode(FileType::SYMLINK, 0o777, 1000, 1000);
+        assert_eq!(
+            check_xattr(&link, &alice, "user.a", true),
+            Err(-libc::EPERM)
+        );
+        let names = b"user.a\0trusted.b\0security.c\0".to_vec();
+        assert_eq!(filter_xattr_names(names.clone(), &root), names);
+        assert_eq!(filter_xattr_names(names, &bob), b"user.a\0security.c\0");
+    }
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    fn stat(path: &str) -> Result<libc::stat, c_int> {
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        match unsafe { rustfs_getattr(c(path).as_ptr(), &mut stat) } {
+            SUCCESS => Ok(stat),
+            ret => Err(ret),
+        }
+    }
+
+    fn open(path: &str, flags: c_int) -> Result<fuse_file_info, c_int> {
+        let mut fi: fuse_file_info = unsafe { std::mem::zeroed() };
+        fi.flags = flags;
+        match unsafe { rustfs_open(c(path).as_ptr(), &mut fi) } {
+            SUCCESS => Ok(fi),
+            ret => Err(ret),
+        }
+    }
+
+    #[test]
+    fn test_interface() {
+        let bpm = ParallelBufferPoolManager::new(1, 128, mkfs(1024, 0));
+        let _mount = TestMount::new(RustFs::mount(bpm, FsOptions::default()).unwrap());
+        let alice = Cred::new(1000, 1000, Vec::new());
+        let bob = Cred::new(1001, 1001, vec![2000]);
+
+        assert_eq!(rustfs_chmod(c("/").as_ptr(), 0o755), SUCCESS);
+        assert_eq!(rustfs_mkdir(c("/tmp").as_ptr(), 0o777), SUCCESS);
+        assert_eq!(rustfs_chmod(c("/tmp").as_ptr(), 0o1777), SUCCESS);
+        assert_eq!(rustfs_mkdir(c("/priv").as_ptr(), S_IRWXU), SUCCESS);
+        assert_eq!(rustfs_mknod(c("/priv/f").as_ptr(), 0o644, 0), SUCCESS);
+
+        as_caller(&alice, || {
+            //路径上的目录没有搜索权限
+            assert_eq!(stat("/priv/f").err(), Some(-libc::EACCES));
+            assert_eq!(
+                rustfs_access(c("/priv/f").as_ptr(), libc::F_OK),
+                -libc::EACCES
+            );
+            assert_eq!(rustfs_access(c("/priv").as_ptr(), libc::F_OK), SUCCESS);
+            assert_eq!(rustfs_access(c("/priv").as_ptr(), R_OK), -libc::EACCES);
+            assert_eq!(
+                rustfs_access(c("/nothing").as_ptr(), libc::F_OK),
+                -libc::ENOENT
+            );
+            //没有父目录的写权限
+            assert_eq!(rustfs_mknod(c("/a").as_ptr(), 0o644, 0), -libc::EACCES);
+            assert_eq!(rustfs_unlink(c("/priv/f").as_ptr()), -libc::EACCES);
+            assert_eq!(rustfs_mknod(c("/tmp/a").as_ptr(), 0o4777, 0), SUCCESS);
+            let attr = stat("/tmp/a").unwrap();
+            assert_eq!((attr.st_uid, attr.st_gid), (1000, 1000));
+            assert_eq!(attr.st_mode, libc::S_IFREG | 0o4777);
+        });
+
+        as_caller(&bob, || {
+            //粘着位目录中不能删除和改名别人的文件
+            assert_eq!(rustfs_unlink(c("/tmp/a").as_ptr()), -libc::EPERM);
+            assert_eq!(
+                rustfs_rename(c("/tmp/a").as_ptr(), c("/tmp/b").as_ptr()),
+                -libc::EPERM
+            );
+            assert_eq!(rustfs_mknod(c("/tmp/b").as_ptr(), 0o644, 0), SUCCESS);
+            assert_eq!(
+                rustfs_rename(c("/tmp/b").as_ptr(), c("/tmp/a").as_ptr()),
+                -libc::EPERM
+            );
+            assert_eq!(rustfs_chmod(c("/tmp/a").as_ptr(), 0o777), -libc::EPERM);
+            assert_eq!(
+                rustfs_chown(c("/tmp/a").as_ptr(), 1001, u32::MAX),
+                -libc::EPERM
+            );
+            //只能显式设置自己的文件的时间
+            let times = [libc::timespec {
+                tv_sec: 1,
+                tv_nsec: 0,
+            }; 2];
+            assert_eq!(
+                unsafe { rustfs_utimens(c("/tmp/a").as_ptr(), &times) },
+                -libc::EPERM
+            );
+            assert_eq!(
+                unsafe { rustfs_utimens(c("/tmp/a").as_ptr(), std::ptr::null()) },
+                SUCCESS
+            );
+            //普通用户写文件时清掉setuid
+            assert_eq!(open("/priv/f", libc::O_RDONLY).err(), Some(-libc::EACCES));
+            let mut fi = open("/tmp/a", libc::O_WRONLY).unwrap();
+            assert_eq!(
+                unsafe { rustfs_write(c("/tmp/a").as_ptr(), c("x").as_ptr(), 1, 0, &mut fi) },
+                1
+            );
+            assert_eq!(
+                unsafe { rustfs_release(c("/tmp/a").as_ptr(), &mut fi) },
+                SUCCESS
+            );
+            assert_eq!(stat("/tmp/a").unwrap().st_mode, libc::S_IFREG | 0o777);
+        });
+        assert_eq!(rustfs_chmod(c("/tmp/a").as_ptr(), 0o644), SUCCESS);
+        assert_eq!(
+            as_caller(&bob, || open("/tmp/a", libc::O_RDWR).err()),
+            Some(-libc::EACCES)
+        );
+        assert_eq!(
+            as_caller(&bob, || open("/tmp", libc::O_WRONLY).err()),
+            Some(-libc::EISDIR)
+        );
+
+        //setgid目录
+        assert_eq!(rustfs_mkdir(c("/shared").as_ptr(), 0o777), SUCCESS);
+        assert_eq!(rustfs_chown(c("/shared").as_ptr(), u32::MAX, 2000), SUCCESS);
+        assert_eq!(rustfs_chmod(c("/shared").as_ptr(), 0o2777), SUCCESS);
+        as_caller(&alice, || {
+            assert_eq!(rustfs_mkdir(c("/shared/d").as_ptr(), 0o755), SUCCESS);
+            let attr = stat("/shared/d").unwrap();
+            assert_eq!((attr.st_gid, attr.st_mode), (2000, libc::S_IFDIR | 0o2755));
+            assert_eq!(rustfs_mknod(c("/shared/f").as_ptr(), 0o2755, 0), SUCCESS);
+            let attr = stat("/shared/f").unwrap();
+            assert_eq!((attr.st_gid, attr.st_mode), (2000, libc::S_IFREG | 0o755));
+            //不能把组改为自己不在的组
+            assert_eq!(
+                rustfs_chown(c("/shared/f").as_ptr(), u32::MAX, 2001),
+                -libc::EPERM
+            );
+            assert_eq!(
+                rustfs_chown(c("/shared/f").as_ptr(), u32::MAX, 1000),
+                SUCCESS
+            );
+        });
+
+        //root不能执行没有x位的文件
+        assert_eq!(rustfs_access(c("/priv/f").as_ptr(), R_OK | W_OK), SUCCESS);
+        assert_eq!(rustfs_access(c("/priv/f").as_ptr(), X_OK), -libc::EACCES);
+    }
+}
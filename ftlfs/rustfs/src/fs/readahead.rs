@@ -0,0 +1,125 @@
+//! 按inode检测顺序读并预读之后的数据块，做法与Linux页缓存的预读类似：
+//!
+//! - 紧接着上次读到的位置往后读时认为是顺序读，否则重置该inode的预读状态，
+//!   从文件开头读时直接开始预读
+//! - 第一次预读READAHEAD_MIN块，之后每次翻倍，不超过--readahead
+//! - 读到已预读部分的后一半时发起下一次预读，本次要读的块中还没读入的部分一起预读，
+//!   由ParallelBufferPoolManager::prefetch合并成一次设备读
+//!
+//! 状态只保存在内存中，最多记录MAX_INODES个inode，超过后清空重新开始
+use crate::fs::types::InodeId;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const READAHEAD_MIN: usize = 4;
+const MAX_INODES: usize = 1024;
+///--readahead的默认值
+pub const DEFAULT_WINDOW: usize = 32;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct State {
+    ///顺序读时下一次读的第一个块
+    next: usize,
+    ///当前的预读窗口，为0表示还没有预读过
+    window: usize,
+    ///已经预读到的块（不含）
+    ahead: usize,
+}
+
+///卷上各inode的预读状态
+pub struct Readahead {
+    states: Mutex<BTreeMap<u32, State>>,
+    ///最多预读的页数，为0时关闭预读
+    max_window: AtomicUsize,
+}
+
+impl Default for Readahead {
+    fn default() -> Self {
+        Readahead {
+            states: Mutex::new(BTreeMap::new()),
+            max_window: AtomicUsize::new(DEFAULT_WINDOW),
+        }
+    }
+}
+
+impl Readahead {
+    pub fn set_max_window(&self, max_window: usize) {
+        self.max_window.store(max_window, Ordering::Relaxed);
+    }
+
+    ///inode要读[first, last]块时调用，file_blocks是文件的块数。
+    ///需要预读时返回要从设备读入的块，可能包括本次要读的块
+    pub fn on_read(
+        &self,
+        inode_id: InodeId,
+        first: usize,
+        last: usize,
+        file_blocks: usize,
+    ) -> Option<Range<usize>> {
+        let max = self.max_window.load(Ordering::Relaxed);
+        if max == 0 {
+            return None;
+        }
+        let mut states = self.states.lock();
+        if states.len() >= MAX_INODES && !states.contains_key(&inode_id.0) {
+            states.clear();
+        }
+        let state = states.entry(inode_id.0).or_default();
+        if first != state.next {
+            *state = State::default();
+            if first != 0 {
+                state.next = last + 1;
+                return None;
+            }
+        }
+        state.next = last + 1;
+        if state.window > 0 && state.ahead > last + state.window / 2 {
+            return None;
+        }
+        state.window = (state.window * 2).clamp(READAHEAD_MIN.min(max), max);
+        let start = state.ahead.max(first);
+        let end = (last + 1 + state.window).min(file_blocks).max(start);
+        state.ahead = end;
+        (start < end).then_some(start..end)
+    }
+
+    ///inode被释放，丢弃它的预读状态
+    pub fn forget(&self, inode_id: InodeId) {
+        self.states.lock().remove(&inode_id.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_readahead_window() {
+        let readahead = Readahead::default();
+        let on_read =
+            |inode, first, last, file_blocks| readahead.on_read(inode, first, last, file_blocks);
+        let inode = InodeId(100);
+        assert_eq!(on_read(inode, 0, 0, 100), Some(0..5));
+        assert_eq!(on_read(inode, 1, 1, 100), None);
+        assert_eq!(on_read(inode, 2, 2, 100), None);
+        //读到预读部分的后一半，窗口翻倍
+        assert_eq!(on_read(inode, 3, 3, 100), Some(5..12));
+        assert_eq!(on_read(inode, 4, 7, 100), None);
+        assert_eq!(on_read(inode, 8, 8, 100), Some(12..25));
+        //窗口不超过--readahead，也不超过文件末尾
+        assert_eq!(on_read(inode, 9, 20, 100), Some(25..53));
+        assert_eq!(on_read(inode, 21, 40, 100), Some(53..73));
+        assert_eq!(on_read(inode, 41, 90, 100), Some(73..100));
+        assert_eq!(on_read(inode, 91, 99, 100), None);
+
+        //随机读不预读，之后接着往后读时重新从最小窗口开始
+        assert_eq!(on_read(inode, 50, 50, 100), None);
+        assert_eq!(on_read(inode, 51, 51, 100), Some(51..56));
+        //回到文件开头重新开始
+        assert_eq!(on_read(inode, 0, 1, 100), Some(0..6));
+        readahead.forget(inode);
+        assert_eq!(on_read(inode, 2, 2, 100), None);
+    }
+}
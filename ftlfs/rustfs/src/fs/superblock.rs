@@ -0,0 +1,364 @@
+use crate::buffer::buffer_pool_manager::AutoUnpin;
+use crate::buffer::buffer_pool_manager::Bpm;
+use crate::buffer::page::LABEL_LEN;
+use crate::buffer::replacer::PageId;
+use crate::fs::alloc::load_groups;
+use crate::fs::custom::{MAX_FILE_NAME, PAGE_SIZE};
+use crate::fs::def::{FEATURE_ALL, FS_MIN_VERSION, FS_VERSION, MAGIC_NUM};
+use crate::fs::journal;
+use crate::fs::layout::{Layout, LayoutOptions, BITS_PER_PAGE};
+use crate::fs::types::{bitmap_count, bitmap_set, FileType, InodeId};
+use crate::fs::volume::Volume;
+use crate::{fetch_page_read, fetch_page_write, new_page};
+use libc::{getgid, getuid};
+use log::{debug, info};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+
+///设备上是否已经有文件系统
+pub fn is_formatted(bpm: &Bpm) -> bool {
+    fetch_page_read!(super_page: super_page, bpm, 0, au);
+    super_page.magic_num() == MAGIC_NUM
+}
+
+///按layout格式化设备：清空两个位图，创建根目录，初始化日志并写入超级块，返回格式化后的卷。
+///卷不记日志，要记日志需要重新挂载
+pub fn format(bpm: Arc<Bpm>, layout: Layout, features: u32) -> Volume {
+    let volume = Volume::new(bpm, layout, features);
+    let vol = &volume;
+    let bitmap_pages = (layout.inode_map_start..layout.inode_map_start + layout.inode_map_pages)
+        .chain(layout.data_map_start..layout.data_map_start + layout.data_map_pages);
+    for page_id in bitmap_pages {
+        new_page!(bitmap_page: bytes, vol, page_id, au);
+        bitmap_page.fill(0);
+    }
+    load_groups(vol);
+    bitmap_set(vol, layout.inode_map_start, 0, true);
+    {
+        let (page_id, offset) = InodeId(0).seek(vol);
+        fetch_page_write!(inode_page: inode_page, vol, page_id, au);
+        let (uid, gid) = unsafe { (getuid(), getgid()) };
+        inode_page.inodes[offset].init(vol, InodeId(0), FileType::DIR, 0o755, uid, gid);
+    }
+    {
+        new_page!(super_page: bytes, vol, 0, au);
+        super_page.fill(0);
+    }
+    {
+        fetch_page_write!(super_page: super_page, vol, 0, au);
+        super_page.set_magic_num(MAGIC_NUM);
+        super_page.set_version(FS_VERSION);
+        super_page.set_features(features);
+        super_page.set_layout(&layout);
+        super_page.set_block_size(PAGE_SIZE as u32);
+        //根目录占用了0号inode
+        super_page.set_free_inodes(layout.inode_num as u32 - 1);
+        super_page.set_free_blocks(layout.data_num as u32);
+        super_page.set_uuid(new_uuid());
+    }
+    journal::format_journal(vol);
+    info!("format {:?} features {:#x}", layout, features);
+    volume
+}
+
+///按默认布局格式化bpm的设备并交还bpm，用于每次挂载时都是空的内存磁盘
+pub fn format_default(bpm: Bpm) -> Result<Bpm, String> {
+    let layout = Layout::new(bpm.page_num(), LayoutOptions::default())?;
+    let vol = format(Arc::new(bpm), layout, 0);
+    //停止flusher时会把所有脏页写回设备
+    vol.stop_flusher();
+    Ok(Arc::into_inner(vol.bpm).expect("bpm is still shared after format"))
+}
+
+///读出超级块并检查版本和布局，按其中的布局与特性创建卷，重放日志并统计各块组的空闲数
+pub fn mount(bpm: Arc<Bpm>) -> Result<Volume, String> {
+    open(bpm, false)
+}
+
+///同mount，但不重放日志，也不改写旧版本的超级块，不会写设备。用于fsck -n，
+///可以用journal::pending查看没有重放的事务数。版本4以前的磁盘没有记录空闲数，会被报告为不一致
+pub fn mount_read_only(bpm: Arc<Bpm>) -> Result<Volume, String> {
+    open(bpm, true)
+}
+
+fn open(bpm: Arc<Bpm>, read_only: bool) -> Result<Volume, String> {
+    let super_page = {
+        let bpm = &*bpm;
+        fetch_page_read!(super_page: super_page, bpm, 0, au);
+        *super_page
+    };
+    if super_page.magic_num() != MAGIC_NUM {
+        return Err("no rustfs file system found".to_string());
+    }
+    let version = super_page.version();
+    if !(FS_MIN_VERSION..=FS_VERSION).contains(&version) {
+        return Err(format!(
+            "unsupported file system version {version}, expect {FS_MIN_VERSION} to {FS_VERSION}"
+        ));
+    }
+    if version >= 4 && super_page.block_size() as usize != PAGE_SIZE {
+        return Err(format!(
+            "unsupported block size {}, expect {PAGE_SIZE}",
+            super_page.block_size()
+        ));
+    }
+    let features = super_page.features();
+    if features & !FEATURE_ALL != 0 {
+        return Err(format!(
+            "unsupported features {:#x}",
+            features & !FEATURE_ALL
+        ));
+    }
+    //版本2没有在超级块中记录布局，使用固定布局
+    let mut layout = if version >= 3 {
+        super_page.layout()
+    } else {
+        Layout::legacy(bpm.page_num())
+    };
+    //版本5以前没有日志区
+    if version < 5 {
+        layout.journal_start = layout.data_start;
+        layout.journal_pages = 0;
+    }
+    //没有记录块组大小时一个数据位图页管理一个块组
+    if layout.group_blocks == 0 {
+        layout.group_blocks = BITS_PER_PAGE;
+        layout.group_inodes = layout.default_group_inodes();
+    }
+    layout.check(bpm.page_num())?;
+    let vol = Volume::new(bpm, layout, features);
+    //版本4以前没有空闲计数，从位图中数出来写进超级块的空闲区域，旧版本不会读这些字段
+    if version < 4 && !read_only {
+        let free_inodes =
+            layout.inode_num - bitmap_count(&vol, layout.inode_map_start, layout.inode_num);
+        let free_blocks =
+            layout.data_num - bitmap_count(&vol, layout.data_map_start, layout.data_num);
+        let vol = &vol;
+        fetch_page_write!(super_page: super_page, vol, 0, au);
+        super_page.set_block_size(PAGE_SIZE as u32);
+        super_page.set_free_inodes(free_inodes as u32);
+        super_page.set_free_blocks(free_blocks as u32);
+    }
+    if read_only {
+        //只检查日志超级块
+        journal::pending(&vol)?;
+    } else {
+        journal::recover(&vol)?;
+    }
+    load_groups(&vol);
+    Ok(vol)
+}
+
+///挂载次数加一，fsck等只读取超级块的工具不调用
+pub fn record_mount(vol: &Volume) {
+    fetch_page_write!(super_page: super_page, vol, 0, au);
+    super_page.set_mount_count(super_page.mount_count() + 1);
+}
+
+///调整超级块中的空闲inode数和空闲块数
+pub fn add_free_counts(vol: &Volume, inodes: i32, blocks: i32) {
+    fetch_page_write!(super_page: super_page, vol, 0, au);
+    super_page.set_free_inodes((super_page.free_inodes() as i64 + inodes as i64).max(0) as u32);
+    super_page.set_free_blocks((super_page.free_blocks() as i64 + blocks as i64).max(0) as u32);
+}
+
+///超级块中记录的(空闲inode数, 空闲块数)
+pub fn free_counts(vol: &Volume) -> (u32, u32) {
+    fetch_page_read!(super_page: super_page, vol, 0, au);
+    (super_page.free_inodes(), super_page.free_blocks())
+}
+
+pub fn set_free_counts(vol: &Volume, free_inodes: u32, free_blocks: u32) {
+    fetch_page_write!(super_page: super_page, vol, 0, au);
+    super_page.set_free_inodes(free_inodes);
+    super_page.set_free_blocks(free_blocks);
+}
+
+pub fn set_label(vol: &Volume, label: &str) -> Result<(), String> {
+    if label.len() > LABEL_LEN {
+        return Err(format!("label {label:?} is longer than {LABEL_LEN} bytes"));
+    }
+    fetch_page_write!(super_page: super_page, vol, 0, au);
+    super_page.set_label(label);
+    Ok(())
+}
+
+///statfs的结果，只统计数据区，超级块、位图和inode表不计入总块数
+pub fn fill_statvfs(vol: &Volume, stat: &mut libc::statvfs) {
+    fetch_page_read!(super_page: super_page, vol, 0, au);
+    let layout = &vol.layout;
+    stat.f_bsize = PAGE_SIZE as _;
+    stat.f_frsize = PAGE_SIZE as _;
+    stat.f_blocks = layout.data_num as _;
+    stat.f_bfree = super_page.free_blocks() as _;
+    stat.f_bavail = super_page.free_blocks() as _;
+    stat.f_files = layout.inode_num as _;
+    stat.f_ffree = super_page.free_inodes() as _;
+    stat.f_favail = super_page.free_inodes() as _;
+    let uuid = super_page.uuid();
+    stat.f_fsid = u64::from_le_bytes(uuid[..8].try_into().unwrap()) as _;
+    stat.f_namemax = MAX_FILE_NAME as _;
+}
+
+///超级块的概要，供mkfs和fsck输出
+pub fn describe(vol: &Volume) -> String {
+    fetch_page_read!(super_page: super_page, vol, 0, au);
+    let layout = &vol.layout;
+    format!(
+        "UUID {}, label {:?}, version {}, {} of {} inodes free, {} of {} blocks free, mounted {} times",
+        uuid_string(&super_page.uuid()),
+        super_page.label(),
+        super_page.version(),
+        super_page.free_inodes(),
+        layout.inode_num,
+        super_page.free_blocks(),
+        layout.data_num,
+        super_page.mount_count()
+    )
+}
+
+pub fn uuid_string(uuid: &[u8; 16]) -> String {
+    let hex: String = uuid.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+///随机生成的版本4 UUID，RandomState的密钥来自操作系统的随机数
+fn new_uuid() -> [u8; 16] {
+    let state = RandomState::new();
+    let mut uuid = [0u8; 16];
+    for (i, chunk) in uuid.chunks_mut(8).enumerate() {
+        let mut hasher = state.build_hasher();
+        hasher.write_usize(i);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    uuid[6] = uuid[6] & 0x0f | 0x40;
+    uuid[8] = uuid[8] & 0x3f | 0x80;
+    uuid
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::{mem_bpm, ParallelBufferPoolManager};
+    use crate::device::MemDevice;
+    use crate::fs::custom::INODE_NUM;
+    use crate::fs::def::{FEATURE_DIR_INDEX, FEATURE_EXTENTS};
+    use crate::fs::filesystem::{Filesystem, FsOptions, RustFs};
+    use crate::fs::types::{alloc_block, alloc_inode, free_block, free_inode};
+
+    #[test]
+    fn test_format_default() {
+        let device = Box::new(MemDevice::new(1024));
+        let bpm = format_default(ParallelBufferPoolManager::new(1, 128, device)).unwrap();
+        assert!(is_formatted(&bpm));
+        let fs = RustFs::mount(bpm, FsOptions::default()).unwrap();
+        assert_eq!(
+            fs.vol.layout,
+            Layout::new(1024, LayoutOptions::default()).unwrap()
+        );
+        fs.destroy();
+        //放不下默认布局的设备
+        let device = Box::new(MemDevice::new(16));
+        assert!(format_default(ParallelBufferPoolManager::new(1, 128, device)).is_err());
+    }
+
+    #[test]
+    fn test_format_and_mount() {
+        let bpm = mem_bpm(1, 20);
+        assert!(!is_formatted(&bpm));
+        assert!(mount(bpm.clone()).is_err());
+        let options = LayoutOptions {
+            inode_num: Some(64),
+            ..Default::default()
+        };
+        let formatted = Layout::new(1024, options).unwrap();
+        let vol = format(bpm.clone(), formatted, FEATURE_DIR_INDEX);
+        assert!(is_formatted(&bpm));
+        assert_eq!(free_counts(&vol), (63, formatted.data_num as u32));
+        drop(vol);
+
+        let vol = &mount(bpm.clone()).unwrap();
+        assert_eq!(vol.layout, formatted);
+        assert!(vol.dir_index);
+        assert!(InodeId(0).load(vol).is_dir());
+        //根目录占用了0号inode，只剩63个
+        for i in 1..64 {
+            assert_eq!(alloc_inode(vol).unwrap().0, i);
+        }
+        assert!(alloc_inode(vol).is_none());
+        assert_eq!(free_counts(vol).0, 0);
+        //重复释放不会重复计数
+        free_inode(vol, InodeId(5));
+        free_inode(vol, InodeId(5));
+        assert_eq!(free_counts(vol).0, 1);
+
+        //版本2的磁盘按固定布局挂载
+        {
+            fetch_page_write!(super_page: super_page, vol, 0, au);
+            super_page.set_version(2);
+            super_page.set_free_inodes(0);
+            super_page.set_free_blocks(0);
+        }
+        let vol = &mount(bpm.clone()).unwrap();
+        assert_eq!(vol.layout, Layout::legacy(1024));
+        //旧版本的空闲计数从位图中数出来
+        assert_eq!(
+            free_counts(vol),
+            (INODE_NUM as u32 - 63, Layout::legacy(1024).data_num as u32)
+        );
+        {
+            fetch_page_write!(super_page: super_page, vol, 0, au);
+            super_page.set_version(FS_VERSION + 1);
+        }
+        assert!(mount(bpm.clone()).is_err());
+        //不认识的特性位
+        format(bpm.clone(), formatted, FEATURE_EXTENTS | 0x100);
+        assert!(mount(bpm.clone()).is_err());
+        format(bpm.clone(), formatted, FEATURE_EXTENTS);
+        let vol = mount(bpm.clone()).unwrap();
+        assert!(vol.extents);
+        assert!(!vol.dir_index);
+        let vol = format(bpm.clone(), formatted, 0);
+        assert!(!vol.extents);
+    }
+
+    #[test]
+    fn test_statfs() {
+        let layout = Layout::new(1024, LayoutOptions::default()).unwrap();
+        let vol = &format(mem_bpm(1, 20), layout, 0);
+        let uuid = {
+            fetch_page_read!(super_page: super_page, vol, 0, au);
+            super_page.uuid()
+        };
+        assert_eq!(uuid[6] >> 4, 4);
+        assert_eq!(uuid_string(&uuid).len(), 36);
+        assert!(set_label(vol, &"x".repeat(LABEL_LEN + 1)).is_err());
+        set_label(vol, "data").unwrap();
+        record_mount(vol);
+        record_mount(vol);
+        assert!(describe(vol).contains("label \"data\""));
+        assert!(describe(vol).contains("mounted 2 times"));
+
+        alloc_inode(vol).unwrap();
+        let block = alloc_block(vol, false).unwrap();
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        fill_statvfs(vol, &mut stat);
+        assert_eq!(stat.f_bsize, PAGE_SIZE as _);
+        assert_eq!(stat.f_blocks, layout.data_num as _);
+        assert_eq!(stat.f_bfree, layout.data_num as u64 - 1);
+        assert_eq!(stat.f_files, layout.inode_num as _);
+        assert_eq!(stat.f_ffree, layout.inode_num as u64 - 2);
+        assert_eq!(stat.f_namemax, MAX_FILE_NAME as _);
+        free_block(vol, block);
+        fill_statvfs(vol, &mut stat);
+        assert_eq!(stat.f_bfree, layout.data_num as _);
+    }
+}
@@ -1,15 +1,23 @@
 use crate::buffer::buffer_pool_manager::AutoUnpin;
-use crate::buffer::buffer_pool_manager::BPM;
 use crate::buffer::page::{Data, Page};
 use crate::buffer::replacer::PageId;
-use crate::ddriver::disk::read_page;
-use crate::fs::custom::DATA_MAP_PAGE_ID;
+use crate::fs::alloc::{alloc_bit, alloc_inode_bit, home_block, note_bit};
 use crate::fs::custom::{
-    DATA_START_PAGE_ID, DIR_ENTRY_PER_PAGE, INODE_START_PAGE_ID, MAX_FILE_NAME,
+    DIRECT_INDEX_NUM, DIR_ENTRY_PER_PAGE, INDEX_PER_PAGE, MAX_FILE_BLOCK_NUM, MAX_FILE_NAME,
+    PAGE_SIZE,
 };
-use crate::{fetch_page_read, fetch_page_write, fetch_page_write_lk};
-use libc::DIR;
+use crate::fs::def::{BLOCK_SIZE, SUCCESS};
+use crate::fs::extent::EXTENT_MAX_FILE_BLOCK_NUM;
+use crate::fs::journal;
+use crate::fs::layout::BITS_PER_PAGE;
+use crate::fs::superblock::add_free_counts;
+use crate::fs::utils::now;
+use crate::fs::volume::Volume;
+use crate::fs::xattr;
+use crate::{fetch_page_read, fetch_page_write, fetch_page_write_lk, new_page};
+use libc::{c_int, timespec, DIR};
 use log::{debug, info, trace};
+use std::collections::BTreeSet;
 
 #[repr(u32)]
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -19,36 +27,67 @@ pub enum FileType {
     SYMLINK = 2,
 }
 
+impl FileType {
+    ///stat中st_mode的文件类型位
+    pub fn st_mode(self) -> libc::mode_t {
+        match self {
+            FileType::REG => libc::S_IFREG,
+            FileType::DIR => libc::S_IFDIR,
+            FileType::SYMLINK => libc::S_IFLNK,
+        }
+    }
+}
+
 ///定长256字节
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct DEntry {
-    pub name: [i8; MAX_FILE_NAME],
+    ///UTF-8编码的名字，不足MAX_FILE_NAME字节时后面补0
+    pub name: [u8; MAX_FILE_NAME],
     pub file_type: FileType,
     pub inode_id: InodeId,
     pub is_valid: bool,
-    blank: [u8; 119],
+    ///名字的字节数，为0时是旧版本写入的以0结尾的名字
+    pub name_len: u8,
+    blank: [u8; 118],
+}
+
+///目录项的名字超过MAX_FILE_NAME字节时返回-ENAMETOOLONG
+pub fn check_name(name: &str) -> c_int {
+    if name.len() > MAX_FILE_NAME {
+        -libc::ENAMETOOLONG
+    } else {
+        SUCCESS
+    }
 }
 
 impl DEntry {
+    pub fn name_bytes(&self) -> &[u8] {
+        let len = match self.name_len {
+            0 => self
+                .name
+                .iter()
+                .position(|&b| b == 0)
+                .unwrap_or(MAX_FILE_NAME),
+            len => (len as usize).min(MAX_FILE_NAME),
+        };
+        &self.name[..len]
+    }
+
+    ///旧版本按字符截断写入的非ASCII名字不是合法的UTF-8，无法解码的部分替换为U+FFFD
     pub fn name(&self) -> String {
-        let mut name = String::new();
-        for i in 0..MAX_FILE_NAME {
-            if self.name[i] == 0 {
-                break;
-            }
-            name.push(self.name[i] as u8 as char);
-        }
-        name
+        String::from_utf8_lossy(self.name_bytes()).into_owned()
     }
 
     pub fn init(&mut self, name: &str, file_type: FileType, inode_id: InodeId) {
+        assert_eq!(check_name(name), SUCCESS, "name too long: {name}");
         self.is_valid = true;
         self.inode_id = inode_id;
         self.file_type = file_type;
-        for (i, c) in name.chars().enumerate() {
-            self.name[i] = c as i8;
-        }
+        //目录项可能被复用，先清掉旧名字
+        self.name = [0; MAX_FILE_NAME];
+        self.name[..name.len()].copy_from_slice(name.as_bytes());
+        self.name_len = name.len() as u8;
     }
 
     pub fn is_dir(&self) -> bool {
@@ -65,13 +104,30 @@ impl DEntry {
 pub struct InodeId(pub u32);
 
 impl InodeId {
-    pub fn seek(&self) -> (usize, usize) {
-        let page_id = self.0 as usize / 32 + INODE_START_PAGE_ID;
-        let offset = self.0 % 32;
-        (page_id, offset as usize)
+    pub fn seek(&self, vol: &Volume) -> (usize, usize) {
+        vol.layout.inode_pos(self.0)
+    }
+
+    ///读出inode的一份拷贝
+    pub fn load(&self, vol: &Volume) -> Inode {
+        let (page_id, offset) = self.seek(vol);
+        fetch_page_read!(inode_page: inode_page, vol, page_id, au);
+        inode_page.inodes[offset]
     }
 }
 
+///不超过这个长度的符号链接目标直接存放在inode的块号字段中，不占用数据块
+pub const INLINE_SYMLINK_LEN: usize = 56;
+
+///写一个块最多修改的元数据页数：数据位图，新的索引块和它们的位图，
+///或extent树从叶子到树根逐层分裂时修改和新分配的节点以及它们的位图
+const WRITE_PAGES: usize = 20;
+
+///截断和释放时一步最多释放的块数，以及这些块所在的数据位图页和二级间接索引块最多修改的页数，
+///加上其他索引块或extent节点，一步的修改放得进一个事务
+const FREE_STEP_BLOCKS: usize = INDEX_PER_PAGE;
+const FREE_STEP_PAGES: usize = 24;
+
 ///inode为128字节
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -81,47 +137,340 @@ pub struct Inode {
     pub direct_index: [i32; 12],
     pub indirect_index: i32,
     pub double_indirect_index: i32,
-    blank: [u8; 64],
+    pub size: u64,
+    ///只保存权限位（含suid/sgid/sticky），文件类型由file_type决定
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub nlink: u32,
+    pub atime: i64,
+    pub mtime: i64,
+    pub ctime: i64,
+    pub atime_nsec: u32,
+    pub mtime_nsec: u32,
+    pub ctime_nsec: u32,
+    ///扩展属性块号加一，0表示没有扩展属性块，这样旧版本中为0的空白字段不需要转换
+    xattr_block: u32,
 }
 
 impl Inode {
-    pub fn init_dir(&mut self, inode_id: InodeId) {
+    pub fn init(
+        &mut self,
+        vol: &Volume,
+        inode_id: InodeId,
+        file_type: FileType,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    ) {
         self.inode_id = inode_id;
-        self.file_type = FileType::DIR;
+        self.file_type = file_type;
         self.direct_index = [-1; 12];
         self.indirect_index = -1;
         self.double_indirect_index = -1;
+        self.size = 0;
+        self.mode = mode & 0o7777;
+        self.uid = uid;
+        self.gid = gid;
+        self.nlink = if file_type == FileType::DIR { 2 } else { 1 };
+        self.xattr_block = 0;
+        if vol.extents {
+            self.init_extents();
+        }
+        let now = now();
+        self.set_atime(now);
+        self.set_mtime(now);
+        self.set_ctime(now);
+    }
+
+    pub fn set_atime(&mut self, time: timespec) {
+        self.atime = time.tv_sec;
+        self.atime_nsec = time.tv_nsec as u32;
+    }
+
+    pub fn set_mtime(&mut self, time: timespec) {
+        self.mtime = time.tv_sec;
+        self.mtime_nsec = time.tv_nsec as u32;
+    }
+
+    pub fn set_ctime(&mut self, time: timespec) {
+        self.ctime = time.tv_sec;
+        self.ctime_nsec = time.tv_nsec as u32;
+    }
+
+    ///扩展属性块号，-1表示没有扩展属性
+    pub fn xattr_block(&self) -> i32 {
+        self.xattr_block as i32 - 1
+    }
+
+    pub fn set_xattr_block(&mut self, block: i32) {
+        self.xattr_block = (block + 1) as u32;
+    }
+
+    ///块号字段中存放的是符号链接目标而不是块号
+    pub fn has_inline_data(&self) -> bool {
+        self.file_type == FileType::SYMLINK && self.size as usize <= INLINE_SYMLINK_LEN
+    }
+
+    ///direct_index、indirect_index和double_indirect_index所在的56字节
+    fn inline_data(&self) -> &[u8; INLINE_SYMLINK_LEN] {
+        let offset = std::mem::offset_of!(Inode, direct_index);
+        unsafe { &*((self as *const Inode as *const u8).add(offset) as *const _) }
+    }
+
+    fn inline_data_mut(&mut self) -> &mut [u8; INLINE_SYMLINK_LEN] {
+        let offset = std::mem::offset_of!(Inode, direct_index);
+        unsafe { &mut *((self as *mut Inode as *mut u8).add(offset) as *mut _) }
+    }
+
+    ///写入刚创建的符号链接的目标，短目标存放在inode中，长目标写入数据块。数据块耗尽时返回false
+    pub fn set_symlink(&mut self, vol: &Volume, target: &[u8]) -> bool {
+        if target.len() <= INLINE_SYMLINK_LEN {
+            let data = self.inline_data_mut();
+            data.fill(0);
+            data[..target.len()].copy_from_slice(target);
+            self.size = target.len() as u64;
+            return true;
+        }
+        self.write(vol, 0, target) == target.len()
+    }
+
+    pub fn symlink_target(&self, vol: &Volume) -> Vec<u8> {
+        let size = self.size as usize;
+        if self.has_inline_data() {
+            return self.inline_data()[..size].to_vec();
+        }
+        let mut target = vec![0; size];
+        self.read(vol, 0, &mut target);
+        target
+    }
+
+    ///类似relatime，只有atime不晚于mtime或ctime时才需要在读取后更新atime
+    pub fn atime_outdated(&self) -> bool {
+        (self.atime, self.atime_nsec) <= (self.mtime, self.mtime_nsec)
+            || (self.atime, self.atime_nsec) <= (self.ctime, self.ctime_nsec)
+    }
+
+    pub fn st_mode(&self) -> libc::mode_t {
+        self.file_type.st_mode() | self.mode
+    }
+
+    pub fn fill_stat(&self, stat: &mut libc::stat) {
+        stat.st_ino = self.inode_id.0 as libc::ino_t;
+        stat.st_mode = self.st_mode();
+        stat.st_nlink = self.nlink as libc::nlink_t;
+        stat.st_uid = self.uid;
+        stat.st_gid = self.gid;
+        stat.st_size = self.size as libc::off_t;
+        stat.st_blksize = PAGE_SIZE as libc::blksize_t;
+        stat.st_blocks = (self.size as usize).div_ceil(PAGE_SIZE) as libc::blkcnt_t
+            * (PAGE_SIZE / BLOCK_SIZE) as libc::blkcnt_t;
+        stat.st_atime = self.atime;
+        stat.st_atime_nsec = self.atime_nsec as i64;
+        stat.st_mtime = self.mtime;
+        stat.st_mtime_nsec = self.mtime_nsec as i64;
+        stat.st_ctime = self.ctime;
+        stat.st_ctime_nsec = self.ctime_nsec as i64;
+    }
+
+    ///从offset开始读取文件内容到buf，返回读取的字节数，空洞部分读出0
+    pub fn read(&self, vol: &Volume, offset: usize, buf: &mut [u8]) -> usize {
+        let size = self.size as usize;
+        if offset >= size {
+            return 0;
+        }
+        let end = size.min(offset + buf.len());
+        let (first, last) = (offset / PAGE_SIZE, (end - 1) / PAGE_SIZE);
+        if let Some(blocks) =
+            vol.readahead.on_read(self.inode_id, first, last, size.div_ceil(PAGE_SIZE))
+        {
+            let page_ids: Vec<PageId> = blocks
+                .filter_map(|block| self.block_page_id(vol, block))
+                .map(PageId)
+                .collect();
+            vol.bpm.prefetch(&page_ids);
+        }
+        let mut pos = offset;
+        while pos < end {
+            let in_page = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - in_page).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match self.block_page_id(vol, pos / PAGE_SIZE) {
+                Some(page_id) => {
+                    fetch_page_read!(data_page: bytes, vol, page_id, au);
+                    dst.copy_from_slice(&data_page[in_page..in_page + len]);
+                }
+                None => dst.fill(0),
+            }
+            pos += len;
+        }
+        end - offset
     }
 
-    ///在该inode代表的目录中插入一个目录项
-    pub fn add_dir_entry(&mut self, name: &str, file_type: FileType, inode_id: InodeId) {
+    ///从offset开始把buf写入文件，按需分配数据块和索引块，返回写入的字节数。
+    ///数据块耗尽或当前事务剩下的日志空间不够再写一个块时提前返回，已写入的部分仍然有效，
+    ///后一种情况由调用者在新的事务中接着写
+    pub fn write(&mut self, vol: &Volume, offset: usize, buf: &[u8]) -> usize {
+        if offset as u64 > self.size {
+            self.zero_tail(vol);
+        }
+        let mut written = 0;
+        while written < buf.len() && journal::room(vol) >= WRITE_PAGES {
+            let pos = offset + written;
+            let in_page = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - in_page).min(buf.len() - written);
+            let Some(page_id) = self.block_page_id_or_alloc(vol, pos / PAGE_SIZE) else {
+                break;
+            };
+            journal::mark_data(vol, page_id);
+            fetch_page_write!(data_page: bytes, vol, page_id, au);
+            data_page[in_page..in_page + len].copy_from_slice(&buf[written..written + len]);
+            written += len;
+        }
+        if written > 0 {
+            self.size = self.size.max((offset + written) as u64);
+            let now = now();
+            self.set_mtime(now);
+            self.set_ctime(now);
+        }
+        written
+    }
+
+    ///把文件内的逻辑块号映射为数据页的page_id，未分配的块返回None
+    pub fn block_page_id(&self, vol: &Volume, block_id: usize) -> Option<usize> {
+        self.block_index(vol, block_id)
+            .map(|index| vol.layout.data_page_id(index))
+    }
+
+    ///逻辑块映射到的数据块号，未分配的块返回None
+    fn block_index(&self, vol: &Volume, block_id: usize) -> Option<i32> {
+        if self.uses_extents(vol) {
+            return self.extent_lookup(vol, block_id);
+        }
+        let index = if block_id < DIRECT_INDEX_NUM {
+            self.direct_index[block_id]
+        } else if block_id < DIRECT_INDEX_NUM + INDEX_PER_PAGE {
+            read_index(vol, self.indirect_index, block_id - DIRECT_INDEX_NUM)
+        } else if block_id < MAX_FILE_BLOCK_NUM {
+            let block_id = block_id - DIRECT_INDEX_NUM - INDEX_PER_PAGE;
+            let first = read_index(vol, self.double_indirect_index, block_id / INDEX_PER_PAGE);
+            read_index(vol, first, block_id % INDEX_PER_PAGE)
+        } else {
+            -1
+        };
+        (index != -1).then_some(index)
+    }
+
+    ///同block_page_id，但会为空洞分配新的数据块以及路径上缺失的索引块，
+    ///数据块耗尽或超过最大文件大小时返回None。新数据块尽量紧接在前一个逻辑块的数据块之后，
+    ///没有前一个块时从inode对应的数据组开始找
+    pub fn block_page_id_or_alloc(&mut self, vol: &Volume, block_id: usize) -> Option<usize> {
+        let goal = block_id
+            .checked_sub(1)
+            .and_then(|prev| self.block_index(vol, prev))
+            .map_or(home_block(vol, self.inode_id), |prev| prev + 1);
+        let goal = Some(goal);
+        let index = if self.uses_extents(vol) {
+            match self.extent_lookup(vol, block_id) {
+                Some(index) => index,
+                None => {
+                    let index = alloc_block_near(vol, goal, false)?;
+                    if !self.extent_insert(vol, block_id, index) {
+                        free_block(vol, index);
+                        return None;
+                    }
+                    index
+                }
+            }
+        } else if block_id < DIRECT_INDEX_NUM {
+            slot_or_alloc(vol, &mut self.direct_index[block_id], goal, false)?
+        } else if block_id < DIRECT_INDEX_NUM + INDEX_PER_PAGE {
+            let indirect = slot_or_alloc(vol, &mut self.indirect_index, None, true)?;
+            index_or_alloc(vol, indirect, block_id - DIRECT_INDEX_NUM, goal, false)?
+        } else if block_id < MAX_FILE_BLOCK_NUM {
+            let block_id = block_id - DIRECT_INDEX_NUM - INDEX_PER_PAGE;
+            let double = slot_or_alloc(vol, &mut self.double_indirect_index, None, true)?;
+            let first = index_or_alloc(vol, double, block_id / INDEX_PER_PAGE, None, true)?;
+            index_or_alloc(vol, first, block_id % INDEX_PER_PAGE, goal, false)?
+        } else {
+            return None;
+        };
+        Some(vol.layout.data_page_id(index))
+    }
+
+    ///存放目录项的数据页，目录的size总是目录页数乘以页大小，目录页从不留空洞。
+    ///索引目录只返回叶子页
+    fn dir_page_ids(&self, vol: &Volume) -> Vec<usize> {
+        if self.is_indexed_dir(vol) {
+            return self.dx_all_leaf_page_ids(vol);
+        }
+        (0..self.size as usize / PAGE_SIZE)
+            .filter_map(|i| self.block_page_id(vol, i))
+            .collect()
+    }
+
+    ///存放目录项的数据页的逻辑块号和页号，按逻辑块号排序
+    fn dir_blocks(&self, vol: &Volume) -> Vec<(usize, usize)> {
+        let blocks: Vec<usize> = if self.is_indexed_dir(vol) {
+            let mut leaves: Vec<usize> = self
+                .dx_leaf_blocks(vol)
+                .into_iter()
+                .map(|block| block as usize)
+                .collect();
+            leaves.sort_unstable();
+            leaves
+        } else {
+            (0..self.size as usize / PAGE_SIZE).collect()
+        };
+        blocks
+            .into_iter()
+            .filter_map(|block| Some((block, self.block_page_id(vol, block)?)))
+            .collect()
+    }
+
+    ///可能含有名为name的目录项的数据页
+    fn dir_page_ids_of(&self, vol: &Volume, name: &str) -> Vec<usize> {
+        if self.is_indexed_dir(vol) {
+            self.dx_leaf_page_ids(vol, name)
+        } else {
+            self.dir_page_ids(vol)
+        }
+    }
+
+    ///在该inode代表的目录中插入一个目录项，优先复用已失效的目录项，
+    ///所有目录页都满时追加一个新的目录页，无法再分配目录页时返回-ENOSPC
+    pub fn add_dir_entry(
+        &mut self,
+        vol: &Volume,
+        name: &str,
+        file_type: FileType,
+        inode_id: InodeId,
+    ) -> c_int {
         assert_eq!(self.file_type, FileType::DIR);
-        let bpm = unsafe { BPM.as_ref().unwrap() };
-        for i in 0..12 {
-            let index = self.direct_index[i];
-            if index == -1 {
-                fetch_page_write!(
-                    data_map_page: bitmap,
-                    bpm,
-                    DATA_MAP_PAGE_ID,
-                    auto_unpin_data_map_page
-                );
-                self.direct_index[i] = data_map_page.alloc().unwrap() as i32;
-            }
-            let page_id = self.direct_index[i] as usize + DATA_START_PAGE_ID;
-            fetch_page_write_lk!(dir_page: dir_page, bpm, page_id, au, lk);
+        if self.is_indexed_dir(vol) {
+            return self.dx_add_entry(vol, name, file_type, inode_id);
+        }
+        for page_id in self.dir_page_ids(vol) {
+            fetch_page_write_lk!(dir_page: dir_page, vol, page_id, au, lk);
             for j in 0..DIR_ENTRY_PER_PAGE {
                 if !dir_page.dir_entries[j].is_valid {
                     dir_page.dir_entries[j].init(name, file_type, inode_id);
                     drop(lk);
-                    return;
+                    return SUCCESS;
                 }
             }
             drop(lk);
         }
+        let Some(page_id) = self.block_page_id_or_alloc(vol, self.size as usize / PAGE_SIZE) else {
+            return -libc::ENOSPC;
+        };
+        self.size += PAGE_SIZE as u64;
+        fetch_page_write!(dir_page: dir_page, vol, page_id, au);
+        dir_page.dir_entries[0].init(name, file_type, inode_id);
+        SUCCESS
     }
     ///通过inode储存的索引到目录页中搜索指定名字的目录项，返回目录项的InodeId，可以通过type_bound限制搜索的类型
-    pub fn search_dir_by_name(&self, name: &str) -> Option<(InodeId, FileType)> {
+    pub fn search_dir_by_name(&self, vol: &Volume, name: &str) -> Option<(InodeId, FileType)> {
         trace!("---------------search dir by name-------------------------------");
         info!("name:{}", name);
         assert_eq!(
@@ -130,19 +479,12 @@ impl Inode {
             "search dir by name but not dir, inode_id:{}",
             self.inode_id.0
         );
-        let bpm = unsafe { BPM.as_ref().unwrap() };
-        //目录只使用直接索引，一个目录最多可支持12 * 16 = 192个目录项
-        for i in 0..12 {
-            let index = self.direct_index[i];
-            if index == -1 {
-                continue;
-            }
-            let page_id = self.direct_index[i] as usize + DATA_START_PAGE_ID;
-            fetch_page_read!(dir_page: dir_page, bpm, page_id, au);
+        for page_id in self.dir_page_ids_of(vol, name) {
+            fetch_page_read!(dir_page: dir_page, vol, page_id, au);
             info!("read dir page:{}", page_id);
             for j in 0..DIR_ENTRY_PER_PAGE {
                 let dir_entry = dir_page.dir_entries[j];
-                if dir_entry.is_valid && dir_entry.name() == name {
+                if dir_entry.is_valid && dir_entry.name_bytes() == name.as_bytes() {
                     trace!("dir_entry.name = {}", dir_entry.name());
                     let dir_entry = dir_page.dir_entries[j];
                     return Some((dir_entry.inode_id, dir_entry.file_type));
@@ -152,25 +494,254 @@ impl Inode {
         None
     }
 
-    pub fn all_dir_entry_name(&self) -> Vec<String> {
+    ///在目录页中找到名为name的有效目录项并交给f修改，目录项不存在时返回None
+    fn modify_dir_entry<T>(
+        &self,
+        vol: &Volume,
+        name: &str,
+        f: impl FnOnce(&mut DEntry) -> T,
+    ) -> Option<T> {
         assert_eq!(self.file_type, FileType::DIR);
-        let bpm = unsafe { BPM.as_ref().unwrap() };
-        let mut result = Vec::new();
-        for i in 0..12 {
-            let index = self.direct_index[i];
-            if index == -1 {
+        for page_id in self.dir_page_ids_of(vol, name) {
+            let found = {
+                fetch_page_read!(dir_page: dir_page, vol, page_id, au);
+                (0..DIR_ENTRY_PER_PAGE).find(|&j| {
+                    dir_page.dir_entries[j].is_valid
+                        && dir_page.dir_entries[j].name_bytes() == name.as_bytes()
+                })
+            };
+            if let Some(j) = found {
+                fetch_page_write!(dir_page: dir_page, vol, page_id, au);
+                return Some(f(&mut dir_page.dir_entries[j]));
+            }
+        }
+        None
+    }
+
+    ///使名为name的目录项失效，返回它指向的inode
+    pub fn remove_dir_entry(&mut self, vol: &Volume, name: &str) -> Option<(InodeId, FileType)> {
+        self.modify_dir_entry(vol, name, |dir_entry| {
+            dir_entry.is_valid = false;
+            (dir_entry.inode_id, dir_entry.file_type)
+        })
+    }
+
+    ///把名为name的目录项原地改为指向另一个inode，返回它原来指向的inode
+    pub fn replace_dir_entry(
+        &mut self,
+        vol: &Volume,
+        name: &str,
+        file_type: FileType,
+        inode_id: InodeId,
+    ) -> Option<(InodeId, FileType)> {
+        self.modify_dir_entry(vol, name, |dir_entry| {
+            let old = (dir_entry.inode_id, dir_entry.file_type);
+            dir_entry.inode_id = inode_id;
+            dir_entry.file_type = file_type;
+            old
+        })
+    }
+
+    pub fn is_empty_dir(&self, vol: &Volume) -> bool {
+        self.all_dir_entry_name(vol).is_empty()
+    }
+
+    ///释放inode占用的所有数据块、索引块和扩展属性块。一次最多释放一个扩展属性的长值，
+    ///或者从末尾往前的一步数据块，返回false表示还没有释放完，调用者在新的事务中再次调用
+    pub fn free_blocks(&mut self, vol: &Volume) -> bool {
+        if !xattr::free(vol, self) {
+            return false;
+        }
+        if self.has_inline_data() {
+            self.direct_index = [-1; 12];
+            self.indirect_index = -1;
+            self.double_indirect_index = -1;
+        } else {
+            let start = self.free_step_start(vol, 0);
+            self.free_blocks_from(vol, start);
+            if start > 0 {
+                self.size = (start * PAGE_SIZE) as u64;
+                return false;
+            }
+        }
+        self.size = 0;
+        true
+    }
+
+    ///inode占用的所有数据块、索引块和扩展属性块的page_id
+    pub fn page_ids(&self, vol: &Volume) -> Vec<usize> {
+        let mut blocks = xattr::blocks(vol, self.xattr_block());
+        if self.uses_extents(vol) {
+            blocks.extend(self.extent_blocks(vol));
+        } else if !self.has_inline_data() {
+            blocks.extend(self.direct_index);
+            collect_index_blocks(vol, self.indirect_index, 1, &mut blocks);
+            collect_index_blocks(vol, self.double_indirect_index, 2, &mut blocks);
+        }
+        blocks
+            .into_iter()
+            .filter(|&block| block != -1)
+            .map(|block| vol.layout.data_page_id(block))
+            .collect()
+    }
+
+    ///把文件大小改为size。缩短时释放末尾不再使用的数据块和索引块；
+    ///增长时清零原来最后一个块中大小之后的部分，新增部分是读出0的空洞。
+    ///缩短时一次最多释放一步，返回false表示大小停在了已释放部分的开头，
+    ///调用者在新的事务中再次调用
+    pub fn truncate(&mut self, vol: &Volume, size: u64) -> bool {
+        if size < self.size {
+            let size = size as usize;
+            let keep = size.div_ceil(PAGE_SIZE);
+            let start = self.free_step_start(vol, keep);
+            if start > keep {
+                self.free_blocks_from(vol, start);
+                self.size = (start * PAGE_SIZE) as u64;
+                return false;
+            }
+            self.free_blocks_from(vol, keep);
+        } else if size > self.size {
+            self.zero_tail(vol);
+        }
+        self.size = size;
+        let now = now();
+        self.set_mtime(now);
+        self.set_ctime(now);
+        true
+    }
+
+    ///文件变长之前清零最后一个块中大小之后的部分，那里可能是截断前留下的数据。
+    ///不在截断时清零：数据页先于元数据写回，截断的事务提交前掉电时，原大小以内的数据已经被清零
+    fn zero_tail(&mut self, vol: &Volume) {
+        let size = self.size as usize;
+        if size.is_multiple_of(PAGE_SIZE) {
+            return;
+        }
+        if let Some(page_id) = self.block_page_id(vol, size / PAGE_SIZE) {
+            journal::mark_data(vol, page_id);
+            fetch_page_write!(data_page: bytes, vol, page_id, au);
+            data_page[size % PAGE_SIZE..].fill(0);
+        }
+    }
+
+    ///从文件末尾往前释放到逻辑块keep时，这一步从哪个逻辑块开始释放：最多FREE_STEP_BLOCKS个块，
+    ///它们所在的数据位图页，加上指向它们的二级间接索引块和这些索引块的位图页，不超过FREE_STEP_PAGES。
+    ///空洞不计入
+    fn free_step_start(&self, vol: &Volume, keep: usize) -> usize {
+        let mut start = (self.size as usize).div_ceil(PAGE_SIZE);
+        let mut maps = BTreeSet::new();
+        let mut indexes = BTreeSet::new();
+        for _ in 0..FREE_STEP_BLOCKS {
+            let Some((block_id, index)) = self.prev_block(vol, start) else {
+                return keep;
+            };
+            if block_id < keep {
+                return keep;
+            }
+            maps.insert(index as usize / BITS_PER_PAGE);
+            if let Some(double) = block_id.checked_sub(DIRECT_INDEX_NUM + INDEX_PER_PAGE) {
+                indexes.insert(double / INDEX_PER_PAGE);
+            }
+            if maps.len() + 2 * indexes.len() > FREE_STEP_PAGES {
+                break;
+            }
+            start = block_id;
+        }
+        start
+    }
+
+    ///小于block_id的最后一个已分配的逻辑块和它的数据块号，未分配的索引块覆盖的空洞整段跳过
+    fn prev_block(&self, vol: &Volume, block_id: usize) -> Option<(usize, i32)> {
+        if self.uses_extents(vol) {
+            return self.extent_prev(vol, block_id);
+        }
+        let mut block_id = block_id.min(MAX_FILE_BLOCK_NUM);
+        while let Some(prev) = block_id.checked_sub(1) {
+            let index = if prev < DIRECT_INDEX_NUM {
+                self.direct_index[prev]
+            } else if prev < DIRECT_INDEX_NUM + INDEX_PER_PAGE {
+                if self.indirect_index == -1 {
+                    block_id = DIRECT_INDEX_NUM;
+                    continue;
+                }
+                read_index(vol, self.indirect_index, prev - DIRECT_INDEX_NUM)
+            } else {
+                let double = prev - DIRECT_INDEX_NUM - INDEX_PER_PAGE;
+                let first = read_index(vol, self.double_indirect_index, double / INDEX_PER_PAGE);
+                if first == -1 {
+                    block_id = prev - double % INDEX_PER_PAGE;
+                    continue;
+                }
+                read_index(vol, first, double % INDEX_PER_PAGE)
+            };
+            if index != -1 {
+                return Some((prev, index));
+            }
+            block_id = prev;
+        }
+        None
+    }
+
+    ///释放逻辑块号不小于keep的所有块，只剩空项的索引块也一并释放
+    fn free_blocks_from(&mut self, vol: &Volume, keep: usize) {
+        if self.uses_extents(vol) {
+            self.extent_truncate(vol, keep);
+            return;
+        }
+        for i in keep.min(DIRECT_INDEX_NUM)..DIRECT_INDEX_NUM {
+            free_block(vol, self.direct_index[i]);
+            self.direct_index[i] = -1;
+        }
+        let keep = keep.saturating_sub(DIRECT_INDEX_NUM);
+        self.indirect_index =
+            truncate_index_block(vol, self.indirect_index, keep.min(INDEX_PER_PAGE), 1);
+        let keep = keep.saturating_sub(INDEX_PER_PAGE);
+        self.double_indirect_index = truncate_index_block(vol, self.double_indirect_index, keep, 2);
+    }
+
+    pub fn all_dir_entry_name(&self, vol: &Volume) -> Vec<String> {
+        self.dir_entries(vol)
+            .into_iter()
+            .map(|(name, ..)| name)
+            .collect()
+    }
+
+    ///从槽位pos开始按槽位顺序把有效的目录项交给f，f返回false时停止。
+    ///槽位号是逻辑块号乘以每页目录项数再加上页内下标，其他目录项的加入和删除不会改变它；
+    ///索引目录的叶子分裂时搬到新叶子的目录项可能被读到两次，但不会漏掉
+    pub fn read_dir_from(&self, vol: &Volume, pos: u64, mut f: impl FnMut(u64, &DEntry) -> bool) {
+        assert_eq!(self.file_type, FileType::DIR);
+        let per_page = DIR_ENTRY_PER_PAGE as u64;
+        for (block, page_id) in self.dir_blocks(vol) {
+            let first = block as u64 * per_page;
+            if first + per_page <= pos {
                 continue;
             }
-            let page_id = self.direct_index[i] as usize + DATA_START_PAGE_ID;
-            fetch_page_read!(dir_page: dir_page, bpm, page_id, au);
+            //复制出来后再交给f，不在持有页锁时调用filler
+            let entries = {
+                fetch_page_read!(dir_page: dir_page, vol, page_id, au);
+                dir_page.dir_entries
+            };
+            for (j, entry) in entries.iter().enumerate() {
+                let slot = first + j as u64;
+                if slot >= pos && entry.is_valid && !f(slot, entry) {
+                    return;
+                }
+            }
+        }
+    }
+
+    ///目录中所有有效的目录项
+    pub fn dir_entries(&self, vol: &Volume) -> Vec<(String, InodeId, FileType)> {
+        assert_eq!(self.file_type, FileType::DIR);
+        let mut result = Vec::new();
+        for page_id in self.dir_page_ids(vol) {
+            fetch_page_read!(dir_page: dir_page, vol, page_id, au);
             for j in 0..DIR_ENTRY_PER_PAGE {
-                if dir_page.dir_entries[j].is_valid {
-                    debug!(
-                        "dir_entry.name = {},j = {}",
-                        dir_page.dir_entries[j].name(),
-                        j
-                    );
-                    result.push(dir_page.dir_entries[j].name());
+                let dir_entry = &dir_page.dir_entries[j];
+                if dir_entry.is_valid {
+                    debug!("dir_entry.name = {},j = {}", dir_entry.name(), j);
+                    result.push((dir_entry.name(), dir_entry.inode_id, dir_entry.file_type));
                 }
             }
         }
@@ -193,6 +764,244 @@ impl Inode {
     }
 }
 
+///文件的最大大小，由当前文件系统映射数据块的方式决定
+pub fn max_file_size(vol: &Volume) -> usize {
+    if vol.extents {
+        EXTENT_MAX_FILE_BLOCK_NUM * PAGE_SIZE
+    } else {
+        MAX_FILE_BLOCK_NUM * PAGE_SIZE
+    }
+}
+
+///读取索引块index_block中的第i项，索引块本身未分配时返回-1
+fn read_index(vol: &Volume, index_block: i32, i: usize) -> i32 {
+    if index_block == -1 {
+        return -1;
+    }
+    fetch_page_read!(index_page: index_page, vol, vol.layout.data_page_id(index_block), au);
+    index_page.index[i]
+}
+
+///slot为-1时在goal附近分配一个新块写入slot，返回slot中的块号
+fn slot_or_alloc(vol: &Volume, slot: &mut i32, goal: Option<i32>, is_index: bool) -> Option<i32> {
+    if *slot == -1 {
+        *slot = alloc_block_near(vol, goal, is_index)?;
+    }
+    Some(*slot)
+}
+
+///索引块index_block中的第i项为-1时分配一个新块写入该项，返回该项中的块号
+fn index_or_alloc(
+    vol: &Volume,
+    index_block: i32,
+    i: usize,
+    goal: Option<i32>,
+    is_index: bool,
+) -> Option<i32> {
+    let page_id = vol.layout.data_page_id(index_block);
+    fetch_page_write!(index_page: index_page, vol, page_id, au);
+    slot_or_alloc(vol, &mut index_page.index[i], goal, is_index)
+}
+
+///从数据位图中分配一个块并初始化，索引块填充-1，数据块清零。磁盘空间不足时返回None
+pub fn alloc_block(vol: &Volume, is_index: bool) -> Option<i32> {
+    alloc_block_near(vol, None, is_index)
+}
+
+///同alloc_block，但优先分配goal，goal已被占用时从goal往后找，使文件的数据块尽量连续。
+///goal为None时从上次分配的位置之后接着找
+pub fn alloc_block_near(vol: &Volume, goal: Option<i32>, is_index: bool) -> Option<i32> {
+    let goal = goal.map(|goal| goal.max(0) as usize);
+    let block = alloc_bit(vol, vol.layout.data_map_start, goal)?;
+    add_free_counts(vol, 0, -1);
+    let page_id = vol.layout.data_page_id(block as i32);
+    //页可能仍留在缓存中，new_page不会清空它，所以这里显式初始化
+    if is_index {
+        new_page!(index_page: index_page, vol, page_id, au);
+        index_page.index.fill(-1);
+    } else {
+        new_page!(data_page: bytes, vol, page_id, au);
+        data_page.fill(0);
+    }
+    Some(block as i32)
+}
+
+///把块归还给数据位图，block为-1时什么也不做
+pub fn free_block(vol: &Volume, block: i32) {
+    if block == -1 {
+        return;
+    }
+    if bitmap_set(vol, vol.layout.data_map_start, block as u32, false) {
+        add_free_counts(vol, 0, 1);
+    }
+}
+
+///释放索引块及其下depth层的所有块，depth为1时索引块的每一项都是数据块
+pub(crate) fn free_index_block(vol: &Volume, index_block: i32, depth: usize) {
+    if index_block == -1 {
+        return;
+    }
+    let index = {
+        fetch_page_read!(index_page: index_page, vol, vol.layout.data_page_id(index_block), au);
+        index_page.index
+    };
+    for block in index {
+        if depth == 1 {
+            free_block(vol, block);
+        } else {
+            free_index_block(vol, block, depth - 1);
+        }
+    }
+    free_block(vol, index_block);
+}
+
+///把索引块本身以及它下面的所有块加入blocks
+pub(crate) fn collect_index_blocks(
+    vol: &Volume,
+    index_block: i32,
+    depth: usize,
+    blocks: &mut Vec<i32>,
+) {
+    if index_block == -1 {
+        return;
+    }
+    let index = {
+        fetch_page_read!(index_page: index_page, vol, vol.layout.data_page_id(index_block), au);
+        index_page.index
+    };
+    blocks.push(index_block);
+    for block in index {
+        if depth == 1 {
+            blocks.push(block);
+        } else {
+            collect_index_blocks(vol, block, depth - 1, blocks);
+        }
+    }
+}
+
+///保留索引块下的前keep个数据块并释放其余的块，返回截断后的索引块号，
+///keep为0时索引块本身也被释放，返回-1
+fn truncate_index_block(vol: &Volume, index_block: i32, keep: usize, depth: usize) -> i32 {
+    if index_block == -1 {
+        return -1;
+    }
+    if keep == 0 {
+        free_index_block(vol, index_block, depth);
+        return -1;
+    }
+    let per_entry = INDEX_PER_PAGE.pow(depth as u32 - 1);
+    let mut index = {
+        fetch_page_read!(index_page: index_page, vol, vol.layout.data_page_id(index_block), au);
+        index_page.index
+    };
+    for (i, entry) in index.iter_mut().enumerate() {
+        let entry_keep = keep.saturating_sub(i * per_entry).min(per_entry);
+        if depth == 1 {
+            if entry_keep == 0 {
+                free_block(vol, *entry);
+                *entry = -1;
+            }
+        } else {
+            *entry = truncate_index_block(vol, *entry, entry_keep, depth - 1);
+        }
+    }
+    fetch_page_write!(index_page: index_page, vol, vol.layout.data_page_id(index_block), au);
+    index_page.index = index;
+    index_block
+}
+
+///从inode位图中分配一个inode号，inode耗尽时返回None
+pub fn alloc_inode(vol: &Volume) -> Option<InodeId> {
+    let inode_id = alloc_bit(vol, vol.layout.inode_map_start, None)?;
+    add_free_counts(vol, -1, 0);
+    Some(InodeId(inode_id))
+}
+
+///同alloc_inode，但按父目录parent和文件类型选择inode组，见fs::alloc
+pub fn alloc_inode_near(vol: &Volume, parent: InodeId, file_type: FileType) -> Option<InodeId> {
+    let inode_id = alloc_inode_bit(vol, parent, file_type)?;
+    add_free_counts(vol, -1, 0);
+    Some(InodeId(inode_id))
+}
+
+///把inode号归还给inode位图
+pub fn free_inode(vol: &Volume, inode_id: InodeId) {
+    vol.readahead.forget(inode_id);
+    if bitmap_set(vol, vol.layout.inode_map_start, inode_id.0, false) {
+        add_free_counts(vol, 1, 0);
+    }
+}
+
+///在位图的[from, to)中分配最低的空闲位
+pub(crate) fn bitmap_alloc_range(
+    vol: &Volume,
+    start: usize,
+    from: usize,
+    to: usize,
+) -> Option<u32> {
+    for i in from / BITS_PER_PAGE..to.div_ceil(BITS_PER_PAGE) {
+        let first = from.saturating_sub(i * BITS_PER_PAGE) as u32;
+        //先在读锁下跳过没有空闲位的页，这些页不需要记入事务
+        let has_free = {
+            fetch_page_read!(bitmap_page: bitmap, vol, start + i, au);
+            bitmap_page.first_zero(first).is_some()
+        };
+        if !has_free {
+            continue;
+        }
+        fetch_page_write!(bitmap_page: bitmap, vol, start + i, au);
+        let Some(bit) = bitmap_page.first_zero(first) else {
+            continue;
+        };
+        let n = i * BITS_PER_PAGE + bit as usize;
+        if n >= to {
+            return None;
+        }
+        bitmap_page.set(bit);
+        return Some(n as u32);
+    }
+    None
+}
+
+///设置从start页开始的位图中的第n位，返回该位原来的值。
+///会更新块组的空闲数，但不会修改超级块中的空闲计数，分配和释放应使用alloc_*和free_*
+pub fn bitmap_set(vol: &Volume, start: usize, n: u32, value: bool) -> bool {
+    let n = n as usize;
+    let old = {
+        fetch_page_write!(bitmap_page: bitmap, vol, start + n / BITS_PER_PAGE, au);
+        let bit = (n % BITS_PER_PAGE) as u32;
+        let old = bitmap_page.test(bit);
+        if value {
+            bitmap_page.set(bit);
+        } else {
+            bitmap_page.clear(bit);
+        }
+        old
+    };
+    if old != value {
+        note_bit(vol, start, n, value);
+    }
+    old
+}
+
+///从start页开始的位图的前n位中已设置的位数
+pub fn bitmap_count(vol: &Volume, start: usize, n: usize) -> usize {
+    let mut count = 0;
+    for i in 0..n.div_ceil(BITS_PER_PAGE) {
+        fetch_page_read!(bitmap_page: bitmap, vol, start + i, au);
+        let bits = (n - i * BITS_PER_PAGE).min(BITS_PER_PAGE) as u32;
+        count += (0..bits).filter(|&bit| bitmap_page.test(bit)).count();
+    }
+    count
+}
+
+///读取从start页开始的位图中的第n位
+pub fn bitmap_test(vol: &Volume, start: usize, n: u32) -> bool {
+    let n = n as usize;
+    fetch_page_read!(bitmap_page: bitmap, vol, start + n / BITS_PER_PAGE, au);
+    bitmap_page.test((n % BITS_PER_PAGE) as u32)
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct BitMap {
@@ -219,15 +1028,20 @@ impl BitMap {
     }
 
     pub fn alloc(&mut self) -> Option<u32> {
-        for i in 0..4096 {
-            if self.data[i] != 0xff {
-                for j in 0..8 {
-                    if self.data[i] & (1 << j) == 0 {
-                        self.data[i] |= 1 << j;
-                        return Some(i as u32 * 8 + j);
-                    }
-                }
+        let bit = self.first_zero(0)?;
+        self.set(bit);
+        Some(bit)
+    }
+
+    ///不小于from的最低的空闲位
+    pub fn first_zero(&self, from: u32) -> Option<u32> {
+        let mut bit = from;
+        while (bit as usize) < self.data.len() * 8 {
+            let byte = self.data[bit as usize / 8] | ((1u8 << (bit % 8)) - 1);
+            if byte != 0xff {
+                return Some(bit / 8 * 8 + byte.trailing_ones());
             }
+            bit = (bit / 8 + 1) * 8;
         }
         None
     }
@@ -235,12 +1049,110 @@ impl BitMap {
 
 #[cfg(test)]
 mod test {
-    use crate::buffer::buffer_pool_manager::{ParallelBufferPoolManager, BPM};
-    use crate::fs::utils::start_flusher;
+    use super::*;
+    use crate::buffer::buffer_pool_manager::mem_bpm;
+    use crate::fs::layout::{Layout, LayoutOptions};
+    use crate::fs::superblock::format;
+
+    fn format_mem() -> Volume {
+        let layout = Layout::new(1024, LayoutOptions::default()).unwrap();
+        format(mem_bpm(1, 20), layout, 0)
+    }
 
     #[test]
     fn test_struct_size() {
         assert_eq!(std::mem::size_of::<super::Inode>(), 128);
         assert_eq!(std::mem::size_of::<super::DEntry>(), 256);
+        //内联的符号链接目标占用连续的三个块号字段
+        assert_eq!(
+            std::mem::offset_of!(Inode, double_indirect_index) + 4,
+            std::mem::offset_of!(Inode, direct_index) + INLINE_SYMLINK_LEN
+        );
+    }
+
+    #[test]
+    fn test_inode_stat() {
+        let vol = &format_mem();
+        let mut inode: Inode = unsafe { std::mem::zeroed() };
+        inode.init(vol, InodeId(3), FileType::DIR, 0o40755, 1000, 100);
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        inode.fill_stat(&mut stat);
+        assert_eq!(stat.st_ino, 3);
+        assert_eq!(stat.st_mode, libc::S_IFDIR | 0o755);
+        assert_eq!(stat.st_nlink, 2);
+        assert_eq!((stat.st_uid, stat.st_gid), (1000, 100));
+        assert_eq!(stat.st_mtime, stat.st_ctime);
+        assert!(inode.atime_outdated());
+        inode.set_atime(libc::timespec {
+            tv_sec: inode.mtime + 1,
+            tv_nsec: 0,
+        });
+        assert!(!inode.atime_outdated());
+    }
+
+    #[test]
+    fn test_file_read_write() {
+        let vol = &format_mem();
+        let mut inode: Inode = unsafe { std::mem::zeroed() };
+        inode.init(vol, InodeId(1), FileType::REG, 0o644, 0, 0);
+        let mut buf = [0u8; 64];
+        assert_eq!(inode.read(vol, 0, &mut buf), 0);
+
+        assert_eq!(inode.write(vol, 0, b"hello"), 5);
+        assert_eq!(inode.size, 5);
+        assert_eq!(inode.read(vol, 0, &mut buf), 5);
+        assert_eq!(&buf[..5], b"hello");
+
+        //跨越直接索引与一级间接索引的边界
+        let boundary = DIRECT_INDEX_NUM * PAGE_SIZE - 3;
+        let data: Vec<u8> = (0..PAGE_SIZE as u32).map(|i| i as u8).collect();
+        assert_eq!(inode.write(vol, boundary, &data), PAGE_SIZE);
+        assert_ne!(inode.indirect_index, -1);
+        let mut read_back = vec![0u8; PAGE_SIZE];
+        assert_eq!(inode.read(vol, boundary, &mut read_back), PAGE_SIZE);
+        assert_eq!(read_back, data);
+
+        //中间的空洞读出0
+        assert_eq!(inode.direct_index[5], -1);
+        assert_eq!(inode.read(vol, 5 * PAGE_SIZE, &mut buf), buf.len());
+        assert!(buf.iter().all(|b| *b == 0));
+
+        //二级间接索引范围内的稀疏写
+        let far = (DIRECT_INDEX_NUM + INDEX_PER_PAGE + 3) * PAGE_SIZE + 7;
+        assert_eq!(inode.write(vol, far, b"far"), 3);
+        assert_ne!(inode.double_indirect_index, -1);
+        assert_eq!(inode.size, far as u64 + 3);
+        assert_eq!(inode.read(vol, far - 2, &mut buf), 5);
+        assert_eq!(&buf[..5], b"\0\0far");
+
+        //缩短到间接索引范围内，二级间接索引块全部被释放
+        let double = inode.double_indirect_index;
+        inode.truncate(vol, boundary as u64 + 5);
+        assert_eq!(inode.double_indirect_index, -1);
+        assert_ne!(inode.indirect_index, -1);
+        assert_eq!(inode.read(vol, boundary, &mut buf), 5);
+        assert_eq!(&buf[..5], &data[..5]);
+        //再次增长时被截掉的部分读出0
+        inode.truncate(vol, boundary as u64 + 64);
+        assert_eq!(inode.read(vol, boundary, &mut buf), 64);
+        assert_eq!(&buf[..5], &data[..5]);
+        assert!(buf[5..].iter().all(|b| *b == 0));
+        //截断后在文件末尾之后写入，中间的部分同样读出0
+        inode.truncate(vol, boundary as u64 + 5);
+        assert_eq!(inode.write(vol, boundary + 100, b"x"), 1);
+        let mut tail = [0u8; 101];
+        assert_eq!(inode.read(vol, boundary, &mut tail), 101);
+        assert_eq!(&tail[..5], &data[..5]);
+        assert!(tail[5..100].iter().all(|b| *b == 0));
+        //缩短到直接索引范围内
+        inode.truncate(vol, 3);
+        assert_eq!(inode.indirect_index, -1);
+        assert_eq!(inode.direct_index[DIRECT_INDEX_NUM - 1], -1);
+        assert_ne!(inode.direct_index[0], -1);
+        inode.truncate(vol, 0);
+        assert_eq!(inode.direct_index[0], -1);
+        fetch_page_read!(data_map_page: bitmap, vol, vol.layout.data_map_start, au);
+        assert!(data_map_page.data.iter().all(|b| *b == 0));
+        assert!(!data_map_page.test(double as u32));
     }
 }
@@ -1,11 +1,8 @@
-use crate::buffer::buffer_pool_manager::AutoUnpin;
-use crate::buffer::buffer_pool_manager::BPM;
-use crate::buffer::flusher::FLUSHER;
 use crate::buffer::replacer::PageId;
-use crate::fetch_page_read;
-use crate::fs::custom::DATA_START_PAGE_ID;
-use crate::fs::types::{DEntry, FileType, InodeId};
-use log::{debug, error, trace, warn};
+use crate::fs::def::SUCCESS;
+use crate::fs::journal;
+use crate::fs::types::{check_name, InodeId};
+use crate::fs::volume::Volume;
 
 pub fn split_path(path: &str) -> (&str, &str) {
     let mut i = path.len() - 1;
@@ -15,25 +12,129 @@ pub fn split_path(path: &str) -> (&str, &str) {
     (&path[0..i + 1], &path[i + 1..])
 }
 
-pub fn start_flusher() {
-    std::thread::spawn(|| {
-        warn!("flusher tid:{}", unsafe { libc::gettid() });
-        let mut flusher = unsafe { &mut FLUSHER };
-        loop {
-            std::thread::sleep(std::time::Duration::from_millis(10));
-            flusher.copy_and_flush();
-        }
-    });
+///路径中有超过MAX_FILE_NAME字节的分量时返回-ENAMETOOLONG
+pub fn check_path(path: &str) -> libc::c_int {
+    path.split('/')
+        .map(check_name)
+        .find(|&ret| ret != SUCCESS)
+        .unwrap_or(SUCCESS)
+}
+
+pub fn now() -> libc::timespec {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut time) };
+    time
+}
+
+///把文件的数据和元数据写到持久存储上。记日志时已提交的事务都已落盘，
+///只需等待正在进行的事务提交；不记日志时写回文件的所有页以及位图和超级块
+pub fn sync_inode(vol: &Volume, inode_id: InodeId) {
+    if journal::sync(vol) {
+        return;
+    }
+    let bpm = &vol.bpm;
+    let inode = inode_id.load(vol);
+    for page_id in inode.page_ids(vol) {
+        bpm.flush_page(PageId(page_id));
+    }
+    bpm.flush_page(PageId(inode_id.seek(vol).0));
+    let layout = &vol.layout;
+    let bitmap_pages = (layout.inode_map_start..layout.inode_map_start + layout.inode_map_pages)
+        .chain(layout.data_map_start..layout.data_map_start + layout.data_map_pages);
+    for page_id in bitmap_pages.chain([0]) {
+        bpm.flush_page(PageId(page_id));
+    }
+    bpm.device().sync();
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::buffer::buffer_pool_manager::{mem_bpm, AutoUnpin};
+    use crate::buffer::flusher::FlusherThread;
+    use crate::buffer::page::InodePage;
+    use crate::fs::custom::PAGE_SIZE;
+    use crate::fs::dcache::DCache;
+    use crate::fs::layout::{Layout, LayoutOptions};
+    use crate::fs::superblock::format;
+    use crate::fs::types::FileType;
+    use crate::{fetch_page_write, new_page};
+    use log::debug;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_get_dir() {
         assert_eq!(split_path("/a/b/c"), ("/a/b/", "c"));
         assert_eq!(split_path("/a/b"), ("/a/", "b"));
         assert_eq!(split_path("/a"), ("/", "a"));
+        assert_eq!(check_path("/a/b"), SUCCESS);
+        assert_eq!(check_path(&format!("/{}/b", "a".repeat(128))), SUCCESS);
+        assert_eq!(
+            check_path(&format!("/{}/b", "a".repeat(129))),
+            -libc::ENAMETOOLONG
+        );
+    }
+
+    #[test]
+    fn test_sync_inode() {
+        let layout = Layout::new(1024, LayoutOptions::default()).unwrap();
+        let vol = &Arc::new(format(mem_bpm(1, 20), layout, 0));
+        //停止flusher后只有sync_inode会写磁盘
+        vol.stop_flusher();
+        let dir_tree = DCache::new(vol.clone(), 100);
+        let f = {
+            let root = dir_tree.search("/").unwrap();
+            dir_tree.insert(&root, "f", FileType::REG, 0o644);
+            dir_tree.search("/f").unwrap().inode_id
+        };
+        let data = vec![9u8; 2 * PAGE_SIZE];
+        {
+            let (page_id, offset) = f.seek(vol);
+            fetch_page_write!(inode_page: inode_page, vol, page_id, au);
+            inode_page.inodes[offset].write(vol, 0, &data);
+        }
+        let read = |page_id: usize| {
+            let mut page = [0; PAGE_SIZE];
+            vol.bpm.device().read_page(PageId(page_id), &mut page);
+            page
+        };
+        let inode = f.load(vol);
+        let pages = inode.page_ids(vol);
+        assert_eq!(pages.len(), 2);
+        assert_ne!(read(pages[0]), [9; PAGE_SIZE]);
+        sync_inode(vol, f);
+        assert_eq!(read(pages[0]), [9; PAGE_SIZE]);
+        assert_eq!(read(pages[1]), [9; PAGE_SIZE]);
+        let (page_id, offset) = f.seek(vol);
+        let on_disk: InodePage = unsafe { std::mem::transmute(read(page_id)) };
+        assert_eq!(on_disk.inodes[offset].size, data.len() as u64);
+    }
+
+    #[test]
+    fn test_dirty_limit() {
+        //flusher只在脏页超过上限时工作
+        let pool = mem_bpm(1, 20);
+        pool.writeback().set_dirty_limit(4);
+        let _flusher = FlusherThread::start(pool.clone(), 0);
+        let bpm = &*pool;
+        for i in 0..4 {
+            new_page!(page: bytes, bpm, i, au);
+            page[0] = 1;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(bpm.dirty_num(), 4);
+        {
+            new_page!(page: bytes, bpm, 4, au);
+            page[0] = 1;
+        }
+        let start = Instant::now();
+        while bpm.dirty_num() > 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        }
     }
 }
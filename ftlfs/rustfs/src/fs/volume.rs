@@ -0,0 +1,73 @@
+//! 卷：一个块设备上的文件系统在内存中的全部状态，包括缓存池、磁盘布局、特性、日志、
+//! 块组分配器、预读状态和打开的句柄。
+//!
+//! superblock::format和mount创建卷，RustFs持有它，各模块的函数通过参数vol访问，
+//! 没有进程级的全局状态，所以同一个进程中可以同时打开多个卷。
+//! fetch_page_write等宏传入卷时，修改页之前会通知卷的日志
+use crate::buffer::buffer_pool_manager::{Bpm, PageSource};
+use crate::buffer::flusher::FlusherThread;
+use crate::fs::alloc::Allocator;
+use crate::fs::def::{FEATURE_DIR_INDEX, FEATURE_EXTENTS};
+use crate::fs::handle::Handles;
+use crate::fs::journal::{self, Journal};
+use crate::fs::layout::Layout;
+use crate::fs::readahead::Readahead;
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+///卷创建时flusher写回脏页的间隔（毫秒），挂载时按挂载参数重新启动
+pub const FLUSH_INTERVAL: u32 = 10;
+
+pub struct Volume {
+    pub bpm: Arc<Bpm>,
+    pub layout: Layout,
+    ///目录使用htree索引
+    pub dir_index: bool,
+    ///新文件用extent树记录数据块
+    pub extents: bool,
+    pub journal: Journal,
+    pub alloc: Allocator,
+    pub readahead: Readahead,
+    pub handles: Handles,
+    flusher: Mutex<Option<FlusherThread>>,
+}
+
+impl Volume {
+    ///在bpm上按layout和特性创建卷并启动flusher，不读写设备
+    pub fn new(bpm: Arc<Bpm>, layout: Layout, features: u32) -> Self {
+        let flusher = FlusherThread::start(bpm.clone(), FLUSH_INTERVAL);
+        Volume {
+            bpm,
+            layout,
+            dir_index: features & FEATURE_DIR_INDEX != 0,
+            extents: features & FEATURE_EXTENTS != 0,
+            journal: Journal::default(),
+            alloc: Allocator::default(),
+            readahead: Readahead::default(),
+            handles: Handles::default(),
+            flusher: Mutex::new(Some(flusher)),
+        }
+    }
+
+    ///按新的间隔重新启动flusher，为0时只在脏页超过上限时写回
+    pub fn start_flusher(&self, interval: u32) {
+        let mut flusher = self.flusher.lock();
+        *flusher = None;
+        *flusher = Some(FlusherThread::start(self.bpm.clone(), interval));
+    }
+
+    ///停止flusher，停止前把能写回的脏页都写回。此后只有提交、检查点和fsync会写磁盘
+    pub fn stop_flusher(&self) {
+        *self.flusher.lock() = None;
+    }
+}
+
+impl PageSource for Volume {
+    fn bpm(&self) -> &Bpm {
+        &self.bpm
+    }
+
+    fn note_write(&self, page_id: usize) {
+        journal::note_write(self, page_id);
+    }
+}
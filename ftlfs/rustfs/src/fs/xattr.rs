@@ -0,0 +1,435 @@
+//! 扩展属性。
+//!
+//! 一个inode的所有扩展属性存放在一个扩展属性块中，块号记在inode里。块中依次紧密存放各个属性，
+//! 每个属性由12字节的属性头、名字和值组成，属性头依次是名字长度（u16）、保留（u16）、
+//! 值长度（u32）和值的索引块号（i32）。不超过XATTR_INLINE_MAX的短值紧跟在名字之后，
+//! 索引块号为-1；更长的值存放在单独的数据块中，由一个一级索引块指向。
+//! 最后一个属性被删除时扩展属性块也被释放。
+use crate::buffer::buffer_pool_manager::AutoUnpin;
+use crate::buffer::replacer::PageId;
+use crate::fs::custom::PAGE_SIZE;
+use crate::fs::def::SUCCESS;
+use crate::fs::journal;
+use crate::fs::types::{alloc_block, collect_index_blocks, free_block, free_index_block, Inode};
+use crate::fs::volume::Volume;
+use crate::{fetch_page_read, fetch_page_write};
+use libc::c_int;
+use log::{debug, warn};
+
+pub const XATTR_MAGIC: u32 = 0x5841_5452;
+
+///属性名的最大长度，与Linux的XATTR_NAME_MAX相同
+pub const XATTR_NAME_MAX: usize = 255;
+
+///属性值的最大长度，与Linux的XATTR_SIZE_MAX相同
+pub const XATTR_SIZE_MAX: usize = 65536;
+
+///不超过这个长度的值直接存放在扩展属性块中
+pub const XATTR_INLINE_MAX: usize = 512;
+
+const ENTRY_HEADER: usize = 12;
+
+///扩展属性块中可用于存放属性的字节数
+const XATTR_SPACE: usize = PAGE_SIZE - 8;
+
+struct Entry {
+    name: Vec<u8>,
+    len: usize,
+    ///值的索引块号，-1表示值存放在inline中
+    value_block: i32,
+    inline: Vec<u8>,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        ENTRY_HEADER + self.name.len() + self.inline.len()
+    }
+}
+
+///读出扩展属性块中的所有属性，block为-1时返回空
+fn load(vol: &Volume, block: i32) -> Vec<Entry> {
+    if block == -1 {
+        return Vec::new();
+    }
+    fetch_page_read!(xattr_page: xattr_page, vol, vol.layout.data_page_id(block), au);
+    if xattr_page.magic != XATTR_MAGIC {
+        warn!("bad xattr block {}", block);
+        return Vec::new();
+    }
+    let data = &xattr_page.entries;
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    for _ in 0..xattr_page.count {
+        if pos + ENTRY_HEADER > data.len() {
+            break;
+        }
+        let name_len = u16::from_le_bytes([data[pos], data[pos + 1]]) as usize;
+        let len = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        let value_block = i32::from_le_bytes(data[pos + 8..pos + 12].try_into().unwrap());
+        let inline_len = if value_block == -1 { len } else { 0 };
+        pos += ENTRY_HEADER;
+        if pos + name_len + inline_len > data.len() {
+            warn!("truncated xattr block {}", block);
+            break;
+        }
+        let name = data[pos..pos + name_len].to_vec();
+        pos += name_len;
+        let inline = data[pos..pos + inline_len].to_vec();
+        pos += inline_len;
+        entries.push(Entry {
+            name,
+            len,
+            value_block,
+            inline,
+        });
+    }
+    entries
+}
+
+///把entries写入扩展属性块，调用者保证放得下
+fn store(vol: &Volume, block: i32, entries: &[Entry]) {
+    fetch_page_write!(xattr_page: xattr_page, vol, vol.layout.data_page_id(block), au);
+    xattr_page.magic = XATTR_MAGIC;
+    xattr_page.count = entries.len() as u32;
+    let data = &mut xattr_page.entries;
+    let mut pos = 0;
+    for entry in entries {
+        data[pos..pos + 2].copy_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        data[pos + 2..pos + 4].fill(0);
+        data[pos + 4..pos + 8].copy_from_slice(&(entry.len as u32).to_le_bytes());
+        data[pos + 8..pos + 12].copy_from_slice(&entry.value_block.to_le_bytes());
+        pos += ENTRY_HEADER;
+        data[pos..pos + entry.name.len()].copy_from_slice(&entry.name);
+        pos += entry.name.len();
+        data[pos..pos + entry.inline.len()].copy_from_slice(&entry.inline);
+        pos += entry.inline.len();
+    }
+    data[pos..].fill(0);
+}
+
+///把长值写入新分配的数据块，返回指向它们的索引块号。磁盘空间不足时释放已分配的块并返回None。
+///值所在的块像文件数据一样在提交前直接写回，不占用事务的元数据页
+fn write_value(vol: &Volume, value: &[u8]) -> Option<i32> {
+    let index_block = alloc_block(vol, true)?;
+    for (i, chunk) in value.chunks(PAGE_SIZE).enumerate() {
+        let Some(block) = alloc_block(vol, false) else {
+            free_index_block(vol, index_block, 1);
+            return None;
+        };
+        {
+            let page_id = vol.layout.data_page_id(index_block);
+            fetch_page_write!(index_page: index_page, vol, page_id, au);
+            index_page.index[i] = block;
+        }
+        let page_id = vol.layout.data_page_id(block);
+        journal::mark_data(vol, page_id);
+        fetch_page_write!(data_page: bytes, vol, page_id, au);
+        data_page[..chunk.len()].copy_from_slice(chunk);
+    }
+    Some(index_block)
+}
+
+fn read_value(vol: &Volume, entry: &Entry) -> Vec<u8> {
+    if entry.value_block == -1 {
+        return entry.inline.clone();
+    }
+    let index = {
+        let page_id = vol.layout.data_page_id(entry.value_block);
+        fetch_page_read!(index_page: index_page, vol, page_id, au);
+        index_page.index
+    };
+    let mut value = vec![0; entry.len];
+    for (chunk, block) in value.chunks_mut(PAGE_SIZE).zip(index) {
+        fetch_page_read!(data_page: bytes, vol, vol.layout.data_page_id(block), au);
+        chunk.copy_from_slice(&data_page[..chunk.len()]);
+    }
+    value
+}
+
+///读取名为name的属性的值，不存在时返回None
+pub fn get(vol: &Volume, inode: &Inode, name: &[u8]) -> Option<Vec<u8>> {
+    let entries = load(vol, inode.xattr_block());
+    let entry = entries.iter().find(|entry| entry.name == name)?;
+    Some(read_value(vol, entry))
+}
+
+///所有属性名，每个名字后跟一个'\0'，与listxattr的返回格式相同
+pub fn list(vol: &Volume, inode: &Inode) -> Vec<u8> {
+    let mut names = Vec::new();
+    for entry in load(vol, inode.xattr_block()) {
+        names.extend_from_slice(&entry.name);
+        names.push(0);
+    }
+    names
+}
+
+///设置名为name的属性，flags为XATTR_CREATE或XATTR_REPLACE时属性必须不存在或已存在。
+///成功时修改inode中的扩展属性块号，调用者负责写回inode
+pub fn set(vol: &Volume, inode: &mut Inode, name: &[u8], value: &[u8], flags: c_int) -> c_int {
+    if name.is_empty() || name.len() > XATTR_NAME_MAX {
+        return -libc::ERANGE;
+    }
+    if value.len() > XATTR_SIZE_MAX {
+        return -libc::E2BIG;
+    }
+    let mut entries = load(vol, inode.xattr_block());
+    let pos = entries.iter().position(|entry| entry.name == name);
+    match pos {
+        Some(_) if flags & libc::XATTR_CREATE != 0 => return -libc::EEXIST,
+        None if flags & libc::XATTR_REPLACE != 0 => return -libc::ENODATA,
+        _ => {}
+    }
+    let entry = if value.len() <= XATTR_INLINE_MAX {
+        Entry {
+            name: name.to_vec(),
+            len: value.len(),
+            value_block: -1,
+            inline: value.to_vec(),
+        }
+    } else {
+        let Some(value_block) = write_value(vol, value) else {
+            return -libc::ENOSPC;
+        };
+        Entry {
+            name: name.to_vec(),
+            len: value.len(),
+            value_block,
+            inline: Vec::new(),
+        }
+    };
+    let value_block = entry.value_block;
+    let old = match pos {
+        Some(i) => Some(std::mem::replace(&mut entries[i], entry)),
+        None => {
+            entries.push(entry);
+            None
+        }
+    };
+    let fits = entries.iter().map(Entry::size).sum::<usize>() <= XATTR_SPACE;
+    let block = match inode.xattr_block() {
+        _ if !fits => None,
+        -1 => alloc_block(vol, false),
+        block => Some(block),
+    };
+    let Some(block) = block else {
+        free_index_block(vol, value_block, 1);
+        return -libc::ENOSPC;
+    };
+    debug!("set xattr of {:?} in block {}", inode.inode_id, block);
+    store(vol, block, &entries);
+    inode.set_xattr_block(block);
+    if let Some(old) = old {
+        free_index_block(vol, old.value_block, 1);
+    }
+    SUCCESS
+}
+
+///删除名为name的属性，最后一个属性被删除时释放扩展属性块
+pub fn remove(vol: &Volume, inode: &mut Inode, name: &[u8]) -> c_int {
+    let block = inode.xattr_block();
+    let mut entries = load(vol, block);
+    let Some(pos) = entries.iter().position(|entry| entry.name == name) else {
+        return -libc::ENODATA;
+    };
+    let entry = entries.remove(pos);
+    free_index_block(vol, entry.value_block, 1);
+    if entries.is_empty() {
+        free_block(vol, block);
+        inode.set_xattr_block(-1);
+    } else {
+        store(vol, block, &entries);
+    }
+    SUCCESS
+}
+
+///释放inode的扩展属性块以及长值占用的块。一次最多释放一个长值，返回false表示还有没释放的长值，
+///调用者在新的事务中再次调用；长值都释放后释放扩展属性块
+pub fn free(vol: &Volume, inode: &mut Inode) -> bool {
+    let block = inode.xattr_block();
+    if block == -1 {
+        return true;
+    }
+    let mut entries = load(vol, block);
+    if let Some(pos) = entries.iter().position(|entry| entry.value_block != -1) {
+        let entry = entries.remove(pos);
+        free_index_block(vol, entry.value_block, 1);
+        store(vol, block, &entries);
+        return false;
+    }
+    free_block(vol, block);
+    inode.set_xattr_block(-1);
+    true
+}
+
+///扩展属性块本身以及长值占用的所有块，block为-1时返回空
+pub fn blocks(vol: &Volume, block: i32) -> Vec<i32> {
+    if block == -1 {
+        return Vec::new();
+    }
+    let mut blocks = vec![block];
+    for entry in load(vol, block) {
+        collect_index_blocks(vol, entry.value_block, 1, &mut blocks);
+    }
+    blocks.retain(|&block| block != -1);
+    blocks
+}
+
+///存放在单独数据块中的长值的(属性名, 索引块号)，供fsck检查
+pub fn value_blocks(vol: &Volume, block: i32) -> Vec<(Vec<u8>, i32)> {
+    load(vol, block)
+        .into_iter()
+        .filter(|entry| entry.value_block != -1)
+        .map(|entry| (entry.name, entry.value_block))
+        .collect()
+}
+
+///从扩展属性块中去掉名为name的属性但不释放任何块，fsck用来丢弃值的块号已损坏的属性
+pub fn forget(vol: &Volume, block: i32, name: &[u8]) {
+    let mut entries = load(vol, block);
+    entries.retain(|entry| entry.name != name);
+    store(vol, block, &entries);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer_pool_manager::ParallelBufferPoolManager;
+    use crate::fs::filesystem::{FsOptions, RustFs};
+    use crate::fs::fsck::fsck;
+    use crate::fs::interface::{
+        fs, rustfs_destory, rustfs_getxattr, rustfs_listxattr, rustfs_mknod, rustfs_removexattr,
+        rustfs_setxattr, rustfs_unlink, set_fs, TestMount,
+    };
+    use crate::fs::superblock::free_counts;
+    use crate::harness::{mkfs, snapshot};
+    use libc::c_char;
+    use std::ffi::CString;
+    use std::ptr::null_mut;
+
+    fn c(s: &str) -> CString {
+        CString::new(s).unwrap()
+    }
+
+    fn setxattr(path: &str, name: &str, value: &[u8], flags: c_int) -> c_int {
+        let (path, name) = (c(path), c(name));
+        rustfs_setxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            value.as_ptr().cast(),
+            value.len(),
+            flags,
+        )
+    }
+
+    fn getxattr(path: &str, name: &str) -> Result<Vec<u8>, c_int> {
+        let (path, name) = (c(path), c(name));
+        let len = rustfs_getxattr(path.as_ptr(), name.as_ptr(), null_mut(), 0);
+        if len < 0 {
+            return Err(len);
+        }
+        let mut buf = vec![0u8; len as usize];
+        let ret = rustfs_getxattr(
+            path.as_ptr(),
+            name.as_ptr(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+        );
+        assert_eq!(ret, len);
+        Ok(buf)
+    }
+
+    fn listxattr(path: &str) -> Vec<u8> {
+        let path = c(path);
+        let len = rustfs_listxattr(path.as_ptr(), null_mut(), 0);
+        let mut buf = vec![0u8; len as usize];
+        let ret = rustfs_listxattr(path.as_ptr(), buf.as_mut_ptr().cast(), buf.len());
+        assert_eq!(ret, len);
+        buf
+    }
+
+    ///卸载后从磁盘上的内容重新挂载
+    fn remount() {
+        let bpm = fs().vol.bpm.clone();
+        rustfs_destory(null_mut());
+        let bpm = ParallelBufferPoolManager::new(1, 128, snapshot(&bpm));
+        set_fs(RustFs::mount(bpm, FsOptions::default()).unwrap());
+    }
+
+    #[test]
+    fn test_xattr() {
+        let bpm = ParallelBufferPoolManager::new(1, 128, mkfs(1024, 0));
+        let _mount = TestMount::new(RustFs::mount(bpm, FsOptions::default()).unwrap());
+        assert_eq!(rustfs_mknod(c("/f").as_ptr(), 0o644, 0), SUCCESS);
+        let free_blocks = free_counts(&fs().vol).1;
+        assert_eq!(getxattr("/f", "user.a"), Err(-libc::ENODATA));
+        assert!(listxattr("/f").is_empty());
+
+        assert_eq!(setxattr("/f", "user.a", b"1", 0), SUCCESS);
+        assert_eq!(
+            setxattr("/f", "user.a", b"2", libc::XATTR_CREATE),
+            -libc::EEXIST
+        );
+        assert_eq!(
+            setxattr("/f", "user.b", b"2", libc::XATTR_REPLACE),
+            -libc::ENODATA
+        );
+        assert_eq!(setxattr("/f", "user.a", b"", libc::XATTR_REPLACE), SUCCESS);
+        assert_eq!(getxattr("/f", "user.a"), Ok(Vec::new()));
+        assert_eq!(setxattr("/f", "user.a", b"short", 0), SUCCESS);
+        //短值和名字一起存放在扩展属性块中
+        assert_eq!(free_counts(&fs().vol).1, free_blocks - 1);
+        //长值占用一个索引块和两个数据块
+        let big: Vec<u8> = (0..PAGE_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        assert_eq!(
+            setxattr("/f", "user.big", &big, libc::XATTR_CREATE),
+            SUCCESS
+        );
+        assert_eq!(free_counts(&fs().vol).1, free_blocks - 4);
+        let too_big = vec![0u8; XATTR_SIZE_MAX + 1];
+        assert_eq!(setxattr("/f", "user.c", &too_big, 0), -libc::E2BIG);
+        let long_name = format!("user.{}", "n".repeat(XATTR_NAME_MAX));
+        assert_eq!(setxattr("/f", &long_name, b"", 0), -libc::ERANGE);
+        assert_eq!(listxattr("/f"), b"user.a\0user.big\0");
+        let (path, name) = (c("/f"), c("user.a"));
+        let mut buf = [0 as c_char; 2];
+        let ret = rustfs_getxattr(path.as_ptr(), name.as_ptr(), buf.as_mut_ptr(), 2);
+        assert_eq!(ret, -libc::ERANGE);
+        let report = fsck(&fs().vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+
+        remount();
+        assert_eq!(getxattr("/f", "user.a"), Ok(b"short".to_vec()));
+        assert_eq!(getxattr("/f", "user.big"), Ok(big));
+        //长值被短值替换后释放它的块
+        assert_eq!(setxattr("/f", "user.big", b"small", 0), SUCCESS);
+        assert_eq!(free_counts(&fs().vol).1, free_blocks - 1);
+        assert_eq!(getxattr("/f", "user.big"), Ok(b"small".to_vec()));
+        assert_eq!(
+            rustfs_removexattr(c("/f").as_ptr(), c("user.a").as_ptr()),
+            SUCCESS
+        );
+        assert_eq!(
+            rustfs_removexattr(c("/f").as_ptr(), c("user.a").as_ptr()),
+            -libc::ENODATA
+        );
+        assert_eq!(listxattr("/f"), b"user.big\0");
+        //删除最后一个属性时释放扩展属性块
+        assert_eq!(
+            rustfs_removexattr(c("/f").as_ptr(), c("user.big").as_ptr()),
+            SUCCESS
+        );
+        assert_eq!(free_counts(&fs().vol).1, free_blocks);
+
+        //删除文件时释放扩展属性占用的所有块
+        assert_eq!(
+            setxattr("/f", "user.big", &vec![1u8; 3 * PAGE_SIZE], 0),
+            SUCCESS
+        );
+        assert_eq!(free_counts(&fs().vol).1, free_blocks - 5);
+        assert_eq!(rustfs_unlink(c("/f").as_ptr()), SUCCESS);
+        assert_eq!(free_counts(&fs().vol).1, free_blocks);
+        let report = fsck(&fs().vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+        rustfs_destory(null_mut());
+    }
+}
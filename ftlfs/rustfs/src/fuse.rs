@@ -91,6 +91,13 @@ pub struct fuse_file_info {
     pub lock_owner: u64,
 }
 
+impl fuse_file_info {
+    ///open中设置direct_io：读写不经过内核的页缓存，读取的长度也不受文件大小限制
+    pub fn set_direct_io(&mut self) {
+        self.bitfield |= 1;
+    }
+}
+
 #[repr(C)]
 pub struct fuse_conn_info {
     /**
@@ -255,7 +262,7 @@ pub struct fuse_operations {
      * Changed in version 2.2
      */
     // int (*open) (const char *, struct fuse_file_info *);
-    pub open: Option<extern "C" fn(path: *const c_char, *mut fuse_file_info) -> c_int>,
+    pub open: Option<unsafe extern "C" fn(path: *const c_char, *mut fuse_file_info) -> c_int>,
 
     /** Read data from an open file
      *
@@ -270,7 +277,7 @@ pub struct fuse_operations {
      */
     // int (*read) (const char *, char *, size_t, off_t, struct fuse_file_info *);
     pub read: Option<
-        extern "C" fn(
+        unsafe extern "C" fn(
             path: *const c_char,
             dst: *mut c_char,
             size_t,
@@ -289,7 +296,7 @@ pub struct fuse_operations {
      */
     // int (*write) (const char *, const char *, size_t, off_t, struct fuse_file_info *);
     pub write: Option<
-        extern "C" fn(
+        unsafe extern "C" fn(
             path: *const c_char,
             src: *const c_char,
             size_t,
@@ -306,7 +313,7 @@ pub struct fuse_operations {
      * version 2.5
      */
     // int (*statfs) (const char *, struct statvfs *);
-    pub statfs: Option<extern "C" fn(path: *const c_char, libc::statvfs) -> c_int>,
+    pub statfs: Option<unsafe extern "C" fn(path: *const c_char, *mut libc::statvfs) -> c_int>,
 
     /** Possibly flush cached data
      *
@@ -332,7 +339,7 @@ pub struct fuse_operations {
      * Changed in version 2.2
      */
     // int (*flush) (const char *, struct fuse_file_info *);
-    pub flush: Option<extern "C" fn(path: *const c_char, *mut fuse_file_info) -> c_int>,
+    pub flush: Option<unsafe extern "C" fn(path: *const c_char, *mut fuse_file_info) -> c_int>,
 
     /** Release an open file
      *
@@ -349,7 +356,7 @@ pub struct fuse_operations {
      * Changed in version 2.2
      */
     // int (*release) (const char *, struct fuse_file_info *);
-    pub release: Option<extern "C" fn(path: *const c_char, *mut fuse_file_info) -> c_int>,
+    pub release: Option<unsafe extern "C" fn(path: *const c_char, *mut fuse_file_info) -> c_int>,
 
     /** Synchronize file contents
      *
@@ -359,7 +366,8 @@ pub struct fuse_operations {
      * Changed in version 2.2
      */
     // int (*fsync) (const char *, int, struct fuse_file_info *);
-    pub fsync: Option<extern "C" fn(path: *const c_char, c_int, *mut fuse_file_info) -> c_int>,
+    pub fsync:
+        Option<unsafe extern "C" fn(path: *const c_char, c_int, *mut fuse_file_info) -> c_int>,
 
     /** Set extended attributes */
     // int (*setxattr) (const char *, const char *, const char *, size_t, int);
@@ -389,7 +397,7 @@ pub struct fuse_operations {
      * Introduced in version 2.3
      */
     // int (*opendir) (const char *, struct fuse_file_info *);
-    pub opendir: Option<extern "C" fn(*const c_char, *mut fuse_file_info) -> c_int>,
+    pub opendir: Option<unsafe extern "C" fn(*const c_char, *mut fuse_file_info) -> c_int>,
 
     /** Read directory
      *
@@ -399,22 +407,22 @@ pub struct fuse_operations {
      * The filesystem may choose between two modes of operation:
      *
      * 1) The readdir implementation ignores the offset parameter, and
-     * passes zero to the filler function's offset.  The filler
-     * function will not return '1' (unless an error happens), so the
-     * whole directory is read in a single readdir operation.  This
-     * works just like the old getdir() method.
+     *    passes zero to the filler function's offset.  The filler
+     *    function will not return '1' (unless an error happens), so the
+     *    whole directory is read in a single readdir operation.  This
+     *    works just like the old getdir() method.
      *
      * 2) The readdir implementation keeps track of the offsets of the
-     * directory entries.  It uses the offset parameter and always
-     * passes non-zero offset to the filler function.  When the buffer
-     * is full (or an error happens) the filler function will return
-     * '1'.
+     *    directory entries.  It uses the offset parameter and always
+     *    passes non-zero offset to the filler function.  When the buffer
+     *    is full (or an error happens) the filler function will return
+     *    '1'.
      *
      * Introduced in version 2.3
      */
     // int (*readdir) (const char *, void *, fuse_fill_dir_t, off_t, struct fuse_file_info *);
     pub readdir: Option<
-        extern "C" fn(
+        unsafe extern "C" fn(
             *const c_char,
             *mut c_void,
             fuse_fill_dir_t,
@@ -428,7 +436,7 @@ pub struct fuse_operations {
      * Introduced in version 2.3
      */
     // int (*releasedir) (const char *, struct fuse_file_info *);
-    pub releasedir: Option<extern "C" fn(*const c_char, *mut fuse_file_info) -> c_int>,
+    pub releasedir: Option<unsafe extern "C" fn(*const c_char, *mut fuse_file_info) -> c_int>,
 
     /** Synchronize directory contents
      *
@@ -438,7 +446,7 @@ pub struct fuse_operations {
      * Introduced in version 2.3
      */
     // int (*fsyncdir) (const char *, int, struct fuse_file_info *);
-    pub fsyncdir: Option<extern "C" fn(*const c_char, c_int, *mut fuse_file_info) -> c_int>,
+    pub fsyncdir: Option<unsafe extern "C" fn(*const c_char, c_int, *mut fuse_file_info) -> c_int>,
 
     /**
      * Initialize filesystem
@@ -490,7 +498,8 @@ pub struct fuse_operations {
      * Introduced in version 2.5
      */
     // int (*create) (const char *, mode_t, struct fuse_file_info *);
-    pub create: Option<extern "C" fn(*const c_char, libc::mode_t, *mut fuse_file_info) -> c_int>,
+    pub create:
+        Option<unsafe extern "C" fn(*const c_char, libc::mode_t, *mut fuse_file_info) -> c_int>,
 
     /**
      * Change the size of an open file
@@ -505,7 +514,7 @@ pub struct fuse_operations {
      * Introduced in version 2.5
      */
     // int (*ftruncate) (const char *, off_t, struct fuse_file_info *);
-    pub ftruncate: Option<extern "C" fn(*const c_char, off_t, *mut fuse_file_info) -> c_int>,
+    pub ftruncate: Option<unsafe extern "C" fn(*const c_char, off_t, *mut fuse_file_info) -> c_int>,
 
     // /**
     //  * Get attributes from an open file
@@ -521,7 +530,7 @@ pub struct fuse_operations {
     //  */
     // int (*fgetattr) (const char *, struct stat *, struct fuse_file_info *);
     pub fgetattr:
-        Option<extern "C" fn(*const c_char, *mut libc::stat, *mut fuse_file_info) -> c_int>,
+        Option<unsafe extern "C" fn(*const c_char, *mut libc::stat, *mut fuse_file_info) -> c_int>,
 
     /**
      * Perform POSIX file locking operation
@@ -572,7 +581,7 @@ pub struct fuse_operations {
      * Introduced in version 2.6
      */
     // int (*utimens) (const char *, const struct timespec tv[2]);
-    pub utimens: Option<extern "C" fn(*const c_char, *const [libc::timespec; 2]) -> c_int>,
+    pub utimens: Option<unsafe extern "C" fn(*const c_char, *const [libc::timespec; 2]) -> c_int>,
 
     /**
      * Map block index within file to block index within device
@@ -765,6 +774,33 @@ impl fuse_operations {
     }
 }
 
+#[repr(C)]
+pub struct fuse_context {
+    /** Pointer to the fuse object */
+    // struct fuse *fuse;
+    pub fuse: *mut c_void,
+
+    /** User ID of the calling process */
+    // uid_t uid;
+    pub uid: libc::uid_t,
+
+    /** Group ID of the calling process */
+    // gid_t gid;
+    pub gid: libc::gid_t,
+
+    /** Thread ID of the calling process */
+    // pid_t pid;
+    pub pid: libc::pid_t,
+
+    /** Private filesystem data */
+    // void *private_data;
+    pub private_data: *mut c_void,
+
+    /** Umask of the calling process (introduced in version 2.8) */
+    // mode_t umask;
+    pub umask: libc::mode_t,
+}
+
 pub type fuse_opt_proc_t = Option<
     extern "C" fn(
         data: *mut c_void,
@@ -781,7 +817,7 @@ pub type fuse_fill_dir_t = extern "C" fn(
     off: off_t,
 ) -> c_int;
 
-#[link(name = "ddriver", kind = "static")]
+#[link(name = "fuse")]
 extern "C" {
     // int fuse_opt_parse(struct fuse_args *args, void *data, const struct fuse_opt opts[], fuse_opt_proc_t proc);
     pub fn fuse_opt_parse(
@@ -801,4 +837,8 @@ extern "C" {
     ) -> c_int;
     // void fuse_opt_free_args(struct fuse_args *args);
     pub fn fuse_opt_free_args(args: *mut fuse_args);
+    // struct fuse_context *fuse_get_context(void);
+    pub fn fuse_get_context() -> *mut fuse_context;
+    // int fuse_getgroups(int size, gid_t list[]);
+    pub fn fuse_getgroups(size: c_int, list: *mut libc::gid_t) -> c_int;
 }
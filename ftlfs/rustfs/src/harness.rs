@@ -0,0 +1,725 @@
+//! 不经过fuse、不需要ddriver的测试工具，crate内的测试直接使用，启用testing特性时对外公开。
+//!
+//! Harness在内存磁盘上格式化并挂载RustFs，按路径调用Filesystem的方法，路径的处理与
+//! interface.rs中的fuse回调相同。挂载后停止flusher，此后只有事务提交、检查点和换出会写磁盘，
+//! crash丢弃缓存中还没有写回的页，从磁盘上已有的内容重新挂载，模拟掉电。
+//! 挂载在CrashDevice上时，掉电还会丢掉设备在指定次数之后收到的写入，可以让掉电发生在提交或检查点的中途。
+//!
+//! Model是只记录目录结构和文件内容的参考实现，test中用随机的操作序列比较两者的返回值和最终状态
+use crate::buffer::buffer_pool_manager::{Bpm, ParallelBufferPoolManager};
+use crate::buffer::replacer::PageId;
+use crate::device::{BlockDevice, MemDevice};
+use crate::fs::custom::PAGE_SIZE;
+use crate::fs::filesystem::{Filesystem, FsOptions, FsResult, PathEntry, RustFs, SetAttr};
+use crate::fs::layout::{Layout, LayoutOptions};
+use crate::fs::perm::Cred;
+use crate::fs::superblock::format;
+use crate::fs::types::InodeId;
+use crate::fs::utils::split_path;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+///缓存池的页数，远小于磁盘，测试中会发生换出
+const POOL_SIZE: usize = 128;
+
+///在page_num页的内存磁盘上按默认布局格式化，features同mkfs，返回格式化好的磁盘
+pub fn mkfs(page_num: usize, features: u32) -> Box<dyn BlockDevice> {
+    let bpm = ParallelBufferPoolManager::new(1, POOL_SIZE, Box::new(MemDevice::new(page_num)));
+    let layout = Layout::new(page_num, LayoutOptions::default()).unwrap();
+    let vol = format(Arc::new(bpm), layout, features);
+    //停止flusher时会把所有脏页写回设备
+    vol.stop_flusher();
+    snapshot(&vol.bpm)
+}
+
+///把缓存池的设备上的内容拷贝成一个新的内存磁盘，缓存中没有写回的页不在其中
+pub fn snapshot(bpm: &Bpm) -> Box<dyn BlockDevice> {
+    let device = bpm.device();
+    let mut pages = vec![[0; PAGE_SIZE]; device.page_num()];
+    for (i, page) in pages.iter_mut().enumerate() {
+        device.read_page(PageId(i), page);
+    }
+    Box::new(MemDevice::from_pages(pages))
+}
+
+struct CrashState {
+    ///设备收到的写入页数
+    writes: usize,
+    ///超出预算、掉电时会丢失的写入
+    lost: BTreeMap<usize, [u8; PAGE_SIZE]>,
+}
+
+///模拟掉电的设备：前budget页写入照常写到inner，之后的写入只记在内存中，掉电时丢失。
+///多页写入按页计数，掉电可能发生在一次多页写入的中间。读出时能看到丢失的写入，
+///文件系统可以一直运行到调用Harness::crash。clone出的设备共享同一份状态
+#[derive(Clone)]
+pub struct CrashDevice {
+    inner: Arc<dyn BlockDevice>,
+    budget: usize,
+    state: Arc<Mutex<CrashState>>,
+}
+
+impl CrashDevice {
+    pub fn new(inner: Box<dyn BlockDevice>, budget: usize) -> Self {
+        CrashDevice {
+            inner: inner.into(),
+            budget,
+            state: Arc::new(Mutex::new(CrashState {
+                writes: 0,
+                lost: BTreeMap::new(),
+            })),
+        }
+    }
+
+    ///到目前为止收到的写入页数，包括丢失的
+    pub fn writes(&self) -> usize {
+        self.state.lock().writes
+    }
+
+    ///掉电后留在磁盘上的内容
+    pub fn durable(&self) -> Box<dyn BlockDevice> {
+        let _state = self.state.lock();
+        let mut pages = vec![[0; PAGE_SIZE]; self.inner.page_num()];
+        for (i, page) in pages.iter_mut().enumerate() {
+            self.inner.read_page(PageId(i), page);
+        }
+        Box::new(MemDevice::from_pages(pages))
+    }
+}
+
+impl BlockDevice for CrashDevice {
+    fn read_page(&self, page_id: PageId, page: &mut [u8; PAGE_SIZE]) {
+        let state = self.state.lock();
+        match state.lost.get(&page_id.0) {
+            Some(lost) => page.copy_from_slice(lost),
+            None => self.inner.read_page(page_id, page),
+        }
+    }
+
+    fn write_page(&self, page_id: PageId, page: &[u8; PAGE_SIZE]) {
+        let mut state = self.state.lock();
+        if state.writes < self.budget {
+            self.inner.write_page(page_id, page);
+        } else {
+            state.lost.insert(page_id.0, *page);
+        }
+        state.writes += 1;
+    }
+
+    fn page_num(&self) -> usize {
+        self.inner.page_num()
+    }
+
+    ///丢失的写入永远不会落盘，没有需要等待的
+    fn sync(&self) {}
+}
+
+pub struct Harness {
+    fs: RustFs,
+    cred: Cred,
+    ///挂载在CrashDevice上时，掉电后从它取出磁盘上的内容
+    crash_device: Option<CrashDevice>,
+}
+
+impl Harness {
+    ///格式化page_num页的内存磁盘并以root的身份挂载，features同mkfs
+    pub fn new(page_num: usize, features: u32) -> Self {
+        Self::mount(mkfs(page_num, features))
+    }
+
+    ///同new，但挂载在CrashDevice上，格式化之后设备再收到budget页写入，之后的写入在掉电时丢失
+    pub fn with_crash_device(page_num: usize, features: u32, budget: usize) -> Self {
+        let device = CrashDevice::new(mkfs(page_num, features), budget);
+        let mut harness = Self::mount(Box::new(device.clone()));
+        harness.crash_device = Some(device);
+        harness
+    }
+
+    fn mount(device: Box<dyn BlockDevice>) -> Self {
+        let bpm = ParallelBufferPoolManager::new(1, POOL_SIZE, device);
+        let fs = RustFs::mount(bpm, FsOptions::default()).unwrap();
+        fs.vol.stop_flusher();
+        Harness {
+            fs,
+            cred: Cred::new(0, 0, Vec::new()),
+            crash_device: None,
+        }
+    }
+
+    pub fn fs(&self) -> &RustFs {
+        &self.fs
+    }
+
+    ///挂载在CrashDevice上时返回这个设备
+    pub fn crash_device(&self) -> Option<&CrashDevice> {
+        self.crash_device.as_ref()
+    }
+
+    ///丢弃缓存中所有没有写回的页，以及CrashDevice丢失的写入，从磁盘上的内容重新挂载
+    pub fn crash(&mut self) {
+        let device = match &self.crash_device {
+            Some(device) => device.durable(),
+            None => snapshot(&self.fs.vol.bpm),
+        };
+        *self = Self::mount(device);
+    }
+
+    ///正常卸载
+    pub fn unmount(self) {
+        self.fs.destroy();
+    }
+
+    fn lookup(&self, path: &str) -> FsResult<PathEntry<'_>> {
+        self.fs.lookup_path(&self.cred, path)
+    }
+
+    fn parent<'a>(&self, path: &'a str) -> FsResult<(PathEntry<'_>, &'a str)> {
+        let (parent, name) = split_path(path);
+        Ok((self.lookup(parent)?, name))
+    }
+
+    pub fn mkdir(&self, path: &str) -> FsResult<()> {
+        let (dir, name) = self.parent(path)?;
+        let attr = self.fs.mkdir(&self.cred, dir.ino, name, 0o755)?;
+        self.fs.forget(InodeId(attr.st_ino as u32), 1);
+        Ok(())
+    }
+
+    pub fn mknod(&self, path: &str) -> FsResult<()> {
+        let (dir, name) = self.parent(path)?;
+        let attr = self.fs.mknod(&self.cred, dir.ino, name, 0o644)?;
+        self.fs.forget(InodeId(attr.st_ino as u32), 1);
+        Ok(())
+    }
+
+    pub fn unlink(&self, path: &str) -> FsResult<()> {
+        let (dir, name) = self.parent(path)?;
+        self.fs.unlink(&self.cred, dir.ino, name)
+    }
+
+    pub fn rmdir(&self, path: &str) -> FsResult<()> {
+        let (dir, name) = self.parent(path)?;
+        self.fs.rmdir(&self.cred, dir.ino, name)
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> FsResult<()> {
+        let (old_dir, old_name) = self.parent(from)?;
+        let (new_dir, new_name) = self.parent(to)?;
+        self.fs
+            .rename(&self.cred, old_dir.ino, old_name, new_dir.ino, new_name)
+    }
+
+    pub fn link(&self, from: &str, to: &str) -> FsResult<()> {
+        let file = self.lookup(from)?;
+        let (dir, name) = self.parent(to)?;
+        let attr = self.fs.link(&self.cred, file.ino, dir.ino, name)?;
+        self.fs.forget(InodeId(attr.st_ino as u32), 1);
+        Ok(())
+    }
+
+    ///打开文件，在offset处写入data后关闭
+    pub fn write(&self, path: &str, offset: usize, data: &[u8]) -> FsResult<usize> {
+        let file = self.lookup(path)?;
+        let fh = self.fs.open(&self.cred, file.ino, libc::O_WRONLY)?;
+        let written = self.fs.write(&self.cred, file.ino, fh, offset as u64, data);
+        self.fs.release(&self.cred, file.ino, fh)?;
+        written
+    }
+
+    pub fn truncate(&self, path: &str, size: usize) -> FsResult<()> {
+        let file = self.lookup(path)?;
+        let attr = SetAttr {
+            size: Some(size as u64),
+            ..Default::default()
+        };
+        self.fs.setattr(&self.cred, file.ino, attr).map(|_| ())
+    }
+
+    ///读出整个文件
+    pub fn read(&self, path: &str) -> FsResult<Vec<u8>> {
+        let file = self.lookup(path)?;
+        let size = self.fs.getattr(&self.cred, file.ino)?.st_size as usize;
+        let fh = self.fs.open(&self.cred, file.ino, libc::O_RDONLY)?;
+        let mut buf = vec![0; size];
+        let read = self.fs.read(&self.cred, file.ino, fh, 0, &mut buf);
+        self.fs.release(&self.cred, file.ino, fh)?;
+        buf.truncate(read?);
+        Ok(buf)
+    }
+
+    ///目录中除"."和".."以外的名字，按名字排序
+    pub fn readdir(&self, path: &str) -> FsResult<Vec<String>> {
+        let dir = self.lookup(path)?;
+        let fh = self.fs.opendir(&self.cred, dir.ino, libc::O_RDONLY)?;
+        let mut names = Vec::new();
+        let mut add = |name: &[u8], _, _, _| {
+            names.push(String::from_utf8(name.to_vec()).unwrap());
+            true
+        };
+        let ret = self.fs.readdir(&self.cred, dir.ino, fh, 2, &mut add);
+        self.fs.releasedir(&self.cred, dir.ino, fh)?;
+        ret?;
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Node {
+    Dir,
+    ///文件内容在Model::files中的下标，硬链接指向同一个下标
+    File(usize),
+}
+
+///参考文件系统，路径到节点的映射。错误码和检查的先后顺序与RustFs相同
+#[derive(Clone, Default)]
+pub struct Model {
+    nodes: BTreeMap<String, Node>,
+    files: Vec<Vec<u8>>,
+}
+
+impl Model {
+    fn node(&self, path: &str) -> Option<Node> {
+        match path {
+            "/" => Some(Node::Dir),
+            _ => self.nodes.get(path).copied(),
+        }
+    }
+
+    ///同DCache::resolve，路径中间的分量必须是目录
+    fn walk(&self, path: &str) -> FsResult<Node> {
+        let mut cur = Node::Dir;
+        let mut prefix = String::new();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if cur != Node::Dir {
+                return Err(-libc::ENOTDIR);
+            }
+            prefix = format!("{prefix}/{name}");
+            cur = self.node(&prefix).ok_or(-libc::ENOENT)?;
+        }
+        Ok(cur)
+    }
+
+    fn file(&self, path: &str) -> FsResult<usize> {
+        match self.walk(path)? {
+            Node::Dir => Err(-libc::EISDIR),
+            Node::File(file) => Ok(file),
+        }
+    }
+
+    fn parent(&self, path: &str) -> FsResult<()> {
+        self.walk(split_path(path).0)?;
+        Ok(())
+    }
+
+    fn dir(&self, path: &str) -> FsResult<()> {
+        match self.walk(split_path(path).0)? {
+            Node::Dir => Ok(()),
+            Node::File(_) => Err(-libc::ENOTDIR),
+        }
+    }
+
+    fn is_empty_dir(&self, path: &str) -> bool {
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        !self.nodes.keys().any(|key| key.starts_with(&prefix))
+    }
+
+    fn create(&mut self, path: &str, node: Node) -> FsResult<()> {
+        self.dir(path)?;
+        if self.node(path).is_some() {
+            return Err(-libc::EEXIST);
+        }
+        self.nodes.insert(path.to_string(), node);
+        Ok(())
+    }
+
+    pub fn mkdir(&mut self, path: &str) -> FsResult<()> {
+        self.create(path, Node::Dir)
+    }
+
+    pub fn mknod(&mut self, path: &str) -> FsResult<()> {
+        self.files.push(Vec::new());
+        self.create(path, Node::File(self.files.len() - 1))
+    }
+
+    pub fn unlink(&mut self, path: &str) -> FsResult<()> {
+        self.dir(path)?;
+        match self.node(path).ok_or(-libc::ENOENT)? {
+            Node::Dir => Err(-libc::EISDIR),
+            Node::File(_) => {
+                self.nodes.remove(path);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn rmdir(&mut self, path: &str) -> FsResult<()> {
+        self.dir(path)?;
+        match self.node(path).ok_or(-libc::ENOENT)? {
+            Node::File(_) => Err(-libc::ENOTDIR),
+            Node::Dir if !self.is_empty_dir(path) => Err(-libc::ENOTEMPTY),
+            Node::Dir => {
+                self.nodes.remove(path);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> FsResult<()> {
+        self.parent(from)?;
+        self.parent(to)?;
+        self.dir(from)?;
+        self.dir(to)?;
+        let node = self.node(from).ok_or(-libc::ENOENT)?;
+        let to_parent = split_path(to).0.trim_end_matches('/');
+        if node == Node::Dir && (to_parent == from || to_parent.starts_with(&format!("{from}/"))) {
+            return Err(-libc::EINVAL);
+        }
+        if let Some(target) = self.node(to) {
+            if target == node && (node != Node::Dir || from == to) {
+                return Ok(());
+            }
+            match (node, target) {
+                (Node::Dir, Node::File(_)) => return Err(-libc::ENOTDIR),
+                (Node::File(_), Node::Dir) => return Err(-libc::EISDIR),
+                (_, Node::Dir) if !self.is_empty_dir(to) => return Err(-libc::ENOTEMPTY),
+                _ => {}
+            }
+        }
+        self.nodes.remove(to);
+        let prefix = format!("{from}/");
+        let moved: Vec<String> = self
+            .nodes
+            .keys()
+            .filter(|key| *key == from || key.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for key in moved {
+            let node = self.nodes.remove(&key).unwrap();
+            self.nodes
+                .insert(format!("{to}{}", &key[from.len()..]), node);
+        }
+        Ok(())
+    }
+
+    pub fn link(&mut self, from: &str, to: &str) -> FsResult<()> {
+        let node = self.walk(from)?;
+        self.parent(to)?;
+        self.dir(to)?;
+        if node == Node::Dir {
+            return Err(-libc::EPERM);
+        }
+        self.create(to, node)
+    }
+
+    pub fn write(&mut self, path: &str, offset: usize, data: &[u8]) -> FsResult<usize> {
+        let file = self.file(path)?;
+        let file = &mut self.files[file];
+        if file.len() < offset + data.len() {
+            file.resize(offset + data.len(), 0);
+        }
+        file[offset..offset + data.len()].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    pub fn truncate(&mut self, path: &str, size: usize) -> FsResult<()> {
+        let file = self.file(path)?;
+        self.files[file].resize(size, 0);
+        Ok(())
+    }
+
+    pub fn read(&self, path: &str) -> FsResult<Vec<u8>> {
+        Ok(self.files[self.file(path)?].clone())
+    }
+
+    pub fn readdir(&self, path: &str) -> FsResult<Vec<String>> {
+        if self.walk(path)? != Node::Dir {
+            return Err(-libc::ENOTDIR);
+        }
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        let names = self
+            .nodes
+            .keys()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .filter(|name| !name.contains('/'))
+            .map(str::to_string)
+            .collect();
+        Ok(names)
+    }
+
+    ///所有目录，包括根目录
+    pub fn dirs(&self) -> Vec<String> {
+        let dirs = self.nodes.iter().filter(|(_, node)| **node == Node::Dir);
+        std::iter::once("/".to_string())
+            .chain(dirs.map(|(path, _)| path.clone()))
+            .collect()
+    }
+
+    pub fn files(&self) -> Vec<String> {
+        let files = self.nodes.iter().filter(|(_, node)| **node != Node::Dir);
+        files.map(|(path, _)| path.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fs::def::{FEATURE_DIR_INDEX, FEATURE_EXTENTS};
+    use crate::fs::fsck::fsck;
+    use crate::fs::journal;
+
+    struct Rng(u32);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as usize % n
+        }
+
+        ///深度不超过3、由a、b、c组成的路径，路径的总数很少，操作经常落在已有的文件上
+        fn path(&mut self) -> String {
+            let depth = self.below(3) + 1;
+            (0..depth)
+                .map(|_| ["/a", "/b", "/c"][self.below(3)])
+                .collect()
+        }
+    }
+
+    #[derive(Debug)]
+    enum Op {
+        Mkdir(String),
+        Mknod(String),
+        Unlink(String),
+        Rmdir(String),
+        Rename(String, String),
+        Link(String, String),
+        Write(String, usize, Vec<u8>),
+        Truncate(String, usize),
+        Read(String),
+        Readdir(String),
+    }
+
+    fn random_op(rng: &mut Rng) -> Op {
+        match rng.below(10) {
+            0 => Op::Mkdir(rng.path()),
+            1 => Op::Mknod(rng.path()),
+            2 => Op::Unlink(rng.path()),
+            3 => Op::Rmdir(rng.path()),
+            4 => Op::Rename(rng.path(), rng.path()),
+            5 => Op::Link(rng.path(), rng.path()),
+            6 => {
+                let offset = rng.below(2 * PAGE_SIZE);
+                let len = rng.below(2 * PAGE_SIZE) + 1;
+                let seed = rng.below(251);
+                let data = (0..len).map(|i| ((seed + i) % 251) as u8).collect();
+                Op::Write(rng.path(), offset, data)
+            }
+            7 => Op::Truncate(rng.path(), rng.below(3 * PAGE_SIZE)),
+            8 => Op::Read(rng.path()),
+            _ => Op::Readdir(rng.path()),
+        }
+    }
+
+    fn apply(harness: &Harness, model: &mut Model, op: &Op) {
+        match op {
+            Op::Mkdir(path) => assert_eq!(harness.mkdir(path), model.mkdir(path), "{op:?}"),
+            Op::Mknod(path) => assert_eq!(harness.mknod(path), model.mknod(path), "{op:?}"),
+            Op::Unlink(path) => assert_eq!(harness.unlink(path), model.unlink(path), "{op:?}"),
+            Op::Rmdir(path) => assert_eq!(harness.rmdir(path), model.rmdir(path), "{op:?}"),
+            Op::Rename(from, to) => {
+                assert_eq!(harness.rename(from, to), model.rename(from, to), "{op:?}")
+            }
+            Op::Link(from, to) => {
+                assert_eq!(harness.link(from, to), model.link(from, to), "{op:?}")
+            }
+            Op::Write(path, offset, data) => assert_eq!(
+                harness.write(path, *offset, data),
+                model.write(path, *offset, data),
+                "{op:?}"
+            ),
+            Op::Truncate(path, size) => assert_eq!(
+                harness.truncate(path, *size),
+                model.truncate(path, *size),
+                "{op:?}"
+            ),
+            Op::Read(path) => assert_eq!(harness.read(path), model.read(path), "{op:?}"),
+            Op::Readdir(path) => assert_eq!(harness.readdir(path), model.readdir(path), "{op:?}"),
+        }
+    }
+
+    ///比较整棵目录树和所有文件的内容，并用fsck检查磁盘
+    fn check(harness: &Harness, model: &Model) {
+        for dir in model.dirs() {
+            assert_eq!(harness.readdir(&dir), model.readdir(&dir), "{dir}");
+        }
+        for file in model.files() {
+            assert_eq!(harness.read(&file), model.read(&file), "{file}");
+        }
+        let report = fsck(&harness.fs().vol, false).unwrap();
+        assert!(report.is_clean(), "{report}");
+    }
+
+    ///目录树和所有文件的内容是否与model相同
+    fn matches(harness: &Harness, model: &Model) -> bool {
+        let same_dir = |dir: &String| harness.readdir(dir) == model.readdir(dir);
+        let same_file = |file: &String| harness.read(file) == model.read(file);
+        model.dirs().iter().all(same_dir) && model.files().iter().all(same_file)
+    }
+
+    ///都会成功的操作序列：建目录和文件，写入、链接、改名、截断，再删掉上一轮留下的一部分
+    fn script(rounds: usize) -> Vec<Op> {
+        let mut ops = Vec::new();
+        for i in 0..rounds {
+            let (dir, file) = (format!("/d{i}"), format!("/d{i}/f"));
+            let data = vec![i as u8 + 1; PAGE_SIZE + 100 * i];
+            ops.push(Op::Mkdir(dir.clone()));
+            ops.push(Op::Mknod(file.clone()));
+            ops.push(Op::Write(file.clone(), 10 * i, data));
+            ops.push(Op::Link(file.clone(), format!("/g{i}")));
+            ops.push(Op::Rename(file.clone(), format!("{dir}/h")));
+            ops.push(Op::Truncate(format!("/g{i}"), 50 * i));
+            if i > 0 {
+                ops.push(Op::Unlink(format!("/d{}/h", i - 1)));
+                ops.push(Op::Rmdir(format!("/d{}", i - 1)));
+            }
+        }
+        ops
+    }
+
+    ///在CrashDevice上执行ops，返回设备收到的写入数
+    fn run_ops(harness: &Harness, model: &mut Model, ops: &[Op]) -> usize {
+        for op in ops {
+            apply(harness, model, op);
+        }
+        harness.crash_device().unwrap().writes()
+    }
+
+    ///按seed生成ops个操作，每隔crash_every个操作掉电一次，为0时不掉电
+    fn run(seed: u32, features: u32, ops: usize, crash_every: usize) {
+        let mut harness = Harness::new(1024, features);
+        let mut model = Model::default();
+        let mut rng = Rng(seed);
+        for i in 1..=ops {
+            let op = random_op(&mut rng);
+            apply(&harness, &mut model, &op);
+            //每个操作都在一个事务中提交，掉电不会丢失已经返回的操作
+            if crash_every != 0 && i % crash_every == 0 {
+                harness.crash();
+                check(&harness, &model);
+            }
+        }
+        check(&harness, &model);
+        harness.unmount();
+    }
+
+    #[test]
+    fn test_model() {
+        for seed in [0x9e37_79b9, 0x85eb_ca6b, 0xc2b2_ae35, 0x27d4_eb2f] {
+            run(seed, 0, 400, 0);
+        }
+        run(0x2545_f491, FEATURE_DIR_INDEX | FEATURE_EXTENTS, 400, 0);
+    }
+
+    #[test]
+    fn test_crash() {
+        for seed in [0x1656_67b1, 0x7feb_352d] {
+            run(seed, 0, 300, 25);
+        }
+        run(0x2545_f491, FEATURE_DIR_INDEX | FEATURE_EXTENTS, 300, 7);
+    }
+
+    #[test]
+    fn test_crash_device() {
+        let device = CrashDevice::new(Box::new(MemDevice::new(4)), 2);
+        for i in 0..4 {
+            device.write_page(PageId(i), &[i as u8 + 1; PAGE_SIZE]);
+        }
+        assert_eq!(device.writes(), 4);
+        //丢失的写入在掉电前仍能读到
+        let mut buf = [0; PAGE_SIZE];
+        device.read_page(PageId(3), &mut buf);
+        assert_eq!(buf[0], 4);
+        let durable = device.durable();
+        for (i, expect) in [1, 2, 0, 0].into_iter().enumerate() {
+            durable.read_page(PageId(i), &mut buf);
+            assert_eq!(buf[0], expect);
+        }
+    }
+
+    ///依次让设备在每一页写入之后掉电，掉电时正在执行的操作要么完整生效，要么完全没有生效。
+    ///写入的页数超过日志区，中途做过检查点，掉电点也会落在检查点中
+    #[test]
+    fn test_crash_in_commit() {
+        let ops = script(8);
+        //先完整地执行一遍，记下每个操作之后的写入数和model
+        let harness = Harness::with_crash_device(1024, 0, usize::MAX);
+        let mut model = Model::default();
+        let mut states = vec![(0, model.clone())];
+        for op in &ops {
+            let writes = run_ops(&harness, &mut model, std::slice::from_ref(op));
+            states.push((writes, model.clone()));
+        }
+        let total = states.last().unwrap().0;
+        assert!(total > harness.fs().vol.layout.journal_pages);
+        for budget in 0..total {
+            let mut harness = Harness::with_crash_device(1024, 0, budget);
+            assert_eq!(run_ops(&harness, &mut Model::default(), &ops), total);
+            harness.crash();
+            let i = states
+                .iter()
+                .position(|(writes, _)| *writes > budget)
+                .unwrap();
+            let (before, after) = (&states[i - 1].1, &states[i].1);
+            assert!(
+                matches(&harness, before) || matches(&harness, after),
+                "crash after {budget} writes in {:?}",
+                ops[i - 1]
+            );
+            let report = fsck(&harness.fs().vol, false).unwrap();
+            assert!(report.is_clean(), "crash after {budget} writes: {report}");
+        }
+    }
+
+    ///让设备在检查点写回元数据页和日志超级块的每一页之后掉电，重放日志后所有操作都在
+    #[test]
+    fn test_crash_in_checkpoint() {
+        let ops = script(4);
+        let mut model = Model::default();
+        let harness = Harness::with_crash_device(1024, FEATURE_DIR_INDEX, usize::MAX);
+        let start = run_ops(&harness, &mut model, &ops);
+        journal::stop(&harness.fs().vol);
+        let end = harness.crash_device().unwrap().writes();
+        assert!(end > start);
+        for budget in start..end {
+            let mut harness = Harness::with_crash_device(1024, FEATURE_DIR_INDEX, budget);
+            run_ops(&harness, &mut Model::default(), &ops);
+            journal::stop(&harness.fs().vol);
+            harness.crash();
+            check(&harness, &model);
+        }
+    }
+
+    #[test]
+    fn test_harness() {
+        let mut harness = Harness::new(1024, 0);
+        assert_eq!(harness.mkdir("/d"), Ok(()));
+        assert_eq!(harness.mknod("/d/f"), Ok(()));
+        assert_eq!(harness.write("/d/f", 3, b"abc"), Ok(3));
+        assert_eq!(harness.link("/d/f", "/g"), Ok(()));
+        assert_eq!(harness.read("/g"), Ok(b"\0\0\0abc".to_vec()));
+        assert_eq!(
+            harness.readdir("/"),
+            Ok(vec!["d".to_string(), "g".to_string()])
+        );
+        assert_eq!(harness.rmdir("/d"), Err(-libc::ENOTEMPTY));
+        assert_eq!(harness.read("/d"), Err(-libc::EISDIR));
+        //提交后元数据只在日志中，原处的页还没有写回
+        assert!(harness.fs().vol.bpm.dirty_num() > 0);
+        harness.crash();
+        assert_eq!(harness.read("/d/f"), Ok(b"\0\0\0abc".to_vec()));
+        harness.unmount();
+    }
+}
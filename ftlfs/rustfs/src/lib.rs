@@ -0,0 +1,44 @@
+#![allow(unused)]
+extern crate core;
+
+use std::ptr;
+
+#[cfg(test)]
+mod bench;
+pub mod buffer;
+#[cfg(feature = "ddriver")]
+pub mod ddriver;
+pub mod device;
+pub mod fs;
+pub mod fuse;
+#[cfg(any(test, feature = "testing"))]
+pub mod harness;
+pub mod utils;
+
+///fuse_opt_parse解析出的挂载参数
+#[repr(C)]
+pub struct CustomOptions {
+    pub device: *const libc::c_char,
+    ///flusher写回脏页的间隔（毫秒），为0时只在脏页超过上限或卸载时写回
+    pub flush_interval: libc::c_uint,
+    ///脏页数的上限，超过后立即写回，为0时取缓存总页数的一半
+    pub dirty_limit: libc::c_uint,
+    ///关闭文件时是否同fsync一样把文件写到磁盘上
+    pub sync_on_close: libc::c_int,
+    ///缓存池的替换算法，见buffer::replacer::ReplacerKind，为空时使用LRU
+    pub replacer: *const libc::c_char,
+    ///顺序读时最多预读的页数，为0时关闭预读
+    pub readahead: libc::c_uint,
+    ///flusher一次设备写最多合并的连续脏页数，为0时取64，为1时不合并
+    pub write_batch: libc::c_uint,
+}
+
+pub static mut NEWFS_OPTIONS: CustomOptions = CustomOptions {
+    device: ptr::null(),
+    flush_interval: 10,
+    dirty_limit: 0,
+    sync_on_close: 0,
+    replacer: ptr::null(),
+    readahead: 32,
+    write_batch: 0,
+};
@@ -1,35 +1,21 @@
-#![feature(raw_ref_op)]
-#![feature(core_intrinsics)]
-#![feature(linked_list_cursors)]
 #![allow(unused)]
-#![feature(once_cell)]
-#![feature(panic_info_message)]
-extern crate core;
 
-use crate::fs::custom::DDRIVER_PATH;
-use fs::interface::*;
+use rustfs::fs::custom::DDRIVER_PATH;
+use rustfs::fs::interface::*;
+use rustfs::{fuse, CustomOptions, NEWFS_OPTIONS};
 use std::{env, ffi::CString, mem, ptr};
 
-pub mod buffer;
-pub mod ddriver;
-pub mod fs;
-pub mod fuse;
-pub mod utils;
-
-#[repr(C)]
-struct CustomOptions {
-    device: *const libc::c_char,
-}
-
-static mut NEWFS_OPTIONS: CustomOptions = CustomOptions {
-    device: ptr::null(),
-};
-
 fn get_operations() -> fuse::fuse_operations {
     let mut op = fuse::fuse_operations::empty();
     op.init = Some(rustfs_init);
     op.destroy = Some(rustfs_destory);
     op.getattr = Some(rustfs_getattr);
+    op.fgetattr = Some(rustfs_fgetattr);
+    op.open = Some(rustfs_open);
+    op.create = Some(rustfs_create);
+    op.release = Some(rustfs_release);
+    op.opendir = Some(rustfs_opendir);
+    op.releasedir = Some(rustfs_releasedir);
     op.readdir = Some(rustfs_readdir);
     op.mkdir = Some(rustfs_mkdir);
     op.mknod = Some(rustfs_mknod);
@@ -40,7 +26,23 @@ fn get_operations() -> fuse::fuse_operations {
     op.rmdir = Some(rustfs_rmdir);
     op.rename = Some(rustfs_rename);
     op.utimens = Some(rustfs_utimens);
+    op.chmod = Some(rustfs_chmod);
+    op.chown = Some(rustfs_chown);
     op.truncate = Some(rustfs_truncate);
+    op.ftruncate = Some(rustfs_ftruncate);
+    op.statfs = Some(rustfs_statfs);
+    op.symlink = Some(rustfs_symlink);
+    op.readlink = Some(rustfs_readlink);
+    op.link = Some(rustfs_link);
+    op.flush = Some(rustfs_flush);
+    op.fsync = Some(rustfs_fsync);
+    op.fsyncdir = Some(rustfs_fsyncdir);
+    op.setxattr = Some(rustfs_setxattr);
+    op.getxattr = Some(rustfs_getxattr);
+    op.listxattr = Some(rustfs_listxattr);
+    op.removexattr = Some(rustfs_removexattr);
+    //flag_nullpath_ok：打开的文件都通过fh访问，-o hard_remove时删除后路径为空也能继续读写
+    op.bitfield = 1;
     op
 }
 
@@ -57,19 +59,52 @@ fn main() -> ! {
         argv: args.as_mut_ptr(),
         allocated: 0,
     };
-    let device_str = CString::new(DDRIVER_PATH).unwrap();
-    unsafe {
-        NEWFS_OPTIONS = CustomOptions {
-            device: libc::strdup(device_str.as_ptr()),
-        }
-    };
+    //没有指定--device时使用ddriver
+    let device_str = CString::new(format!("ddriver:{DDRIVER_PATH}")).unwrap();
+    unsafe { NEWFS_OPTIONS.device = libc::strdup(device_str.as_ptr()) };
     let templ_str = CString::new("--device=%s").unwrap();
-    let option_spec: [fuse::fuse_opt; 2] = [
+    let flush_interval_str = CString::new("--flush_interval=%u").unwrap();
+    let dirty_limit_str = CString::new("--dirty_limit=%u").unwrap();
+    let sync_on_close_str = CString::new("--sync_on_close").unwrap();
+    let replacer_str = CString::new("--replacer=%s").unwrap();
+    let readahead_str = CString::new("--readahead=%u").unwrap();
+    let write_batch_str = CString::new("--write_batch=%u").unwrap();
+    let option_spec: [fuse::fuse_opt; 8] = [
         fuse::fuse_opt {
             templ: templ_str.as_ptr(),
             offset: 0,
             value: 1,
         },
+        fuse::fuse_opt {
+            templ: flush_interval_str.as_ptr(),
+            offset: mem::offset_of!(CustomOptions, flush_interval) as libc::c_ulong,
+            value: 0,
+        },
+        fuse::fuse_opt {
+            templ: dirty_limit_str.as_ptr(),
+            offset: mem::offset_of!(CustomOptions, dirty_limit) as libc::c_ulong,
+            value: 0,
+        },
+        fuse::fuse_opt {
+            templ: sync_on_close_str.as_ptr(),
+            offset: mem::offset_of!(CustomOptions, sync_on_close) as libc::c_ulong,
+            value: 1,
+        },
+        fuse::fuse_opt {
+            templ: replacer_str.as_ptr(),
+            offset: mem::offset_of!(CustomOptions, replacer) as libc::c_ulong,
+            value: 0,
+        },
+        fuse::fuse_opt {
+            templ: readahead_str.as_ptr(),
+            offset: mem::offset_of!(CustomOptions, readahead) as libc::c_ulong,
+            value: 0,
+        },
+        fuse::fuse_opt {
+            templ: write_batch_str.as_ptr(),
+            offset: mem::offset_of!(CustomOptions, write_batch) as libc::c_ulong,
+            value: 0,
+        },
         fuse::fuse_opt {
             templ: ptr::null(),
             offset: 0,
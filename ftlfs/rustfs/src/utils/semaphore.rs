@@ -31,6 +31,16 @@ impl Semaphore {
         *count -= 1;
     }
 
+    ///不等待，信号量为0时返回false
+    pub fn try_acquire(&self) -> bool {
+        let mut count = self.lock.lock().unwrap();
+        if *count <= 0 {
+            return false;
+        }
+        *count -= 1;
+        true
+    }
+
     pub fn release(&self) {
         let mut count = self.lock.lock().unwrap();
         *count += 1;